@@ -0,0 +1,41 @@
+// The header of an ICMP message as the sniffer prints it, packet-kit decodes the IPv4
+// packet carrying it and names the message types
+
+use serde::Serialize;
+
+pub use packet_kit::names::icmp_type_name;
+
+#[derive(Serialize)]
+pub struct Icmp {
+    #[serde(rename = "type")]
+    pub type_: u8,
+    pub code: u8,
+    #[serde(rename = "checksum")]
+    pub sum: u16,
+    pub id: u16,
+    pub seq: u16,
+}
+
+impl Icmp {
+    // The type, code and checksum, then the identifier and sequence number of echo
+    // messages. None when the buffer is shorter than those 8 bytes.
+    pub fn new(buff: &[u8]) -> Option<Self> {
+        if buff.len() < 8 {
+            return None;
+        }
+        let header = (
+            buff[0],
+            buff[1],
+            u16::from_be_bytes([buff[2], buff[3]]),
+            u16::from_be_bytes([buff[4], buff[5]]),
+            u16::from_be_bytes([buff[6], buff[7]]),
+        );
+        Some(Icmp {
+            type_: header.0,
+            code: header.1,
+            sum: header.2,
+            id: header.3,
+            seq: header.4,
+        })
+    }
+}
@@ -0,0 +1,56 @@
+// The header of a TCP segment as the sniffer prints it, packet-kit decodes the IPv4
+// packet carrying it
+
+use serde::Serialize;
+
+// Constant for TCP header size
+pub const TCP_HEADER_SIZE: usize = 20;
+
+#[derive(Serialize)]
+pub struct Tcp {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub sequence_number: u32,
+    pub acknowledgment_number: u32,
+    pub data_offset: u8,
+    pub reserved: u8,
+    pub flags: u16,
+    pub window_size: u16,
+    pub checksum: u16,
+    pub urgent_pointer: u16,
+}
+
+impl Tcp {
+    // None when the buffer is too short for the fixed part of the header
+    pub fn new(buffer: &[u8]) -> Option<Self> {
+        if buffer.len() < TCP_HEADER_SIZE {
+            return None;
+        }
+        // Parse the TCP header fields from the buffer
+        let source_port = u16::from_be_bytes([buffer[0], buffer[1]]);
+        let destination_port = u16::from_be_bytes([buffer[2], buffer[3]]);
+        let sequence_number = u32::from_be_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]);
+        let acknowledgment_number =
+            u32::from_be_bytes([buffer[8], buffer[9], buffer[10], buffer[11]]);
+        let data_offset = (buffer[12] >> 4) * 4; // The top 4 bits represent the data offset
+        let reserved = (buffer[12] >> 1) & 0b00000111;
+        // The 9 flag bits start with NS, the lowest bit of byte 12
+        let flags = u16::from_be_bytes([buffer[12], buffer[13]]) & 0x01ff;
+        let window_size = u16::from_be_bytes([buffer[14], buffer[15]]);
+        let checksum = u16::from_be_bytes([buffer[16], buffer[17]]);
+        let urgent_pointer = u16::from_be_bytes([buffer[18], buffer[19]]);
+
+        Some(Tcp {
+            source_port,
+            destination_port,
+            sequence_number,
+            acknowledgment_number,
+            data_offset,
+            reserved,
+            flags,
+            window_size,
+            checksum,
+            urgent_pointer,
+        })
+    }
+}
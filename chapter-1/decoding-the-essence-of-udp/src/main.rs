@@ -1,31 +1,57 @@
-use socket2::{Domain, Protocol, Socket, Type};
+use packet_kit::cli::{self, DecoderArgs};
+use packet_kit::output::{self, Output};
+use packet_kit::pcap::LINKTYPE_RAW;
+use packet_kit::{PcapReader, PROTOCOL_ICMP};
+use serde::Serialize;
 use std::io::Result;
 use std::mem::MaybeUninit;
-use std::net::SocketAddr;
 
-fn main() -> Result<()> {
-    // Define the host to listen on
-    let host: SocketAddr = "0.0.0.0:12345".parse().unwrap();
+// What the JSON formats print, the raw bytes as they came off the socket
+#[derive(Serialize)]
+struct Record<'a> {
+    length: usize,
+    data: &'a [u8],
+}
+
+// Prints the packet as it came off the socket
+fn print_packet(raw_buffer: &[u8], output: Output) {
+    match output {
+        // Show which header field every byte of the packet belongs to,
+        // coloured by layer when printing to a terminal
+        Output::Text | Output::Hex => output::print_hex(LINKTYPE_RAW, raw_buffer),
+        Output::Json | Output::Ndjson => {
+            let record = Record {
+                length: raw_buffer.len(),
+                data: raw_buffer,
+            };
+            output.print_json(&record);
+        }
+    }
+}
 
-    // Create a raw socket, bind to the public interface
-    let socket_protocol = if cfg!(target_os = "windows") { 0 } else { 1 };
+fn main() -> Result<()> {
+    let args = DecoderArgs::parse(false);
 
-    let sniffer = Socket::new(
-        Domain::IPV4,
-        Type::RAW,
-        Some(Protocol::from(socket_protocol)),
-    )?;
+    // The first IPv4 packet of the capture, whatever it carries
+    if let Some(path) = &args.path {
+        let mut capture = PcapReader::open(path)?;
+        while let Some(packet) = capture.next_packet()? {
+            if let Some(packet) = packet.ipv4() {
+                print_packet(packet, args.output);
+                break;
+            }
+        }
+        return Ok(());
+    }
 
-    sniffer.bind(&host.into())?;
+    let sniffer = cli::open_sniffer(PROTOCOL_ICMP);
 
     // Read one packet
     let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
-    let _ = sniffer.recv_from(&mut buffer)?;
+    let (length, _) = sniffer.recv_from(&mut buffer)?;
     let raw_buffer: &[u8] =
-        unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, buffer.len()) };
-
-    // Print the first 120 bytes of the captured packet
-    println!("{:?}", &raw_buffer[..120]);
+        unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, length) };
 
+    print_packet(raw_buffer, args.output);
     Ok(())
 }
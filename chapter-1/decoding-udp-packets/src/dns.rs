@@ -0,0 +1,495 @@
+// DNS messages (RFC 1035) as multicast DNS (RFC 6762) sends them, along with the
+// DNS-SD service instances (RFC 6763) their PTR, SRV and TXT records describe
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use serde::Serialize;
+
+pub const DNS_PORT: u16 = 53;
+pub const MDNS_PORT: u16 = 5353;
+
+const DNS_HEADER_SIZE: usize = 12;
+
+// Compression pointers followed before a name is taken for a loop
+const MAX_POINTERS: usize = 64;
+
+const TYPE_A: u16 = 1;
+const TYPE_NS: u16 = 2;
+const TYPE_CNAME: u16 = 5;
+const TYPE_PTR: u16 = 12;
+const TYPE_HINFO: u16 = 13;
+const TYPE_MX: u16 = 15;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+
+// mDNS reuses the top bit of the class, as unicast-response in questions and
+// cache-flush in records
+const CLASS_MDNS_BIT: u16 = 0x8000;
+
+pub fn type_name(kind: u16) -> String {
+    let name = match kind {
+        TYPE_A => "A",
+        TYPE_NS => "NS",
+        TYPE_CNAME => "CNAME",
+        6 => "SOA",
+        TYPE_PTR => "PTR",
+        TYPE_HINFO => "HINFO",
+        TYPE_MX => "MX",
+        TYPE_TXT => "TXT",
+        TYPE_AAAA => "AAAA",
+        TYPE_SRV => "SRV",
+        41 => "OPT",
+        47 => "NSEC",
+        255 => "ANY",
+        _ => return format!("TYPE{}", kind),
+    };
+    name.to_string()
+}
+
+fn u16_at(message: &[u8], offset: usize) -> Option<u16> {
+    let bytes = message.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+// Reads the possibly compressed name at `offset`, returns it and where it ends
+pub fn read_name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    // Where the name ends in the message, set by the first pointer followed
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let length = *message.get(offset)? as usize;
+        match length {
+            0 => break,
+            length if length & 0xc0 == 0xc0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                end.get_or_insert(offset + 2);
+                offset = (u16_at(message, offset)? & 0x3fff) as usize;
+            }
+            length if length & 0xc0 == 0 => {
+                let label = message.get(offset + 1..offset + 1 + length)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + length;
+            }
+            _ => return None,
+        }
+    }
+    let name = if labels.is_empty() {
+        String::from(".")
+    } else {
+        labels.join(".")
+    };
+    Some((name, end.unwrap_or(offset + 1)))
+}
+
+#[derive(Serialize)]
+pub struct Question {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    // mDNS questions asking for a unicast answer
+    pub unicast_response: bool,
+}
+
+#[derive(Serialize)]
+pub struct ResourceRecord {
+    // answer, authority or additional
+    pub section: &'static str,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub ttl: u32,
+    // mDNS records replacing what caches hold for the name
+    pub cache_flush: bool,
+    pub data: String,
+    // Decoded SRV and TXT data, for the DNS-SD summary
+    #[serde(skip)]
+    target: Option<(String, u16)>,
+    #[serde(skip)]
+    txt: Vec<String>,
+}
+
+// A DNS-SD service instance, such as "Printer._ipp._tcp.local"
+#[derive(Serialize)]
+pub struct ServiceInstance {
+    pub instance: String,
+    pub service: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub txt: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct Dns {
+    pub id: u16,
+    pub response: bool,
+    pub opcode: u8,
+    pub rcode: u8,
+    pub questions: Vec<Question>,
+    pub records: Vec<ResourceRecord>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<ServiceInstance>,
+}
+
+// Character strings of TXT and HINFO records, each prefixed by its length
+fn character_strings(mut data: &[u8]) -> Vec<String> {
+    let mut strings = Vec::new();
+    while let Some(&length) = data.first() {
+        let Some(string) = data.get(1..1 + length as usize) else {
+            break;
+        };
+        strings.push(String::from_utf8_lossy(string).into_owned());
+        data = &data[1 + length as usize..];
+    }
+    strings
+}
+
+// Splits "Instance Name._service._proto.domain" into the instance and service names
+fn split_instance(name: &str) -> Option<(&str, &str)> {
+    let service = name.find("._")?;
+    Some((&name[..service], &name[service + 1..]))
+}
+
+impl Dns {
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        if message.len() < DNS_HEADER_SIZE {
+            return None;
+        }
+        let flags = u16_at(message, 2)?;
+        let counts = [
+            u16_at(message, 4)?,
+            u16_at(message, 6)?,
+            u16_at(message, 8)?,
+            u16_at(message, 10)?,
+        ];
+        let mut dns = Dns {
+            id: u16_at(message, 0)?,
+            response: flags & 0x8000 != 0,
+            opcode: ((flags >> 11) & 0x0f) as u8,
+            rcode: (flags & 0x0f) as u8,
+            questions: Vec::new(),
+            records: Vec::new(),
+            services: Vec::new(),
+        };
+
+        let mut offset = DNS_HEADER_SIZE;
+        for _ in 0..counts[0] {
+            let (name, end) = read_name(message, offset)?;
+            let kind = u16_at(message, end)?;
+            let class = u16_at(message, end + 2)?;
+            dns.questions.push(Question {
+                name,
+                kind: type_name(kind),
+                unicast_response: class & CLASS_MDNS_BIT != 0,
+            });
+            offset = end + 4;
+        }
+        for (section, count) in [
+            ("answer", counts[1]),
+            ("authority", counts[2]),
+            ("additional", counts[3]),
+        ] {
+            for _ in 0..count {
+                let (record, end) = Self::record(message, offset, section)?;
+                dns.records.push(record);
+                offset = end;
+            }
+        }
+        dns.services = dns.service_instances();
+        Some(dns)
+    }
+
+    // Reads the resource record at `offset`, returns it and where it ends
+    fn record(
+        message: &[u8],
+        offset: usize,
+        section: &'static str,
+    ) -> Option<(ResourceRecord, usize)> {
+        let (name, offset) = read_name(message, offset)?;
+        let kind = u16_at(message, offset)?;
+        let class = u16_at(message, offset + 2)?;
+        let ttl = u32::from_be_bytes(message.get(offset + 4..offset + 8)?.try_into().ok()?);
+        let length = u16_at(message, offset + 8)? as usize;
+        let start = offset + 10;
+        let data = message.get(start..start + length)?;
+
+        let mut target = None;
+        let mut txt = Vec::new();
+        let text = match kind {
+            TYPE_A if length == 4 => Ipv4Addr::new(data[0], data[1], data[2], data[3]).to_string(),
+            TYPE_AAAA if length == 16 => {
+                Ipv6Addr::from(<[u8; 16]>::try_from(data).unwrap()).to_string()
+            }
+            TYPE_NS | TYPE_CNAME | TYPE_PTR => read_name(message, start)?.0,
+            TYPE_MX => format!("{} {}", u16_at(data, 0)?, read_name(message, start + 2)?.0),
+            TYPE_SRV => {
+                let port = u16_at(data, 4)?;
+                let host = read_name(message, start + 6)?.0;
+                let text = format!(
+                    "{} {} {} {}",
+                    u16_at(data, 0)?,
+                    u16_at(data, 2)?,
+                    port,
+                    host
+                );
+                target = Some((host, port));
+                text
+            }
+            TYPE_TXT | TYPE_HINFO => {
+                txt = character_strings(data);
+                let quoted: Vec<String> =
+                    txt.iter().map(|string| format!("{:?}", string)).collect();
+                quoted.join(" ")
+            }
+            _ => format!("{} bytes", length),
+        };
+        let record = ResourceRecord {
+            section,
+            name,
+            kind: type_name(kind),
+            ttl,
+            cache_flush: class & CLASS_MDNS_BIT != 0,
+            data: text,
+            target,
+            txt: if kind == TYPE_TXT { txt } else { Vec::new() },
+        };
+        Some((record, start + length))
+    }
+
+    // Service instances named by PTR records or described by SRV records, with the
+    // host, port and TXT attributes found for them in the same message
+    fn service_instances(&self) -> Vec<ServiceInstance> {
+        let mut names: Vec<&str> = Vec::new();
+        for record in &self.records {
+            let name = match record.kind.as_str() {
+                // Except the listing of service types under _services._dns-sd._udp
+                "PTR" if !record.name.starts_with("_services._dns-sd.") => &record.data,
+                "SRV" => &record.name,
+                _ => continue,
+            };
+            if split_instance(name).is_some() && !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let (instance, service) = split_instance(name)?;
+                if instance.is_empty() {
+                    return None;
+                }
+                let srv = self
+                    .records
+                    .iter()
+                    .find_map(|record| record.target.as_ref().filter(|_| record.name == name));
+                let txt = self
+                    .records
+                    .iter()
+                    .find(|record| record.kind == "TXT" && record.name == name)
+                    .map(|record| record.txt.clone())
+                    .unwrap_or_default();
+                Some(ServiceInstance {
+                    instance: instance.to_string(),
+                    service: service.to_string(),
+                    target: srv.map(|(host, _)| host.clone()),
+                    port: srv.map(|(_, port)| *port),
+                    txt: txt
+                        .into_iter()
+                        .filter(|string| !string.is_empty())
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Dns {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: ID: 0x{:04x} Opcode: {} Rcode: {} Questions: {} Records: {}",
+            if self.response { "Response" } else { "Query" },
+            self.id,
+            self.opcode,
+            self.rcode,
+            self.questions.len(),
+            self.records.len()
+        )?;
+        for question in &self.questions {
+            writeln!(
+                f,
+                "  Question: {} {}{}",
+                question.name,
+                question.kind,
+                if question.unicast_response {
+                    " (QU)"
+                } else {
+                    ""
+                }
+            )?;
+        }
+        for record in &self.records {
+            writeln!(
+                f,
+                "  {}: {} {} TTL {}{} {}",
+                record.section,
+                record.name,
+                record.kind,
+                record.ttl,
+                if record.cache_flush { " (flush)" } else { "" },
+                record.data
+            )?;
+        }
+        for service in &self.services {
+            write!(f, "  Service: {} ({})", service.instance, service.service)?;
+            if let (Some(target), Some(port)) = (&service.target, service.port) {
+                write!(f, " at {}:{}", target, port)?;
+            }
+            if !service.txt.is_empty() {
+                write!(f, " [{}]", service.txt.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A response for example.com, the answer's name points back at the question
+    const RESPONSE: &[u8] = b"\x12\x34\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\
+        \x07example\x03com\x00\x00\x01\x00\x01\
+        \xc0\x0c\x00\x01\x00\x01\x00\x00\x0e\x10\x00\x04\x5d\xb8\xd8\x22";
+
+    // An mDNS announcement of an IPP printer, PTR, SRV, TXT and A records
+    const ANNOUNCEMENT: &[u8] = b"\x00\x00\x84\x00\x00\x00\x00\x04\x00\x00\x00\x00\
+        \x04_ipp\x04_tcp\x05local\x00\x00\x0c\x00\x01\x00\x00\x11\x94\x00\x0a\x07Printer\xc0\x0c\
+        \xc0\x27\x00\x21\x80\x01\x00\x00\x00\x78\x00\x0d\x00\x00\x00\x00\x02\x77\x04host\xc0\x16\
+        \xc0\x27\x00\x10\x80\x01\x00\x00\x11\x94\x00\x14\x0brp=printers\x07color=T\
+        \xc0\x43\x00\x01\x80\x01\x00\x00\x00\x78\x00\x04\xc0\xa8\x01\x14";
+
+    #[test]
+    fn parses_a_response() {
+        let dns = Dns::parse(RESPONSE).unwrap();
+        assert_eq!(dns.id, 0x1234);
+        assert!(dns.response);
+        assert_eq!((dns.opcode, dns.rcode), (0, 0));
+        assert_eq!(dns.questions.len(), 1);
+        assert_eq!(dns.questions[0].name, "example.com");
+        assert_eq!(dns.questions[0].kind, "A");
+        assert!(!dns.questions[0].unicast_response);
+
+        assert_eq!(dns.records.len(), 1);
+        let answer = &dns.records[0];
+        assert_eq!(answer.section, "answer");
+        assert_eq!(answer.name, "example.com");
+        assert_eq!(answer.kind, "A");
+        assert_eq!(answer.ttl, 3600);
+        assert_eq!(answer.data, "93.184.216.34");
+        assert!(dns.services.is_empty());
+        assert!(dns
+            .to_string()
+            .starts_with("Response: ID: 0x1234 Opcode: 0 Rcode: 0 Questions: 1 Records: 1\n"));
+    }
+
+    #[test]
+    fn gathers_service_instances() {
+        let dns = Dns::parse(ANNOUNCEMENT).unwrap();
+        let kinds: Vec<&str> = dns
+            .records
+            .iter()
+            .map(|record| record.kind.as_str())
+            .collect();
+        assert_eq!(kinds, ["PTR", "SRV", "TXT", "A"]);
+        assert!(dns.records[1..].iter().all(|record| record.cache_flush));
+        assert_eq!(dns.records[0].data, "Printer._ipp._tcp.local");
+        assert_eq!(dns.records[1].data, "0 0 631 host.local");
+        assert_eq!(dns.records[2].data, "\"rp=printers\" \"color=T\"");
+        assert_eq!(dns.records[3].name, "host.local");
+
+        assert_eq!(dns.services.len(), 1);
+        let service = &dns.services[0];
+        assert_eq!(service.instance, "Printer");
+        assert_eq!(service.service, "_ipp._tcp.local");
+        assert_eq!(service.target.as_deref(), Some("host.local"));
+        assert_eq!(service.port, Some(631));
+        assert_eq!(service.txt, ["rp=printers", "color=T"]);
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        for length in 0..RESPONSE.len() {
+            assert!(
+                Dns::parse(&RESPONSE[..length]).is_none(),
+                "{} bytes",
+                length
+            );
+        }
+        for length in 0..ANNOUNCEMENT.len() {
+            assert!(
+                Dns::parse(&ANNOUNCEMENT[..length]).is_none(),
+                "{} bytes",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn reads_compressed_names() {
+        assert_eq!(
+            read_name(RESPONSE, 12),
+            Some((String::from("example.com"), 25))
+        );
+        // A pointer ends the name two bytes on, wherever it leads
+        assert_eq!(
+            read_name(RESPONSE, 29),
+            Some((String::from("example.com"), 31))
+        );
+        assert_eq!(read_name(b"\x00", 0), Some((String::from("."), 1)));
+        // Label types 01 and 10 are not in use
+        assert_eq!(read_name(b"\x40a\x00", 0), None);
+        assert_eq!(read_name(b"\x80a\x00", 0), None);
+        // A label running past the message
+        assert_eq!(read_name(b"\x05abc", 0), None);
+    }
+
+    #[test]
+    fn rejects_pointer_loops() {
+        // A pointer to itself, and two pointing at each other
+        assert_eq!(read_name(b"\xc0\x00", 0), None);
+        assert_eq!(read_name(b"\xc0\x02\xc0\x00", 0), None);
+        let mut looping = RESPONSE.to_vec();
+        looping[29..31].copy_from_slice(b"\xc0\x1d");
+        assert!(Dns::parse(&looping).is_none());
+
+        // A chain of pointers is followed up to the limit
+        let chain = |pointers: usize| {
+            let mut message = b"\x01a\x00".to_vec();
+            let mut target = 0u16;
+            for _ in 0..pointers {
+                let next = message.len() as u16;
+                message.extend_from_slice(&(0xc000 | target).to_be_bytes());
+                target = next;
+            }
+            message
+        };
+        let message = chain(MAX_POINTERS);
+        assert_eq!(
+            read_name(&message, message.len() - 2),
+            Some((String::from("a"), message.len()))
+        );
+        let message = chain(MAX_POINTERS + 1);
+        assert_eq!(read_name(&message, message.len() - 2), None);
+    }
+}
@@ -0,0 +1,44 @@
+// The UDP header in front of every datagram the sniffer decodes, and the decoders of
+// the services it recognises in their payload
+
+use serde::Serialize;
+
+pub mod dns;
+pub mod netbios;
+pub mod ntp;
+pub mod quic;
+pub mod services;
+pub mod snmp;
+pub mod ssdp;
+
+// Constants for UDP header size
+pub const UDP_HEADER_SIZE: usize = 8;
+
+#[derive(Serialize)]
+pub struct Udp {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub length: u16,
+    pub checksum: u16,
+}
+
+impl Udp {
+    // None when the buffer can't hold the 8 bytes of the header
+    pub fn new(buffer: &[u8]) -> Option<Self> {
+        if buffer.len() < UDP_HEADER_SIZE {
+            return None;
+        }
+        // Parse the UDP header fields from the buffer
+        let source_port = u16::from_be_bytes([buffer[0], buffer[1]]);
+        let destination_port = u16::from_be_bytes([buffer[2], buffer[3]]);
+        let length = u16::from_be_bytes([buffer[4], buffer[5]]);
+        let checksum = u16::from_be_bytes([buffer[6], buffer[7]]);
+
+        Some(Udp {
+            source_port,
+            destination_port,
+            length,
+            checksum,
+        })
+    }
+}
@@ -0,0 +1,408 @@
+// NetBIOS Name Service (RFC 1002), DNS-shaped packets resolving and registering
+// NetBIOS names on the local network
+
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use serde::Serialize;
+
+use crate::dns::read_name;
+
+pub const NETBIOS_NS_PORT: u16 = 137;
+
+const NBNS_HEADER_SIZE: usize = 12;
+
+const TYPE_NB: u16 = 0x0020;
+const TYPE_NBSTAT: u16 = 0x0021;
+
+// Size of an NB address entry and of an NBSTAT name entry
+const NB_ADDRESS_SIZE: usize = 6;
+const NBSTAT_NAME_SIZE: usize = 18;
+
+// Flags of address and name entries
+const FLAG_GROUP: u16 = 0x8000;
+const FLAG_ACTIVE: u16 = 0x0400;
+
+#[derive(Serialize)]
+pub struct Question {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+}
+
+// An address an NB record maps the name to
+#[derive(Serialize)]
+pub struct NameAddress {
+    pub address: Ipv4Addr,
+    pub group: bool,
+    // B, P, M or H node
+    pub node_type: &'static str,
+}
+
+// A name of the node status an NBSTAT record answers with
+#[derive(Serialize)]
+pub struct NodeName {
+    pub name: String,
+    pub group: bool,
+    pub active: bool,
+}
+
+#[derive(Serialize)]
+pub struct ResourceRecord {
+    pub section: &'static str,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub ttl: u32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub addresses: Vec<NameAddress>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub names: Vec<NodeName>,
+    // Unit ID of the node status, the MAC address on Ethernet
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct NetbiosNs {
+    pub id: u16,
+    pub response: bool,
+    pub opcode: &'static str,
+    pub broadcast: bool,
+    pub rcode: u8,
+    pub questions: Vec<Question>,
+    pub records: Vec<ResourceRecord>,
+}
+
+fn opcode_name(opcode: u16) -> &'static str {
+    match opcode {
+        0 => "query",
+        5 => "registration",
+        6 => "release",
+        7 => "WACK",
+        8 | 9 => "refresh",
+        15 => "multi-homed registration",
+        _ => "unknown",
+    }
+}
+
+fn type_name(kind: u16) -> Option<&'static str> {
+    match kind {
+        TYPE_NB => Some("NB"),
+        TYPE_NBSTAT => Some("NBSTAT"),
+        // Negative answers and redirects
+        0x0001 => Some("A"),
+        0x0002 => Some("NS"),
+        0x000a => Some("NULL"),
+        _ => None,
+    }
+}
+
+// A 16 byte NetBIOS name, 15 characters padded with spaces and the suffix byte naming
+// the service, written the usual way as "NAME<20>"
+fn format_name(name: &[u8]) -> String {
+    let (characters, suffix) = name.split_at(name.len() - 1);
+    let characters = String::from_utf8_lossy(characters);
+    format!(
+        "{}<{:02x}>",
+        characters.trim_end_matches([' ', '\0']),
+        suffix[0]
+    )
+}
+
+// Undoes the first-level encoding of the name at `offset`, which spreads each half
+// byte of the name over a letter from 'A' to 'P'. The scope, if any, is appended.
+fn read_netbios_name(message: &[u8], offset: usize) -> Option<(String, usize)> {
+    let (encoded, end) = read_name(message, offset)?;
+    let (label, scope) = match encoded.split_once('.') {
+        Some((label, scope)) => (label, Some(scope)),
+        None => (encoded.as_str(), None),
+    };
+    if label.len() != 32 {
+        return None;
+    }
+    let mut name = Vec::with_capacity(16);
+    for pair in label.as_bytes().chunks(2) {
+        let high = pair[0].checked_sub(b'A').filter(|nibble| *nibble < 16)?;
+        let low = pair[1].checked_sub(b'A').filter(|nibble| *nibble < 16)?;
+        name.push((high << 4) | low);
+    }
+    let mut name = format_name(&name);
+    if let Some(scope) = scope {
+        name = format!("{}.{}", name, scope);
+    }
+    Some((name, end))
+}
+
+fn u16_at(message: &[u8], offset: usize) -> Option<u16> {
+    let bytes = message.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+impl NetbiosNs {
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        if message.len() < NBNS_HEADER_SIZE {
+            return None;
+        }
+        let flags = u16_at(message, 2)?;
+        let mut nbns = NetbiosNs {
+            id: u16_at(message, 0)?,
+            response: flags & 0x8000 != 0,
+            opcode: opcode_name((flags >> 11) & 0x0f),
+            broadcast: flags & 0x0010 != 0,
+            rcode: (flags & 0x0f) as u8,
+            questions: Vec::new(),
+            records: Vec::new(),
+        };
+
+        let mut offset = NBNS_HEADER_SIZE;
+        for _ in 0..u16_at(message, 4)? {
+            let (name, end) = read_netbios_name(message, offset)?;
+            // The class is always IN, but has to be there
+            u16_at(message, end + 2)?;
+            nbns.questions.push(Question {
+                name,
+                kind: type_name(u16_at(message, end)?)?,
+            });
+            offset = end + 4;
+        }
+        for (section, count) in [
+            ("answer", u16_at(message, 6)?),
+            ("authority", u16_at(message, 8)?),
+            ("additional", u16_at(message, 10)?),
+        ] {
+            for _ in 0..count {
+                let (record, end) = Self::record(message, offset, section)?;
+                nbns.records.push(record);
+                offset = end;
+            }
+        }
+        Some(nbns)
+    }
+
+    // Reads the resource record at `offset`, returns it and where it ends
+    fn record(
+        message: &[u8],
+        offset: usize,
+        section: &'static str,
+    ) -> Option<(ResourceRecord, usize)> {
+        let (name, offset) = read_netbios_name(message, offset)?;
+        let kind = u16_at(message, offset)?;
+        let ttl = u32::from_be_bytes(message.get(offset + 4..offset + 8)?.try_into().ok()?);
+        let length = u16_at(message, offset + 8)? as usize;
+        let start = offset + 10;
+        let data = message.get(start..start + length)?;
+
+        let mut record = ResourceRecord {
+            section,
+            name,
+            kind: type_name(kind)?,
+            ttl,
+            addresses: Vec::new(),
+            names: Vec::new(),
+            mac: None,
+        };
+        match kind {
+            TYPE_NB => {
+                for entry in data.chunks_exact(NB_ADDRESS_SIZE) {
+                    let flags = u16::from_be_bytes([entry[0], entry[1]]);
+                    record.addresses.push(NameAddress {
+                        address: Ipv4Addr::new(entry[2], entry[3], entry[4], entry[5]),
+                        group: flags & FLAG_GROUP != 0,
+                        node_type: ["B", "P", "M", "H"][((flags >> 13) & 0x03) as usize],
+                    });
+                }
+            }
+            TYPE_NBSTAT => {
+                let count = *data.first()? as usize;
+                let names = data.get(1..1 + count * NBSTAT_NAME_SIZE)?;
+                for entry in names.chunks_exact(NBSTAT_NAME_SIZE) {
+                    let flags = u16::from_be_bytes([entry[16], entry[17]]);
+                    record.names.push(NodeName {
+                        name: format_name(&entry[..16]),
+                        group: flags & FLAG_GROUP != 0,
+                        active: flags & FLAG_ACTIVE != 0,
+                    });
+                }
+                let statistics = 1 + count * NBSTAT_NAME_SIZE;
+                record.mac = data.get(statistics..statistics + 6).map(|mac| {
+                    let octets: Vec<String> =
+                        mac.iter().map(|byte| format!("{:02x}", byte)).collect();
+                    octets.join(":")
+                });
+            }
+            _ => {}
+        }
+        Some((record, start + length))
+    }
+}
+
+impl fmt::Display for NetbiosNs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "NetBIOS-NS {} {}: ID: 0x{:04x} Rcode: {}{}",
+            self.opcode,
+            if self.response { "response" } else { "request" },
+            self.id,
+            self.rcode,
+            if self.broadcast { " (broadcast)" } else { "" }
+        )?;
+        for question in &self.questions {
+            writeln!(f, "  Question: {} {}", question.name, question.kind)?;
+        }
+        for record in &self.records {
+            writeln!(
+                f,
+                "  {}: {} {} TTL {}",
+                record.section, record.name, record.kind, record.ttl
+            )?;
+            for address in &record.addresses {
+                writeln!(
+                    f,
+                    "    {} ({}-node{})",
+                    address.address,
+                    address.node_type,
+                    if address.group { ", group" } else { "" }
+                )?;
+            }
+            for name in &record.names {
+                writeln!(
+                    f,
+                    "    {}{}{}",
+                    name.name,
+                    if name.group { " group" } else { " unique" },
+                    if name.active { " active" } else { "" }
+                )?;
+            }
+            if let Some(mac) = &record.mac {
+                writeln!(f, "    MAC: {}", mac)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "WPAD<00>" first-level encoded
+    const WPAD: &[u8] = b"\x20FHFAEBEECACACACACACACACACACACAAA\x00";
+
+    fn message(header: &[u8], body: &[&[u8]]) -> Vec<u8> {
+        let mut message = header.to_vec();
+        for part in body {
+            message.extend_from_slice(part);
+        }
+        message
+    }
+
+    #[test]
+    fn parses_a_broadcast_query() {
+        let query = message(
+            b"\x80\x01\x01\x10\x00\x01\x00\x00\x00\x00\x00\x00",
+            &[WPAD, b"\x00\x20\x00\x01"],
+        );
+        let nbns = NetbiosNs::parse(&query).unwrap();
+        assert_eq!(nbns.id, 0x8001);
+        assert!(!nbns.response);
+        assert_eq!(nbns.opcode, "query");
+        assert!(nbns.broadcast);
+        assert_eq!(nbns.questions.len(), 1);
+        assert_eq!(nbns.questions[0].name, "WPAD<00>");
+        assert_eq!(nbns.questions[0].kind, "NB");
+        assert!(nbns
+            .to_string()
+            .starts_with("NetBIOS-NS query request: ID: 0x8001 Rcode: 0 (broadcast)\n"));
+    }
+
+    #[test]
+    fn parses_name_addresses() {
+        let response = message(
+            b"\x80\x01\x85\x00\x00\x00\x00\x01\x00\x00\x00\x00",
+            &[
+                WPAD,
+                b"\x00\x20\x00\x01\x00\x04\x93\xe0\x00\x0c",
+                b"\x60\x00\xc0\xa8\x01\x0a\x80\x00\xc0\xa8\x01\x0b",
+            ],
+        );
+        let nbns = NetbiosNs::parse(&response).unwrap();
+        assert!(nbns.response);
+        let record = &nbns.records[0];
+        assert_eq!(record.section, "answer");
+        assert_eq!(record.name, "WPAD<00>");
+        assert_eq!(record.kind, "NB");
+        assert_eq!(record.ttl, 300_000);
+        let addresses: Vec<(Ipv4Addr, bool, &str)> = record
+            .addresses
+            .iter()
+            .map(|address| (address.address, address.group, address.node_type))
+            .collect();
+        assert_eq!(
+            addresses,
+            [
+                (Ipv4Addr::new(192, 168, 1, 10), false, "H"),
+                (Ipv4Addr::new(192, 168, 1, 11), true, "B"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_a_node_status() {
+        let mut statistics = vec![2];
+        statistics.extend_from_slice(b"HOST           \x00\x04\x00");
+        statistics.extend_from_slice(b"WORKGROUP      \x00\x84\x00");
+        statistics.extend_from_slice(b"\x00\x11\x22\x33\x44\x55");
+        statistics.extend_from_slice(&[0; 40]);
+        let response = message(
+            b"\x00\x02\x84\x00\x00\x00\x00\x01\x00\x00\x00\x00",
+            &[
+                b"\x20CKAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\x00",
+                b"\x00\x21\x00\x01\x00\x00\x00\x00\x00\x53",
+                &statistics,
+            ],
+        );
+        let nbns = NetbiosNs::parse(&response).unwrap();
+        let record = &nbns.records[0];
+        assert_eq!(record.name, "*<00>");
+        assert_eq!(record.kind, "NBSTAT");
+        let names: Vec<(&str, bool, bool)> = record
+            .names
+            .iter()
+            .map(|name| (name.name.as_str(), name.group, name.active))
+            .collect();
+        assert_eq!(
+            names,
+            [("HOST<00>", false, true), ("WORKGROUP<00>", true, true)]
+        );
+        assert_eq!(record.mac.as_deref(), Some("00:11:22:33:44:55"));
+    }
+
+    #[test]
+    fn rejects_badly_encoded_names() {
+        let query = |name: &[u8]| {
+            message(
+                b"\x80\x01\x01\x10\x00\x01\x00\x00\x00\x00\x00\x00",
+                &[name, b"\x00\x20\x00\x01"],
+            )
+        };
+        // A letter past 'P', and a label of the wrong length
+        assert!(NetbiosNs::parse(&query(b"\x20QHFAEBEECACACACACACACACACACACAAA\x00")).is_none());
+        assert!(NetbiosNs::parse(&query(b"\x04FHFA\x00")).is_none());
+        // A scope follows the name
+        let scoped = query(b"\x20FHFAEBEECACACACACACACACACACACAAA\x04corp\x00");
+        assert_eq!(
+            NetbiosNs::parse(&scoped).unwrap().questions[0].name,
+            "WPAD<00>.corp"
+        );
+        let complete = query(WPAD);
+        for length in 0..complete.len() {
+            assert!(
+                NetbiosNs::parse(&complete[..length]).is_none(),
+                "{} bytes",
+                length
+            );
+        }
+    }
+}
@@ -0,0 +1,229 @@
+// Network Time Protocol packets, versions 3 (RFC 1305) and 4 (RFC 5905)
+
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use serde::Serialize;
+
+pub const NTP_PORT: u16 = 123;
+
+// Size of a packet without extension fields or authenticator
+const NTP_PACKET_SIZE: usize = 48;
+
+// Seconds between the NTP epoch (1900) and the Unix epoch (1970)
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+
+#[derive(Serialize)]
+pub struct Ntp {
+    pub leap_indicator: u8,
+    pub version: u8,
+    pub mode: &'static str,
+    pub stratum: u8,
+    // Log2 of the poll interval and of the clock precision, in seconds
+    pub poll: i8,
+    pub precision: i8,
+    // In seconds
+    pub root_delay: f64,
+    pub root_dispersion: f64,
+    // Kiss code, reference clock or upstream server, depending on the stratum
+    pub reference_id: String,
+    // UTC times, None for the zero timestamps of unset fields
+    pub reference_time: Option<String>,
+    pub origin_time: Option<String>,
+    pub receive_time: Option<String>,
+    pub transmit_time: Option<String>,
+}
+
+fn mode_name(mode: u8) -> Option<&'static str> {
+    match mode {
+        1 => Some("symmetric active"),
+        2 => Some("symmetric passive"),
+        3 => Some("client"),
+        4 => Some("server"),
+        5 => Some("broadcast"),
+        _ => None,
+    }
+}
+
+// Days since the Unix epoch to a year, month and day of the proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// A 64-bit timestamp, 32 bits of seconds since 1900 and 32 bits of fraction
+fn timestamp(bytes: &[u8]) -> Option<String> {
+    let seconds = u32::from_be_bytes(bytes[..4].try_into().unwrap()) as i64;
+    let fraction = u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as u64;
+    if seconds == 0 && fraction == 0 {
+        return None;
+    }
+    // Timestamps with the top bit clear are taken to be in era 1, which starts in 2036
+    let era = if seconds < 0x8000_0000 { 1 << 32 } else { 0 };
+    let unix = seconds + era - NTP_UNIX_OFFSET;
+    let micros = (fraction * 1_000_000) >> 32;
+    let (year, month, day) = civil_from_days(unix.div_euclid(86_400));
+    let time = unix.rem_euclid(86_400);
+    Some(format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:06} UTC",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        micros
+    ))
+}
+
+// A 32-bit short format value, 16 bits of seconds and 16 bits of fraction
+fn short(bytes: &[u8]) -> f64 {
+    u32::from_be_bytes(bytes[..4].try_into().unwrap()) as f64 / 65536.0
+}
+
+impl Ntp {
+    pub fn parse(buffer: &[u8]) -> Option<Self> {
+        if buffer.len() < NTP_PACKET_SIZE {
+            return None;
+        }
+        let version = (buffer[0] >> 3) & 0x07;
+        // Control (6) and private (7) messages have a layout of their own
+        let mode = mode_name(buffer[0] & 0x07)?;
+        if !(1..=4).contains(&version) {
+            return None;
+        }
+        let stratum = buffer[1];
+        let reference = &buffer[12..16];
+        let reference_id = match stratum {
+            // Kiss-o'-Death codes and reference clocks are four ASCII characters
+            0 | 1 => String::from_utf8_lossy(reference)
+                .trim_end_matches('\0')
+                .to_string(),
+            // The upstream server's IPv4 address, or a hash of its IPv6 one
+            _ => Ipv4Addr::new(reference[0], reference[1], reference[2], reference[3]).to_string(),
+        };
+        Some(Ntp {
+            leap_indicator: buffer[0] >> 6,
+            version,
+            mode,
+            stratum,
+            poll: buffer[2] as i8,
+            precision: buffer[3] as i8,
+            root_delay: short(&buffer[4..8]),
+            root_dispersion: short(&buffer[8..12]),
+            reference_id,
+            reference_time: timestamp(&buffer[16..24]),
+            origin_time: timestamp(&buffer[24..32]),
+            receive_time: timestamp(&buffer[32..40]),
+            transmit_time: timestamp(&buffer[40..48]),
+        })
+    }
+}
+
+impl fmt::Display for Ntp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "NTPv{} {}: Stratum: {} Reference: {} Leap: {}",
+            self.version, self.mode, self.stratum, self.reference_id, self.leap_indicator
+        )?;
+        writeln!(
+            f,
+            "  Poll: 2^{}s Precision: 2^{}s Root Delay: {:.6}s Root Dispersion: {:.6}s",
+            self.poll, self.precision, self.root_delay, self.root_dispersion
+        )?;
+        for (name, time) in [
+            ("Reference", &self.reference_time),
+            ("Origin", &self.origin_time),
+            ("Receive", &self.receive_time),
+            ("Transmit", &self.transmit_time),
+        ] {
+            if let Some(time) = time {
+                writeln!(f, "  {} Time: {}", name, time)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A stratum 2 server reply synchronised to 192.0.2.1
+    const SERVER_REPLY: &[u8] = b"\x24\x02\x06\xec\x00\x00\x01\x00\x00\x00\x02\x00\xc0\x00\x02\x01\
+        \xe9\x3c\x7f\x00\x00\x00\x00\x00\
+        \x00\x00\x00\x00\x00\x00\x00\x00\
+        \xe9\x8a\xf0\x41\x80\x00\x00\x00\
+        \xe9\x8a\xf0\x41\xc0\x00\x00\x00";
+
+    #[test]
+    fn parses_a_server_reply() {
+        let ntp = Ntp::parse(SERVER_REPLY).unwrap();
+        assert_eq!(ntp.leap_indicator, 0);
+        assert_eq!(ntp.version, 4);
+        assert_eq!(ntp.mode, "server");
+        assert_eq!(ntp.stratum, 2);
+        assert_eq!((ntp.poll, ntp.precision), (6, -20));
+        assert_eq!(ntp.root_delay, 1.0 / 256.0);
+        assert_eq!(ntp.root_dispersion, 2.0 / 256.0);
+        assert_eq!(ntp.reference_id, "192.0.2.1");
+        assert_eq!(
+            ntp.reference_time.as_deref(),
+            Some("2024-01-01 00:00:00.000000 UTC")
+        );
+        // The origin timestamp is zero, it isn't shown
+        assert_eq!(ntp.origin_time, None);
+        assert_eq!(
+            ntp.receive_time.as_deref(),
+            Some("2024-02-29 12:00:01.500000 UTC")
+        );
+        assert_eq!(
+            ntp.transmit_time.as_deref(),
+            Some("2024-02-29 12:00:01.750000 UTC")
+        );
+    }
+
+    #[test]
+    fn reads_reference_clocks_and_era_one() {
+        let mut packet = SERVER_REPLY.to_vec();
+        packet[1] = 1;
+        packet[12..16].copy_from_slice(b"GPS\0");
+        // 2040-06-01, past the 2036 rollover of the seconds
+        packet[40..48].copy_from_slice(b"\x08\x1d\x61\x00\x00\x00\x00\x00");
+        let ntp = Ntp::parse(&packet).unwrap();
+        assert_eq!(ntp.reference_id, "GPS");
+        assert_eq!(
+            ntp.transmit_time.as_deref(),
+            Some("2040-06-01 00:00:00.000000 UTC")
+        );
+
+        // A Kiss-o'-Death asking the client to slow down
+        packet[1] = 0;
+        packet[12..16].copy_from_slice(b"RATE");
+        assert_eq!(Ntp::parse(&packet).unwrap().reference_id, "RATE");
+    }
+
+    #[test]
+    fn rejects_other_messages() {
+        assert!(Ntp::parse(&SERVER_REPLY[..NTP_PACKET_SIZE - 1]).is_none());
+        let mut packet = SERVER_REPLY.to_vec();
+        for first in [0x26, 0x27, 0x04, 0x2c] {
+            // Control and private modes, version 0 and version 5
+            packet[0] = first;
+            assert!(Ntp::parse(&packet).is_none(), "0x{:02x}", first);
+        }
+        // Extension fields and a MAC may follow the header
+        packet[0] = 0x23;
+        packet.extend_from_slice(&[0; 20]);
+        assert_eq!(Ntp::parse(&packet).unwrap().mode, "client");
+    }
+}
@@ -0,0 +1,924 @@
+// QUIC packet headers (RFC 9000) and the protection of Initial packets (RFC 9001).
+// Initial keys only depend on the Destination Connection ID the client picked, so anyone
+// on the path can decrypt them and read the TLS ClientHello they carry.
+
+use std::collections::{BTreeMap, HashMap};
+
+use packet_kit::dissect::{Dissection, Dissector, Next};
+use packet_kit::FieldSpan;
+use ring::aead::quic::{HeaderProtectionKey, AES_128};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_128_GCM};
+use ring::digest::{digest, SHA256};
+use ring::hkdf::{KeyType, Prk, Salt, HKDF_SHA256};
+use serde::Serialize;
+
+pub const QUIC_PORT: u16 = 443;
+
+const VERSION_1: u32 = 0x0000_0001;
+const VERSION_2: u32 = 0x6b33_43cf;
+const VERSION_DRAFT_29: u32 = 0xff00_001d;
+
+// Salts of the Initial secrets, RFC 9001 section 5.2, RFC 9369 section 3.3.1 and draft 29
+const SALT_V1: [u8; 20] = [
+    0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17, 0x9a, 0xe6, 0xa4, 0xc8, 0x0c, 0xad,
+    0xcc, 0xbb, 0x7f, 0x0a,
+];
+const SALT_V2: [u8; 20] = [
+    0x0d, 0xed, 0xe3, 0xde, 0xf7, 0x00, 0xa6, 0xdb, 0x81, 0x93, 0x81, 0xbe, 0x6e, 0x26, 0x9d, 0xcb,
+    0xf9, 0xbd, 0x2e, 0xd9,
+];
+const SALT_DRAFT_29: [u8; 20] = [
+    0xaf, 0xbf, 0xec, 0x28, 0x99, 0x93, 0xd2, 0x4c, 0x9e, 0x97, 0x86, 0xf1, 0x9c, 0x61, 0x11, 0xe0,
+    0x43, 0x90, 0xa8, 0x99,
+];
+
+// Header protection samples 16 bytes, starting 4 bytes after the packet number
+const SAMPLE_SIZE: usize = 16;
+const TAG_SIZE: usize = 16;
+
+const FRAME_PADDING: u64 = 0x00;
+const FRAME_PING: u64 = 0x01;
+const FRAME_ACK: u64 = 0x02;
+const FRAME_ACK_ECN: u64 = 0x03;
+const FRAME_CRYPTO: u64 = 0x06;
+const FRAME_CONNECTION_CLOSE: u64 = 0x1c;
+
+// Datagrams between two purges of the tracker, and how many may go by without a word
+// from a connection before what the tracker remembers of it is dropped
+const PURGE_INTERVAL: u64 = 1024;
+const MAX_IDLE: u64 = 64 * 1024;
+
+// Handshake bytes buffered for a ClientHello, longer ones are left undecoded
+const MAX_CLIENT_HELLO: u64 = 16 * 1024;
+
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+const HANDSHAKE_SERVER_HELLO: u8 = 2;
+
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+const EXTENSION_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXTENSION_ALPN: u16 = 0x0010;
+const EXTENSION_SUPPORTED_VERSIONS: u16 = 0x002b;
+
+// Reads a variable-length integer (RFC 9000 section 16), the top two bits give its size
+fn read_varint(buffer: &[u8], offset: &mut usize) -> Option<u64> {
+    let first = *buffer.get(*offset)?;
+    let length = 1 << (first >> 6);
+    let bytes = buffer.get(*offset..*offset + length)?;
+    let mut value = (first & 0x3f) as u64;
+    for byte in &bytes[1..] {
+        value = (value << 8) | *byte as u64;
+    }
+    *offset += length;
+    Some(value)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Output length of an HKDF expansion
+struct Length(usize);
+
+impl KeyType for Length {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+// HKDF-Expand-Label of TLS 1.3 (RFC 8446 section 7.1) with an empty context
+fn expand_label(secret: &Prk, label: &str, length: usize) -> Vec<u8> {
+    let label = format!("tls13 {}", label);
+    let mut info = Vec::new();
+    info.extend_from_slice(&(length as u16).to_be_bytes());
+    info.push(label.len() as u8);
+    info.extend_from_slice(label.as_bytes());
+    info.push(0);
+
+    let mut out = vec![0; length];
+    secret
+        .expand(&[&info], Length(length))
+        .and_then(|okm| okm.fill(&mut out))
+        .expect("HKDF output length is valid");
+    out
+}
+
+// Packet key, IV and header protection key of the client or the server Initials,
+// derived from the client's first DCID
+fn initial_secrets(version: u32, dcid: &[u8], server: bool) -> Option<[Vec<u8>; 3]> {
+    let (salt, prefix) = match version {
+        VERSION_1 => (&SALT_V1, "quic"),
+        VERSION_2 => (&SALT_V2, "quicv2"),
+        VERSION_DRAFT_29 => (&SALT_DRAFT_29, "quic"),
+        _ => return None,
+    };
+    let initial = Salt::new(HKDF_SHA256, salt).extract(dcid);
+    let label = if server { "server in" } else { "client in" };
+    let secret = Prk::new_less_safe(HKDF_SHA256, &expand_label(&initial, label, 32));
+    Some([
+        expand_label(&secret, &format!("{} key", prefix), 16),
+        expand_label(&secret, &format!("{} iv", prefix), 12),
+        expand_label(&secret, &format!("{} hp", prefix), 16),
+    ])
+}
+
+// Packet protection keys of one side of the connection
+struct Keys {
+    packet: LessSafeKey,
+    iv: [u8; 12],
+    header: HeaderProtectionKey,
+}
+
+impl Keys {
+    fn initial(version: u32, dcid: &[u8], server: bool) -> Option<Self> {
+        let [key, iv, hp] = initial_secrets(version, dcid, server)?;
+        Some(Keys {
+            packet: LessSafeKey::new(UnboundKey::new(&AES_128_GCM, &key).ok()?),
+            iv: iv.try_into().ok()?,
+            header: HeaderProtectionKey::new(&AES_128, &hp).ok()?,
+        })
+    }
+
+    // Removes header protection and decrypts the packet, `pn_offset` is where the packet
+    // number starts and `end` where the packet ends. Returns the packet number and plaintext.
+    fn open(&self, packet: &[u8], pn_offset: usize, end: usize) -> Option<(u64, Vec<u8>)> {
+        let sample = packet.get(pn_offset + 4..pn_offset + 4 + SAMPLE_SIZE)?;
+        let mask = self.header.new_mask(sample).ok()?;
+
+        let mut header = packet.get(..pn_offset + 4)?.to_vec();
+        // Long headers protect the low 4 bits of the first byte, short headers the low 5
+        header[0] ^= mask[0] & if header[0] & 0x80 != 0 { 0x0f } else { 0x1f };
+        let pn_length = (header[0] & 0x03) as usize + 1;
+        header.truncate(pn_offset + pn_length);
+        let mut number = 0;
+        for index in 0..pn_length {
+            header[pn_offset + index] ^= mask[1 + index];
+            number = (number << 8) | header[pn_offset + index] as u64;
+        }
+
+        let mut nonce = self.iv;
+        for (byte, pn) in nonce[4..].iter_mut().zip(number.to_be_bytes()) {
+            *byte ^= pn;
+        }
+        let mut payload = packet.get(pn_offset + pn_length..end)?.to_vec();
+        if payload.len() < TAG_SIZE {
+            return None;
+        }
+        let plaintext = self
+            .packet
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(&header),
+                &mut payload,
+            )
+            .ok()?;
+        Some((number, plaintext.to_vec()))
+    }
+}
+
+// What a TLS ClientHello says about the client
+#[derive(Clone, Serialize)]
+pub struct ClientHello {
+    pub sni: Option<String>,
+    pub alpn: Vec<String>,
+    // Versions offered in supported_versions, GREASE left out
+    pub versions: Vec<String>,
+    pub cipher_suites: Vec<u16>,
+    // Extension types in the order they were sent
+    pub extensions: Vec<u16>,
+    pub signature_algorithms: Vec<u16>,
+    pub ja4: String,
+}
+
+// GREASE values (RFC 8701) are 0x?a?a with both bytes equal
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn version_name(version: u16) -> &'static str {
+    match version {
+        0x0304 => "13",
+        0x0303 => "12",
+        0x0302 => "11",
+        0x0301 => "10",
+        0x0300 => "s3",
+        _ => "00",
+    }
+}
+
+// First 12 hex digits of the SHA-256 of a comma separated list, zeros for an empty list
+fn truncated_hash(values: &str) -> String {
+    if values.is_empty() {
+        return String::from("000000000000");
+    }
+    hex(&digest(&SHA256, values.as_bytes()).as_ref()[..6])
+}
+
+impl ClientHello {
+    // Parses a ClientHello handshake message, `body` starts after the type and length
+    fn parse(body: &[u8]) -> Option<Self> {
+        let mut reader = Reader(body);
+        let legacy_version = reader.u16()?;
+        reader.take(32)?;
+        let session_id = reader.u8()? as usize;
+        reader.take(session_id)?;
+        let suites = reader.u16()? as usize;
+        let mut suites = Reader(reader.take(suites)?);
+        let mut cipher_suites = Vec::new();
+        while let Some(suite) = suites.u16() {
+            cipher_suites.push(suite);
+        }
+        let compression = reader.u8()? as usize;
+        reader.take(compression)?;
+
+        let mut hello = ClientHello {
+            sni: None,
+            alpn: Vec::new(),
+            versions: Vec::new(),
+            cipher_suites,
+            extensions: Vec::new(),
+            signature_algorithms: Vec::new(),
+            ja4: String::new(),
+        };
+        let mut offered = Vec::new();
+        let extensions = reader.u16()? as usize;
+        let mut extensions = Reader(reader.take(extensions)?);
+        while let Some(kind) = extensions.u16() {
+            let length = extensions.u16()? as usize;
+            let mut data = Reader(extensions.take(length)?);
+            hello.extensions.push(kind);
+            match kind {
+                EXTENSION_SERVER_NAME => {
+                    data.u16()?;
+                    // Name type, 0 for host names
+                    if data.u8()? == 0 {
+                        let length = data.u16()? as usize;
+                        hello.sni = Some(String::from_utf8_lossy(data.take(length)?).into_owned());
+                    }
+                }
+                EXTENSION_ALPN => {
+                    data.u16()?;
+                    while let Some(length) = data.u8() {
+                        let protocol = data.take(length as usize)?;
+                        hello
+                            .alpn
+                            .push(String::from_utf8_lossy(protocol).into_owned());
+                    }
+                }
+                EXTENSION_SUPPORTED_VERSIONS => {
+                    data.u8()?;
+                    while let Some(version) = data.u16() {
+                        if !is_grease(version) {
+                            offered.push(version);
+                        }
+                    }
+                }
+                EXTENSION_SIGNATURE_ALGORITHMS => {
+                    data.u16()?;
+                    while let Some(algorithm) = data.u16() {
+                        hello.signature_algorithms.push(algorithm);
+                    }
+                }
+                _ => {}
+            }
+        }
+        hello.versions = offered
+            .iter()
+            .map(|version| version_name(*version).to_string())
+            .collect();
+        let version = offered.into_iter().max().unwrap_or(legacy_version);
+        hello.ja4 = hello.ja4(version);
+        Some(hello)
+    }
+
+    // JA4 fingerprint of a QUIC client: the protocol, TLS version, SNI presence, cipher and
+    // extension counts and ALPN, then hashes of the sorted ciphers and of the sorted
+    // extensions followed by the signature algorithms
+    fn ja4(&self, version: u16) -> String {
+        let ciphers: Vec<u16> = self
+            .cipher_suites
+            .iter()
+            .copied()
+            .filter(|suite| !is_grease(*suite))
+            .collect();
+        let extensions: Vec<u16> = self
+            .extensions
+            .iter()
+            .copied()
+            .filter(|extension| !is_grease(*extension))
+            .collect();
+        let alpn = match self.alpn.first().map(|alpn| alpn.as_bytes()) {
+            Some([first, .., last])
+                if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() =>
+            {
+                format!("{}{}", *first as char, *last as char)
+            }
+            Some([only]) if only.is_ascii_alphanumeric() => {
+                format!("{}{}", *only as char, *only as char)
+            }
+            Some(bytes @ [_, ..]) => {
+                let hex = hex(bytes);
+                format!("{}{}", &hex[..1], &hex[hex.len() - 1..])
+            }
+            _ => String::from("00"),
+        };
+        let a = format!(
+            "q{}{}{:02}{:02}{}",
+            version_name(version),
+            if self.sni.is_some() { "d" } else { "i" },
+            ciphers.len().min(99),
+            extensions.len().min(99),
+            alpn
+        );
+
+        let join = |values: &[u16]| {
+            values
+                .iter()
+                .map(|value| format!("{:04x}", value))
+                .collect::<Vec<String>>()
+                .join(",")
+        };
+        let mut sorted_ciphers = ciphers;
+        sorted_ciphers.sort_unstable();
+        // SNI and ALPN already show in the first part
+        let mut sorted_extensions: Vec<u16> = extensions
+            .into_iter()
+            .filter(|extension| *extension != EXTENSION_SERVER_NAME && *extension != EXTENSION_ALPN)
+            .collect();
+        sorted_extensions.sort_unstable();
+        let mut c = join(&sorted_extensions);
+        if !self.signature_algorithms.is_empty() {
+            c = format!("{}_{}", c, join(&self.signature_algorithms));
+        }
+        format!(
+            "{}_{}_{}",
+            a,
+            truncated_hash(&join(&sorted_ciphers)),
+            truncated_hash(&c)
+        )
+    }
+}
+
+// A cursor over big-endian fields
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        if self.0.len() < length {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.take(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+}
+
+// A QUIC packet of a datagram, with what decrypting it told us
+#[derive(Serialize)]
+pub struct QuicPacket {
+    // Initial, 0-RTT, Handshake, Retry, Version Negotiation or 1-RTT
+    pub packet_type: &'static str,
+    pub version: Option<String>,
+    pub dcid: String,
+    pub scid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packet_number: Option<u64>,
+    // Frames of decrypted packets
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub frames: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_hello: Option<ClientHello>,
+}
+
+// Type of a long header packet, from its version and first byte
+fn long_packet_type(version: u32, first: u8) -> &'static str {
+    if version == 0 {
+        return "Version Negotiation";
+    }
+    // QUIC v2 shuffled the type bits around
+    match (version == VERSION_2, (first >> 4) & 0x03) {
+        (false, 0) | (true, 1) => "Initial",
+        (false, 1) | (true, 2) => "0-RTT",
+        (false, 2) | (true, 3) => "Handshake",
+        _ => "Retry",
+    }
+}
+
+// Recognises QUIC for the dissector registry from the header of the first packet alone.
+// Decrypting Initials takes what a QuicTracker remembers across datagrams.
+pub struct Quic;
+
+impl Dissector for Quic {
+    fn name(&self) -> &'static str {
+        "QUIC"
+    }
+
+    fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>> {
+        let first = *data.first()?;
+        let summary = if first & 0x80 == 0 {
+            // The fixed bit is all a short header has to show
+            if first & 0x40 == 0 {
+                return None;
+            }
+            String::from("1-RTT")
+        } else {
+            let version = u32::from_be_bytes(data.get(1..5)?.try_into().ok()?);
+            let dcid_length = *data.get(5)? as usize;
+            let dcid = data.get(6..6 + dcid_length)?;
+            if version != 0 && (first & 0x40 == 0 || dcid_length > 20) {
+                return None;
+            }
+            format!(
+                "{}, Version 0x{:08x}, DCID {}",
+                long_packet_type(version, first),
+                version,
+                hex(dcid)
+            )
+        };
+        // The layer covers the whole datagram, coalesced packets included
+        let fields = vec![FieldSpan::new("Datagram", 0, data.len(), &summary)];
+        Some(Dissection::new(
+            summary,
+            fields,
+            &data[data.len()..],
+            Next::End,
+        ))
+    }
+}
+
+// Handshake bytes of a connection, reassembled from the CRYPTO frames of its Initials
+#[derive(Default)]
+struct CryptoStream {
+    fragments: BTreeMap<u64, Vec<u8>>,
+    // Datagram the last fragment came in
+    last_seen: u64,
+}
+
+impl CryptoStream {
+    fn add(&mut self, offset: u64, data: &[u8], datagram: u64) {
+        self.last_seen = datagram;
+        if offset.saturating_add(data.len() as u64) > MAX_CLIENT_HELLO {
+            return;
+        }
+        self.fragments
+            .entry(offset)
+            .or_insert_with(|| data.to_vec());
+    }
+
+    // The bytes received without gaps from the start of the stream
+    fn contiguous(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (offset, data) in &self.fragments {
+            let offset = *offset as usize;
+            if offset > bytes.len() {
+                break;
+            }
+            if offset + data.len() > bytes.len() {
+                bytes.extend_from_slice(&data[bytes.len() - offset..]);
+            }
+        }
+        bytes
+    }
+}
+
+// What the dissector remembers across datagrams: the client's first DCID of every
+// connection, which the server's Initial keys derive from as well, and the connection IDs
+// seen in long headers, to tell where the DCID of short headers ends. Entries are stamped
+// with the datagram they were last used in and dropped once idle for MAX_IDLE datagrams.
+#[derive(Default)]
+pub struct QuicTracker {
+    // Original DCID by the client's SCID, which the server sends its packets to
+    original_dcids: HashMap<Vec<u8>, (Vec<u8>, u64)>,
+    // ClientHello reassembly by original DCID, until the ClientHello is complete
+    streams: HashMap<Vec<u8>, CryptoStream>,
+    connection_ids: HashMap<Vec<u8>, u64>,
+    datagrams: u64,
+}
+
+impl QuicTracker {
+    // Dissects every QUIC packet coalesced in the datagram, None when it doesn't look like QUIC
+    pub fn dissect(&mut self, datagram: &[u8]) -> Option<Vec<QuicPacket>> {
+        self.datagrams += 1;
+        if self.datagrams.is_multiple_of(PURGE_INTERVAL) {
+            self.purge();
+        }
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        while offset < datagram.len() {
+            let packet = &datagram[offset..];
+            // The fixed bit is set in every packet but Version Negotiation
+            if packet[0] & 0x80 == 0 {
+                packets.push(self.short_header(packet)?);
+                break;
+            }
+            let (dissected, length) = self.long_header(packet)?;
+            packets.push(dissected);
+            offset += length;
+        }
+        (!packets.is_empty()).then_some(packets)
+    }
+
+    // Forgets the connections nothing was heard of for MAX_IDLE datagrams
+    fn purge(&mut self) {
+        let now = self.datagrams;
+        let fresh = |last_seen: u64| now - last_seen < MAX_IDLE;
+        self.original_dcids
+            .retain(|_, (_, last_seen)| fresh(*last_seen));
+        self.streams.retain(|_, stream| fresh(stream.last_seen));
+        self.connection_ids.retain(|_, last_seen| fresh(*last_seen));
+    }
+
+    fn short_header(&self, packet: &[u8]) -> Option<QuicPacket> {
+        if packet[0] & 0x40 == 0 {
+            return None;
+        }
+        // The DCID length isn't sent, it has to be one of the IDs handed out earlier
+        let dcid = self
+            .connection_ids
+            .keys()
+            .filter(|id| packet.get(1..1 + id.len()) == Some(id.as_slice()))
+            .max_by_key(|id| id.len())
+            .map(|id| hex(id))
+            .unwrap_or_else(|| String::from("unknown"));
+        Some(QuicPacket {
+            packet_type: "1-RTT",
+            version: None,
+            dcid,
+            scid: None,
+            packet_number: None,
+            frames: Vec::new(),
+            client_hello: None,
+        })
+    }
+
+    // Dissects the long header packet at the start of `packet`, returns it with its length
+    fn long_header(&mut self, packet: &[u8]) -> Option<(QuicPacket, usize)> {
+        let mut offset = 1;
+        let version = u32::from_be_bytes(packet.get(1..5)?.try_into().ok()?);
+        offset += 4;
+        let dcid_length = *packet.get(offset)? as usize;
+        let dcid = packet.get(offset + 1..offset + 1 + dcid_length)?.to_vec();
+        offset += 1 + dcid_length;
+        let scid_length = *packet.get(offset)? as usize;
+        let scid = packet.get(offset + 1..offset + 1 + scid_length)?.to_vec();
+        offset += 1 + scid_length;
+
+        let mut dissected = QuicPacket {
+            packet_type: long_packet_type(version, packet[0]),
+            version: Some(format!("0x{:08x}", version)),
+            dcid: hex(&dcid),
+            scid: Some(hex(&scid)),
+            packet_number: None,
+            frames: Vec::new(),
+            client_hello: None,
+        };
+        if version == 0 {
+            return Some((dissected, packet.len()));
+        }
+        if packet[0] & 0x40 == 0 || dcid_length > 20 || scid_length > 20 {
+            return None;
+        }
+        for id in [&dcid, &scid] {
+            if !id.is_empty() {
+                self.connection_ids.insert(id.clone(), self.datagrams);
+            }
+        }
+
+        if dissected.packet_type == "Retry" {
+            return Some((dissected, packet.len()));
+        }
+        if dissected.packet_type == "Initial" {
+            let token = read_varint(packet, &mut offset)? as usize;
+            offset += token;
+        }
+        let length = read_varint(packet, &mut offset)? as usize;
+        let end = offset + length;
+        if end > packet.len() {
+            return None;
+        }
+        if dissected.packet_type == "Initial" {
+            self.decrypt_initial(
+                &mut dissected,
+                version,
+                &dcid,
+                &scid,
+                &packet[..end],
+                offset,
+            );
+        }
+        Some((dissected, end))
+    }
+
+    fn decrypt_initial(
+        &mut self,
+        dissected: &mut QuicPacket,
+        version: u32,
+        dcid: &[u8],
+        scid: &[u8],
+        packet: &[u8],
+        pn_offset: usize,
+    ) {
+        // Packets sent to a known client SCID come from the server
+        let now = self.datagrams;
+        let (original, server) = match self.original_dcids.get_mut(dcid) {
+            Some((original, last_seen)) => {
+                *last_seen = now;
+                (original.clone(), true)
+            }
+            None => {
+                if !scid.is_empty() {
+                    self.original_dcids
+                        .entry(scid.to_vec())
+                        .and_modify(|(_, last_seen)| *last_seen = now)
+                        .or_insert_with(|| (dcid.to_vec(), now));
+                }
+                (dcid.to_vec(), false)
+            }
+        };
+        let Some(keys) = Keys::initial(version, &original, server) else {
+            return;
+        };
+        let Some((number, plaintext)) = keys.open(packet, pn_offset, packet.len()) else {
+            dissected.frames.push(String::from("undecryptable"));
+            return;
+        };
+        dissected.packet_number = Some(number);
+
+        let mut offset = 0;
+        let mut padding = 0;
+        while offset < plaintext.len() {
+            let Some(frame) = read_varint(&plaintext, &mut offset) else {
+                break;
+            };
+            if frame != FRAME_PADDING && padding > 0 {
+                dissected
+                    .frames
+                    .push(format!("PADDING ({} bytes)", padding));
+                padding = 0;
+            }
+            match frame {
+                FRAME_PADDING => padding += 1,
+                FRAME_PING => dissected.frames.push(String::from("PING")),
+                FRAME_ACK | FRAME_ACK_ECN => {
+                    let Some(largest) = skip_ack(&plaintext, &mut offset, frame == FRAME_ACK_ECN)
+                    else {
+                        break;
+                    };
+                    dissected.frames.push(format!("ACK (largest {})", largest));
+                }
+                FRAME_CRYPTO => {
+                    let (Some(start), Some(length)) = (
+                        read_varint(&plaintext, &mut offset),
+                        read_varint(&plaintext, &mut offset),
+                    ) else {
+                        break;
+                    };
+                    let Some(data) = plaintext.get(offset..offset + length as usize) else {
+                        break;
+                    };
+                    offset += length as usize;
+                    dissected
+                        .frames
+                        .push(format!("CRYPTO (offset {}, {} bytes)", start, length));
+                    // Server Initials carry the ServerHello, there's nothing to reassemble
+                    if server {
+                        if start == 0 && data.first() == Some(&HANDSHAKE_SERVER_HELLO) {
+                            dissected.frames.push(String::from("ServerHello"));
+                        }
+                    } else {
+                        self.streams
+                            .entry(original.clone())
+                            .or_default()
+                            .add(start, data, now);
+                    }
+                }
+                FRAME_CONNECTION_CLOSE => {
+                    let code = read_varint(&plaintext, &mut offset);
+                    dissected.frames.push(format!(
+                        "CONNECTION_CLOSE (error 0x{:x})",
+                        code.unwrap_or(0)
+                    ));
+                    break;
+                }
+                // Nothing else may be sent in Initial packets
+                _ => {
+                    dissected
+                        .frames
+                        .push(format!("unexpected frame 0x{:02x}", frame));
+                    break;
+                }
+            }
+        }
+        if padding > 0 {
+            dissected
+                .frames
+                .push(format!("PADDING ({} bytes)", padding));
+        }
+
+        // The ClientHello may span several Initials, it's reported once complete and the
+        // stream dropped, there's nothing else the tracker reads from it
+        if let Some(stream) = self.streams.get(&original).filter(|_| !server) {
+            let handshake = stream.contiguous();
+            if handshake.len() >= 4 && handshake[0] == HANDSHAKE_CLIENT_HELLO {
+                let length = u32::from_be_bytes([0, handshake[1], handshake[2], handshake[3]]);
+                if let Some(body) = handshake.get(4..4 + length as usize) {
+                    dissected.client_hello = ClientHello::parse(body);
+                    self.streams.remove(&original);
+                }
+            }
+        }
+    }
+}
+
+// Skips the body of an ACK frame, returns the largest acknowledged packet number
+fn skip_ack(frame: &[u8], offset: &mut usize, ecn: bool) -> Option<u64> {
+    let largest = read_varint(frame, offset)?;
+    read_varint(frame, offset)?;
+    let ranges = read_varint(frame, offset)?;
+    read_varint(frame, offset)?;
+    for _ in 0..ranges {
+        read_varint(frame, offset)?;
+        read_varint(frame, offset)?;
+    }
+    if ecn {
+        for _ in 0..3 {
+            read_varint(frame, offset)?;
+        }
+    }
+    Some(largest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 9001 Appendix A, the client's DCID and the CRYPTO frame of its first Initial
+    const DCID: [u8; 8] = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+    const CLIENT_CRYPTO: &str = concat!(
+        "060040f1010000ed0303ebf8fa56f12939b9584a3896472ec40bb863cfd3e868",
+        "04fe3a47f06a2b69484c00000413011302010000c000000010000e00000b6578",
+        "616d706c652e636f6dff01000100000a00080006001d00170018001000070005",
+        "04616c706e000500050100000000003300260024001d00209370b2c9caa47fba",
+        "baf4559fedba753de171fa71f50f1ce15d43e994ec74d748002b000302030400",
+        "0d0010000e0403050306030203080408050806002d00020101001c0002400100",
+        "3900320408ffffffffffffffff05048000ffff07048000ffff08011001048000",
+        "75300901100f088394c8f03e51570806048000ffff",
+    );
+    const CLIENT_HEADER: &str = "c300000001088394c8f03e5157080000449e00000002";
+    const SERVER_INITIAL: &str = concat!(
+        "cf000000010008f067a5502a4262b5004075c0d95a482cd0991cd25b0aac406a",
+        "5816b6394100f37a1c69797554780bb38cc5a99f5ede4cf73c3ec2493a1839b3",
+        "dbcba3f6ea46c5b7684df3548e7ddeb9c3bf9c73cc3f3bded74b562bfb19fb84",
+        "022f8ef4cdd93795d77d06edbb7aaf2f58891850abbdca3d20398c276456cbc4",
+        "2158407dd074ee",
+    );
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+            .collect()
+    }
+
+    // The client Initial of Appendix A.2: the CRYPTO frame padded to a 1200-byte
+    // datagram, sealed under packet number 2, then header protected
+    fn client_initial() -> Vec<u8> {
+        let keys = Keys::initial(VERSION_1, &DCID, false).unwrap();
+        let header = bytes(CLIENT_HEADER);
+        let pn_offset = header.len() - 4;
+        let mut payload = bytes(CLIENT_CRYPTO);
+        payload.resize(1200 - header.len() - TAG_SIZE, 0);
+
+        let mut nonce = keys.iv;
+        nonce[11] ^= 2;
+        keys.packet
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(&header),
+                &mut payload,
+            )
+            .unwrap();
+        let mask = keys.header.new_mask(&payload[..SAMPLE_SIZE]).unwrap();
+        let mut packet = header;
+        packet[0] ^= mask[0] & 0x0f;
+        for index in 0..4 {
+            packet[pn_offset + index] ^= mask[1 + index];
+        }
+        packet.extend_from_slice(&payload);
+        packet
+    }
+
+    #[test]
+    fn initial_secrets_match_rfc_9001() {
+        let [key, iv, hp] = initial_secrets(VERSION_1, &DCID, false).unwrap();
+        assert_eq!(hex(&key), "1f369613dd76d5467730efcbe3b1a22d");
+        assert_eq!(hex(&iv), "fa044b2f42a3fd3b46fb255c");
+        assert_eq!(hex(&hp), "9f50449e04a0e810283a1e9933adedd2");
+
+        let [key, iv, hp] = initial_secrets(VERSION_1, &DCID, true).unwrap();
+        assert_eq!(hex(&key), "cf3a5331653c364c88f0f379b6067e37");
+        assert_eq!(hex(&iv), "0ac1493ca1905853b0bba03e");
+        assert_eq!(hex(&hp), "c206b8d9b9f0f37644430b490eeaa314");
+
+        assert!(initial_secrets(0x1234_5678, &DCID, false).is_none());
+    }
+
+    #[test]
+    fn header_protection_masks_match_rfc_9001() {
+        let client = Keys::initial(VERSION_1, &DCID, false).unwrap();
+        let mask = client
+            .header
+            .new_mask(&bytes("d1b1c98dd7689fb8ec11d242b123dc9b"))
+            .unwrap();
+        assert_eq!(hex(&mask), "437b9aec36");
+
+        let server = Keys::initial(VERSION_1, &DCID, true).unwrap();
+        let mask = server
+            .header
+            .new_mask(&bytes("2cd0991cd25b0aac406a5816b6394100"))
+            .unwrap();
+        assert_eq!(hex(&mask), "2ec0d8356a");
+    }
+
+    #[test]
+    fn client_initial_matches_rfc_9001() {
+        let packet = client_initial();
+        assert_eq!(
+            hex(&packet[..22]),
+            "c000000001088394c8f03e5157080000449e7b9aec34"
+        );
+        assert_eq!(hex(&packet[22..38]), "d1b1c98dd7689fb8ec11d242b123dc9b");
+        assert_eq!(
+            hex(&packet[packet.len() - 16..]),
+            "e221af44860018ab0856972e194cd934"
+        );
+    }
+
+    #[test]
+    fn client_initial_gives_up_its_client_hello() {
+        let mut tracker = QuicTracker::default();
+        let packets = tracker.dissect(&client_initial()).unwrap();
+        assert_eq!(packets.len(), 1);
+        let packet = &packets[0];
+        assert_eq!(packet.packet_type, "Initial");
+        assert_eq!(packet.dcid, "8394c8f03e515708");
+        assert_eq!(packet.packet_number, Some(2));
+        assert_eq!(
+            packet.frames,
+            ["CRYPTO (offset 0, 241 bytes)", "PADDING (917 bytes)"]
+        );
+
+        let hello = packet.client_hello.as_ref().unwrap();
+        assert_eq!(hello.sni.as_deref(), Some("example.com"));
+        assert_eq!(hello.alpn, ["alpn"]);
+        assert_eq!(hello.versions, ["13"]);
+        assert_eq!(hello.cipher_suites, [0x1301, 0x1302]);
+        assert_eq!(hello.ja4, "q13d0211an_62ed6f6ca7ad_4d634acda6c0");
+        // Nothing is left of the handshake once the ClientHello is out
+        assert!(tracker.streams.is_empty());
+    }
+
+    #[test]
+    fn server_initial_decrypts() {
+        let packet = bytes(SERVER_INITIAL);
+        let keys = Keys::initial(VERSION_1, &DCID, true).unwrap();
+        let (number, plaintext) = keys.open(&packet, 18, packet.len()).unwrap();
+        assert_eq!(number, 1);
+        // An ACK of packet 0, then the ServerHello in a CRYPTO frame
+        assert_eq!(hex(&plaintext[..16]), "02000000000600405a020000560303ee");
+        assert_eq!(plaintext.len(), packet.len() - 20 - TAG_SIZE);
+    }
+
+    #[test]
+    fn idle_connections_are_forgotten() {
+        let mut tracker = QuicTracker::default();
+        // A Handshake packet from SCID 02.. to DCID 01.., with a 4-byte payload
+        let mut handshake = vec![0xe0, 0, 0, 0, 1, 8];
+        handshake.extend_from_slice(&[1; 8]);
+        handshake.push(8);
+        handshake.extend_from_slice(&[2; 8]);
+        handshake.extend_from_slice(&[4, 0, 0, 0, 0]);
+        assert_eq!(
+            tracker.dissect(&handshake).unwrap()[0].packet_type,
+            "Handshake"
+        );
+        assert_eq!(tracker.connection_ids.len(), 2);
+
+        let mut short = vec![0x40];
+        short.extend_from_slice(&[1; 8]);
+        assert_eq!(tracker.dissect(&short).unwrap()[0].dcid, "0101010101010101");
+        for _ in 0..MAX_IDLE + PURGE_INTERVAL {
+            tracker.dissect(&[0x40, 0, 0]);
+        }
+        assert!(tracker.connection_ids.is_empty());
+        assert_eq!(tracker.dissect(&short).unwrap()[0].dcid, "unknown");
+    }
+}
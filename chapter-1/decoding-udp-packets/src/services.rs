@@ -0,0 +1,166 @@
+// Decoders of the services running over UDP, added to the dissector registry of
+// packet-kit by port, and by what their payload looks like when no decoder claims
+// either port
+
+use std::fmt;
+use std::sync::OnceLock;
+
+use packet_kit::dissect::{DecodedPacket, Dissection, Dissector, Key, Next, Registry};
+use packet_kit::pcap::LINKTYPE_RAW;
+use packet_kit::FieldSpan;
+use serde::Serialize;
+
+use crate::dns::{Dns, DNS_PORT, MDNS_PORT};
+use crate::netbios::{NetbiosNs, NETBIOS_NS_PORT};
+use crate::ntp::{Ntp, NTP_PORT};
+use crate::quic::{Quic, QUIC_PORT};
+use crate::snmp::{Snmp, SNMP_PORT, SNMP_TRAP_PORT};
+use crate::ssdp::{looks_like_ssdp, Ssdp, SSDP_PORT};
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Service {
+    Ntp(Ntp),
+    Snmp(Snmp),
+    Ssdp(Ssdp),
+    Dns(Dns),
+    NetbiosNs(NetbiosNs),
+}
+
+// A decoded payload and the name of the decoder that understood it
+#[derive(Serialize)]
+pub struct Decoded {
+    pub protocol: &'static str,
+    #[serde(flatten)]
+    pub service: Service,
+}
+
+#[derive(Clone, Copy)]
+struct ServiceDissector {
+    name: &'static str,
+    ports: &'static [u16],
+    // Whether a payload on any other port is this protocol, None for protocols
+    // too loosely structured to be recognised without their port
+    heuristic: Option<fn(&[u8]) -> bool>,
+    decode: fn(&[u8]) -> Option<Service>,
+}
+
+impl Dissector for ServiceDissector {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>> {
+        let decoded = Decoded {
+            protocol: self.name,
+            service: (self.decode)(data)?,
+        };
+        let text = decoded.to_string();
+        let summary = text.lines().next().unwrap_or_default();
+        // The layer covers the whole message
+        let fields = vec![FieldSpan::new("Message", 0, data.len(), summary)];
+        Some(Dissection::new(
+            summary,
+            fields,
+            &data[data.len()..],
+            Next::End,
+        ))
+    }
+
+    fn heuristic(&self, data: &[u8]) -> bool {
+        self.heuristic.is_some_and(|heuristic| heuristic(data))
+    }
+}
+
+// SNMP messages are recognised by decoding them, BER leaves little room for accidents
+fn looks_like_snmp(payload: &[u8]) -> bool {
+    payload.first() == Some(&0x30) && Snmp::parse(payload).is_some()
+}
+
+const SERVICES: &[ServiceDissector] = &[
+    ServiceDissector {
+        name: "NTP",
+        ports: &[NTP_PORT],
+        heuristic: None,
+        decode: |payload| Ntp::parse(payload).map(Service::Ntp),
+    },
+    ServiceDissector {
+        name: "SNMP",
+        ports: &[SNMP_PORT, SNMP_TRAP_PORT],
+        heuristic: Some(looks_like_snmp),
+        decode: |payload| Snmp::parse(payload).map(Service::Snmp),
+    },
+    ServiceDissector {
+        name: "SSDP",
+        ports: &[SSDP_PORT],
+        heuristic: Some(looks_like_ssdp),
+        decode: |payload| Ssdp::parse(payload).map(Service::Ssdp),
+    },
+    ServiceDissector {
+        name: "mDNS",
+        ports: &[MDNS_PORT],
+        heuristic: None,
+        decode: |payload| Dns::parse(payload).map(Service::Dns),
+    },
+    ServiceDissector {
+        name: "DNS",
+        ports: &[DNS_PORT],
+        heuristic: None,
+        decode: |payload| Dns::parse(payload).map(Service::Dns),
+    },
+    ServiceDissector {
+        name: "NetBIOS-NS",
+        ports: &[NETBIOS_NS_PORT],
+        heuristic: None,
+        decode: |payload| NetbiosNs::parse(payload).map(Service::NetbiosNs),
+    },
+];
+
+// The dissectors of packet-kit along with those of the services and QUIC. The registry
+// tries the destination port before the source port, so that requests sent from a
+// well-known port to another one go to the decoder of the service they reach.
+pub fn registry() -> Registry {
+    let mut registry = Registry::new();
+    for service in SERVICES {
+        let mut keys: Vec<Key> = service
+            .ports
+            .iter()
+            .map(|port| Key::UdpPort(*port))
+            .collect();
+        if service.heuristic.is_some() {
+            keys.push(Key::Heuristic);
+        }
+        registry.register(&keys, *service);
+    }
+    registry.register(&[Key::UdpPort(QUIC_PORT)], Quic);
+    registry
+}
+
+// Decodes an IPv4 packet, as the raw socket and the captures hand them over
+pub fn decode(packet: &[u8]) -> DecodedPacket {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(registry).decode(LINKTYPE_RAW, packet)
+}
+
+// The service the innermost layer of the packet was decoded as, along with what it holds
+pub fn service(packet: &DecodedPacket, frame: &[u8]) -> Option<Decoded> {
+    let layer = packet.innermost()?;
+    let dissector = SERVICES.iter().find(|service| service.name == layer.name)?;
+    Some(Decoded {
+        protocol: dissector.name,
+        service: (dissector.decode)(frame.get(layer.offset..layer.offset + layer.length)?)?,
+    })
+}
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.service {
+            Service::Ntp(ntp) => write!(f, "{}", ntp),
+            Service::Snmp(snmp) => write!(f, "{}", snmp),
+            Service::Ssdp(ssdp) => write!(f, "{}", ssdp),
+            // DNS and mDNS share a decoder, the name tells them apart
+            Service::Dns(dns) => write!(f, "{} {}", self.protocol, dns),
+            Service::NetbiosNs(nbns) => write!(f, "{}", nbns),
+        }
+    }
+}
@@ -0,0 +1,496 @@
+// SNMPv1 (RFC 1157) and SNMPv2c (RFC 1901, RFC 3416) messages, which are BER encoded ASN.1
+
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use serde::Serialize;
+
+pub const SNMP_PORT: u16 = 161;
+pub const SNMP_TRAP_PORT: u16 = 162;
+
+// Universal tags
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_NULL: u8 = 0x05;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+
+// Application tags of the SNMP types
+const TAG_IP_ADDRESS: u8 = 0x40;
+const TAG_COUNTER32: u8 = 0x41;
+const TAG_GAUGE32: u8 = 0x42;
+const TAG_TIMETICKS: u8 = 0x43;
+const TAG_OPAQUE: u8 = 0x44;
+const TAG_COUNTER64: u8 = 0x46;
+
+// Context tags of the exceptions SNMPv2 responses put in place of values
+const TAG_NO_SUCH_OBJECT: u8 = 0x80;
+const TAG_NO_SUCH_INSTANCE: u8 = 0x81;
+const TAG_END_OF_MIB_VIEW: u8 = 0x82;
+
+const PDU_GET_BULK_REQUEST: u8 = 0xa5;
+const PDU_TRAP_V1: u8 = 0xa4;
+
+// A name and value pair of a PDU
+#[derive(Serialize)]
+pub struct Variable {
+    pub oid: String,
+    pub value: String,
+}
+
+#[derive(Serialize)]
+pub struct Snmp {
+    pub version: &'static str,
+    pub community: String,
+    pub pdu: &'static str,
+    // Every PDU but the SNMPv1 trap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_index: Option<i64>,
+    // GetBulkRequest reuses the error fields for these
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub non_repeaters: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_repetitions: Option<i64>,
+    // SNMPv1 trap fields
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enterprise: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agent_address: Option<Ipv4Addr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generic_trap: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub specific_trap: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    pub variables: Vec<Variable>,
+}
+
+// Reads BER tag, length, value triplets off a buffer
+struct Ber<'a>(&'a [u8]);
+
+impl<'a> Ber<'a> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Reads the next element, returns its tag and contents
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        let tag = *self.0.first()?;
+        let first = *self.0.get(1)? as usize;
+        let (length, header) = if first & 0x80 == 0 {
+            (first, 2)
+        } else {
+            // Long form, the low bits give how many bytes the length takes
+            let count = first & 0x7f;
+            if count == 0 || count > 4 {
+                return None;
+            }
+            let bytes = self.0.get(2..2 + count)?;
+            let length = bytes
+                .iter()
+                .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+            (length, 2 + count)
+        };
+        let contents = self.0.get(header..header + length)?;
+        self.0 = &self.0[header + length..];
+        Some((tag, contents))
+    }
+
+    // Reads the next element and checks its tag
+    fn expect(&mut self, expected: u8) -> Option<&'a [u8]> {
+        let (tag, contents) = self.next()?;
+        (tag == expected).then_some(contents)
+    }
+
+    fn integer(&mut self) -> Option<i64> {
+        integer(self.expect(TAG_INTEGER)?)
+    }
+}
+
+// A two's complement INTEGER
+fn integer(contents: &[u8]) -> Option<i64> {
+    if contents.is_empty() || contents.len() > 8 {
+        return None;
+    }
+    let sign = if contents[0] & 0x80 != 0 { -1i64 } else { 0 };
+    Some(
+        contents
+            .iter()
+            .fold(sign, |value, byte| (value << 8) | *byte as i64),
+    )
+}
+
+// The unsigned counters, gauges and time ticks
+fn unsigned(contents: &[u8]) -> Option<u64> {
+    if contents.is_empty() || contents.len() > 9 {
+        return None;
+    }
+    Some(
+        contents
+            .iter()
+            .fold(0u64, |value, byte| (value << 8) | *byte as u64),
+    )
+}
+
+// An OBJECT IDENTIFIER, the first byte packs the first two arcs
+fn oid(contents: &[u8]) -> Option<String> {
+    let first = *contents.first()?;
+    let mut arcs = vec![
+        (first / 40).min(2) as u64,
+        first as u64 - (first / 40).min(2) as u64 * 40,
+    ];
+    let mut arc = 0u64;
+    for byte in &contents[1..] {
+        arc = (arc << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            arcs.push(arc);
+            arc = 0;
+        }
+    }
+    let arcs: Vec<String> = arcs.iter().map(|arc| arc.to_string()).collect();
+    Some(arcs.join("."))
+}
+
+// OCTET STRINGs are printed as text when they are, in hexadecimal otherwise
+fn octet_string(contents: &[u8]) -> String {
+    match std::str::from_utf8(contents) {
+        Ok(text)
+            if !text
+                .chars()
+                .any(|c| c.is_control() && c != '\r' && c != '\n' && c != '\t') =>
+        {
+            format!("{:?}", text)
+        }
+        _ => {
+            let hex: Vec<String> = contents
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            format!("0x{}", hex.join(""))
+        }
+    }
+}
+
+fn value(tag: u8, contents: &[u8]) -> Option<String> {
+    Some(match tag {
+        TAG_INTEGER => integer(contents)?.to_string(),
+        TAG_OCTET_STRING => octet_string(contents),
+        TAG_NULL => String::from("NULL"),
+        TAG_OID => oid(contents)?,
+        TAG_IP_ADDRESS => {
+            let octets: [u8; 4] = contents.try_into().ok()?;
+            format!("IpAddress {}", Ipv4Addr::from(octets))
+        }
+        TAG_COUNTER32 => format!("Counter32 {}", unsigned(contents)?),
+        TAG_GAUGE32 => format!("Gauge32 {}", unsigned(contents)?),
+        TAG_TIMETICKS => format!("Timeticks {}", unsigned(contents)?),
+        TAG_OPAQUE => format!("Opaque {}", octet_string(contents)),
+        TAG_COUNTER64 => format!("Counter64 {}", unsigned(contents)?),
+        TAG_NO_SUCH_OBJECT => String::from("noSuchObject"),
+        TAG_NO_SUCH_INSTANCE => String::from("noSuchInstance"),
+        TAG_END_OF_MIB_VIEW => String::from("endOfMibView"),
+        _ => format!("tag 0x{:02x} ({} bytes)", tag, contents.len()),
+    })
+}
+
+fn pdu_name(tag: u8) -> Option<&'static str> {
+    match tag {
+        0xa0 => Some("GetRequest"),
+        0xa1 => Some("GetNextRequest"),
+        0xa2 => Some("Response"),
+        0xa3 => Some("SetRequest"),
+        PDU_TRAP_V1 => Some("Trap"),
+        PDU_GET_BULK_REQUEST => Some("GetBulkRequest"),
+        0xa6 => Some("InformRequest"),
+        0xa7 => Some("SNMPv2-Trap"),
+        0xa8 => Some("Report"),
+        _ => None,
+    }
+}
+
+fn error_name(status: i64) -> String {
+    let name = match status {
+        0 => "noError",
+        1 => "tooBig",
+        2 => "noSuchName",
+        3 => "badValue",
+        4 => "readOnly",
+        5 => "genErr",
+        6 => "noAccess",
+        7 => "wrongType",
+        8 => "wrongLength",
+        9 => "wrongEncoding",
+        10 => "wrongValue",
+        11 => "noCreation",
+        12 => "inconsistentValue",
+        13 => "resourceUnavailable",
+        14 => "commitFailed",
+        15 => "undoFailed",
+        16 => "authorizationError",
+        17 => "notWritable",
+        18 => "inconsistentName",
+        _ => return status.to_string(),
+    };
+    name.to_string()
+}
+
+fn generic_trap_name(trap: i64) -> Option<&'static str> {
+    match trap {
+        0 => Some("coldStart"),
+        1 => Some("warmStart"),
+        2 => Some("linkDown"),
+        3 => Some("linkUp"),
+        4 => Some("authenticationFailure"),
+        5 => Some("egpNeighborLoss"),
+        6 => Some("enterpriseSpecific"),
+        _ => None,
+    }
+}
+
+impl Snmp {
+    // Decodes the message, which has to fill the whole buffer
+    pub fn parse(buffer: &[u8]) -> Option<Self> {
+        let mut outer = Ber(buffer);
+        let mut message = Ber(outer.expect(TAG_SEQUENCE)?);
+        if !outer.is_empty() {
+            return None;
+        }
+        // SNMPv3 messages carry a security model instead of a community
+        let version = match message.integer()? {
+            0 => "v1",
+            1 => "v2c",
+            _ => return None,
+        };
+        let community = String::from_utf8_lossy(message.expect(TAG_OCTET_STRING)?).into_owned();
+        let (tag, pdu) = message.next()?;
+        let mut snmp = Snmp {
+            version,
+            community,
+            pdu: pdu_name(tag)?,
+            request_id: None,
+            error_status: None,
+            error_index: None,
+            non_repeaters: None,
+            max_repetitions: None,
+            enterprise: None,
+            agent_address: None,
+            generic_trap: None,
+            specific_trap: None,
+            timestamp: None,
+            variables: Vec::new(),
+        };
+
+        let mut pdu = Ber(pdu);
+        if tag == PDU_TRAP_V1 {
+            snmp.enterprise = Some(oid(pdu.expect(TAG_OID)?)?);
+            let address: [u8; 4] = pdu.expect(TAG_IP_ADDRESS)?.try_into().ok()?;
+            snmp.agent_address = Some(Ipv4Addr::from(address));
+            snmp.generic_trap = generic_trap_name(pdu.integer()?);
+            snmp.specific_trap = Some(pdu.integer()?);
+            snmp.timestamp = Some(unsigned(pdu.expect(TAG_TIMETICKS)?)? as i64);
+        } else {
+            snmp.request_id = Some(pdu.integer()?);
+            let (first, second) = (pdu.integer()?, pdu.integer()?);
+            if tag == PDU_GET_BULK_REQUEST {
+                snmp.non_repeaters = Some(first);
+                snmp.max_repetitions = Some(second);
+            } else {
+                snmp.error_status = Some(error_name(first));
+                snmp.error_index = Some(second);
+            }
+        }
+
+        let mut bindings = Ber(pdu.expect(TAG_SEQUENCE)?);
+        while !bindings.is_empty() {
+            let mut binding = Ber(bindings.expect(TAG_SEQUENCE)?);
+            let name = oid(binding.expect(TAG_OID)?)?;
+            let (tag, contents) = binding.next()?;
+            snmp.variables.push(Variable {
+                oid: name,
+                value: value(tag, contents)?,
+            });
+        }
+        Some(snmp)
+    }
+}
+
+impl fmt::Display for Snmp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SNMP{} {}: Community: {:?}",
+            self.version, self.pdu, self.community
+        )?;
+        if let Some(id) = self.request_id {
+            write!(f, " Request ID: {}", id)?;
+        }
+        if let (Some(status), Some(index)) = (&self.error_status, self.error_index) {
+            write!(f, " Error: {} (index {})", status, index)?;
+        }
+        if let (Some(non_repeaters), Some(max)) = (self.non_repeaters, self.max_repetitions) {
+            write!(
+                f,
+                " Non-repeaters: {} Max-repetitions: {}",
+                non_repeaters, max
+            )?;
+        }
+        writeln!(f)?;
+        if let (Some(enterprise), Some(agent)) = (&self.enterprise, self.agent_address) {
+            writeln!(
+                f,
+                "  Enterprise: {} Agent: {} Trap: {} ({}) Uptime: {}",
+                enterprise,
+                agent,
+                self.generic_trap.unwrap_or("unknown"),
+                self.specific_trap.unwrap_or(0),
+                self.timestamp.unwrap_or(0)
+            )?;
+        }
+        for variable in &self.variables {
+            writeln!(f, "  {} = {}", variable.oid, variable.value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A v2c GetRequest for sysDescr.0
+    const GET_REQUEST: &[u8] = b"\x30\x29\x02\x01\x01\x04\x06public\
+        \xa0\x1c\x02\x04\x12\x34\x56\x78\x02\x01\x00\x02\x01\x00\
+        \x30\x0e\x30\x0c\x06\x08\x2b\x06\x01\x02\x01\x01\x01\x00\x05\x00";
+
+    // The GetRequest with its outer length written in the given form
+    fn with_length(length: &[u8]) -> Vec<u8> {
+        let mut message = vec![TAG_SEQUENCE];
+        message.extend_from_slice(length);
+        message.extend_from_slice(&GET_REQUEST[2..]);
+        message
+    }
+
+    #[test]
+    fn parses_a_get_request() {
+        let snmp = Snmp::parse(GET_REQUEST).unwrap();
+        assert_eq!(snmp.version, "v2c");
+        assert_eq!(snmp.community, "public");
+        assert_eq!(snmp.pdu, "GetRequest");
+        assert_eq!(snmp.request_id, Some(0x12345678));
+        assert_eq!(snmp.error_status.as_deref(), Some("noError"));
+        assert_eq!(snmp.error_index, Some(0));
+        assert_eq!(snmp.variables.len(), 1);
+        assert_eq!(snmp.variables[0].oid, "1.3.6.1.2.1.1.1.0");
+        assert_eq!(snmp.variables[0].value, "NULL");
+        assert!(snmp
+            .to_string()
+            .starts_with("SNMPv2c GetRequest: Community: \"public\""));
+    }
+
+    #[test]
+    fn decodes_values_of_a_response() {
+        let message = b"\x30\x81\x95\x02\x01\x01\x04\x06public\
+            \xa2\x81\x87\x02\x04\x12\x34\x56\x78\x02\x01\x00\x02\x01\x00\x30\x79\
+            \x30\x18\x06\x08\x2b\x06\x01\x02\x01\x01\x01\x00\x04\x0cLinux router\
+            \x30\x0f\x06\x08\x2b\x06\x01\x02\x01\x01\x03\x00\x43\x03\x01\xe2\x40\
+            \x30\x13\x06\x0a\x2b\x06\x01\x02\x01\x02\x02\x01\x0a\x01\x41\x05\x00\xff\xff\xff\xff\
+            \x30\x16\x06\x0e\x2b\x06\x01\x02\x01\x04\x14\x01\x01\x81\x40\x00\x02\x01\
+            \x40\x04\xc0\x00\x02\x01\
+            \x30\x0f\x06\x0a\x2b\x06\x01\x04\x01\x8f\x65\x04\x05\x00\x02\x01\xff\
+            \x30\x0e\x06\x0a\x2b\x06\x01\x02\x01\x01\x09\x01\x02\x01\x80\x00";
+        let snmp = Snmp::parse(message).unwrap();
+        assert_eq!(snmp.pdu, "Response");
+        let variables: Vec<(&str, &str)> = snmp
+            .variables
+            .iter()
+            .map(|variable| (variable.oid.as_str(), variable.value.as_str()))
+            .collect();
+        assert_eq!(
+            variables,
+            [
+                ("1.3.6.1.2.1.1.1.0", "\"Linux router\""),
+                ("1.3.6.1.2.1.1.3.0", "Timeticks 123456"),
+                ("1.3.6.1.2.1.2.2.1.10.1", "Counter32 4294967295"),
+                ("1.3.6.1.2.1.4.20.1.1.192.0.2.1", "IpAddress 192.0.2.1"),
+                ("1.3.6.1.4.1.2021.4.5.0", "-1"),
+                ("1.3.6.1.2.1.1.9.1.2.1", "noSuchObject"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_a_v1_trap() {
+        let message = b"\x30\x3c\x02\x01\x00\x04\x06public\
+            \xa4\x2f\x06\x0a\x2b\x06\x01\x04\x01\xbf\x08\x03\x02\x0a\x40\x04\xc0\x00\x02\x07\
+            \x02\x01\x03\x02\x01\x00\x43\x02\x10\x68\
+            \x30\x11\x30\x0f\x06\x0a\x2b\x06\x01\x02\x01\x02\x02\x01\x01\x02\x02\x01\x02";
+        let snmp = Snmp::parse(message).unwrap();
+        assert_eq!(snmp.version, "v1");
+        assert_eq!(snmp.pdu, "Trap");
+        assert_eq!(snmp.enterprise.as_deref(), Some("1.3.6.1.4.1.8072.3.2.10"));
+        assert_eq!(snmp.agent_address, Some(Ipv4Addr::new(192, 0, 2, 7)));
+        assert_eq!(snmp.generic_trap, Some("linkUp"));
+        assert_eq!(snmp.specific_trap, Some(0));
+        assert_eq!(snmp.timestamp, Some(4200));
+        assert_eq!(snmp.request_id, None);
+        assert_eq!(snmp.variables[0].oid, "1.3.6.1.2.1.2.2.1.1.2");
+        assert_eq!(snmp.variables[0].value, "2");
+    }
+
+    #[test]
+    fn parses_a_get_bulk_request() {
+        let message = b"\x30\x26\x02\x01\x01\x04\x07private\
+            \xa5\x18\x02\x01\x07\x02\x01\x00\x02\x01\x0a\
+            \x30\x0d\x30\x0b\x06\x07\x2b\x06\x01\x02\x01\x02\x02\x05\x00";
+        let snmp = Snmp::parse(message).unwrap();
+        assert_eq!(snmp.pdu, "GetBulkRequest");
+        assert_eq!(snmp.community, "private");
+        assert_eq!(snmp.non_repeaters, Some(0));
+        assert_eq!(snmp.max_repetitions, Some(10));
+        assert_eq!(snmp.error_status, None);
+    }
+
+    #[test]
+    fn reads_long_form_lengths() {
+        for length in [&b"\x81\x29"[..], b"\x82\x00\x29", b"\x84\x00\x00\x00\x29"] {
+            let snmp = Snmp::parse(&with_length(length)).unwrap();
+            assert_eq!(snmp.request_id, Some(0x12345678));
+        }
+        // Indefinite lengths and lengths of more than four bytes
+        assert!(Snmp::parse(&with_length(b"\x80")).is_none());
+        assert!(Snmp::parse(&with_length(b"\x85\x00\x00\x00\x00\x29")).is_none());
+        // Lengths running past the buffer, or short of it
+        assert!(Snmp::parse(&with_length(b"\x81\x2a")).is_none());
+        assert!(Snmp::parse(&with_length(b"\x84\xff\xff\xff\xff")).is_none());
+        assert!(Snmp::parse(&with_length(b"\x81\x28")).is_none());
+        // The length bytes themselves cut off
+        assert!(Snmp::parse(b"\x30\x82\x00").is_none());
+
+        let mut reader = Ber(b"\x04\x81\x03abc\x05\x00");
+        assert_eq!(reader.next(), Some((TAG_OCTET_STRING, &b"abc"[..])));
+        assert_eq!(reader.next(), Some((TAG_NULL, &b""[..])));
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn rejects_truncated_and_trailing_bytes() {
+        for length in 0..GET_REQUEST.len() {
+            assert!(
+                Snmp::parse(&GET_REQUEST[..length]).is_none(),
+                "{} bytes",
+                length
+            );
+        }
+        let mut trailing = GET_REQUEST.to_vec();
+        trailing.push(0);
+        assert!(Snmp::parse(&trailing).is_none());
+        // SNMPv3
+        let mut v3 = GET_REQUEST.to_vec();
+        v3[4] = 3;
+        assert!(Snmp::parse(&v3).is_none());
+    }
+}
@@ -0,0 +1,131 @@
+// Simple Service Discovery Protocol of UPnP, HTTP requests and responses sent over UDP
+
+use std::fmt;
+
+use serde::Serialize;
+
+pub const SSDP_PORT: u16 = 1900;
+
+#[derive(Serialize)]
+pub struct Ssdp {
+    // M-SEARCH, NOTIFY or the response status
+    pub kind: String,
+    pub start_line: String,
+    // Headers in the order they were sent
+    pub headers: Vec<(String, String)>,
+}
+
+// Header names are case insensitive, these are the ones worth showing
+const INTERESTING_HEADERS: &[&str] = &[
+    "ST",
+    "NT",
+    "NTS",
+    "USN",
+    "LOCATION",
+    "SERVER",
+    "MAN",
+    "MX",
+    "CACHE-CONTROL",
+];
+
+// Whether the datagram starts like an SSDP message
+pub fn looks_like_ssdp(buffer: &[u8]) -> bool {
+    [b"M-SEARCH * ".as_slice(), b"NOTIFY * ", b"HTTP/1.1 "]
+        .iter()
+        .any(|start| buffer.starts_with(start))
+}
+
+impl Ssdp {
+    pub fn parse(buffer: &[u8]) -> Option<Self> {
+        if !looks_like_ssdp(buffer) {
+            return None;
+        }
+        let text = std::str::from_utf8(buffer).ok()?;
+        let mut lines = text.split("\r\n");
+        let start_line = lines.next()?.to_string();
+        let kind = match start_line.split(' ').next()? {
+            "HTTP/1.1" => format!("Response {}", start_line.split_once(' ')?.1),
+            method => method.to_string(),
+        };
+
+        let mut headers = Vec::new();
+        for line in lines.take_while(|line| !line.is_empty()) {
+            let (name, value) = line.split_once(':')?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+        Some(Ssdp {
+            kind,
+            start_line,
+            headers,
+        })
+    }
+
+    // Value of a header, names compared case insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl fmt::Display for Ssdp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "SSDP {}", self.kind)?;
+        for name in INTERESTING_HEADERS {
+            if let Some(value) = self.header(name) {
+                writeln!(f, "  {}: {}", name, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_search() {
+        let search = b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\
+            MAN: \"ssdp:discover\"\r\nMX: 2\r\nST: ssdp:all\r\n\r\n";
+        let ssdp = Ssdp::parse(search).unwrap();
+        assert_eq!(ssdp.kind, "M-SEARCH");
+        assert_eq!(ssdp.start_line, "M-SEARCH * HTTP/1.1");
+        assert_eq!(ssdp.headers.len(), 4);
+        assert_eq!(ssdp.header("host"), Some("239.255.255.250:1900"));
+        assert_eq!(ssdp.header("St"), Some("ssdp:all"));
+        assert_eq!(
+            ssdp.to_string(),
+            "SSDP M-SEARCH\n  ST: ssdp:all\n  MAN: \"ssdp:discover\"\n  MX: 2\n"
+        );
+    }
+
+    #[test]
+    fn parses_notifications_and_responses() {
+        let notify = b"NOTIFY * HTTP/1.1\r\nNT: upnp:rootdevice\r\nNTS: ssdp:alive\r\n\
+            Location: http://192.168.1.1:5000/rootDesc.xml\r\n\r\n";
+        let ssdp = Ssdp::parse(notify).unwrap();
+        assert_eq!(ssdp.kind, "NOTIFY");
+        assert_eq!(
+            ssdp.header("LOCATION"),
+            Some("http://192.168.1.1:5000/rootDesc.xml")
+        );
+
+        let response = b"HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=1800\r\n\
+            USN: uuid:1234::upnp:rootdevice\r\n\r\n";
+        let ssdp = Ssdp::parse(response).unwrap();
+        assert_eq!(ssdp.kind, "Response 200 OK");
+        assert_eq!(ssdp.header("usn"), Some("uuid:1234::upnp:rootdevice"));
+        assert_eq!(ssdp.header("SERVER"), None);
+    }
+
+    #[test]
+    fn rejects_other_datagrams() {
+        assert!(!looks_like_ssdp(b"GET / HTTP/1.1\r\n\r\n"));
+        assert!(Ssdp::parse(b"GET / HTTP/1.1\r\n\r\n").is_none());
+        // A header line without a colon
+        assert!(Ssdp::parse(b"NOTIFY * HTTP/1.1\r\nNT upnp-rootdevice\r\n\r\n").is_none());
+        assert!(Ssdp::parse(b"NOTIFY * HTTP/1.1\r\nNT: \xff\r\n\r\n").is_none());
+    }
+}
@@ -0,0 +1,6 @@
+#![no_main]
+
+use chapter_1_fuzz::PacketSpec;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|spec: PacketSpec| chapter_1_fuzz::round_trip(&spec));
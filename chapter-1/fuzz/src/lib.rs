@@ -0,0 +1,440 @@
+// Fuzz targets and property tests for the binary parsers of chapter 1: the header
+// constructors of the decoding tools, the service and QUIC decoders of the UDP tool, the
+// packet-kit parsers and dissectors, and the capture reader. Each check below is shared by a libFuzzer target of the same name,
+// the proptest suites and the corpus replay, so a crash found by one is caught by all.
+//
+//     cargo +nightly fuzz run tcp_header
+//
+// Inputs worth keeping, crashes included once fixed, go into corpus/<target>/, which
+// `cargo test` replays on stable.
+
+use std::io::Cursor;
+use std::net::{IpAddr, Ipv4Addr};
+
+use arbitrary::Arbitrary;
+use decoding_udp_packets::dns::{self, Dns};
+use decoding_udp_packets::netbios::NetbiosNs;
+use decoding_udp_packets::ntp::Ntp;
+use decoding_udp_packets::quic::QuicTracker;
+use decoding_udp_packets::services;
+use decoding_udp_packets::snmp::Snmp;
+use decoding_udp_packets::ssdp::Ssdp;
+use packet_kit::ipv4::{DONT_FRAGMENT, MORE_FRAGMENTS};
+use packet_kit::names::protocol_name;
+use packet_kit::pcap::{
+    LINKTYPE_ETHERNET, LINKTYPE_IPV4, LINKTYPE_IPV6, LINKTYPE_LINUX_SLL, LINKTYPE_NULL,
+    LINKTYPE_RAW,
+};
+use packet_kit::tcp::URG;
+use packet_kit::{
+    checksum, pseudo_header_checksum, IcmpBuilder, IcmpHeader, Ipv4Builder, Ipv4Header, PcapReader,
+    TcpBuilder, TcpHeader, UdpBuilder, UdpHeader, PROTOCOL_ICMP, PROTOCOL_TCP, PROTOCOL_UDP,
+};
+
+// Link types the dissectors are exercised with, the first byte of the input picks one
+const LINKTYPES: [u32; 6] = [
+    LINKTYPE_NULL,
+    LINKTYPE_ETHERNET,
+    LINKTYPE_RAW,
+    LINKTYPE_LINUX_SLL,
+    LINKTYPE_IPV4,
+    LINKTYPE_IPV6,
+];
+
+// Options of the IPv4 and TCP headers can't take more than 40 bytes
+const MAX_OPTIONS: usize = 40;
+// Keeps the total length within its 16 bits, whatever the headers take
+const MAX_PAYLOAD: usize = 65535 - 2 * (20 + MAX_OPTIONS);
+
+// The header and payload the IPv4 parser hands out lie within the buffer, the decoding
+// tools print that header rather than parsing their own
+pub fn ip_header(data: &[u8]) {
+    if let Some((header, payload)) = Ipv4Header::parse(data) {
+        assert!(header.header_length >= 20 && header.header_length <= data.len());
+        assert!(payload.len() <= data.len() - header.header_length);
+        assert_eq!(header.options.len(), header.header_length - 20);
+        protocol_name(header.protocol);
+    }
+}
+
+pub fn tcp_header(data: &[u8]) {
+    let tool = decoding_tcp_packets::Tcp::new(data);
+    assert_eq!(tool.is_some(), data.len() >= 20);
+    if let Some((header, payload)) = TcpHeader::parse(data) {
+        let tool = tool.unwrap();
+        assert_eq!(tool.source_port, header.source_port);
+        assert_eq!(tool.destination_port, header.destination_port);
+        assert_eq!(tool.sequence_number, header.sequence_number);
+        assert_eq!(tool.acknowledgment_number, header.acknowledgment_number);
+        assert_eq!(tool.data_offset as usize, header.data_offset);
+        assert_eq!(tool.flags, header.flags);
+        assert_eq!(tool.window_size, header.window_size);
+        assert_eq!(tool.checksum, header.checksum);
+        assert_eq!(tool.urgent_pointer, header.urgent_pointer);
+        assert_eq!(header.data_offset + payload.len(), data.len());
+    }
+}
+
+pub fn udp_header(data: &[u8]) {
+    let tool = decoding_udp_packets::Udp::new(data);
+    assert_eq!(tool.is_some(), data.len() >= 8);
+    if let Some((header, payload)) = UdpHeader::parse(data) {
+        let tool = tool.unwrap();
+        assert_eq!(tool.source_port, header.source_port);
+        assert_eq!(tool.destination_port, header.destination_port);
+        assert_eq!(tool.length, header.length);
+        assert_eq!(tool.checksum, header.checksum);
+        assert!(payload.len() <= data.len() - 8);
+    }
+}
+
+pub fn icmp_header(data: &[u8]) {
+    let tool = decoding_icmp_packets::Icmp::new(data);
+    assert_eq!(tool.is_some(), data.len() >= 8);
+    if let Some(tool) = &tool {
+        decoding_icmp_packets::icmp_type_name(tool.type_, tool.code);
+    }
+    if let Some((header, payload)) = IcmpHeader::parse(data) {
+        let tool = tool.unwrap();
+        assert_eq!(tool.type_, header.type_);
+        assert_eq!(tool.code, header.code);
+        assert_eq!(tool.sum, header.checksum);
+        assert_eq!(tool.id, header.id());
+        assert_eq!(tool.seq, header.sequence());
+        assert_eq!(payload.len(), data.len() - 8);
+    }
+}
+
+// Decodes the frame with every dissector, all the layers and fields must lie within it
+pub fn dissect(data: &[u8]) {
+    let Some((&selector, frame)) = data.split_first() else {
+        return;
+    };
+    let linktype = LINKTYPES[selector as usize % LINKTYPES.len()];
+    let packet = packet_kit::dissect::decode(linktype, frame);
+
+    assert!(packet.payload_offset + packet.payload_length <= frame.len());
+    for layer in packet.layers() {
+        assert!(layer.offset + layer.length <= frame.len());
+    }
+    for layer in packet.spans() {
+        for field in &layer.fields {
+            assert!(
+                field.offset + field.length <= frame.len(),
+                "{} ends past the frame",
+                field.name
+            );
+        }
+    }
+    packet.payload(frame);
+    packet.addresses();
+    packet.ports();
+    packet_kit::layout::byte_owners(frame.len(), &packet.spans());
+}
+
+// Reads every record of a pcap or pcapng capture until the end or the first error
+pub fn pcap(data: &[u8]) {
+    let Ok(mut capture) = PcapReader::new(Cursor::new(data)) else {
+        return;
+    };
+    while let Ok(Some(packet)) = capture.next_packet() {
+        assert!(packet.data.len() <= data.len());
+        packet.ipv4();
+    }
+}
+
+// Decodes the payload with the decoder the registry picks for its ports, then with each
+// decoder regardless of the ports. The first four bytes are the source and destination
+// ports.
+pub fn udp_services(data: &[u8]) {
+    let Some((ports, payload)) = data.split_first_chunk::<4>() else {
+        return;
+    };
+    let source_port = u16::from_be_bytes([ports[0], ports[1]]);
+    let destination_port = u16::from_be_bytes([ports[2], ports[3]]);
+    let packet = Ipv4Builder::new()
+        .src(Ipv4Addr::LOCALHOST)
+        .dst(Ipv4Addr::LOCALHOST)
+        .payload(UdpBuilder::new(source_port, destination_port).payload(truncated(payload)))
+        .build();
+    let decoded = services::decode(&packet);
+    // Tunnels over UDP may carry datagrams of their own, the outer one comes right after IP
+    let udp = decoded
+        .root
+        .as_ref()
+        .and_then(|ip| ip.inner.as_deref())
+        .expect("datagram isn't dissected");
+    assert_eq!(udp.ports, Some((source_port, destination_port)));
+    if let Some(service) = services::service(&decoded, &packet) {
+        assert_eq!(
+            Some(service.protocol),
+            decoded.innermost().map(|layer| layer.name)
+        );
+        service.to_string();
+    }
+
+    if let Some(dns) = Dns::parse(payload) {
+        dns.to_string();
+    }
+    if let Some((_, end)) = dns::read_name(payload, 0) {
+        assert!(end <= payload.len());
+    }
+    if let Some(nbns) = NetbiosNs::parse(payload) {
+        nbns.to_string();
+    }
+    if let Some(ntp) = Ntp::parse(payload) {
+        ntp.to_string();
+    }
+    if let Some(snmp) = Snmp::parse(payload) {
+        snmp.to_string();
+    }
+    if let Some(ssdp) = Ssdp::parse(payload) {
+        ssdp.to_string();
+    }
+}
+
+// Hands the datagrams to a single tracker, so that state carried from one to the next
+// gets exercised. Each datagram is prefixed with its length on 16 bits.
+pub fn quic(data: &[u8]) {
+    let mut tracker = QuicTracker::default();
+    let mut rest = data;
+    while let Some((length, tail)) = rest.split_first_chunk::<2>() {
+        let length = (u16::from_be_bytes(*length) as usize).min(tail.len());
+        let (datagram, tail) = tail.split_at(length);
+        tracker.dissect(datagram);
+        rest = tail;
+    }
+}
+
+// A packet the builders can produce, generated from the fuzzer input
+#[derive(Arbitrary, Clone, Debug)]
+pub struct PacketSpec {
+    pub src: [u8; 4],
+    pub dst: [u8; 4],
+    pub tos: u8,
+    pub id: u16,
+    pub ttl: u8,
+    pub dont_fragment: bool,
+    pub more_fragments: bool,
+    pub fragment_offset: u16,
+    pub options: Vec<u8>,
+    pub transport: TransportSpec,
+}
+
+#[derive(Arbitrary, Clone, Debug)]
+pub enum TransportSpec {
+    Tcp {
+        source_port: u16,
+        destination_port: u16,
+        sequence_number: u32,
+        acknowledgment_number: u32,
+        flags: u16,
+        window_size: u16,
+        urgent_pointer: u16,
+        options: Vec<u8>,
+        payload: Vec<u8>,
+    },
+    Udp {
+        source_port: u16,
+        destination_port: u16,
+        payload: Vec<u8>,
+    },
+    Icmp {
+        type_: u8,
+        code: u8,
+        rest: u32,
+        payload: Vec<u8>,
+    },
+}
+
+impl PacketSpec {
+    fn src(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.src)
+    }
+
+    fn dst(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.dst)
+    }
+
+    // The options as the builder pads them, cut to what the header can hold
+    fn padded_options(&self) -> Vec<u8> {
+        let mut options = self.options[..self.options.len().min(MAX_OPTIONS)].to_vec();
+        options.resize(options.len().div_ceil(4) * 4, 0);
+        options
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut builder = Ipv4Builder::new()
+            .src(self.src())
+            .dst(self.dst())
+            .tos(self.tos)
+            .id(self.id)
+            .ttl(self.ttl)
+            .fragment_offset(self.fragment_offset & 0x1fff)
+            .options(&self.padded_options());
+        if self.dont_fragment {
+            builder = builder.dont_fragment();
+        }
+        if self.more_fragments {
+            builder = builder.more_fragments();
+        }
+        match &self.transport {
+            TransportSpec::Tcp {
+                source_port,
+                destination_port,
+                sequence_number,
+                acknowledgment_number,
+                flags,
+                window_size,
+                urgent_pointer,
+                options,
+                payload,
+            } => builder
+                .payload(
+                    TcpBuilder::new(*source_port, *destination_port)
+                        .sequence_number(*sequence_number)
+                        .ack(*acknowledgment_number)
+                        .flags(flags & 0x1ff)
+                        .window_size(*window_size)
+                        .urg(*urgent_pointer)
+                        .raw_options(&options[..options.len().min(MAX_OPTIONS)])
+                        .payload(truncated(payload)),
+                )
+                .build(),
+            TransportSpec::Udp {
+                source_port,
+                destination_port,
+                payload,
+            } => builder
+                .payload(
+                    UdpBuilder::new(*source_port, *destination_port).payload(truncated(payload)),
+                )
+                .build(),
+            TransportSpec::Icmp {
+                type_,
+                code,
+                rest,
+                payload,
+            } => builder
+                .payload(
+                    IcmpBuilder::new(*type_, *code)
+                        .rest_of_header(*rest)
+                        .payload(truncated(payload)),
+                )
+                .build(),
+        }
+    }
+}
+
+fn truncated(payload: &[u8]) -> &[u8] {
+    &payload[..payload.len().min(MAX_PAYLOAD)]
+}
+
+// Builds the packet, parses it back with packet-kit and the decoding tools, and checks
+// that every field and checksum survived
+pub fn round_trip(spec: &PacketSpec) {
+    let packet = spec.build();
+
+    let (ip, segment) = Ipv4Header::parse(&packet).expect("built packet doesn't parse");
+    assert_eq!(ip.header_length, 20 + spec.padded_options().len());
+    assert_eq!(ip.total_length as usize, packet.len());
+    assert_eq!(ip.tos, spec.tos);
+    assert_eq!(ip.id, spec.id);
+    assert_eq!(ip.ttl, spec.ttl);
+    assert_eq!(
+        ip.flags,
+        (spec.dont_fragment as u8 * DONT_FRAGMENT) | (spec.more_fragments as u8 * MORE_FRAGMENTS)
+    );
+    assert_eq!(ip.fragment_offset, spec.fragment_offset & 0x1fff);
+    assert_eq!(ip.options, spec.padded_options());
+    assert_eq!((ip.src, ip.dst), (spec.src(), spec.dst()));
+    assert!(Ipv4Header::checksum_valid(&packet));
+    assert_eq!(segment.len(), packet.len() - ip.header_length);
+
+    let (src, dst) = (IpAddr::V4(spec.src()), IpAddr::V4(spec.dst()));
+    let ports = match &spec.transport {
+        TransportSpec::Tcp {
+            source_port,
+            destination_port,
+            sequence_number,
+            acknowledgment_number,
+            flags,
+            window_size,
+            urgent_pointer,
+            options,
+            payload,
+        } => {
+            assert_eq!(ip.protocol, PROTOCOL_TCP);
+            assert_eq!(pseudo_header_checksum(src, dst, PROTOCOL_TCP, segment), 0);
+            let (tcp, data) = TcpHeader::parse(segment).expect("built segment doesn't parse");
+            assert_eq!(tcp.source_port, *source_port);
+            assert_eq!(tcp.destination_port, *destination_port);
+            assert_eq!(tcp.sequence_number, *sequence_number);
+            assert_eq!(tcp.acknowledgment_number, *acknowledgment_number);
+            // Setting the urgent pointer raises URG
+            assert_eq!(tcp.flags, flags & 0x1ff | URG);
+            assert_eq!(tcp.window_size, *window_size);
+            assert_eq!(tcp.urgent_pointer, *urgent_pointer);
+            assert!(tcp
+                .options
+                .starts_with(&options[..options.len().min(MAX_OPTIONS)]));
+            assert_eq!(data, truncated(payload));
+
+            let tool = decoding_tcp_packets::Tcp::new(segment).unwrap();
+            assert_eq!(tool.data_offset as usize, tcp.data_offset);
+            assert_eq!(tool.flags, tcp.flags);
+            assert_eq!(tool.checksum, tcp.checksum);
+            Some((*source_port, *destination_port))
+        }
+        TransportSpec::Udp {
+            source_port,
+            destination_port,
+            payload,
+        } => {
+            assert_eq!(ip.protocol, PROTOCOL_UDP);
+            assert_eq!(pseudo_header_checksum(src, dst, PROTOCOL_UDP, segment), 0);
+            let (udp, data) = UdpHeader::parse(segment).expect("built datagram doesn't parse");
+            assert_eq!(udp.source_port, *source_port);
+            assert_eq!(udp.destination_port, *destination_port);
+            assert_eq!(udp.length as usize, segment.len());
+            assert_eq!(data, truncated(payload));
+
+            let tool = decoding_udp_packets::Udp::new(segment).unwrap();
+            assert_eq!(tool.length, udp.length);
+            assert_eq!(tool.checksum, udp.checksum);
+            Some((*source_port, *destination_port))
+        }
+        TransportSpec::Icmp {
+            type_,
+            code,
+            rest,
+            payload,
+        } => {
+            assert_eq!(ip.protocol, PROTOCOL_ICMP);
+            assert_eq!(checksum(segment), 0);
+            let (icmp, data) = IcmpHeader::parse(segment).expect("built message doesn't parse");
+            assert_eq!((icmp.type_, icmp.code), (*type_, *code));
+            assert_eq!(icmp.rest, rest.to_be_bytes());
+            assert_eq!(data, truncated(payload));
+
+            let tool = decoding_icmp_packets::Icmp::new(segment).unwrap();
+            assert_eq!(tool.id, (rest >> 16) as u16);
+            assert_eq!(tool.seq, *rest as u16);
+            None
+        }
+    };
+
+    // The dissectors see the same packet, down to the transport layer unless it's a
+    // later fragment
+    let decoded = packet_kit::dissect::decode(LINKTYPE_RAW, &packet);
+    let root = decoded.root.as_ref().expect("built packet isn't dissected");
+    assert_eq!(root.name, "IPv4");
+    assert_eq!(root.addresses, Some((src, dst)));
+    if ip.fragment_offset == 0 {
+        let transport = root
+            .inner
+            .as_ref()
+            .expect("transport layer isn't dissected");
+        assert_eq!(transport.ports, ports);
+    }
+}
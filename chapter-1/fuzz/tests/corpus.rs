@@ -0,0 +1,49 @@
+// Replays the checked-in corpus of every fuzz target, the inputs that once crashed a
+// parser included, so that stable `cargo test` catches a regression without a fuzzer
+
+use std::fs;
+use std::path::Path;
+
+use arbitrary::{Arbitrary, Unstructured};
+use chapter_1_fuzz::PacketSpec;
+
+// libfuzzer-sys builds the round trip input from the whole buffer the same way
+fn round_trip(data: &[u8]) {
+    if let Ok(spec) = PacketSpec::arbitrary_take_rest(Unstructured::new(data)) {
+        chapter_1_fuzz::round_trip(&spec);
+    }
+}
+
+// Runs the checks of a target on one input
+type Check = fn(&[u8]);
+
+const TARGETS: [(&str, Check); 9] = [
+    ("ip_header", chapter_1_fuzz::ip_header),
+    ("tcp_header", chapter_1_fuzz::tcp_header),
+    ("udp_header", chapter_1_fuzz::udp_header),
+    ("icmp_header", chapter_1_fuzz::icmp_header),
+    ("dissect", chapter_1_fuzz::dissect),
+    ("pcap", chapter_1_fuzz::pcap),
+    ("udp_services", chapter_1_fuzz::udp_services),
+    ("quic", chapter_1_fuzz::quic),
+    ("round_trip", round_trip),
+];
+
+#[test]
+fn corpus_replays_cleanly() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus");
+    for (target, check) in TARGETS {
+        let directory = corpus.join(target);
+        let entries = fs::read_dir(&directory)
+            .unwrap_or_else(|err| panic!("{}: {}", directory.display(), err));
+        let mut replayed = 0;
+        for entry in entries {
+            let path = entry.unwrap().path();
+            let data = fs::read(&path).unwrap();
+            eprintln!("{}", path.display());
+            check(&data);
+            replayed += 1;
+        }
+        assert!(replayed > 0, "the corpus of {} is empty", target);
+    }
+}
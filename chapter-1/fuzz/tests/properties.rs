@@ -0,0 +1,150 @@
+// Property tests over the same checks as the fuzz targets. Arbitrary bytes for the
+// parsers, bytes turned into packet descriptions for the round trip, so that proptest
+// shrinks a failure down to the smallest packet that shows it.
+
+use arbitrary::{Arbitrary, Unstructured};
+use chapter_1_fuzz::PacketSpec;
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+// The global header of a little-endian pcap capture of raw IPv4 packets
+const PCAP_HEADER: [u8; 24] = [
+    0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 101, 0, 0, 0,
+];
+
+// Ports the UDP tool has a decoder for
+const SERVICE_PORTS: [u16; 8] = [53, 123, 137, 161, 162, 443, 1900, 5353];
+
+// A pcapng block, padded to 4 bytes and framed by its length
+fn pcapng_block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let padded = body.len().next_multiple_of(4);
+    let length = (padded + 12) as u32;
+    let mut block = Vec::with_capacity(padded + 12);
+    block.extend(block_type.to_le_bytes());
+    block.extend(length.to_le_bytes());
+    block.extend(body);
+    block.resize(8 + padded, 0);
+    block.extend(length.to_le_bytes());
+    block
+}
+
+// The section header of a little-endian pcapng capture, version 1.0 and no length given
+fn pcapng_section() -> Vec<u8> {
+    let mut body = 0x1a2b3c4du32.to_le_bytes().to_vec();
+    body.extend([1, 0, 0, 0]);
+    body.extend((-1i64).to_le_bytes());
+    pcapng_block(0x0a0d0d0a, &body)
+}
+
+proptest! {
+    #[test]
+    fn ip_header_accepts_any_bytes(data in vec(any::<u8>(), 0..80)) {
+        chapter_1_fuzz::ip_header(&data);
+    }
+
+    #[test]
+    fn tcp_header_accepts_any_bytes(data in vec(any::<u8>(), 0..100)) {
+        chapter_1_fuzz::tcp_header(&data);
+    }
+
+    #[test]
+    fn udp_header_accepts_any_bytes(data in vec(any::<u8>(), 0..40)) {
+        chapter_1_fuzz::udp_header(&data);
+    }
+
+    #[test]
+    fn icmp_header_accepts_any_bytes(data in vec(any::<u8>(), 0..40)) {
+        chapter_1_fuzz::icmp_header(&data);
+    }
+
+    #[test]
+    fn dissectors_accept_any_frame(data in vec(any::<u8>(), 0..512)) {
+        chapter_1_fuzz::dissect(&data);
+    }
+
+    // Built packets behind random link-layer bytes reach deeper into the dissectors
+    #[test]
+    fn dissectors_accept_built_packets(
+        selector in any::<u8>(),
+        prefix in vec(any::<u8>(), 0..20),
+        bytes in vec(any::<u8>(), 0..256),
+    ) {
+        let spec = PacketSpec::arbitrary_take_rest(Unstructured::new(&bytes));
+        prop_assume!(spec.is_ok());
+        let mut data = vec![selector];
+        data.extend(prefix);
+        data.extend(spec.unwrap().build());
+        chapter_1_fuzz::dissect(&data);
+    }
+
+    #[test]
+    fn pcap_reader_accepts_any_records(records in vec(any::<u8>(), 0..512)) {
+        let mut data = PCAP_HEADER.to_vec();
+        data.extend(records);
+        chapter_1_fuzz::pcap(&data);
+    }
+
+    #[test]
+    fn pcapng_reader_accepts_any_blocks(blocks in vec(any::<u8>(), 0..512)) {
+        let mut data = pcapng_section();
+        data.extend(blocks);
+        chapter_1_fuzz::pcap(&data);
+    }
+
+    // Well-framed interface and packet blocks, as the option parser and the packet blocks
+    // only get reached once the framing is right. Options are the end marker, the name or
+    // the timestamp resolution, with a declared length that may not match their value.
+    #[test]
+    fn pcapng_reader_accepts_any_interface_options(
+        options in vec(
+            (prop_oneof![Just(0u16), Just(2), Just(9), any::<u16>()], 0u16..4, vec(any::<u8>(), 0..6)),
+            0..4,
+        ),
+        packet in vec(any::<u8>(), 0..128),
+    ) {
+        let mut interface = vec![1, 0, 0, 0, 0xff, 0xff, 0, 0];
+        for (code, length, value) in options {
+            interface.extend(code.to_le_bytes());
+            interface.extend(length.to_le_bytes());
+            interface.extend(value);
+        }
+        let mut data = pcapng_section();
+        data.extend(pcapng_block(1, &interface));
+        data.extend(pcapng_block(6, &packet));
+        chapter_1_fuzz::pcap(&data);
+    }
+
+    #[test]
+    fn service_decoders_accept_any_payload(
+        source_port in proptest::sample::select(&SERVICE_PORTS[..]),
+        destination_port in proptest::sample::select(&SERVICE_PORTS[..]),
+        payload in vec(any::<u8>(), 0..256),
+    ) {
+        let mut data = source_port.to_be_bytes().to_vec();
+        data.extend(destination_port.to_be_bytes());
+        data.extend(payload);
+        chapter_1_fuzz::udp_services(&data);
+    }
+
+    // Long headers of QUIC v1, the rest left to chance
+    #[test]
+    fn quic_tracker_accepts_any_datagrams(
+        datagrams in vec((any::<u8>(), vec(any::<u8>(), 0..200)), 1..4),
+    ) {
+        let mut data = Vec::new();
+        for (first, rest) in datagrams {
+            let mut datagram = vec![first | 0xc0, 0, 0, 0, 1];
+            datagram.extend(rest);
+            data.extend((datagram.len() as u16).to_be_bytes());
+            data.extend(datagram);
+        }
+        chapter_1_fuzz::quic(&data);
+    }
+
+    #[test]
+    fn built_packets_survive_a_round_trip(bytes in vec(any::<u8>(), 0..512)) {
+        let spec = PacketSpec::arbitrary_take_rest(Unstructured::new(&bytes));
+        prop_assume!(spec.is_ok());
+        chapter_1_fuzz::round_trip(&spec.unwrap());
+    }
+}
@@ -0,0 +1,109 @@
+// Integration test harness for the raw-socket tools of chapter 1.
+//
+// Every test gets two fresh network namespaces joined by a veth pair, a client and a
+// server, so tools can sniff, scan and ping without touching the host network:
+//
+//     let Some(harness) = Harness::new() else { return };
+//     let _listener = responders::tcp_listener(&harness.server, harness.server_socket(8080))?;
+//     let mut sniffer = Tool::start(&harness.server, "decoding-tcp-packets", &["--output", "ndjson"])?;
+//
+// Namespaces need root or CAP_NET_ADMIN. Without them the tests report themselves as
+// skipped and pass, unless NETNS_HARNESS_REQUIRED is set, as it should be on CI.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub mod namespace;
+pub mod responders;
+pub mod tools;
+
+pub use namespace::Namespace;
+pub use responders::Responder;
+pub use tools::Tool;
+
+pub const CLIENT_ADDRESS: Ipv4Addr = Ipv4Addr::new(10, 200, 0, 1);
+pub const SERVER_ADDRESS: Ipv4Addr = Ipv4Addr::new(10, 200, 0, 2);
+// An address of the link nobody answers for
+pub const UNUSED_ADDRESS: Ipv4Addr = Ipv4Addr::new(10, 200, 0, 3);
+const PREFIX_LENGTH: u8 = 24;
+
+// Set to fail the tests instead of skipping them when namespaces can't be created
+const REQUIRED_VARIABLE: &str = "NETNS_HARNESS_REQUIRED";
+
+// Harnesses created by this process, tests run in parallel
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Harness {
+    pub client: Namespace,
+    pub server: Namespace,
+    // Interface names of the veth pair, as seen inside each namespace
+    pub client_link: String,
+    pub server_link: String,
+}
+
+impl Harness {
+    // Returns None, after saying why, when the namespaces can't be created
+    pub fn new() -> Option<Self> {
+        match Harness::create() {
+            Ok(harness) => Some(harness),
+            Err(err) if std::env::var_os(REQUIRED_VARIABLE).is_some() => {
+                panic!("Can't create the test namespaces: {}", err)
+            }
+            Err(err) => {
+                eprintln!(
+                    "Skipping, the test namespaces need root or CAP_NET_ADMIN: {}",
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    fn create() -> io::Result<Self> {
+        // Names stay unique across parallel tests and within the 15 bytes of an interface name
+        let id = format!(
+            "nh{}x{}",
+            std::process::id(),
+            CREATED.fetch_add(1, Ordering::Relaxed)
+        );
+        let client = Namespace::create(&format!("{}c", id))?;
+        let server = Namespace::create(&format!("{}s", id))?;
+        let client_link = format!("{}c0", id);
+        let server_link = format!("{}s0", id);
+
+        namespace::ip(&[
+            "link",
+            "add",
+            &client_link,
+            "netns",
+            &client.name,
+            "type",
+            "veth",
+            "peer",
+            "name",
+            &server_link,
+            "netns",
+            &server.name,
+        ])?;
+        for (namespace, link, address) in [
+            (&client, &client_link, CLIENT_ADDRESS),
+            (&server, &server_link, SERVER_ADDRESS),
+        ] {
+            let address = format!("{}/{}", address, PREFIX_LENGTH);
+            namespace.ip(&["addr", "add", &address, "dev", link])?;
+            namespace.ip(&["link", "set", link, "up"])?;
+        }
+
+        Ok(Harness {
+            client,
+            server,
+            client_link,
+            server_link,
+        })
+    }
+
+    pub fn server_socket(&self, port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(SERVER_ADDRESS), port)
+    }
+}
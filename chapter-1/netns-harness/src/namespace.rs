@@ -0,0 +1,141 @@
+// Network namespaces managed with iproute2, named so that `ip netns` lists them while a
+// test runs. Deleting a namespace takes its end of the veth pair, and the peer, with it.
+
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::thread;
+
+// Where `ip netns add` mounts the namespaces
+const NETNS_DIR: &str = "/run/netns";
+
+// capset(2) header and data, version 3 takes two words per set
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+// Runs `ip` with the arguments, failing with what it printed on error
+pub fn ip(args: &[&str]) -> io::Result<()> {
+    let output = Command::new("ip")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(io::Error::other(format!(
+        "ip {}: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    )))
+}
+
+pub struct Namespace {
+    pub name: String,
+}
+
+impl Namespace {
+    // Creates the namespace with its loopback interface up
+    pub fn create(name: &str) -> io::Result<Self> {
+        ip(&["netns", "add", name])?;
+        let namespace = Namespace {
+            name: name.to_string(),
+        };
+        namespace.ip(&["link", "set", "lo", "up"])?;
+        Ok(namespace)
+    }
+
+    // Runs `ip` inside the namespace
+    pub fn ip(&self, args: &[&str]) -> io::Result<()> {
+        let mut full = vec!["-n", self.name.as_str()];
+        full.extend_from_slice(args);
+        ip(&full)
+    }
+
+    // Runs the closure on a thread that joined the namespace, the calling thread stays
+    // where it is. Sockets keep the namespace they were created in, so a socket opened
+    // here can be handed back and used from any thread.
+    pub fn run<T, F>(&self, f: F) -> io::Result<T>
+    where
+        F: FnOnce() -> io::Result<T> + Send,
+        T: Send,
+    {
+        let file = File::open(format!("{}/{}", NETNS_DIR, self.name))?;
+        thread::scope(|scope| {
+            scope
+                .spawn(move || {
+                    if unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    f()
+                })
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("namespace thread panicked")))
+        })
+    }
+
+    // Sets a sysctl of the namespace, such as net.ipv4.icmp_echo_ignore_all. The net
+    // entries of /proc/sys belong to the namespace of whoever opens them.
+    pub fn sysctl(&self, key: &str, value: &str) -> io::Result<()> {
+        let path = format!("/proc/sys/{}", key.replace('.', "/"));
+        self.run(|| fs::write(&path, value))
+    }
+
+    // A command that runs inside the namespace, `ip netns exec` replaces itself with it
+    pub fn command(&self, program: impl AsRef<OsStr>) -> Command {
+        let mut command = Command::new("ip");
+        command.args(["netns", "exec", &self.name]).arg(program);
+        command
+    }
+
+    // A command that runs inside the namespace without a single capability, as a user
+    // would run it. The child joins the namespace itself, `ip netns exec` would need the
+    // capabilities it is meant to go without.
+    pub fn unprivileged_command(&self, program: impl AsRef<OsStr>) -> io::Result<Command> {
+        let file = File::open(format!("{}/{}", NETNS_DIR, self.name))?;
+        let mut command = Command::new(program);
+        unsafe {
+            // Only system calls between fork and exec, nothing that allocates
+            command.pre_exec(move || {
+                let header = CapUserHeader {
+                    version: LINUX_CAPABILITY_VERSION_3,
+                    pid: 0,
+                };
+                let data = [CapUserData {
+                    effective: 0,
+                    permitted: 0,
+                    inheritable: 0,
+                }; 2];
+                if libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) != 0
+                    || libc::syscall(libc::SYS_capset, &header, data.as_ptr()) != 0
+                    || libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(command)
+    }
+}
+
+impl Drop for Namespace {
+    fn drop(&mut self) {
+        let _ = ip(&["netns", "delete", &self.name]);
+    }
+}
@@ -0,0 +1,179 @@
+// Small servers the tools are pointed at. Each one binds its socket inside a namespace
+// before returning, so the traffic of a test never races the responder, then serves
+// from a thread of the test process until dropped.
+
+use std::io::{self, Write};
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use packet_kit::{IcmpBuilder, IcmpHeader, Ipv4Header, Layer};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::namespace::Namespace;
+
+// How often the serving threads look for a stop request
+const POLL: Duration = Duration::from_millis(100);
+
+// What the TCP listener sends to every connection before closing it
+pub const TCP_BANNER: &[u8] = b"netns-harness\r\n";
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+// A serving thread, stopped and joined when dropped
+pub struct Responder {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Responder {
+    fn spawn<F>(serve: F) -> Self
+    where
+        F: FnOnce(&AtomicBool) -> io::Result<()> + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let stop = stop.clone();
+            move || serve(&stop)
+        });
+        Responder {
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    // Stops serving and returns the error that ended the thread early, if any
+    pub fn stop(mut self) -> io::Result<()> {
+        self.join()
+    }
+
+    fn join(&mut self) -> io::Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(io::Error::other("responder thread panicked")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Responder {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+fn timed_out(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+// Accepts connections and greets each one with the banner before closing it
+pub fn tcp_listener(namespace: &Namespace, address: SocketAddr) -> io::Result<Responder> {
+    let listener = namespace.run(|| TcpListener::bind(address))?;
+    listener.set_nonblocking(true)?;
+    Ok(Responder::spawn(move |stop| {
+        while !stop.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((mut stream, _)) => {
+                    // The peer may be a scanner that resets the connection at once
+                    let _ = stream.write_all(TCP_BANNER);
+                }
+                Err(ref err) if timed_out(err) => thread::sleep(POLL),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }))
+}
+
+// Sends every datagram back to where it came from
+pub fn udp_echo(namespace: &Namespace, address: SocketAddr) -> io::Result<Responder> {
+    let socket = namespace.run(|| UdpSocket::bind(address))?;
+    socket.set_read_timeout(Some(POLL))?;
+    Ok(Responder::spawn(move |stop| {
+        let mut buffer = [0u8; 65535];
+        while !stop.load(Ordering::Relaxed) {
+            match socket.recv_from(&mut buffer) {
+                Ok((length, peer)) => {
+                    socket.send_to(&buffer[..length], peer)?;
+                }
+                Err(ref err) if timed_out(err) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }))
+}
+
+// Answers ICMP echo requests from a raw socket. The kernel of the namespace is told to
+// ignore them, so every reply the tools see was built here.
+pub fn icmp_echo(namespace: &Namespace) -> io::Result<Responder> {
+    namespace.sysctl("net.ipv4.icmp_echo_ignore_all", "1")?;
+    let socket = namespace.run(|| Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4)))?;
+    socket.set_read_timeout(Some(POLL))?;
+    Ok(Responder::spawn(move |stop| {
+        let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+        while !stop.load(Ordering::Relaxed) {
+            let length = match socket.recv_from(&mut buffer) {
+                Ok((length, _)) => length,
+                Err(ref err) if timed_out(err) => continue,
+                Err(err) => return Err(err),
+            };
+            let packet: &[u8] =
+                unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, length) };
+            let Some((ip, message)) = Ipv4Header::parse(packet) else {
+                continue;
+            };
+            let Some((icmp, body)) = IcmpHeader::parse(message) else {
+                continue;
+            };
+            if icmp.type_ != ICMP_ECHO_REQUEST || icmp.code != 0 {
+                continue;
+            }
+            let reply = IcmpBuilder::echo_reply(icmp.id(), icmp.sequence())
+                .payload(body)
+                .build(IpAddr::V4(ip.dst), IpAddr::V4(ip.src));
+            socket.send_to(&reply, &SocketAddr::new(IpAddr::V4(ip.src), 0).into())?;
+        }
+        Ok(())
+    }))
+}
+
+// Sends an echo request and waits for the matching reply, from the namespace the
+// calling thread is in. Returns whether a reply came back in time.
+pub fn ping(target: Ipv4Addr, id: u16, sequence: u16, timeout: Duration) -> io::Result<bool> {
+    let socket = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?;
+    let request = IcmpBuilder::echo_request(id, sequence)
+        .payload(b"netns-harness")
+        .build(IpAddr::V4(Ipv4Addr::UNSPECIFIED), IpAddr::V4(target));
+    socket.send_to(&request, &SocketAddr::new(IpAddr::V4(target), 0).into())?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        socket.set_read_timeout(Some(left.max(Duration::from_millis(1))))?;
+        let length = match socket.recv_from(&mut buffer) {
+            Ok((length, _)) => length,
+            Err(ref err) if timed_out(err) => break,
+            Err(err) => return Err(err),
+        };
+        let packet: &[u8] =
+            unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, length) };
+        let reply = Ipv4Header::parse(packet)
+            .filter(|(ip, _)| ip.src == target)
+            .and_then(|(_, message)| IcmpHeader::parse(message));
+        if let Some((icmp, _)) = reply {
+            if icmp.type_ == ICMP_ECHO_REPLY && icmp.id() == id && icmp.sequence() == sequence {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
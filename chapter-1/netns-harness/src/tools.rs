@@ -0,0 +1,176 @@
+// The chapter-1 tools run as child processes inside a namespace. Every crate builds into
+// its own target directory, the harness builds the ones a test needs on first use.
+
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::namespace::Namespace;
+
+// How often `expect` generates traffic while waiting
+const POKE_INTERVAL: Duration = Duration::from_millis(200);
+
+// Crates already built by this test run
+static BUILT: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+// Directory holding the crates of the chapter
+fn chapter() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap()
+}
+
+// Builds the crate with the cargo running the tests and returns its binary
+pub fn binary(name: &str) -> io::Result<PathBuf> {
+    let manifest = chapter().join(name).join("Cargo.toml");
+    let target = match std::env::var_os("CARGO_TARGET_DIR") {
+        Some(target) => PathBuf::from(target),
+        None => chapter().join(name).join("target"),
+    };
+
+    let mut built = BUILT.lock().unwrap();
+    let built = built.get_or_insert_with(HashSet::new);
+    if !built.contains(name) {
+        let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+        let status = std::process::Command::new(cargo)
+            .args(["build", "--quiet", "--manifest-path"])
+            .arg(&manifest)
+            .status()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("building {} failed", name)));
+        }
+        built.insert(name.to_string());
+    }
+    Ok(target.join("debug").join(name))
+}
+
+// Forwards the lines of a pipe, stdout and stderr share one channel
+fn forward(pipe: impl Read + Send + 'static, lines: Sender<String>) {
+    thread::spawn(move || {
+        for line in BufReader::new(pipe).lines() {
+            let Ok(line) = line else { break };
+            if lines.send(line).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// A running tool, killed when dropped
+pub struct Tool {
+    name: String,
+    child: Child,
+    lines: Receiver<String>,
+    // Every line printed so far, for the failure messages
+    pub output: Vec<String>,
+}
+
+impl Tool {
+    pub fn start(namespace: &Namespace, name: &str, args: &[&str]) -> io::Result<Self> {
+        Tool::spawn(name, namespace.command(binary(name)?), args)
+    }
+
+    // Starts the tool without capabilities, to check how it copes without privileges
+    pub fn start_unprivileged(
+        namespace: &Namespace,
+        name: &str,
+        args: &[&str],
+    ) -> io::Result<Self> {
+        Tool::spawn(name, namespace.unprivileged_command(binary(name)?)?, args)
+    }
+
+    fn spawn(name: &str, mut command: Command, args: &[&str]) -> io::Result<Self> {
+        let mut child = command
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let (sender, lines) = mpsc::channel();
+        forward(child.stdout.take().unwrap(), sender.clone());
+        forward(child.stderr.take().unwrap(), sender);
+        Ok(Tool {
+            name: name.to_string(),
+            child,
+            lines,
+            output: Vec::new(),
+        })
+    }
+
+    fn failure(&self, reason: &str) -> String {
+        format!(
+            "{} {}, it printed:\n{}",
+            self.name,
+            reason,
+            self.output.join("\n")
+        )
+    }
+
+    // Waits for a line the predicate accepts, calling `poke` every so often to send the
+    // traffic the tool should see. Sniffers give no sign of being ready, so the traffic
+    // is repeated until it shows up.
+    pub fn expect(
+        &mut self,
+        timeout: Duration,
+        mut poke: impl FnMut(),
+        matches: impl Fn(&str) -> bool,
+    ) -> Result<String, String> {
+        let deadline = Instant::now() + timeout;
+        let mut next_poke = Instant::now();
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(self.failure("never printed the expected line"));
+            }
+            if now >= next_poke {
+                poke();
+                next_poke = Instant::now() + POKE_INTERVAL;
+            }
+            let wait = next_poke
+                .min(deadline)
+                .saturating_duration_since(Instant::now());
+            match self.lines.recv_timeout(wait) {
+                Ok(line) => {
+                    self.output.push(line.clone());
+                    if matches(&line) {
+                        return Ok(line);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(self.failure("exited before printing the expected line"))
+                }
+            }
+        }
+    }
+
+    // Waits for the tool to exit on its own and returns everything it printed
+    pub fn finish(mut self, timeout: Duration) -> Result<Vec<String>, String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            match self.lines.recv_timeout(wait) {
+                Ok(line) => self.output.push(line),
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(self.failure("didn't exit in time"));
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        match self.child.wait() {
+            Ok(status) if status.success() => Ok(std::mem::take(&mut self.output)),
+            Ok(status) => Err(self.failure(&format!("exited with {}", status))),
+            Err(err) => Err(self.failure(&err.to_string())),
+        }
+    }
+}
+
+impl Drop for Tool {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,110 @@
+// The tools run without capabilities, as a user would start them. Sniffers must say how to
+// get the privileges they lack, the scanner falls back to what ordinary sockets can do.
+
+use std::fs;
+use std::time::Duration;
+
+use netns_harness::{responders, Harness, Tool, SERVER_ADDRESS, UNUSED_ADDRESS};
+use serde_json::Value;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+#[test]
+fn sniffer_explains_the_missing_capability() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let mut sniffer =
+        Tool::start_unprivileged(&harness.server, "decoding-tcp-packets", &[]).unwrap();
+    sniffer
+        .expect(
+            TIMEOUT,
+            || {},
+            |line| line.contains("setcap cap_net_raw+ep"),
+        )
+        .unwrap();
+    sniffer
+        .expect(TIMEOUT, || {}, |line| line.contains("-r <capture.pcap>"))
+        .unwrap();
+}
+
+#[test]
+fn syn_scan_falls_back_to_a_connect_scan() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let _listener = responders::tcp_listener(&harness.server, harness.server_socket(8080)).unwrap();
+
+    let report = std::env::temp_dir().join(format!("{}.jsonl", harness.client.name));
+    let output = Tool::start_unprivileged(
+        &harness.client,
+        "syn-flood-port-scanning",
+        &[
+            "-T4",
+            "-oJ",
+            report.to_str().unwrap(),
+            "-sS",
+            &SERVER_ADDRESS.to_string(),
+            "8080,8081",
+        ],
+    )
+    .unwrap()
+    .finish(TIMEOUT)
+    .unwrap();
+    let records: Vec<Value> = fs::read_to_string(&report)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let _ = fs::remove_file(&report);
+
+    assert!(
+        output
+            .iter()
+            .any(|line| line.contains("running a connect scan instead")),
+        "{}",
+        output.join("\n")
+    );
+    let state = |port: u16| {
+        records
+            .iter()
+            .find(|record| record["port"] == port)
+            .map(|record| record["state"].clone())
+    };
+    assert_eq!(state(8080), Some("open".into()), "{:?}", records);
+    assert_eq!(state(8081), Some("closed".into()), "{:?}", records);
+}
+
+#[test]
+fn ping_sweep_uses_datagram_sockets() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let _echo = responders::icmp_echo(&harness.server).unwrap();
+    // Ping sockets are open to every group, as distributions set them up
+    harness
+        .client
+        .sysctl("net.ipv4.ping_group_range", "0 2147483647")
+        .unwrap();
+
+    let output = Tool::start_unprivileged(
+        &harness.client,
+        "syn-flood-port-scanning",
+        &[
+            "-T4",
+            "-sn",
+            &SERVER_ADDRESS.to_string(),
+            &UNUSED_ADDRESS.to_string(),
+        ],
+    )
+    .unwrap()
+    .finish(TIMEOUT)
+    .unwrap();
+    let up = format!("Host {} is up", SERVER_ADDRESS);
+    assert!(
+        output.iter().any(|line| line.starts_with(&up)),
+        "{}",
+        output.join("\n")
+    );
+    assert!(output.iter().any(|line| line == "1 of 2 hosts up"));
+}
@@ -0,0 +1,78 @@
+// The responders answer across the veth pair, the other tests rely on them
+
+use std::io::Read;
+use std::net::{TcpStream, UdpSocket};
+use std::time::Duration;
+
+use netns_harness::responders::{self, TCP_BANNER};
+use netns_harness::{Harness, SERVER_ADDRESS, UNUSED_ADDRESS};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[test]
+fn tcp_listener_sends_banner() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let address = harness.server_socket(8080);
+    let _listener = responders::tcp_listener(&harness.server, address).unwrap();
+
+    let banner = harness
+        .client
+        .run(|| {
+            let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            let mut banner = Vec::new();
+            stream.read_to_end(&mut banner)?;
+            Ok(banner)
+        })
+        .unwrap();
+    assert_eq!(banner, TCP_BANNER);
+}
+
+#[test]
+fn udp_echo_returns_datagrams() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let address = harness.server_socket(9000);
+    let _echo = responders::udp_echo(&harness.server, address).unwrap();
+
+    let reply = harness
+        .client
+        .run(|| {
+            let socket = UdpSocket::bind("0.0.0.0:0")?;
+            socket.set_read_timeout(Some(TIMEOUT))?;
+            socket.send_to(b"netns-harness", address)?;
+            let mut buffer = [0u8; 64];
+            let (length, peer) = socket.recv_from(&mut buffer)?;
+            assert_eq!(peer, address);
+            Ok(buffer[..length].to_vec())
+        })
+        .unwrap();
+    assert_eq!(reply, b"netns-harness");
+}
+
+#[test]
+fn icmp_echo_replies_in_place_of_the_kernel() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    // The kernel is told to stay quiet, nothing answers before the responder starts
+    harness
+        .server
+        .sysctl("net.ipv4.icmp_echo_ignore_all", "1")
+        .unwrap();
+    let ping = |target, sequence| {
+        harness
+            .client
+            .run(move || responders::ping(target, 0x4e48, sequence, TIMEOUT))
+            .unwrap()
+    };
+    assert!(!ping(SERVER_ADDRESS, 1));
+
+    let echo = responders::icmp_echo(&harness.server).unwrap();
+    assert!(ping(SERVER_ADDRESS, 2));
+    assert!(!ping(UNUSED_ADDRESS, 3));
+    echo.stop().unwrap();
+}
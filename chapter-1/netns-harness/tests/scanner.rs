@@ -0,0 +1,126 @@
+// The scanner runs in the client namespace against the responders of the server, each
+// scan type is checked on an open port and on one nobody listens on
+
+use std::fs;
+use std::time::Duration;
+
+use netns_harness::{responders, Harness, Tool, SERVER_ADDRESS, UNUSED_ADDRESS};
+use serde_json::Value;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+// Runs a scan to the end and returns what it printed and the records of its JSON report
+fn scan(harness: &Harness, args: &[&str]) -> (Vec<String>, Vec<Value>) {
+    let report = std::env::temp_dir().join(format!("{}.jsonl", harness.client.name));
+    let report_path = report.to_str().unwrap();
+    let mut full = vec![
+        "-e",
+        harness.client_link.as_str(),
+        "-T4",
+        "-oJ",
+        report_path,
+    ];
+    full.extend_from_slice(args);
+    let output = Tool::start(&harness.client, "syn-flood-port-scanning", &full)
+        .unwrap()
+        .finish(TIMEOUT)
+        .unwrap();
+
+    let records = fs::read_to_string(&report)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let _ = fs::remove_file(&report);
+    (output, records)
+}
+
+fn record(records: &[Value], port: u16) -> &Value {
+    records
+        .iter()
+        .find(|record| record["port"] == port)
+        .unwrap_or_else(|| panic!("no record for port {} in {:?}", port, records))
+}
+
+#[test]
+fn connect_scan_finds_the_listener() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let _listener = responders::tcp_listener(&harness.server, harness.server_socket(8080)).unwrap();
+
+    let target = SERVER_ADDRESS.to_string();
+    let (output, records) = scan(&harness, &[&target, "8080,8081"]);
+    assert!(
+        output
+            .iter()
+            .any(|line| line.starts_with("Port 8080/tcp open")),
+        "{}",
+        output.join("\n")
+    );
+    assert_eq!(record(&records, 8080)["state"], "open");
+    assert_eq!(record(&records, 8081)["state"], "closed");
+    assert_eq!(record(&records, 8081)["reason"], "conn-refused");
+    assert_eq!(record(&records, 8080)["host"], target);
+}
+
+#[test]
+fn syn_scan_finds_the_listener() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let _listener = responders::tcp_listener(&harness.server, harness.server_socket(8080)).unwrap();
+
+    let target = SERVER_ADDRESS.to_string();
+    let (output, records) = scan(&harness, &["-sS", &target, "8080,8081"]);
+    assert_eq!(
+        record(&records, 8080)["state"],
+        "open",
+        "{}",
+        output.join("\n")
+    );
+    assert_eq!(record(&records, 8081)["state"], "closed");
+}
+
+#[test]
+fn udp_scan_finds_the_echo() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let _echo = responders::udp_echo(&harness.server, harness.server_socket(9000)).unwrap();
+
+    let target = SERVER_ADDRESS.to_string();
+    let (output, records) = scan(&harness, &["-sU", &target, "9000,9001"]);
+    assert_eq!(
+        record(&records, 9000)["state"],
+        "open",
+        "{}",
+        output.join("\n")
+    );
+    // The kernel of the server answers with a port unreachable
+    assert_eq!(record(&records, 9001)["state"], "closed");
+}
+
+#[test]
+fn ping_sweep_finds_the_responder() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let _echo = responders::icmp_echo(&harness.server).unwrap();
+
+    let (output, _) = scan(
+        &harness,
+        &[
+            "-sn",
+            &SERVER_ADDRESS.to_string(),
+            &UNUSED_ADDRESS.to_string(),
+        ],
+    );
+    let up = format!("Host {} is up", SERVER_ADDRESS);
+    assert!(
+        output.iter().any(|line| line.starts_with(&up)),
+        "{}",
+        output.join("\n")
+    );
+    assert!(output.iter().any(|line| line == "1 of 2 hosts up"));
+}
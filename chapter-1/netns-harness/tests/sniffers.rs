@@ -0,0 +1,261 @@
+// The decoding tools and the live monitors run in the server namespace, the client
+// sends them traffic until they print what it should decode to
+
+use std::fs;
+use std::io::Read;
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+use netns_harness::{responders, Harness, Tool, CLIENT_ADDRESS, SERVER_ADDRESS};
+use serde_json::Value;
+
+const TIMEOUT: Duration = Duration::from_secs(20);
+const PAYLOAD: &[u8] = b"netns-harness";
+
+fn connect(harness: &Harness, address: SocketAddr) {
+    let _ = harness.client.run(|| {
+        let mut stream = TcpStream::connect_timeout(&address, Duration::from_secs(1))?;
+        stream.read_to_end(&mut Vec::new())
+    });
+}
+
+fn send_datagram(harness: &Harness, address: SocketAddr) {
+    let _ = harness.client.run(|| {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.send_to(PAYLOAD, address)
+    });
+}
+
+fn ping(harness: &Harness) {
+    let _ = harness
+        .client
+        .run(|| responders::ping(SERVER_ADDRESS, 0x4e48, 1, Duration::from_millis(100)));
+}
+
+fn json(line: &str) -> Value {
+    serde_json::from_str(line).unwrap_or_else(|err| panic!("{}: {}", err, line))
+}
+
+#[test]
+fn decoding_tcp_packets_decodes_segments() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let address = harness.server_socket(8080);
+    let _listener = responders::tcp_listener(&harness.server, address).unwrap();
+    let mut sniffer = Tool::start(
+        &harness.server,
+        "decoding-tcp-packets",
+        &["--output", "ndjson"],
+    )
+    .unwrap();
+
+    let line = sniffer
+        .expect(
+            TIMEOUT,
+            || connect(&harness, address),
+            |line| line.contains("\"destination_port\":8080"),
+        )
+        .unwrap();
+    let record = json(&line);
+    assert_eq!(record["ip"]["version"], 4);
+    assert_eq!(record["ip"]["protocol"], 6);
+    assert_eq!(record["ip"]["src"], CLIENT_ADDRESS.to_string());
+    assert_eq!(record["ip"]["dst"], SERVER_ADDRESS.to_string());
+    assert_eq!(record["tcp"]["destination_port"], 8080);
+    assert_eq!(record["src_geoip"]["label"], "private");
+}
+
+#[test]
+fn decoding_udp_packets_decodes_datagrams() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let address = harness.server_socket(9000);
+    let _echo = responders::udp_echo(&harness.server, address).unwrap();
+    let mut sniffer = Tool::start(
+        &harness.server,
+        "decoding-udp-packets",
+        &["--output", "ndjson"],
+    )
+    .unwrap();
+
+    let line = sniffer
+        .expect(
+            TIMEOUT,
+            || send_datagram(&harness, address),
+            |line| line.contains("\"destination_port\":9000"),
+        )
+        .unwrap();
+    let record = json(&line);
+    assert_eq!(record["ip"]["protocol"], 17);
+    assert_eq!(record["ip"]["src"], CLIENT_ADDRESS.to_string());
+    assert_eq!(record["udp"]["length"], 8 + PAYLOAD.len());
+    assert_eq!(record["payload_length"], PAYLOAD.len());
+}
+
+#[test]
+fn decoding_icmp_packets_decodes_echo_requests() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let _echo = responders::icmp_echo(&harness.server).unwrap();
+    let mut sniffer = Tool::start(
+        &harness.server,
+        "decoding-icmp-packets",
+        &["--output", "ndjson"],
+    )
+    .unwrap();
+
+    let line = sniffer
+        .expect(
+            TIMEOUT,
+            || ping(&harness),
+            |line| line.contains("\"type\":8"),
+        )
+        .unwrap();
+    let record = json(&line);
+    assert_eq!(record["ip"]["src"], CLIENT_ADDRESS.to_string());
+    assert_eq!(record["icmp"]["code"], 0);
+    assert_eq!(record["icmp"]["id"], 0x4e48);
+    assert_eq!(record["description"], "Echo");
+}
+
+#[test]
+fn decoding_the_ip_header_prints_addresses() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let _echo = responders::icmp_echo(&harness.server).unwrap();
+    let mut sniffer = Tool::start(&harness.server, "decoding-the-ip-header", &[]).unwrap();
+
+    sniffer
+        .expect(
+            TIMEOUT,
+            || ping(&harness),
+            |line| {
+                line == format!(
+                    "Protocol: ICMP {} (private) -> {} (private)",
+                    CLIENT_ADDRESS, SERVER_ADDRESS
+                )
+            },
+        )
+        .unwrap();
+    sniffer
+        .expect(
+            TIMEOUT,
+            || {},
+            |line| line.starts_with("Header Length: 20 "),
+        )
+        .unwrap();
+}
+
+#[test]
+fn decoding_the_essence_of_udp_reads_one_packet() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let _echo = responders::icmp_echo(&harness.server).unwrap();
+    let mut sniffer = Tool::start(
+        &harness.server,
+        "decoding-the-essence-of-udp",
+        &["--output", "ndjson"],
+    )
+    .unwrap();
+
+    let line = sniffer
+        .expect(
+            TIMEOUT,
+            || ping(&harness),
+            |line| line.starts_with("{\"length\""),
+        )
+        .unwrap();
+    let record = json(&line);
+    let data: Vec<u8> = record["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|byte| byte.as_u64().unwrap() as u8)
+        .collect();
+    assert_eq!(record["length"], data.len());
+    assert_eq!(data[9], 1);
+    assert_eq!(data[12..16], CLIENT_ADDRESS.octets());
+    assert_eq!(data[16..20], SERVER_ADDRESS.octets());
+    sniffer.finish(TIMEOUT).unwrap();
+}
+
+#[test]
+fn traffic_stats_counts_live_packets() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let address = harness.server_socket(8080);
+    let _listener = responders::tcp_listener(&harness.server, address).unwrap();
+    let mut stats = Tool::start(
+        &harness.server,
+        "traffic-stats",
+        &["-i", &harness.server_link, "-c", "20"],
+    )
+    .unwrap();
+
+    stats
+        .expect(
+            TIMEOUT,
+            || connect(&harness, address),
+            |line| line.starts_with("Traffic statistics: 20 packets"),
+        )
+        .unwrap();
+    let report = stats.finish(TIMEOUT).unwrap();
+    let source = format!("{} (private)", CLIENT_ADDRESS);
+    assert!(
+        report
+            .iter()
+            .any(|line| line.trim_start().starts_with(&source)),
+        "{}",
+        report.join("\n")
+    );
+    assert!(report
+        .iter()
+        .any(|line| line.trim_start().starts_with("8080/tcp")));
+}
+
+#[test]
+fn packet_ids_alerts_on_live_traffic() {
+    let Some(harness) = Harness::new() else {
+        return;
+    };
+    let rules = std::env::temp_dir().join(format!("{}.rules", harness.server.name));
+    fs::write(
+        &rules,
+        "alert tcp any any -> any 8080 (msg:\"netns-harness\"; sid:1000001; rev:1;)\n",
+    )
+    .unwrap();
+    let address = harness.server_socket(8080);
+    let _listener = responders::tcp_listener(&harness.server, address).unwrap();
+    let mut ids = Tool::start(
+        &harness.server,
+        "packet-ids",
+        &[
+            "-i",
+            &harness.server_link,
+            "-R",
+            rules.to_str().unwrap(),
+            "--output",
+            "ndjson",
+        ],
+    )
+    .unwrap();
+
+    let line = ids.expect(
+        TIMEOUT,
+        || connect(&harness, address),
+        |line| line.contains("\"sid\":1000001"),
+    );
+    fs::remove_file(&rules).unwrap();
+    let alert = json(&line.unwrap());
+    assert_eq!(alert["msg"], "netns-harness");
+    assert_eq!(alert["protocol"], "TCP");
+    assert_eq!(alert["src"], CLIENT_ADDRESS.to_string());
+    assert_eq!(alert["dst_port"], 8080);
+    assert_eq!(alert["dst_geoip"]["label"], "private");
+}
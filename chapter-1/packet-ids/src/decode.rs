@@ -0,0 +1,91 @@
+use std::net::IpAddr;
+
+use packet_kit::dissect;
+use packet_kit::{IcmpHeader, Ipv4Header, Ipv6Header, TcpHeader, UdpHeader};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    // Anything carried by IP that isn't decoded further, later fragments included
+    Ip,
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+impl Protocol {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protocol::Ip => "IP",
+            Protocol::Tcp => "TCP",
+            Protocol::Udp => "UDP",
+            Protocol::Icmp => "ICMP",
+        }
+    }
+}
+
+// The header fields and payload rules are matched against
+pub struct Packet<'a> {
+    pub protocol: Protocol,
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub src_port: Option<u16>,
+    pub dst_port: Option<u16>,
+    pub tcp_flags: Option<u16>,
+    // ICMP type and code
+    pub icmp: Option<(u8, u8)>,
+    // Transport payload, or what follows the IP header when there's no transport layer
+    pub payload: &'a [u8],
+}
+
+// Decodes the IP packet carried by the frame, None for anything that isn't IP. The
+// dissectors follow tunnels, rules see the innermost packet that decodes.
+pub fn decode(linktype: u32, frame: &[u8]) -> Option<Packet<'_>> {
+    let decoded = dissect::decode(linktype, frame);
+    let ip = decoded
+        .layers()
+        .filter(|layer| matches!(layer.name, "IPv4" | "IPv6"))
+        .last()?;
+    let (src, dst) = ip.addresses?;
+
+    // Layers only tell where the headers are, which are read again from the frame
+    let payload = match ip.name {
+        "IPv4" => Ipv4Header::parse(&frame[ip.offset..])?.1,
+        _ => Ipv6Header::parse(&frame[ip.offset..])?.1,
+    };
+    let mut packet = Packet {
+        protocol: Protocol::Ip,
+        src,
+        dst,
+        src_port: None,
+        dst_port: None,
+        tcp_flags: None,
+        icmp: None,
+        payload,
+    };
+    // Later fragments and protocols without a dissector stop at the IP layer
+    match ip.inner.as_deref().map(|layer| layer.name) {
+        Some("TCP") => {
+            let (header, data) = TcpHeader::parse(payload)?;
+            packet.protocol = Protocol::Tcp;
+            packet.src_port = Some(header.source_port);
+            packet.dst_port = Some(header.destination_port);
+            packet.tcp_flags = Some(header.flags);
+            packet.payload = data;
+        }
+        Some("UDP") => {
+            let (header, data) = UdpHeader::parse(payload)?;
+            packet.protocol = Protocol::Udp;
+            packet.src_port = Some(header.source_port);
+            packet.dst_port = Some(header.destination_port);
+            packet.payload = data;
+        }
+        Some("ICMP" | "ICMPv6") => {
+            let (header, data) = IcmpHeader::parse(payload)?;
+            packet.protocol = Protocol::Icmp;
+            packet.icmp = Some((header.type_, header.code));
+            packet.payload = data;
+        }
+        _ => {}
+    }
+    Some(packet)
+}
@@ -0,0 +1,336 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use packet_kit::Enrichment;
+use serde::Serialize;
+
+use crate::decode::Packet;
+use crate::rules::{Distinct, Rule, ThresholdType, Track};
+
+// How often windows that ran out are dropped, in packets
+const PURGE_INTERVAL: u64 = 10_000;
+
+// A rule firing on a packet
+#[derive(Serialize)]
+pub struct Alert {
+    // Seconds since the epoch
+    pub timestamp: f64,
+    pub sid: u32,
+    pub rev: u32,
+    pub msg: String,
+    pub protocol: &'static str,
+    pub src: IpAddr,
+    pub src_port: Option<u16>,
+    pub dst: IpAddr,
+    pub dst_port: Option<u16>,
+    // Filled in from the GeoIP databases before the alert is printed as JSON
+    #[serde(skip_serializing_if = "Enrichment::is_empty")]
+    pub src_geoip: Enrichment,
+    #[serde(skip_serializing_if = "Enrichment::is_empty")]
+    pub dst_geoip: Enrichment,
+}
+
+fn endpoint(address: IpAddr, port: Option<u16>) -> String {
+    match (address, port) {
+        (IpAddr::V4(address), Some(port)) => format!("{}:{}", address, port),
+        (IpAddr::V6(address), Some(port)) => format!("[{}]:{}", address, port),
+        (address, None) => address.to_string(),
+    }
+}
+
+// The fast alert format of Snort
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.6} [**] [1:{}:{}] {} [**] {{{}}} {} -> {}",
+            self.timestamp,
+            self.sid,
+            self.rev,
+            self.msg,
+            self.protocol,
+            endpoint(self.src, self.src_port),
+            endpoint(self.dst, self.dst_port)
+        )
+    }
+}
+
+// Matches of one rule for one tracked address within `seconds` of the first one
+struct Window {
+    start: Duration,
+    matches: u64,
+    // Destination ports or hosts seen, for distinct thresholds
+    seen: HashSet<(IpAddr, Option<u16>)>,
+    alerted: bool,
+}
+
+pub struct Engine {
+    rules: Vec<Rule>,
+    windows: HashMap<(usize, IpAddr), Window>,
+    inspected: u64,
+}
+
+impl Engine {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Engine {
+            rules,
+            windows: HashMap::new(),
+            inspected: 0,
+        }
+    }
+
+    // Runs every rule over the packet, `timestamp` is the capture time since the epoch
+    pub fn inspect(&mut self, timestamp: Duration, packet: &Packet) -> Vec<Alert> {
+        self.inspected += 1;
+        if self.inspected.is_multiple_of(PURGE_INTERVAL) {
+            let rules = &self.rules;
+            self.windows.retain(|(index, _), window| {
+                let seconds = rules[*index]
+                    .threshold
+                    .map_or(0, |threshold| threshold.seconds);
+                timestamp.saturating_sub(window.start) < Duration::from_secs(seconds)
+            });
+        }
+
+        let mut alerts = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            if !rule.matches(packet) {
+                continue;
+            }
+            if let Some(threshold) = rule.threshold {
+                let tracked = match threshold.track {
+                    Track::BySrc => packet.src,
+                    Track::ByDst => packet.dst,
+                };
+                let window = self
+                    .windows
+                    .entry((index, tracked))
+                    .or_insert_with(|| Window {
+                        start: timestamp,
+                        matches: 0,
+                        seen: HashSet::new(),
+                        alerted: false,
+                    });
+                if timestamp.saturating_sub(window.start) >= Duration::from_secs(threshold.seconds)
+                {
+                    *window = Window {
+                        start: timestamp,
+                        matches: 0,
+                        seen: HashSet::new(),
+                        alerted: false,
+                    };
+                }
+
+                let count = match threshold.distinct {
+                    Some(Distinct::DstPort) => {
+                        window.seen.insert((packet.dst, packet.dst_port));
+                        window.seen.len() as u64
+                    }
+                    Some(Distinct::DstHost) => {
+                        window.seen.insert((packet.dst, None));
+                        window.seen.len() as u64
+                    }
+                    None => {
+                        window.matches += 1;
+                        window.matches
+                    }
+                };
+                let fire = match threshold.kind {
+                    ThresholdType::Limit => count <= threshold.count,
+                    ThresholdType::Threshold => count.is_multiple_of(threshold.count),
+                    ThresholdType::Both => !window.alerted && count >= threshold.count,
+                };
+                if !fire {
+                    continue;
+                }
+                window.alerted = true;
+            }
+            alerts.push(Alert {
+                timestamp: timestamp.as_secs_f64(),
+                sid: rule.sid,
+                rev: rule.rev,
+                msg: rule.msg.clone(),
+                protocol: packet.protocol.name(),
+                src: packet.src,
+                src_port: packet.src_port,
+                dst: packet.dst,
+                dst_port: packet.dst_port,
+                src_geoip: Enrichment::default(),
+                dst_geoip: Enrichment::default(),
+            });
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use packet_kit::tcp::{ACK, FIN, PSH, SYN, URG};
+
+    use super::*;
+    use crate::decode::Protocol;
+    use crate::rules::parse_rules;
+
+    const ATTACKER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 66));
+
+    fn host(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    fn tcp(src: IpAddr, dst: IpAddr, dst_port: u16, flags: u16) -> Packet<'static> {
+        Packet {
+            protocol: Protocol::Tcp,
+            src,
+            dst,
+            src_port: Some(40000),
+            dst_port: Some(dst_port),
+            tcp_flags: Some(flags),
+            icmp: None,
+            payload: b"",
+        }
+    }
+
+    fn engine(rules: &str) -> Engine {
+        Engine::new(parse_rules("test.rules", rules).unwrap())
+    }
+
+    fn default_engine() -> Engine {
+        engine(include_str!("default.rules"))
+    }
+
+    fn at(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    // Sids of the alerts raised by each packet, in order
+    fn sids(
+        engine: &mut Engine,
+        packets: impl IntoIterator<Item = (Duration, Packet<'static>)>,
+    ) -> Vec<u32> {
+        packets
+            .into_iter()
+            .flat_map(|(timestamp, packet)| engine.inspect(timestamp, &packet))
+            .map(|alert| alert.sid)
+            .collect()
+    }
+
+    // Which of the packets, numbered from 1, raised an alert
+    fn firing(engine: &mut Engine, packets: &[(Duration, Packet<'static>)]) -> Vec<usize> {
+        packets
+            .iter()
+            .enumerate()
+            .filter(|(_, (timestamp, packet))| !engine.inspect(*timestamp, packet).is_empty())
+            .map(|(index, _)| index + 1)
+            .collect()
+    }
+
+    fn threshold_rule(kind: &str, track: &str) -> String {
+        format!(
+            "alert tcp any any -> any 80 (msg:\"web\"; \
+             threshold:type {}, track {}, count 3, seconds 10; sid:1;)",
+            kind, track
+        )
+    }
+
+    #[test]
+    fn limit_alerts_on_the_first_matches_of_each_window() {
+        let mut engine = engine(&threshold_rule("limit", "by_src"));
+        let packets: Vec<_> = (0..6)
+            .map(|second| (at(second * 1000), tcp(ATTACKER, host(1), 80, ACK)))
+            .chain([(at(11_000), tcp(ATTACKER, host(1), 80, ACK))])
+            .collect();
+        assert_eq!(firing(&mut engine, &packets), [1, 2, 3, 7]);
+    }
+
+    #[test]
+    fn threshold_alerts_on_every_nth_match() {
+        let mut engine = engine(&threshold_rule("threshold", "by_src"));
+        let packets: Vec<_> = (0..7)
+            .map(|second| (at(second * 1000), tcp(ATTACKER, host(1), 80, ACK)))
+            .collect();
+        assert_eq!(firing(&mut engine, &packets), [3, 6]);
+    }
+
+    #[test]
+    fn both_alerts_once_per_window_and_tracker() {
+        let mut engine = engine(&threshold_rule("both", "by_dst"));
+        let mut packets: Vec<_> = (0..5)
+            .map(|second| (at(second * 1000), tcp(ATTACKER, host(1), 80, ACK)))
+            .collect();
+        // Another destination is tracked on its own
+        packets.extend((0..3).map(|second| (at(second * 1000), tcp(ATTACKER, host(2), 80, ACK))));
+        // The window of the first destination ran out, it starts counting again
+        packets.extend((10..13).map(|second| (at(second * 1000), tcp(ATTACKER, host(1), 80, ACK))));
+        assert_eq!(firing(&mut engine, &packets), [3, 8, 11]);
+    }
+
+    #[test]
+    fn distinct_counts_destinations_not_matches() {
+        let mut engine = engine(
+            "alert tcp any any -> any any (msg:\"sweep\"; \
+             threshold:type both, track by_src, count 3, seconds 10; distinct:dst_host; sid:1;)",
+        );
+        let packets = [
+            (at(0), tcp(ATTACKER, host(1), 80, SYN)),
+            (at(1), tcp(ATTACKER, host(1), 443, SYN)),
+            (at(2), tcp(ATTACKER, host(2), 80, SYN)),
+            (at(3), tcp(ATTACKER, host(2), 22, SYN)),
+            (at(4), tcp(ATTACKER, host(3), 80, SYN)),
+            (at(5), tcp(ATTACKER, host(4), 80, SYN)),
+        ];
+        assert_eq!(firing(&mut engine, &packets), [5]);
+    }
+
+    #[test]
+    fn syn_flood() {
+        let mut engine = default_engine();
+        // 200 SYNs to one host within a second, from addresses all over the place
+        let flood = (0..500u32).map(|index| {
+            let src = IpAddr::V4(Ipv4Addr::from(0xc633_6400 + index));
+            (at(index as u64 * 4), tcp(src, host(1), 80, SYN))
+        });
+        let alerts = sids(&mut engine, flood);
+        // Once in the first second and once in the next
+        assert_eq!(alerts, [1000001, 1000001]);
+
+        // The handshakes completing aren't SYNs
+        let mut engine = default_engine();
+        let handshakes =
+            (0..400u64).map(|index| (at(index), tcp(ATTACKER, host(1), 80, SYN | ACK)));
+        assert!(sids(&mut engine, handshakes).is_empty());
+    }
+
+    #[test]
+    fn port_sweep() {
+        let mut engine = default_engine();
+        let sweep =
+            (1..=25u16).map(|port| (at(port as u64 * 100), tcp(ATTACKER, host(1), port, SYN)));
+        assert_eq!(sids(&mut engine, sweep), [1000002]);
+
+        // Connections to the same port aren't a sweep
+        let mut engine = default_engine();
+        let repeats = (1..=25u64).map(|index| (at(index * 100), tcp(ATTACKER, host(1), 443, SYN)));
+        assert!(sids(&mut engine, repeats).is_empty());
+
+        // Nor is a sweep slower than the window
+        let mut engine = default_engine();
+        let slow =
+            (1..=25u16).map(|port| (at(port as u64 * 1000), tcp(ATTACKER, host(1), port, SYN)));
+        assert!(sids(&mut engine, slow).is_empty());
+    }
+
+    #[test]
+    fn stealth_scans() {
+        let mut engine = default_engine();
+        let scans = [
+            (at(0), tcp(ATTACKER, host(1), 22, 0)),
+            (at(1), tcp(ATTACKER, host(1), 22, FIN)),
+            (at(2), tcp(ATTACKER, host(1), 22, FIN | PSH | URG)),
+        ];
+        assert_eq!(sids(&mut engine, scans), [1000010, 1000011, 1000012]);
+    }
+}
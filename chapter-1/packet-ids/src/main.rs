@@ -0,0 +1,127 @@
+use std::fs;
+use std::io;
+use std::mem::MaybeUninit;
+
+use packet_kit::capture::LiveCapture;
+use packet_kit::privilege::{self, Capability};
+use packet_kit::{GeoIp, Output, PcapReader};
+
+mod decode;
+mod engine;
+mod rules;
+
+use engine::{Alert, Engine};
+
+// Rules for the SYN floods, sweeps and probes of the chapter-1 scanner and for SQL injection
+const DEFAULT_RULES: &str = include_str!("default.rules");
+
+// The fast alert format stays as Snort prints it, the JSON formats carry the enrichment
+fn print_alert(mut alert: Alert, output: Output, geoip: &GeoIp) {
+    if let Output::Text = output {
+        println!("{}", alert);
+        return;
+    }
+    alert.src_geoip = geoip.lookup(alert.src);
+    alert.dst_geoip = geoip.lookup(alert.dst);
+    output.print_json(&alert);
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [-i <iface> | -r <capture.pcap>] [-R <rules>] [--output text|json|ndjson] \
+         [--geoip <database.mmdb>]...",
+        program
+    );
+    eprintln!("  GeoLite2 City, Country or ASN databases annotate the addresses of JSON alerts");
+    std::process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let mut args = std::env::args();
+    let program = args.next().unwrap();
+
+    let mut iface = None;
+    let mut path = None;
+    let mut rules_path = None;
+    let mut output = Output::Text;
+    let mut geoip = GeoIp::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage(&program));
+        match arg.as_str() {
+            "-i" => iface = Some(value()),
+            "-r" => path = Some(value()),
+            "-R" => rules_path = Some(value()),
+            "--output" => {
+                // Snort fast alerts stand for the text format, there's no hex dump of alerts
+                output = Output::from_option(&value())
+                    .filter(|output| *output != Output::Hex)
+                    .unwrap_or_else(|| usage(&program))
+            }
+            "--geoip" => {
+                if let Err(err) = geoip.open(value()) {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            }
+            _ => usage(&program),
+        }
+    }
+
+    let parsed = match &rules_path {
+        Some(rules_path) => rules::parse_rules(rules_path, &fs::read_to_string(rules_path)?),
+        None => rules::parse_rules("default.rules", DEFAULT_RULES),
+    };
+    let rules = parsed.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    eprintln!("Loaded {} rules", rules.len());
+    let mut engine = Engine::new(rules);
+
+    if let Some(path) = path {
+        if iface.is_some() {
+            usage(&program);
+        }
+        let mut capture = PcapReader::open(&path)?;
+        while let Some(packet) = capture.next_packet()? {
+            // pcapng files carry a link type per interface
+            if let Some(decoded) = decode::decode(packet.linktype, &packet.data) {
+                for alert in engine.inspect(packet.timestamp, &decoded) {
+                    print_alert(alert, output, &geoip);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    let mut capture = LiveCapture::open(iface.as_deref()).unwrap_or_else(|err| {
+        eprintln!(
+            "{}",
+            privilege::explain(
+                "Can't capture live traffic",
+                &err,
+                Capability::NetRaw,
+                Some("inspect a capture file with -r <capture.pcap>")
+            )
+        );
+        std::process::exit(1);
+    });
+    // Rules and databases are loaded, from here on only the socket is read
+    if let Err(err) = privilege::drop_privileges() {
+        eprintln!("Can't drop privileges: {}", err);
+        std::process::exit(1);
+    }
+    let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+    loop {
+        let Some(frame) = capture.next_frame(&mut buffer)? else {
+            continue;
+        };
+        let raw_buffer: &[u8] =
+            unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, frame.length) };
+        if let Some(decoded) = decode::decode(frame.linktype, raw_buffer) {
+            for alert in engine.inspect(frame.timestamp, &decoded) {
+                print_alert(alert, output, &geoip);
+            }
+        }
+    }
+}
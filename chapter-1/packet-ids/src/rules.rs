@@ -0,0 +1,850 @@
+// A Snort-like rule language:
+//
+//     alert tcp any any -> 10.0.0.0/8 [80,443,8000:8100] (msg:"SQL injection"; \
+//         content:"union"; nocase; pcre:"/union\s+select/i"; sid:1000001;)
+//
+// The header picks the protocol, addresses and ports, the options between parentheses
+// match on TCP flags, ICMP type and code and the payload, and set thresholds.
+
+use std::net::IpAddr;
+
+use packet_kit::tcp::{ACK, CWR, ECE, FIN, PSH, RST, SYN, URG};
+use regex::bytes::{Regex, RegexBuilder};
+
+use crate::decode::{Packet, Protocol};
+
+// Addresses a rule applies to
+#[derive(Debug)]
+pub enum AddressSpec {
+    Any,
+    // Address and prefix length
+    Network(IpAddr, u8),
+    List(Vec<AddressSpec>),
+    Not(Box<AddressSpec>),
+}
+
+impl AddressSpec {
+    fn matches(&self, address: IpAddr) -> bool {
+        match self {
+            AddressSpec::Any => true,
+            AddressSpec::Network(network, prefix) => match (network, address) {
+                (IpAddr::V4(network), IpAddr::V4(address)) => {
+                    let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                    u32::from(*network) & mask == u32::from(address) & mask
+                }
+                (IpAddr::V6(network), IpAddr::V6(address)) => {
+                    let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                    u128::from(*network) & mask == u128::from(address) & mask
+                }
+                _ => false,
+            },
+            AddressSpec::List(specs) => specs.iter().any(|spec| spec.matches(address)),
+            AddressSpec::Not(spec) => !spec.matches(address),
+        }
+    }
+}
+
+// Ports a rule applies to, only `any` matches packets without ports
+#[derive(Debug)]
+pub enum PortSpec {
+    Any,
+    Range(u16, u16),
+    List(Vec<PortSpec>),
+    Not(Box<PortSpec>),
+}
+
+impl PortSpec {
+    fn matches(&self, port: Option<u16>) -> bool {
+        match (self, port) {
+            (PortSpec::Any, _) => true,
+            (_, None) => false,
+            (PortSpec::Range(low, high), Some(port)) => (*low..=*high).contains(&port),
+            (PortSpec::List(specs), port) => specs.iter().any(|spec| spec.matches(port)),
+            (PortSpec::Not(spec), port) => !spec.matches(port),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Content {
+    pattern: Vec<u8>,
+    nocase: bool,
+    // Matches payloads that don't contain the pattern
+    negated: bool,
+}
+
+impl Content {
+    fn matches(&self, payload: &[u8]) -> bool {
+        let found = payload.windows(self.pattern.len()).any(|window| {
+            if self.nocase {
+                window.eq_ignore_ascii_case(&self.pattern)
+            } else {
+                window == self.pattern.as_slice()
+            }
+        });
+        found != self.negated
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum FlagMode {
+    // Exactly these flags
+    Exact,
+    // At least these flags, written with a trailing +
+    All,
+    // Any of these flags, written with a trailing *
+    Any,
+    // None of these flags, written with a leading !
+    None,
+}
+
+#[derive(Debug)]
+struct Flags {
+    mask: u16,
+    mode: FlagMode,
+}
+
+impl Flags {
+    fn matches(&self, flags: u16) -> bool {
+        match self.mode {
+            FlagMode::Exact => flags & 0xff == self.mask,
+            FlagMode::All => flags & self.mask == self.mask,
+            FlagMode::Any => flags & self.mask != 0,
+            FlagMode::None => flags & self.mask == 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThresholdType {
+    // Alerts on the first `count` matches of each window
+    Limit,
+    // Alerts on every `count`th match
+    Threshold,
+    // Alerts once per window, when the `count`th match comes
+    Both,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Track {
+    BySrc,
+    ByDst,
+}
+
+// What a threshold counts instead of matches, to catch sweeps
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Distinct {
+    DstPort,
+    DstHost,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Threshold {
+    pub kind: ThresholdType,
+    pub track: Track,
+    pub count: u64,
+    pub seconds: u64,
+    pub distinct: Option<Distinct>,
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    pub sid: u32,
+    pub rev: u32,
+    pub msg: String,
+    protocol: Protocol,
+    src: AddressSpec,
+    src_ports: PortSpec,
+    // Written <> instead of ->, the rule matches both ways
+    bidirectional: bool,
+    dst: AddressSpec,
+    dst_ports: PortSpec,
+    contents: Vec<Content>,
+    patterns: Vec<Regex>,
+    flags: Option<Flags>,
+    itype: Option<u8>,
+    icode: Option<u8>,
+    pub threshold: Option<Threshold>,
+}
+
+impl Rule {
+    // Whether the packet matches the header and every option, thresholds aside
+    pub fn matches(&self, packet: &Packet) -> bool {
+        if self.protocol != Protocol::Ip && self.protocol != packet.protocol {
+            return false;
+        }
+        let forward = self.src.matches(packet.src)
+            && self.src_ports.matches(packet.src_port)
+            && self.dst.matches(packet.dst)
+            && self.dst_ports.matches(packet.dst_port);
+        let backward = self.bidirectional
+            && self.src.matches(packet.dst)
+            && self.src_ports.matches(packet.dst_port)
+            && self.dst.matches(packet.src)
+            && self.dst_ports.matches(packet.src_port);
+        if !forward && !backward {
+            return false;
+        }
+
+        if let Some(flags) = &self.flags {
+            match packet.tcp_flags {
+                Some(packet_flags) if flags.matches(packet_flags) => {}
+                _ => return false,
+            }
+        }
+        let (itype, icode) = match packet.icmp {
+            Some((itype, icode)) => (Some(itype), Some(icode)),
+            None => (None, None),
+        };
+        if self.itype.is_some() && self.itype != itype {
+            return false;
+        }
+        if self.icode.is_some() && self.icode != icode {
+            return false;
+        }
+        self.contents
+            .iter()
+            .all(|content| content.matches(packet.payload))
+            && self
+                .patterns
+                .iter()
+                .all(|pattern| pattern.is_match(packet.payload))
+    }
+}
+
+fn parse_address(spec: &str) -> Result<AddressSpec, String> {
+    if let Some(spec) = spec.strip_prefix('!') {
+        return Ok(AddressSpec::Not(Box::new(parse_address(spec)?)));
+    }
+    if let Some(list) = spec
+        .strip_prefix('[')
+        .and_then(|spec| spec.strip_suffix(']'))
+    {
+        return Ok(AddressSpec::List(
+            list.split(',')
+                .map(|spec| parse_address(spec.trim()))
+                .collect::<Result<_, _>>()?,
+        ));
+    }
+    if spec == "any" {
+        return Ok(AddressSpec::Any);
+    }
+    let invalid = || format!("Invalid address '{}'", spec);
+    let (address, prefix) = match spec.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (spec, None),
+    };
+    let address: IpAddr = address.parse().map_err(|_| invalid())?;
+    let bits = if address.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix
+            .parse()
+            .ok()
+            .filter(|prefix| *prefix <= bits)
+            .ok_or_else(invalid)?,
+        None => bits,
+    };
+    Ok(AddressSpec::Network(address, prefix))
+}
+
+fn parse_ports(spec: &str) -> Result<PortSpec, String> {
+    if let Some(spec) = spec.strip_prefix('!') {
+        return Ok(PortSpec::Not(Box::new(parse_ports(spec)?)));
+    }
+    if let Some(list) = spec
+        .strip_prefix('[')
+        .and_then(|spec| spec.strip_suffix(']'))
+    {
+        return Ok(PortSpec::List(
+            list.split(',')
+                .map(|spec| parse_ports(spec.trim()))
+                .collect::<Result<_, _>>()?,
+        ));
+    }
+    if spec == "any" {
+        return Ok(PortSpec::Any);
+    }
+    let invalid = || format!("Invalid port '{}'", spec);
+    let port = |value: &str, default: u16| match value {
+        "" => Ok(default),
+        value => value.parse::<u16>().map_err(|_| invalid()),
+    };
+    match spec.split_once(':') {
+        // Open ranges such as 1024: or :1023
+        Some((low, high)) => Ok(PortSpec::Range(port(low, 0)?, port(high, u16::MAX)?)),
+        None => {
+            let port = spec.parse::<u16>().map_err(|_| invalid())?;
+            Ok(PortSpec::Range(port, port))
+        }
+    }
+}
+
+// Unquotes a string option, |41 42| sequences inside stand for raw bytes
+fn parse_content(value: &str) -> Result<Vec<u8>, String> {
+    let quoted = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .ok_or_else(|| format!("Expected a quoted string, got {}", value))?;
+
+    let mut bytes = Vec::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let escaped = chars.next().ok_or("Dangling escape")?;
+                let mut buffer = [0; 4];
+                bytes.extend_from_slice(escaped.encode_utf8(&mut buffer).as_bytes());
+            }
+            '|' => {
+                let hex: String = chars.by_ref().take_while(|c| *c != '|').collect();
+                for byte in hex.split_whitespace() {
+                    bytes.push(
+                        u8::from_str_radix(byte, 16)
+                            .map_err(|_| format!("Invalid hex byte '{}'", byte))?,
+                    );
+                }
+            }
+            c => {
+                let mut buffer = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            }
+        }
+    }
+    if bytes.is_empty() {
+        return Err(String::from("Empty content"));
+    }
+    Ok(bytes)
+}
+
+// Parses "/pattern/flags" the way pcre options are written
+fn parse_pcre(value: &str) -> Result<Regex, String> {
+    let quoted = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .ok_or_else(|| format!("Expected a quoted regex, got {}", value))?;
+    let (pattern, flags) = quoted
+        .strip_prefix('/')
+        .and_then(|quoted| quoted.rsplit_once('/'))
+        .ok_or_else(|| format!("Expected /pattern/flags, got {}", quoted))?;
+    RegexBuilder::new(pattern)
+        .unicode(false)
+        .case_insensitive(flags.contains('i'))
+        .dot_matches_new_line(flags.contains('s'))
+        .multi_line(flags.contains('m'))
+        .ignore_whitespace(flags.contains('x'))
+        .build()
+        .map_err(|err| err.to_string())
+}
+
+fn parse_flags(value: &str) -> Result<Flags, String> {
+    let (value, mode) = if let Some(value) = value.strip_prefix('!') {
+        (value, FlagMode::None)
+    } else if let Some(value) = value.strip_suffix('+') {
+        (value, FlagMode::All)
+    } else if let Some(value) = value.strip_suffix('*') {
+        (value, FlagMode::Any)
+    } else {
+        (value, FlagMode::Exact)
+    };
+    let mut mask = 0;
+    for flag in value.chars() {
+        mask |= match flag.to_ascii_uppercase() {
+            'F' => FIN,
+            'S' => SYN,
+            'R' => RST,
+            'P' => PSH,
+            'A' => ACK,
+            'U' => URG,
+            'E' => ECE,
+            'C' => CWR,
+            // No flags at all, as in NULL scans
+            '0' => 0,
+            _ => return Err(format!("Unknown TCP flag '{}'", flag)),
+        };
+    }
+    Ok(Flags { mask, mode })
+}
+
+// Parses "type both, track by_src, count 20, seconds 5"
+fn parse_threshold(value: &str) -> Result<Threshold, String> {
+    let (mut kind, mut track, mut count, mut seconds) = (None, None, None, None);
+    for setting in value.split(',') {
+        let (name, value) = setting
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("Invalid threshold setting '{}'", setting.trim()))?;
+        let value = value.trim();
+        match name {
+            "type" => {
+                kind = Some(match value {
+                    "limit" => ThresholdType::Limit,
+                    "threshold" => ThresholdType::Threshold,
+                    "both" => ThresholdType::Both,
+                    _ => return Err(format!("Unknown threshold type '{}'", value)),
+                })
+            }
+            "track" => {
+                track = Some(match value {
+                    "by_src" => Track::BySrc,
+                    "by_dst" => Track::ByDst,
+                    _ => return Err(format!("Unknown threshold track '{}'", value)),
+                })
+            }
+            "count" => count = value.parse::<u64>().ok().filter(|count| *count > 0),
+            "seconds" => seconds = value.parse::<u64>().ok().filter(|seconds| *seconds > 0),
+            _ => return Err(format!("Unknown threshold setting '{}'", name)),
+        }
+    }
+    Ok(Threshold {
+        kind: kind.ok_or("Threshold needs a type")?,
+        track: track.ok_or("Threshold needs a track")?,
+        count: count.ok_or("Threshold needs a positive count")?,
+        seconds: seconds.ok_or("Threshold needs positive seconds")?,
+        distinct: None,
+    })
+}
+
+// Splits the options on semicolons outside quotes
+fn split_options(options: &str) -> Result<Vec<(&str, Option<&str>)>, String> {
+    let mut split = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in options.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                let option = options[start..index].trim();
+                if !option.is_empty() {
+                    split.push(match option.split_once(':') {
+                        Some((name, value)) => (name.trim(), Some(value.trim())),
+                        None => (option, None),
+                    });
+                }
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    if quoted || !options[start..].trim().is_empty() {
+        return Err(String::from("Options must end with a semicolon"));
+    }
+    Ok(split)
+}
+
+fn parse_rule(line: &str) -> Result<Rule, String> {
+    let (header, options) = line
+        .split_once('(')
+        .ok_or("Missing options between parentheses")?;
+    let options = options
+        .trim_end()
+        .strip_suffix(')')
+        .ok_or("Missing closing parenthesis")?;
+
+    let fields: Vec<&str> = header.split_whitespace().collect();
+    let [action, protocol, src, src_ports, direction, dst, dst_ports] = fields[..] else {
+        return Err(String::from(
+            "Expected: alert <protocol> <src> <port> -> <dst> <port> (<options>)",
+        ));
+    };
+    if action != "alert" {
+        return Err(format!("Unsupported action '{}'", action));
+    }
+    let mut rule = Rule {
+        sid: 0,
+        rev: 1,
+        msg: String::new(),
+        protocol: match protocol {
+            "ip" => Protocol::Ip,
+            "tcp" => Protocol::Tcp,
+            "udp" => Protocol::Udp,
+            "icmp" => Protocol::Icmp,
+            _ => return Err(format!("Unknown protocol '{}'", protocol)),
+        },
+        src: parse_address(src)?,
+        src_ports: parse_ports(src_ports)?,
+        bidirectional: match direction {
+            "->" => false,
+            "<>" => true,
+            _ => return Err(format!("Unknown direction '{}'", direction)),
+        },
+        dst: parse_address(dst)?,
+        dst_ports: parse_ports(dst_ports)?,
+        contents: Vec::new(),
+        patterns: Vec::new(),
+        flags: None,
+        itype: None,
+        icode: None,
+        threshold: None,
+    };
+
+    let mut distinct = None;
+    for (name, value) in split_options(options)? {
+        let required = || value.ok_or_else(|| format!("{} needs a value", name));
+        let number = |value: &str| {
+            value
+                .parse::<u32>()
+                .map_err(|_| format!("Invalid {} '{}'", name, value))
+        };
+        match name {
+            "msg" => rule.msg = String::from_utf8_lossy(&parse_content(required()?)?).into_owned(),
+            "sid" => rule.sid = number(required()?)?,
+            "rev" => rule.rev = number(required()?)?,
+            "content" => {
+                let value = required()?;
+                let (value, negated) = match value.strip_prefix('!') {
+                    Some(value) => (value.trim_start(), true),
+                    None => (value, false),
+                };
+                rule.contents.push(Content {
+                    pattern: parse_content(value)?,
+                    nocase: false,
+                    negated,
+                });
+            }
+            "nocase" => {
+                rule.contents
+                    .last_mut()
+                    .ok_or("nocase needs a content before it")?
+                    .nocase = true
+            }
+            "pcre" => rule.patterns.push(parse_pcre(required()?)?),
+            "flags" => rule.flags = Some(parse_flags(required()?)?),
+            "itype" => {
+                rule.itype = Some(
+                    number(required()?)?
+                        .try_into()
+                        .map_err(|_| "itype must fit a byte")?,
+                )
+            }
+            "icode" => {
+                rule.icode = Some(
+                    number(required()?)?
+                        .try_into()
+                        .map_err(|_| "icode must fit a byte")?,
+                )
+            }
+            "threshold" => rule.threshold = Some(parse_threshold(required()?)?),
+            "distinct" => {
+                distinct = Some(match required()? {
+                    "dst_port" => Distinct::DstPort,
+                    "dst_host" => Distinct::DstHost,
+                    value => return Err(format!("Unknown distinct '{}'", value)),
+                })
+            }
+            // Informational options of Snort rules
+            "classtype" | "reference" | "priority" | "metadata" => {}
+            _ => return Err(format!("Unknown option '{}'", name)),
+        }
+    }
+
+    if rule.sid == 0 {
+        return Err(String::from("Rule needs a sid"));
+    }
+    if rule.flags.is_some() && rule.protocol != Protocol::Tcp {
+        return Err(String::from("flags only apply to tcp rules"));
+    }
+    if let Some(distinct) = distinct {
+        // Distinct values only grow, only a once per window threshold makes sense
+        match &mut rule.threshold {
+            Some(threshold) if threshold.kind == ThresholdType::Both => {
+                threshold.distinct = Some(distinct)
+            }
+            _ => return Err(String::from("distinct needs a threshold of type both")),
+        }
+    }
+    Ok(rule)
+}
+
+// Parses a rules file, one rule per line. Blank lines and # comments are skipped and
+// a trailing backslash continues a rule on the next line.
+pub fn parse_rules(name: &str, text: &str) -> Result<Vec<Rule>, String> {
+    let mut rules = Vec::new();
+    let mut pending = String::new();
+    let mut first_line = 0;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if pending.is_empty() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            first_line = number + 1;
+        }
+        match line.strip_suffix('\\') {
+            Some(line) => {
+                pending.push_str(line);
+                pending.push(' ');
+                continue;
+            }
+            None => pending.push_str(line),
+        }
+        let rule =
+            parse_rule(&pending).map_err(|err| format!("{}:{}: {}", name, first_line, err))?;
+        if rules.iter().any(|other: &Rule| other.sid == rule.sid) {
+            return Err(format!(
+                "{}:{}: duplicate sid {}",
+                name, first_line, rule.sid
+            ));
+        }
+        rules.push(rule);
+        pending.clear();
+    }
+    if !pending.is_empty() {
+        return Err(format!("{}:{}: unterminated rule", name, first_line));
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn packet(protocol: Protocol, dst_port: u16, flags: u16, payload: &[u8]) -> Packet<'_> {
+        Packet {
+            protocol,
+            src: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            dst: IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3)),
+            src_port: Some(40000),
+            dst_port: Some(dst_port),
+            tcp_flags: (protocol == Protocol::Tcp).then_some(flags),
+            icmp: None,
+            payload,
+        }
+    }
+
+    fn parsed(line: &str) -> Rule {
+        parse_rule(line).unwrap_or_else(|err| panic!("{}: {}", line, err))
+    }
+
+    #[test]
+    fn content_escapes_and_hex() {
+        for (value, bytes) in [
+            (r#""GET""#, &b"GET"[..]),
+            (r#""say \"hi\"""#, b"say \"hi\""),
+            (r#""a\;b\\c""#, b"a;b\\c"),
+            (r#""|0d 0a|Host:""#, b"\r\nHost:"),
+            (r#""x|41 42|y|00|""#, b"xABy\0"),
+            (r#""|ff|""#, b"\xff"),
+        ] {
+            assert_eq!(parse_content(value).unwrap(), bytes, "{}", value);
+        }
+        for value in [r#""""#, "GET", r#""a\""#, r#""|zz|""#, r#""|4142|""#] {
+            assert!(parse_content(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn contents_match_payloads() {
+        let rule = parsed(
+            r#"alert tcp any any -> any 80 (msg:"admin"; content:"GET |2f|admin"; nocase; content:!"cookie"; sid:1;)"#,
+        );
+        assert!(rule.matches(&packet(Protocol::Tcp, 80, ACK, b"get /ADMIN HTTP/1.1")));
+        assert!(!rule.matches(&packet(Protocol::Tcp, 80, ACK, b"get /admin\r\ncookie: x")));
+        assert!(!rule.matches(&packet(Protocol::Tcp, 80, ACK, b"GET /index")));
+        assert!(!rule.matches(&packet(Protocol::Udp, 80, 0, b"GET /admin")));
+
+        // A quoted semicolon doesn't end the option
+        let rule = parsed(r#"alert udp any any -> any any (msg:"a;b"; content:"x;y"; sid:2;)"#);
+        assert_eq!(rule.msg, "a;b");
+        assert!(rule.matches(&packet(Protocol::Udp, 53, 0, b"x;y")));
+    }
+
+    #[test]
+    fn port_specs() {
+        for (spec, matching, other) in [
+            ("80", &[80][..], &[79, 81][..]),
+            ("1024:", &[1024, 65535], &[1023]),
+            (":1023", &[0, 1023], &[1024]),
+            ("8000:8100", &[8000, 8050, 8100], &[7999, 8101]),
+            ("[80,443,8000:8100]", &[80, 443, 8080], &[22, 8101]),
+            ("!22", &[21, 23], &[22]),
+            ("![1:1023]", &[1024], &[80]),
+        ] {
+            let ports = parse_ports(spec).unwrap();
+            for port in matching {
+                assert!(ports.matches(Some(*port)), "{} {}", spec, port);
+            }
+            for port in other {
+                assert!(!ports.matches(Some(*port)), "{} {}", spec, port);
+            }
+            // Packets without ports only match any
+            assert!(!ports.matches(None), "{}", spec);
+        }
+        assert!(PortSpec::Any.matches(None));
+        for spec in ["65536", "http", "1:x", "[80,]"] {
+            assert!(parse_ports(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn address_specs() {
+        let address = |text: &str| text.parse::<IpAddr>().unwrap();
+        for (spec, matching, other) in [
+            ("10.0.0.0/8", "10.255.0.1", "11.0.0.1"),
+            ("0.0.0.0/0", "203.0.113.9", "2001:db8::1"),
+            ("[192.0.2.1,10.0.0.0/8]", "192.0.2.1", "192.0.2.2"),
+            ("!10.0.0.0/8", "192.0.2.1", "10.0.0.1"),
+            ("2001:db8::/32", "2001:db8:1::1", "2001:db9::1"),
+        ] {
+            let addresses = parse_address(spec).unwrap();
+            assert!(
+                addresses.matches(address(matching)),
+                "{} {}",
+                spec,
+                matching
+            );
+            assert!(!addresses.matches(address(other)), "{} {}", spec, other);
+        }
+        for spec in ["10.0.0.0/33", "2001:db8::/129", "10.0.0", "host"] {
+            assert!(parse_address(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn flag_modes() {
+        for (spec, matching, other) in [
+            ("S", SYN, SYN | ACK),
+            ("SA+", SYN | ACK | ECE, SYN),
+            ("FPU*", PSH, SYN | ACK),
+            ("!A", SYN, SYN | ACK),
+            ("0", 0, FIN),
+        ] {
+            let flags = parse_flags(spec).unwrap();
+            assert!(flags.matches(matching), "{}", spec);
+            assert!(!flags.matches(other), "{}", spec);
+        }
+        assert!(parse_flags("SX").is_err());
+    }
+
+    #[test]
+    fn thresholds_are_validated() {
+        let threshold = parse_threshold("type both, track by_src, count 20, seconds 5").unwrap();
+        assert_eq!(threshold.kind, ThresholdType::Both);
+        assert_eq!(threshold.track, Track::BySrc);
+        assert_eq!((threshold.count, threshold.seconds), (20, 5));
+        assert_eq!(threshold.distinct, None);
+
+        for (value, error) in [
+            ("track by_src, count 1, seconds 1", "Threshold needs a type"),
+            ("type limit, count 1, seconds 1", "Threshold needs a track"),
+            (
+                "type limit, track by_dst, count 0, seconds 1",
+                "Threshold needs a positive count",
+            ),
+            (
+                "type limit, track by_dst, count 1, seconds -1",
+                "Threshold needs positive seconds",
+            ),
+            (
+                "type sometimes, track by_dst, count 1, seconds 1",
+                "Unknown threshold type 'sometimes'",
+            ),
+            (
+                "type limit, track by_port, count 1, seconds 1",
+                "Unknown threshold track 'by_port'",
+            ),
+            (
+                "type limit, track by_src, count 1, seconds 1, burst 2",
+                "Unknown threshold setting 'burst'",
+            ),
+            ("type", "Invalid threshold setting 'type'"),
+        ] {
+            assert_eq!(parse_threshold(value).unwrap_err(), error, "{}", value);
+        }
+    }
+
+    #[test]
+    fn rules_are_validated() {
+        for (line, error) in [
+            (
+                r#"alert tcp any any -> any any (msg:"x"; distinct:dst_port; threshold:type limit, track by_src, count 1, seconds 1; sid:1;)"#,
+                "distinct needs a threshold of type both",
+            ),
+            (
+                r#"alert udp any any -> any any (msg:"x"; distinct:dst_port; sid:1;)"#,
+                "distinct needs a threshold of type both",
+            ),
+            (
+                r#"alert udp any any -> any any (msg:"x"; flags:S; sid:1;)"#,
+                "flags only apply to tcp rules",
+            ),
+            (
+                r#"alert tcp any any -> any any (msg:"x";)"#,
+                "Rule needs a sid",
+            ),
+            (
+                r#"drop tcp any any -> any any (sid:1;)"#,
+                "Unsupported action 'drop'",
+            ),
+            (
+                r#"alert sctp any any -> any any (sid:1;)"#,
+                "Unknown protocol 'sctp'",
+            ),
+            (
+                r#"alert tcp any any <- any any (sid:1;)"#,
+                "Unknown direction '<-'",
+            ),
+            (
+                r#"alert tcp any any -> any any (nocase; sid:1;)"#,
+                "nocase needs a content before it",
+            ),
+            (
+                r#"alert tcp any any -> any any (sid:1)"#,
+                "Options must end with a semicolon",
+            ),
+            (
+                r#"alert icmp any any -> any any (itype:256; sid:1;)"#,
+                "itype must fit a byte",
+            ),
+        ] {
+            assert_eq!(parse_rule(line).unwrap_err(), error, "{}", line);
+        }
+    }
+
+    #[test]
+    fn rules_files() {
+        let text = "# comment\n\
+                    \n\
+                    alert tcp any any -> any 80 (msg:\"one\"; \\\n\
+                    \x20   sid:1;)\n\
+                    alert udp any any <> 10.0.0.0/8 53 (msg:\"two\"; sid:2; rev:3;)\n";
+        let rules = parse_rules("test.rules", text).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(
+            (rules[0].sid, rules[0].rev, rules[0].msg.as_str()),
+            (1, 1, "one")
+        );
+        assert_eq!((rules[1].sid, rules[1].rev), (2, 3));
+
+        // Bidirectional rules match replies too
+        let mut reply = packet(Protocol::Udp, 40000, 0, b"");
+        (reply.src, reply.dst) = (reply.dst, reply.src);
+        reply.src_port = Some(53);
+        assert!(rules[1].matches(&reply));
+        assert!(!rules[0].matches(&packet(Protocol::Tcp, 443, SYN, b"")));
+
+        let duplicate = format!("{}alert tcp any any -> any any (sid:1;)\n", text);
+        assert_eq!(
+            parse_rules("test.rules", &duplicate).unwrap_err(),
+            "test.rules:6: duplicate sid 1"
+        );
+        assert_eq!(
+            parse_rules("test.rules", "\nalert tcp any any -> any any (sid:0x1;)").unwrap_err(),
+            "test.rules:2: Invalid sid '0x1'"
+        );
+        assert_eq!(
+            parse_rules("test.rules", "alert tcp any any -> any any (sid:1; \\").unwrap_err(),
+            "test.rules:1: unterminated rule"
+        );
+    }
+
+    #[test]
+    fn default_rules_parse() {
+        let rules = parse_rules("default.rules", include_str!("default.rules")).unwrap();
+        assert!(rules.iter().any(|rule| rule.sid == 1000001));
+    }
+}
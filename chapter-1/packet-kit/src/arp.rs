@@ -0,0 +1,94 @@
+use std::net::Ipv4Addr;
+
+use serde::Serialize;
+
+use crate::ethernet::{MacAddr, ETHERTYPE_IPV4};
+use crate::layout::FieldSpan;
+
+// Size of an ARP packet mapping IPv4 addresses to Ethernet ones
+pub const ARP_PACKET_SIZE: usize = 28;
+
+pub const ARP_HARDWARE_ETHERNET: u16 = 1;
+pub const ARP_REQUEST: u16 = 1;
+pub const ARP_REPLY: u16 = 2;
+
+// An ARP packet for IPv4 over Ethernet (RFC 826), the only kind we decode
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ArpPacket {
+    pub operation: u16,
+    pub sender_mac: MacAddr,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddr,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    // Asks who has the target address, the target hardware address is left empty
+    pub fn request(sender_mac: MacAddr, sender_ip: Ipv4Addr, target_ip: Ipv4Addr) -> Self {
+        ArpPacket {
+            operation: ARP_REQUEST,
+            sender_mac,
+            sender_ip,
+            target_mac: MacAddr::default(),
+            target_ip,
+        }
+    }
+
+    pub fn parse(buffer: &[u8]) -> Option<Self> {
+        if buffer.len() < ARP_PACKET_SIZE {
+            return None;
+        }
+        let hardware = u16::from_be_bytes([buffer[0], buffer[1]]);
+        let protocol = u16::from_be_bytes([buffer[2], buffer[3]]);
+        // Hardware and protocol address lengths
+        if hardware != ARP_HARDWARE_ETHERNET || protocol != ETHERTYPE_IPV4 || buffer[4..6] != [6, 4]
+        {
+            return None;
+        }
+        Some(ArpPacket {
+            operation: u16::from_be_bytes([buffer[6], buffer[7]]),
+            sender_mac: MacAddr(buffer[8..14].try_into().unwrap()),
+            sender_ip: Ipv4Addr::new(buffer[14], buffer[15], buffer[16], buffer[17]),
+            target_mac: MacAddr(buffer[18..24].try_into().unwrap()),
+            target_ip: Ipv4Addr::new(buffer[24], buffer[25], buffer[26], buffer[27]),
+        })
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(ARP_PACKET_SIZE);
+        packet.extend_from_slice(&ARP_HARDWARE_ETHERNET.to_be_bytes());
+        packet.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        packet.extend_from_slice(&[6, 4]);
+        packet.extend_from_slice(&self.operation.to_be_bytes());
+        packet.extend_from_slice(&self.sender_mac.0);
+        packet.extend_from_slice(&self.sender_ip.octets());
+        packet.extend_from_slice(&self.target_mac.0);
+        packet.extend_from_slice(&self.target_ip.octets());
+        packet
+    }
+
+    // Announcements of the sender's own address, either a request or a reply about itself
+    pub fn is_gratuitous(&self) -> bool {
+        self.sender_ip == self.target_ip
+    }
+
+    // Where each field sits in the packet
+    pub fn spans(&self) -> Vec<FieldSpan> {
+        let operation = match self.operation {
+            ARP_REQUEST => String::from("request"),
+            ARP_REPLY => String::from("reply"),
+            operation => operation.to_string(),
+        };
+        vec![
+            FieldSpan::new("Hardware Type", 0, 2, "Ethernet"),
+            FieldSpan::new("Protocol Type", 2, 2, "IPv4"),
+            FieldSpan::new("Hardware Size", 4, 1, 6),
+            FieldSpan::new("Protocol Size", 5, 1, 4),
+            FieldSpan::new("Operation", 6, 2, operation),
+            FieldSpan::new("Sender MAC", 8, 6, self.sender_mac),
+            FieldSpan::new("Sender IP", 14, 4, self.sender_ip),
+            FieldSpan::new("Target MAC", 18, 6, self.target_mac),
+            FieldSpan::new("Target IP", 24, 4, self.target_ip),
+        ]
+    }
+}
@@ -0,0 +1,227 @@
+// Live capture and injection through AF_PACKET sockets, which hand over whole frames
+// along with the interface and hardware type they were seen on
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::mem::MaybeUninit;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+use crate::pcap::{LINKTYPE_ETHERNET, LINKTYPE_RAW};
+
+// Address family of AF_PACKET sockets
+const AF_PACKET: u16 = 17;
+// Every protocol, packet sockets expect it in network byte order
+pub const ETH_P_ALL: u16 = 0x0003;
+// Size of struct sockaddr_ll
+const SOCKADDR_LL_SIZE: u32 = 20;
+
+// Hardware types of struct sockaddr_ll
+// Refer to ---> https://github.com/torvalds/linux/blob/master/include/uapi/linux/if_arp.h
+const ARPHRD_ETHER: u16 = 1;
+const ARPHRD_PPP: u16 = 512;
+const ARPHRD_RAWIP: u16 = 519;
+const ARPHRD_TUNNEL: u16 = 768;
+const ARPHRD_TUNNEL6: u16 = 769;
+const ARPHRD_LOOPBACK: u16 = 772;
+const ARPHRD_SIT: u16 = 776;
+const ARPHRD_IPGRE: u16 = 778;
+const ARPHRD_IP6GRE: u16 = 823;
+const ARPHRD_NONE: u16 = 0xfffe;
+
+// Packet types of struct sockaddr_ll
+pub const PACKET_HOST: u8 = 0;
+pub const PACKET_BROADCAST: u8 = 1;
+pub const PACKET_MULTICAST: u8 = 2;
+pub const PACKET_OTHERHOST: u8 = 3;
+pub const PACKET_OUTGOING: u8 = 4;
+
+// A frame read from a packet socket
+pub struct Frame {
+    // Time since the epoch the frame was read at
+    pub timestamp: Duration,
+    // Index of the interface it was seen on
+    pub interface: i32,
+    pub linktype: u32,
+    // Whether it was for us, broadcast, multicast, for another host or sent by us
+    pub packet_type: u8,
+    pub length: usize,
+}
+
+// Link type of the frames a packet socket reads off a hardware type, None for the ones
+// whose link header the dissectors don't know
+pub fn linktype(hardware: u16) -> Option<u32> {
+    match hardware {
+        ARPHRD_ETHER | ARPHRD_LOOPBACK => Some(LINKTYPE_ETHERNET),
+        // Tunnels and other devices without a link header hand over bare IP packets
+        ARPHRD_PPP | ARPHRD_RAWIP | ARPHRD_TUNNEL | ARPHRD_TUNNEL6 | ARPHRD_SIT | ARPHRD_IPGRE
+        | ARPHRD_IP6GRE | ARPHRD_NONE => Some(LINKTYPE_RAW),
+        _ => None,
+    }
+}
+
+// Index of the interface, as listed under /sys/class/net
+pub fn interface_index(iface: &str) -> io::Result<i32> {
+    let index = fs::read_to_string(format!("/sys/class/net/{}/ifindex", iface)).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No interface named {}", iface),
+        )
+    })?;
+    index
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid interface index"))
+}
+
+// Interface names by index, as listed under /sys/class/net
+pub fn interface_names() -> HashMap<i32, String> {
+    let mut names = HashMap::new();
+    if let Ok(entries) = fs::read_dir("/sys/class/net") {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Ok(index) = fs::read_to_string(entry.path().join("ifindex")) {
+                if let Ok(index) = index.trim().parse() {
+                    names.insert(index, name);
+                }
+            }
+        }
+    }
+    names
+}
+
+// Opens a packet socket receiving the frames of one protocol (an EtherType, or ETH_P_ALL),
+// bound to an interface when one is given. Protocol 0 opens a socket that only sends.
+pub fn open(protocol: u16, iface: Option<&str>) -> io::Result<Socket> {
+    let socket = Socket::new(
+        Domain::PACKET,
+        Type::RAW,
+        Some(Protocol::from(protocol.to_be() as i32)),
+    )?;
+    if let Some(iface) = iface {
+        let index = interface_index(iface)?;
+
+        // struct sockaddr_ll: family, protocol, interface index, then fields only used on receive
+        let (_, address) = unsafe {
+            SockAddr::try_init(|storage, length| {
+                let storage = storage as *mut u8;
+                std::ptr::copy_nonoverlapping(AF_PACKET.to_ne_bytes().as_ptr(), storage, 2);
+                std::ptr::copy_nonoverlapping(protocol.to_be_bytes().as_ptr(), storage.add(2), 2);
+                std::ptr::copy_nonoverlapping(index.to_ne_bytes().as_ptr(), storage.add(4), 4);
+                *length = SOCKADDR_LL_SIZE;
+                Ok(())
+            })
+        }?;
+        socket.bind(&address)?;
+    }
+    Ok(socket)
+}
+
+// A packet socket capturing every frame on one interface or on all of them
+pub struct LiveCapture {
+    socket: Socket,
+    names: HashMap<i32, String>,
+}
+
+impl LiveCapture {
+    pub fn open(iface: Option<&str>) -> io::Result<Self> {
+        Ok(LiveCapture {
+            socket: open(ETH_P_ALL, iface)?,
+            names: interface_names(),
+        })
+    }
+
+    // Makes next_frame give up after the timeout, so callers get to check for quitting
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket.set_read_timeout(timeout)
+    }
+
+    // Reads the next frame into the buffer. None when the read timed out or a signal cut it
+    // short, and for frames to skip: the copies loopback hands over on the way out, and
+    // frames of hardware types without a known link type.
+    pub fn next_frame(&mut self, buffer: &mut [MaybeUninit<u8>]) -> io::Result<Option<Frame>> {
+        let (length, address) = match self.socket.recv_from(buffer) {
+            Ok(received) => received,
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut
+                    || err.kind() == io::ErrorKind::Interrupted =>
+            {
+                return Ok(None)
+            }
+            Err(err) => return Err(err),
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        // struct sockaddr_ll: the interface index at 4, the hardware type at 8, the packet type at 10
+        let raw = address.as_ptr() as *const u8;
+        let (interface, hardware, packet_type) = unsafe {
+            (
+                (raw.add(4) as *const i32).read_unaligned(),
+                (raw.add(8) as *const u16).read_unaligned(),
+                *raw.add(10),
+            )
+        };
+        // Loopback hands every packet over once on the way out and once on the way in
+        if hardware == ARPHRD_LOOPBACK && packet_type == PACKET_OUTGOING {
+            return Ok(None);
+        }
+        let Some(linktype) = linktype(hardware) else {
+            return Ok(None);
+        };
+        Ok(Some(Frame {
+            timestamp,
+            interface,
+            linktype,
+            packet_type,
+            length,
+        }))
+    }
+
+    // Name of an interface frames were seen on, looked up again for interfaces that
+    // showed up after the capture started
+    pub fn interface_name(&mut self, index: i32) -> String {
+        if !self.names.contains_key(&index) {
+            self.names = interface_names();
+        }
+        match self.names.get(&index) {
+            Some(name) => name.clone(),
+            None => format!("if{}", index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_hardware_types() {
+        assert_eq!(linktype(ARPHRD_ETHER), Some(LINKTYPE_ETHERNET));
+        assert_eq!(linktype(ARPHRD_LOOPBACK), Some(LINKTYPE_ETHERNET));
+        // tun devices, WireGuard and GRE tunnels carry bare IP packets
+        assert_eq!(linktype(ARPHRD_NONE), Some(LINKTYPE_RAW));
+        assert_eq!(linktype(ARPHRD_IPGRE), Some(LINKTYPE_RAW));
+        assert_eq!(linktype(ARPHRD_SIT), Some(LINKTYPE_RAW));
+        // CAN and radiotap headers aren't dissected
+        assert_eq!(linktype(280), None);
+        assert_eq!(linktype(803), None);
+    }
+
+    #[test]
+    fn finds_the_loopback_interface() {
+        let index = interface_index("lo").unwrap();
+        assert_eq!(
+            interface_names().get(&index).map(String::as_str),
+            Some("lo")
+        );
+        assert_eq!(
+            interface_index("no-such-interface").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
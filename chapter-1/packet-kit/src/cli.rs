@@ -0,0 +1,122 @@
+// Command line and startup shared by the decoders. Each of them reads the packets of a
+// capture file given with -r, or sniffs them live off a raw socket, which needs CAP_NET_RAW.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use serde::Serialize;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::geoip::{Enrichment, GeoIp};
+use crate::output::Output;
+use crate::privilege::{self, Capability};
+
+// What works without the privileges of a raw socket
+const CAPTURE_FALLBACK: &str = "decode a capture file with -r <capture.pcap>";
+
+pub struct DecoderArgs {
+    // Capture file to decode instead of sniffing
+    pub path: Option<String>,
+    pub output: Output,
+    pub geoip: GeoIp,
+}
+
+impl DecoderArgs {
+    // Parses -r, --output and, for the decoders annotating addresses, --geoip.
+    // Prints the usage and exits on anything else.
+    pub fn parse(annotates: bool) -> Self {
+        let mut args = std::env::args();
+        let program = args.next().unwrap_or_default();
+
+        let mut parsed = DecoderArgs {
+            path: None,
+            output: Output::Text,
+            geoip: GeoIp::new(),
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().unwrap_or_else(|| usage(&program, annotates));
+            match arg.as_str() {
+                "-r" => parsed.path = Some(value()),
+                "--output" => {
+                    parsed.output =
+                        Output::from_option(&value()).unwrap_or_else(|| usage(&program, annotates))
+                }
+                "--geoip" if annotates => {
+                    if let Err(err) = parsed.geoip.open(value()) {
+                        fail(err);
+                    }
+                }
+                _ => usage(&program, annotates),
+            }
+        }
+        parsed
+    }
+}
+
+fn usage(program: &str, annotates: bool) -> ! {
+    let geoip = if annotates {
+        " [--geoip <database.mmdb>]..."
+    } else {
+        ""
+    };
+    eprintln!(
+        "Usage: {} [-r <capture.pcap>] [--output text|json|ndjson|hex]{}",
+        program, geoip
+    );
+    eprintln!("  Without -r, packets are sniffed live, which needs root or CAP_NET_RAW");
+    if annotates {
+        eprintln!("  GeoLite2 City, Country or ASN databases annotate the addresses");
+    }
+    std::process::exit(1);
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+// Opens the raw socket receiving the IPv4 packets of the protocol, then gives up every
+// privilege since the socket is all that needed them. Exits saying how to get
+// CAP_NET_RAW when the socket can't be opened.
+pub fn open_sniffer(protocol: u8) -> Socket {
+    let sniffer = Socket::new(
+        Domain::IPV4,
+        Type::RAW,
+        Some(Protocol::from(protocol as i32)),
+    )
+    .unwrap_or_else(|err| {
+        fail(privilege::explain(
+            "Can't open a raw socket",
+            &err,
+            Capability::NetRaw,
+            Some(CAPTURE_FALLBACK),
+        ))
+    });
+    // Raw sockets have no ports, the address only picks the packets received
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+    if let Err(err) = sniffer.bind(&address.into()) {
+        fail(format!("Can't bind the raw socket: {}", err));
+    }
+    if let Err(err) = privilege::drop_privileges() {
+        fail(format!("Can't drop privileges: {}", err));
+    }
+    sniffer
+}
+
+// What the GeoIP databases know about the addresses of a packet, flattened into the
+// JSON records of the decoders
+#[derive(Serialize)]
+pub struct AddressEnrichment {
+    #[serde(skip_serializing_if = "Enrichment::is_empty")]
+    pub src_geoip: Enrichment,
+    #[serde(skip_serializing_if = "Enrichment::is_empty")]
+    pub dst_geoip: Enrichment,
+}
+
+impl AddressEnrichment {
+    pub fn new(geoip: &GeoIp, src: Ipv4Addr, dst: Ipv4Addr) -> Self {
+        AddressEnrichment {
+            src_geoip: geoip.lookup(IpAddr::V4(src)),
+            dst_geoip: geoip.lookup(IpAddr::V4(dst)),
+        }
+    }
+}
@@ -0,0 +1,920 @@
+// Protocol decoders registered by what announces them (a link type, an EtherType, an IP
+// protocol or a port) or by what their payloads look like, and the pipeline walking a
+// frame through them layer by layer:
+//
+//     let packet = packet_kit::dissect::decode(LINKTYPE_ETHERNET, &frame);
+//     for layer in packet.layers() {
+//         println!("{}: {}", layer.name, layer.summary);
+//     }
+//
+// Tools add their own decoders to a registry of their own:
+//
+//     let mut registry = Registry::new();
+//     registry.register(&[Key::UdpPort(5353)], Mdns);
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+use serde::Serialize;
+
+use crate::arp::{ArpPacket, ARP_PACKET_SIZE, ARP_REPLY, ARP_REQUEST};
+use crate::ethernet::{
+    ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6, ETHERTYPE_MPLS, ETHERTYPE_MPLS_MULTICAST,
+    ETHERTYPE_TEB,
+};
+use crate::http::{HttpMessage, HTTP_PORTS};
+use crate::layout::{FieldSpan, LayerSpans};
+use crate::names::icmp_type_name;
+use crate::pcap::{
+    LINKTYPE_ETHERNET, LINKTYPE_IPV4, LINKTYPE_IPV6, LINKTYPE_LINUX_SLL, LINKTYPE_NULL,
+    LINKTYPE_RAW,
+};
+use crate::tcp::flag_names;
+use crate::tunnel::{self, Inner, Tunnel, MAX_ENCAPSULATION};
+use crate::{
+    EthernetHeader, IcmpHeader, Ipv4Header, Ipv6Header, TcpHeader, UdpHeader, PROTOCOL_ETHERIP,
+    PROTOCOL_GRE, PROTOCOL_ICMP, PROTOCOL_ICMPV6, PROTOCOL_IPIP, PROTOCOL_IPV4, PROTOCOL_IPV6,
+    PROTOCOL_MPLS_IN_IP, PROTOCOL_TCP, PROTOCOL_UDP,
+};
+
+// Layers decoded before giving up on a frame, tunnels are limited on their own
+const MAX_LAYERS: usize = 32;
+
+const LINUX_SLL_HEADER_SIZE: usize = 16;
+const NULL_HEADER_SIZE: usize = 4;
+
+// What a dissector is registered for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum Key {
+    LinkType(u32),
+    EtherType(u16),
+    IpProtocol(u8),
+    TcpPort(u16),
+    UdpPort(u16),
+    // Tried on the TCP and UDP payloads no port claims, when `Dissector::heuristic` agrees
+    Heuristic,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+// What the payload of a layer is announced as
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Next {
+    LinkType(u32),
+    EtherType(u16),
+    IpProtocol(u8),
+    // IPv4 or IPv6, the version nibble tells them apart
+    Ip,
+    Ports {
+        transport: Transport,
+        source: u16,
+        destination: u16,
+    },
+    // Nothing decodable follows
+    End,
+}
+
+// What a dissector made of the start of the data it was given
+pub struct Dissection<'a> {
+    // One line describing the layer
+    pub summary: String,
+    // Offsets from the start of the layer
+    pub fields: Vec<FieldSpan>,
+    // What the layer carries, a slice of the data the dissector was given
+    pub payload: &'a [u8],
+    pub next: Next,
+    // Tunnels nest the layers they carry one level deeper
+    pub tunnel: bool,
+    pub addresses: Option<(IpAddr, IpAddr)>,
+    pub ports: Option<(u16, u16)>,
+}
+
+impl<'a> Dissection<'a> {
+    pub fn new(
+        summary: impl ToString,
+        fields: Vec<FieldSpan>,
+        payload: &'a [u8],
+        next: Next,
+    ) -> Self {
+        Dissection {
+            summary: summary.to_string(),
+            fields,
+            payload,
+            next,
+            tunnel: false,
+            addresses: None,
+            ports: None,
+        }
+    }
+}
+
+pub trait Dissector: Send + Sync {
+    // Name of the layer, as shown in layer trees and protocol columns
+    fn name(&self) -> &'static str;
+
+    // Decodes the layer at the start of the data, None when it isn't one
+    fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>>;
+
+    // Whether a payload no port claims looks like this protocol
+    fn heuristic(&self, _data: &[u8]) -> bool {
+        false
+    }
+}
+
+// A decoded layer, holding the layer decoded from its payload
+#[derive(Clone, Debug, Serialize)]
+pub struct DecodedLayer {
+    pub name: &'static str,
+    pub summary: String,
+    // Tunnels the layer is carried in, 0 for the outer packet
+    pub depth: usize,
+    // Bytes of the frame the layer's header covers
+    pub offset: usize,
+    pub length: usize,
+    // Offsets from the start of the frame
+    pub fields: Vec<FieldSpan>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub addresses: Option<(IpAddr, IpAddr)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ports: Option<(u16, u16)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inner: Option<Box<DecodedLayer>>,
+}
+
+impl DecodedLayer {
+    // Value of the first field with this name
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|field| field.name == name)
+            .map(|field| field.value.as_str())
+    }
+}
+
+// Every layer decoded from a frame, outermost first
+#[derive(Clone, Debug, Serialize)]
+pub struct DecodedPacket {
+    pub length: usize,
+    pub root: Option<DecodedLayer>,
+    // What the bytes after the last layer were announced as, End when nothing was
+    pub next: Next,
+    pub payload_offset: usize,
+    pub payload_length: usize,
+    // Tunnels the payload is carried in
+    pub payload_depth: usize,
+}
+
+impl DecodedPacket {
+    pub fn layers(&self) -> impl Iterator<Item = &DecodedLayer> {
+        std::iter::successors(self.root.as_ref(), |layer| layer.inner.as_deref())
+    }
+
+    // The highest layer that was decoded
+    pub fn innermost(&self) -> Option<&DecodedLayer> {
+        self.layers().last()
+    }
+
+    // The innermost layer with this name
+    pub fn find(&self, name: &str) -> Option<&DecodedLayer> {
+        self.layers().filter(|layer| layer.name == name).last()
+    }
+
+    // Addresses of the innermost network layer
+    pub fn addresses(&self) -> Option<(IpAddr, IpAddr)> {
+        self.layers().filter_map(|layer| layer.addresses).last()
+    }
+
+    // Ports of the transport layer over the innermost network layer, tunnels carried
+    // over UDP don't lend theirs to the packets inside
+    pub fn ports(&self) -> Option<(u16, u16)> {
+        let mut ports = None;
+        for layer in self.layers() {
+            if layer.addresses.is_some() {
+                ports = None;
+            }
+            ports = layer.ports.or(ports);
+        }
+        ports
+    }
+
+    // The bytes no layer decoded
+    pub fn payload<'a>(&self, frame: &'a [u8]) -> &'a [u8] {
+        &frame[self.payload_offset..self.payload_offset + self.payload_length]
+    }
+
+    // The fields of every layer, then the payload. Tunnels without a header of their
+    // own, such as IP-in-IP, have nothing to show.
+    pub fn spans(&self) -> Vec<LayerSpans> {
+        let mut layers: Vec<LayerSpans> = self
+            .layers()
+            .filter(|layer| !layer.fields.is_empty())
+            .map(|layer| LayerSpans {
+                name: layer.name,
+                depth: layer.depth,
+                fields: layer.fields.clone(),
+            })
+            .collect();
+        if self.payload_length > 0 {
+            let data = format!("{} bytes", self.payload_length);
+            layers.push(LayerSpans {
+                name: "Payload",
+                depth: self.payload_depth,
+                fields: vec![FieldSpan::new(
+                    "Data",
+                    self.payload_offset,
+                    self.payload_length,
+                    data,
+                )],
+            });
+        }
+        layers
+    }
+}
+
+pub struct Registry {
+    dissectors: Vec<Box<dyn Dissector>>,
+    // Dissectors registered for each key, tried in the order they were registered
+    keys: HashMap<Key, Vec<usize>>,
+}
+
+// Offset of a subslice within the slice it was taken from
+fn offset_in(data: &[u8], payload: &[u8]) -> Option<usize> {
+    let offset = (payload.as_ptr() as usize).checked_sub(data.as_ptr() as usize)?;
+    (offset + payload.len() <= data.len()).then_some(offset)
+}
+
+impl Registry {
+    // A registry without any dissector
+    pub fn empty() -> Self {
+        Registry {
+            dissectors: Vec::new(),
+            keys: HashMap::new(),
+        }
+    }
+
+    // A registry with the dissectors of every protocol this crate decodes
+    pub fn new() -> Self {
+        let mut registry = Registry::empty();
+        registry.register(
+            &[
+                Key::LinkType(LINKTYPE_ETHERNET),
+                Key::EtherType(ETHERTYPE_TEB),
+            ],
+            Ethernet,
+        );
+        registry.register(&[Key::LinkType(LINKTYPE_LINUX_SLL)], LinuxSll);
+        registry.register(&[Key::LinkType(LINKTYPE_NULL)], Null);
+        registry.register(&[Key::EtherType(ETHERTYPE_ARP)], Arp);
+        registry.register(&[Key::EtherType(ETHERTYPE_IPV4)], Ipv4);
+        registry.register(&[Key::EtherType(ETHERTYPE_IPV6)], Ipv6);
+        registry.register(&[Key::IpProtocol(PROTOCOL_TCP)], Tcp);
+        registry.register(&[Key::IpProtocol(PROTOCOL_UDP)], Udp);
+        registry.register(&[Key::IpProtocol(PROTOCOL_ICMP)], Icmp { v6: false });
+        registry.register(&[Key::IpProtocol(PROTOCOL_ICMPV6)], Icmp { v6: true });
+
+        let mut http: Vec<Key> = HTTP_PORTS.iter().map(|port| Key::TcpPort(*port)).collect();
+        http.push(Key::Heuristic);
+        registry.register(&http, Http);
+
+        let tunnels: [(&[Key], &'static str, TunnelParser); 7] = [
+            (
+                &[
+                    Key::IpProtocol(PROTOCOL_GRE),
+                    Key::UdpPort(tunnel::PORT_GRE_IN_UDP),
+                ],
+                "GRE",
+                tunnel::gre,
+            ),
+            (
+                &[
+                    Key::IpProtocol(PROTOCOL_IPV4),
+                    Key::IpProtocol(PROTOCOL_IPIP),
+                ],
+                "IP-in-IP",
+                |payload| tunnel::over_ip(PROTOCOL_IPIP, payload),
+            ),
+            (&[Key::IpProtocol(PROTOCOL_IPV6)], "6in4", |payload| {
+                tunnel::over_ip(PROTOCOL_IPV6, payload)
+            }),
+            (
+                &[Key::IpProtocol(PROTOCOL_ETHERIP)],
+                "EtherIP",
+                tunnel::etherip,
+            ),
+            (
+                &[
+                    Key::EtherType(ETHERTYPE_MPLS),
+                    Key::EtherType(ETHERTYPE_MPLS_MULTICAST),
+                    Key::IpProtocol(PROTOCOL_MPLS_IN_IP),
+                    Key::UdpPort(tunnel::PORT_MPLS_IN_UDP),
+                ],
+                "MPLS",
+                tunnel::mpls,
+            ),
+            (&[Key::UdpPort(tunnel::PORT_VXLAN)], "VXLAN", tunnel::vxlan),
+            (
+                &[Key::UdpPort(tunnel::PORT_GENEVE)],
+                "GENEVE",
+                tunnel::geneve,
+            ),
+        ];
+        for (keys, name, parse) in tunnels {
+            registry.register(keys, TunnelDissector { name, parse });
+        }
+        registry
+    }
+
+    pub fn register(&mut self, keys: &[Key], dissector: impl Dissector + 'static) {
+        let index = self.dissectors.len();
+        self.dissectors.push(Box::new(dissector));
+        for key in keys {
+            self.keys.entry(*key).or_default().push(index);
+        }
+    }
+
+    fn registered(&self, key: Key) -> &[usize] {
+        self.keys
+            .get(&key)
+            .map_or(&[], |indices| indices.as_slice())
+    }
+
+    // Dissectors to try on a payload, in order
+    fn candidates(&self, next: Next, data: &[u8]) -> Vec<usize> {
+        match next {
+            Next::LinkType(linktype) => self.registered(Key::LinkType(linktype)).to_vec(),
+            Next::EtherType(ethertype) => self.registered(Key::EtherType(ethertype)).to_vec(),
+            Next::IpProtocol(protocol) => self.registered(Key::IpProtocol(protocol)).to_vec(),
+            Next::Ip => match data.first().map(|byte| byte >> 4) {
+                Some(4) => self.registered(Key::EtherType(ETHERTYPE_IPV4)).to_vec(),
+                Some(6) => self.registered(Key::EtherType(ETHERTYPE_IPV6)).to_vec(),
+                _ => Vec::new(),
+            },
+            Next::Ports {
+                transport,
+                source,
+                destination,
+            } => {
+                let key = |port| match transport {
+                    Transport::Tcp => Key::TcpPort(port),
+                    Transport::Udp => Key::UdpPort(port),
+                };
+                // The destination port names the service of requests, the source that of replies
+                let mut candidates = self.registered(key(destination)).to_vec();
+                candidates.extend_from_slice(self.registered(key(source)));
+                candidates.extend(
+                    self.registered(Key::Heuristic)
+                        .iter()
+                        .filter(|index| self.dissectors[**index].heuristic(data)),
+                );
+                candidates
+            }
+            Next::End => Vec::new(),
+        }
+    }
+
+    // Walks the frame through the dissectors, each layer's payload going to the
+    // dissector registered for what the layer announced. Decoding stops at the first
+    // payload no dissector claims.
+    pub fn decode(&self, linktype: u32, frame: &[u8]) -> DecodedPacket {
+        let mut layers = Vec::new();
+        let mut next = match linktype {
+            // Raw captures have no link layer, the packet starts right away
+            LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Next::Ip,
+            linktype => Next::LinkType(linktype),
+        };
+        let mut data = frame;
+        let mut offset = 0;
+        let mut depth = 0;
+
+        while layers.len() < MAX_LAYERS && depth < MAX_ENCAPSULATION {
+            let found = self.candidates(next, data).into_iter().find_map(|index| {
+                let dissector = &self.dissectors[index];
+                let dissection = dissector.dissect(data)?;
+                let length = offset_in(data, dissection.payload)?;
+                Some((dissector.name(), dissection, length))
+            });
+            let Some((name, dissection, length)) = found else {
+                break;
+            };
+            layers.push(DecodedLayer {
+                name,
+                summary: dissection.summary,
+                depth,
+                offset,
+                length,
+                fields: dissection
+                    .fields
+                    .into_iter()
+                    .map(|field| FieldSpan {
+                        offset: field.offset + offset,
+                        ..field
+                    })
+                    .collect(),
+                addresses: dissection.addresses,
+                ports: dissection.ports,
+                inner: None,
+            });
+            offset += length;
+            data = dissection.payload;
+            next = dissection.next;
+            if dissection.tunnel {
+                depth += 1;
+            }
+        }
+
+        // Each layer holds the next one
+        let root = layers.into_iter().rev().fold(None, |inner, mut layer| {
+            layer.inner = inner.map(Box::new);
+            Some(layer)
+        });
+        DecodedPacket {
+            length: frame.len(),
+            root,
+            next: if data.is_empty() { Next::End } else { next },
+            payload_offset: offset,
+            payload_length: data.len(),
+            payload_depth: depth,
+        }
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+// Decodes the frame with the dissectors of every protocol this crate knows
+pub fn decode(linktype: u32, frame: &[u8]) -> DecodedPacket {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Registry::new).decode(linktype, frame)
+}
+
+struct Ethernet;
+
+impl Dissector for Ethernet {
+    fn name(&self) -> &'static str {
+        "Ethernet"
+    }
+
+    fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>> {
+        let (header, payload) = EthernetHeader::parse(data)?;
+        // Ethernet right behind Ethernet only comes out of tunnels
+        let next = match header.ethertype {
+            ETHERTYPE_TEB => Next::End,
+            ethertype => Next::EtherType(ethertype),
+        };
+        let summary = format!("{} -> {}", header.source, header.destination);
+        Some(Dissection::new(summary, header.spans(), payload, next))
+    }
+}
+
+// Linux cooked capture, what captures on the "any" interface start with
+struct LinuxSll;
+
+impl Dissector for LinuxSll {
+    fn name(&self) -> &'static str {
+        "Linux cooked capture"
+    }
+
+    fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>> {
+        let header = data.get(..LINUX_SLL_HEADER_SIZE)?;
+        let field = |offset: usize| u16::from_be_bytes([header[offset], header[offset + 1]]);
+        let protocol = field(14);
+        let address_length = (field(4) as usize).min(8);
+        let address: Vec<String> = header[6..6 + address_length]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let fields = vec![
+            FieldSpan::new("Packet Type", 0, 2, field(0)),
+            FieldSpan::new("Address Type", 2, 2, field(2)),
+            FieldSpan::new("Address Length", 4, 2, address_length),
+            FieldSpan::new("Address", 6, 8, address.join(":")),
+            FieldSpan::new("Protocol", 14, 2, format!("0x{:04x}", protocol)),
+        ];
+        Some(Dissection::new(
+            format!("Protocol 0x{:04x}", protocol),
+            fields,
+            &data[LINUX_SLL_HEADER_SIZE..],
+            Next::EtherType(protocol),
+        ))
+    }
+}
+
+// BSD loopback, the address family in host byte order
+struct Null;
+
+impl Dissector for Null {
+    fn name(&self) -> &'static str {
+        "Null/Loopback"
+    }
+
+    fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>> {
+        let family: [u8; NULL_HEADER_SIZE] = data.get(..NULL_HEADER_SIZE)?.try_into().ok()?;
+        // Families are small numbers, whichever byte order the capturing host used
+        let family = u32::from_le_bytes(family).min(u32::from_be_bytes(family));
+        Some(Dissection::new(
+            format!("Family {}", family),
+            vec![FieldSpan::new("Family", 0, NULL_HEADER_SIZE, family)],
+            &data[NULL_HEADER_SIZE..],
+            Next::Ip,
+        ))
+    }
+}
+
+struct Arp;
+
+impl Dissector for Arp {
+    fn name(&self) -> &'static str {
+        "ARP"
+    }
+
+    fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>> {
+        let arp = ArpPacket::parse(data)?;
+        let summary = match arp.operation {
+            ARP_REQUEST => format!("Who has {}? Tell {}", arp.target_ip, arp.sender_ip),
+            ARP_REPLY => format!("{} is at {}", arp.sender_ip, arp.sender_mac),
+            operation => format!("Operation {}", operation),
+        };
+        let mut dissection =
+            Dissection::new(summary, arp.spans(), &data[ARP_PACKET_SIZE..], Next::End);
+        dissection.addresses = Some((IpAddr::V4(arp.sender_ip), IpAddr::V4(arp.target_ip)));
+        Some(dissection)
+    }
+}
+
+struct Ipv4;
+
+impl Dissector for Ipv4 {
+    fn name(&self) -> &'static str {
+        "IPv4"
+    }
+
+    fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>> {
+        let (header, payload) = Ipv4Header::parse(data)?;
+        let mut summary = format!("{} -> {}", header.src, header.dst);
+        // Later fragments don't start with a transport header
+        let next = match header.fragment_offset {
+            0 => Next::IpProtocol(header.protocol),
+            offset => {
+                summary = format!("{}, fragment at offset {}", summary, offset as usize * 8);
+                Next::End
+            }
+        };
+        let mut dissection = Dissection::new(summary, header.spans(), payload, next);
+        dissection.addresses = Some((IpAddr::V4(header.src), IpAddr::V4(header.dst)));
+        Some(dissection)
+    }
+}
+
+struct Ipv6;
+
+impl Dissector for Ipv6 {
+    fn name(&self) -> &'static str {
+        "IPv6"
+    }
+
+    fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>> {
+        let (header, payload) = Ipv6Header::parse(data)?;
+        let summary = format!("{} -> {}", header.src, header.dst);
+        let next = Next::IpProtocol(header.next_header);
+        let mut dissection = Dissection::new(summary, header.spans(), payload, next);
+        dissection.addresses = Some((IpAddr::V6(header.src), IpAddr::V6(header.dst)));
+        Some(dissection)
+    }
+}
+
+struct Tcp;
+
+impl Dissector for Tcp {
+    fn name(&self) -> &'static str {
+        "TCP"
+    }
+
+    fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>> {
+        let (header, payload) = TcpHeader::parse(data)?;
+        let summary = format!(
+            "{} -> {} [{}] Seq={} Ack={} Win={} Len={}",
+            header.source_port,
+            header.destination_port,
+            flag_names(header.flags),
+            header.sequence_number,
+            header.acknowledgment_number,
+            header.window_size,
+            payload.len()
+        );
+        let next = Next::Ports {
+            transport: Transport::Tcp,
+            source: header.source_port,
+            destination: header.destination_port,
+        };
+        let mut dissection = Dissection::new(summary, header.spans(), payload, next);
+        dissection.ports = Some((header.source_port, header.destination_port));
+        Some(dissection)
+    }
+}
+
+struct Udp;
+
+impl Dissector for Udp {
+    fn name(&self) -> &'static str {
+        "UDP"
+    }
+
+    fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>> {
+        let (header, payload) = UdpHeader::parse(data)?;
+        let summary = format!(
+            "{} -> {} Len={}",
+            header.source_port,
+            header.destination_port,
+            payload.len()
+        );
+        let next = Next::Ports {
+            transport: Transport::Udp,
+            source: header.source_port,
+            destination: header.destination_port,
+        };
+        let mut dissection = Dissection::new(summary, header.spans(), payload, next);
+        dissection.ports = Some((header.source_port, header.destination_port));
+        Some(dissection)
+    }
+}
+
+struct Icmp {
+    v6: bool,
+}
+
+impl Dissector for Icmp {
+    fn name(&self) -> &'static str {
+        if self.v6 {
+            "ICMPv6"
+        } else {
+            "ICMP"
+        }
+    }
+
+    fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>> {
+        let (header, payload) = IcmpHeader::parse(data)?;
+        let summary = if self.v6 {
+            format!("Type {} Code {}", header.type_, header.code)
+        } else {
+            icmp_type_name(header.type_, header.code)
+        };
+        Some(Dissection::new(summary, header.spans(), payload, Next::End))
+    }
+}
+
+struct Http;
+
+impl Dissector for Http {
+    fn name(&self) -> &'static str {
+        "HTTP"
+    }
+
+    fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>> {
+        let message = HttpMessage::parse(data)?;
+        Some(Dissection::new(
+            &message.start_line,
+            message.spans(),
+            &data[message.header_length..],
+            Next::End,
+        ))
+    }
+
+    fn heuristic(&self, data: &[u8]) -> bool {
+        HttpMessage::looks_like_http(data)
+    }
+}
+
+type TunnelParser = fn(&[u8]) -> Option<Tunnel<'_>>;
+
+// A tunnel header from the tunnel module, followed into the packet it carries
+struct TunnelDissector {
+    name: &'static str,
+    parse: TunnelParser,
+}
+
+impl Dissector for TunnelDissector {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>> {
+        let tunnel = (self.parse)(data)?;
+        let next = match tunnel.inner {
+            Inner::Ethernet => Next::EtherType(ETHERTYPE_TEB),
+            Inner::Ip => Next::Ip,
+            Inner::Mpls => Next::EtherType(ETHERTYPE_MPLS),
+        };
+        let summary = format!("{} bytes inside", tunnel.payload.len());
+        let mut dissection = Dissection::new(summary, tunnel.spans, tunnel.payload, next);
+        dissection.tunnel = true;
+        Some(dissection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::ethernet::MacAddr;
+    use crate::{Ipv4Builder, TcpBuilder};
+
+    // Takes the first byte as its layer, when it accepts the data at all
+    struct Marker {
+        name: &'static str,
+        accepts: bool,
+        heuristic: bool,
+    }
+
+    impl Marker {
+        fn new(name: &'static str) -> Self {
+            Marker {
+                name,
+                accepts: true,
+                heuristic: false,
+            }
+        }
+    }
+
+    impl Dissector for Marker {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>> {
+            if !self.accepts || data.is_empty() {
+                return None;
+            }
+            Some(Dissection::new(
+                self.name,
+                Vec::new(),
+                &data[1..],
+                Next::End,
+            ))
+        }
+
+        fn heuristic(&self, _data: &[u8]) -> bool {
+            self.heuristic
+        }
+    }
+
+    fn segment(source: u16, destination: u16, payload: &[u8]) -> Vec<u8> {
+        let packet = Ipv4Builder::new()
+            .src(Ipv4Addr::new(10, 0, 0, 1))
+            .dst(Ipv4Addr::new(10, 0, 0, 2))
+            .payload(TcpBuilder::new(source, destination).payload(payload))
+            .build();
+        EthernetHeader::new(MacAddr::BROADCAST, MacAddr::BROADCAST, ETHERTYPE_IPV4).build(&packet)
+    }
+
+    fn innermost(registry: &Registry, frame: &[u8]) -> Option<&'static str> {
+        registry
+            .decode(LINKTYPE_ETHERNET, frame)
+            .innermost()
+            .map(|layer| layer.name)
+    }
+
+    fn registry() -> Registry {
+        let mut registry = Registry::new();
+        registry.register(&[Key::TcpPort(7000)], Marker::new("Port 7000"));
+        registry.register(&[Key::TcpPort(7001)], Marker::new("Port 7001"));
+        registry.register(
+            &[Key::Heuristic],
+            Marker {
+                heuristic: true,
+                ..Marker::new("Guess")
+            },
+        );
+        registry
+    }
+
+    const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+
+    #[test]
+    fn ports_win_over_heuristics() {
+        let registry = registry();
+        // HTTP and the guess would both take the request, the port says otherwise
+        assert_eq!(
+            innermost(&registry, &segment(40000, 7000, REQUEST)),
+            Some("Port 7000")
+        );
+        // Replies are recognised by their source port
+        assert_eq!(
+            innermost(&registry, &segment(7000, 40000, REQUEST)),
+            Some("Port 7000")
+        );
+        // The destination port names the service before the source port does
+        assert_eq!(
+            innermost(&registry, &segment(7000, 7001, REQUEST)),
+            Some("Port 7001")
+        );
+        // Data the HTTP port's dissector refuses goes on to the heuristics
+        assert_eq!(
+            innermost(&registry, &segment(40000, 8080, b"\x16\x03")),
+            Some("Guess")
+        );
+        assert_eq!(
+            innermost(&registry, &segment(40000, 80, REQUEST)),
+            Some("HTTP")
+        );
+    }
+
+    #[test]
+    fn dissectors_refusing_the_data_pass_it_on() {
+        let mut registry = registry();
+        let refusing = Marker {
+            accepts: false,
+            ..Marker::new("Refusing")
+        };
+        registry.register(&[Key::TcpPort(7002)], refusing);
+        registry.register(&[Key::TcpPort(7002)], Marker::new("Second"));
+        assert_eq!(
+            innermost(&registry, &segment(40000, 7002, b"x")),
+            Some("Second")
+        );
+    }
+
+    #[test]
+    fn heuristics_take_what_no_port_claims() {
+        let registry = Registry::new();
+        let decoded = registry.decode(LINKTYPE_ETHERNET, &segment(40000, 5000, REQUEST));
+        let names: Vec<&str> = decoded.layers().map(|layer| layer.name).collect();
+        assert_eq!(names, ["Ethernet", "IPv4", "TCP", "HTTP"]);
+        assert_eq!(decoded.innermost().unwrap().offset, 54);
+        assert_eq!(decoded.payload_length, 0);
+        assert_eq!(decoded.next, Next::End);
+
+        // Nothing looks like binary data, it's left as the payload
+        let frame = segment(40000, 5000, b"\x00\x01\x02\x03");
+        let decoded = registry.decode(LINKTYPE_ETHERNET, &frame);
+        let innermost = decoded.innermost().unwrap();
+        assert_eq!(
+            (innermost.name, innermost.offset, innermost.length),
+            ("TCP", 34, 20)
+        );
+        assert_eq!((decoded.payload_offset, decoded.payload_length), (54, 4));
+        assert_eq!(decoded.payload(&frame), b"\x00\x01\x02\x03");
+        assert!(matches!(
+            decoded.next,
+            Next::Ports {
+                transport: Transport::Tcp,
+                source: 40000,
+                destination: 5000,
+            }
+        ));
+        assert_eq!(decoded.ports(), Some((40000, 5000)));
+
+        // An empty registry decodes nothing, the whole frame is payload
+        let decoded = Registry::empty().decode(LINKTYPE_ETHERNET, &frame);
+        assert!(decoded.innermost().is_none());
+        assert_eq!(
+            (decoded.payload_offset, decoded.payload_length),
+            (0, frame.len())
+        );
+        assert_eq!(decoded.next, Next::LinkType(LINKTYPE_ETHERNET));
+    }
+
+    #[test]
+    fn nesting_stops_at_the_encapsulation_limit() {
+        // IP-in-IP nested past the limit, the innermost packet carries a TCP segment
+        let mut packet = Ipv4Builder::new()
+            .payload(TcpBuilder::new(40000, 80))
+            .build();
+        let tunnels = MAX_ENCAPSULATION + 2;
+        for _ in 0..tunnels {
+            packet = Ipv4Builder::new()
+                .protocol(PROTOCOL_IPV4)
+                .payload(packet)
+                .build();
+        }
+
+        let decoded = decode(LINKTYPE_RAW, &packet);
+        assert_eq!(decoded.layers().count(), MAX_ENCAPSULATION * 2);
+        let innermost = decoded.innermost().unwrap();
+        assert_eq!(innermost.name, "IP-in-IP");
+        assert_eq!(innermost.depth, MAX_ENCAPSULATION - 1);
+        // What the last tunnel carries is left as the payload, one level deeper
+        assert_eq!(decoded.payload_offset, MAX_ENCAPSULATION * 20);
+        assert_eq!(decoded.payload_depth, MAX_ENCAPSULATION);
+        assert_eq!(
+            decoded.payload_length,
+            packet.len() - MAX_ENCAPSULATION * 20
+        );
+        assert_eq!(decoded.next, Next::Ip);
+        assert!(decoded.find("TCP").is_none());
+
+        // Within the limit every layer is decoded
+        let decoded = decode(LINKTYPE_RAW, &packet[(tunnels - 2) * 20..]);
+        assert_eq!(decoded.innermost().unwrap().name, "TCP");
+        assert_eq!(decoded.innermost().unwrap().depth, 2);
+        assert_eq!(decoded.payload_length, 0);
+    }
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Serialize, Serializer};
+
+use crate::layout::FieldSpan;
+
+pub const ETHERNET_HEADER_SIZE: usize = 14;
+
+// EtherTypes, refer to ---> https://www.iana.org/assignments/ieee-802-numbers/ieee-802-numbers.xhtml
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+// Transparent Ethernet Bridging, Ethernet frames carried by GRE and GENEVE
+pub const ETHERTYPE_TEB: u16 = 0x6558;
+pub const ETHERTYPE_VLAN: u16 = 0x8100;
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+pub const ETHERTYPE_MPLS: u16 = 0x8847;
+pub const ETHERTYPE_MPLS_MULTICAST: u16 = 0x8848;
+pub const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    pub const BROADCAST: MacAddr = MacAddr([0xff; 6]);
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+// Serialized the way it is displayed, "aa:bb:cc:dd:ee:ff"
+impl Serialize for MacAddr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+// Accepts colon or dash separated hexadecimal octets
+impl FromStr for MacAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let octets: Vec<&str> = s.split([':', '-']).collect();
+        if octets.len() != 6 {
+            return Err(format!("Invalid MAC address '{}'", s));
+        }
+        let mut mac = [0u8; 6];
+        for (byte, octet) in mac.iter_mut().zip(octets) {
+            *byte = u8::from_str_radix(octet, 16)
+                .map_err(|_| format!("Invalid MAC address '{}'", s))?;
+        }
+        Ok(MacAddr(mac))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct EthernetHeader {
+    pub destination: MacAddr,
+    pub source: MacAddr,
+    // VLAN identifiers of the 802.1Q tags, outermost first
+    pub vlans: Vec<u16>,
+    // EtherType of the payload, after the VLAN tags
+    pub ethertype: u16,
+}
+
+impl EthernetHeader {
+    pub fn new(destination: MacAddr, source: MacAddr, ethertype: u16) -> Self {
+        EthernetHeader {
+            destination,
+            source,
+            vlans: Vec::new(),
+            ethertype,
+        }
+    }
+
+    // Frames the payload behind the header and its VLAN tags
+    pub fn build(&self, payload: &[u8]) -> Vec<u8> {
+        let mut frame =
+            Vec::with_capacity(ETHERNET_HEADER_SIZE + self.vlans.len() * 4 + payload.len());
+        frame.extend_from_slice(&self.destination.0);
+        frame.extend_from_slice(&self.source.0);
+        for vlan in &self.vlans {
+            frame.extend_from_slice(&ETHERTYPE_VLAN.to_be_bytes());
+            frame.extend_from_slice(&vlan.to_be_bytes());
+        }
+        frame.extend_from_slice(&self.ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    // Parses the header along with its VLAN tags and returns it with the payload
+    pub fn parse(buffer: &[u8]) -> Option<(Self, &[u8])> {
+        if buffer.len() < ETHERNET_HEADER_SIZE {
+            return None;
+        }
+        let mut header = EthernetHeader {
+            destination: MacAddr(buffer[0..6].try_into().unwrap()),
+            source: MacAddr(buffer[6..12].try_into().unwrap()),
+            vlans: Vec::new(),
+            ethertype: u16::from_be_bytes([buffer[12], buffer[13]]),
+        };
+        let mut offset = ETHERNET_HEADER_SIZE;
+        while header.ethertype == ETHERTYPE_VLAN || header.ethertype == ETHERTYPE_QINQ {
+            // Tag control information, then the inner EtherType
+            let tag = buffer.get(offset..offset + 4)?;
+            header
+                .vlans
+                .push(u16::from_be_bytes([tag[0], tag[1]]) & 0x0fff);
+            header.ethertype = u16::from_be_bytes([tag[2], tag[3]]);
+            offset += 4;
+        }
+        Some((header, &buffer[offset..]))
+    }
+
+    // Where each field sits in the header, tags included
+    pub fn spans(&self) -> Vec<FieldSpan> {
+        let mut spans = vec![
+            FieldSpan::new("Destination", 0, 6, self.destination),
+            FieldSpan::new("Source", 6, 6, self.source),
+        ];
+        for (index, vlan) in self.vlans.iter().enumerate() {
+            spans.push(FieldSpan::new("Tag Protocol", 12 + index * 4, 2, "802.1Q"));
+            spans.push(FieldSpan::new("VLAN", 14 + index * 4, 2, vlan));
+        }
+        spans.push(FieldSpan::new(
+            "Type",
+            12 + self.vlans.len() * 4,
+            2,
+            format!("0x{:04x}", self.ethertype),
+        ));
+        spans
+    }
+}
@@ -0,0 +1,213 @@
+// Offline enrichment of addresses with the country, city, AS number and organisation
+// found in MaxMind-format databases (GeoLite2 City, Country and ASN). Addresses in
+// private, reserved or unallocated ranges never reach the databases, they get a label
+// naming the range instead.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+use maxminddb::{geoip2, Reader};
+use serde::Serialize;
+
+// IPv4 ranges that aren't routed on the internet
+// Refer to ---> https://www.iana.org/assignments/iana-ipv4-special-registry/iana-ipv4-special-registry.xhtml
+const SPECIAL_V4: &[(Ipv4Addr, u32, &str)] = &[
+    (Ipv4Addr::new(0, 0, 0, 0), 8, "this network"),
+    (Ipv4Addr::new(10, 0, 0, 0), 8, "private"),
+    (Ipv4Addr::new(100, 64, 0, 0), 10, "carrier-grade NAT"),
+    (Ipv4Addr::new(127, 0, 0, 0), 8, "loopback"),
+    (Ipv4Addr::new(169, 254, 0, 0), 16, "link-local"),
+    (Ipv4Addr::new(172, 16, 0, 0), 12, "private"),
+    (Ipv4Addr::new(192, 0, 0, 0), 24, "reserved"),
+    (Ipv4Addr::new(192, 0, 2, 0), 24, "documentation"),
+    (Ipv4Addr::new(192, 88, 99, 0), 24, "reserved"),
+    (Ipv4Addr::new(192, 168, 0, 0), 16, "private"),
+    (Ipv4Addr::new(198, 18, 0, 0), 15, "benchmarking"),
+    (Ipv4Addr::new(198, 51, 100, 0), 24, "documentation"),
+    (Ipv4Addr::new(203, 0, 113, 0), 24, "documentation"),
+    (Ipv4Addr::new(224, 0, 0, 0), 4, "multicast"),
+    (Ipv4Addr::new(255, 255, 255, 255), 32, "broadcast"),
+    (Ipv4Addr::new(240, 0, 0, 0), 4, "reserved"),
+];
+
+// Refer to ---> https://www.iana.org/assignments/iana-ipv6-special-registry/iana-ipv6-special-registry.xhtml
+const SPECIAL_V6: &[(Ipv6Addr, u32, &str)] = &[
+    (Ipv6Addr::UNSPECIFIED, 128, "unspecified"),
+    (Ipv6Addr::LOCALHOST, 128, "loopback"),
+    (Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0), 96, "NAT64"),
+    (Ipv6Addr::new(0x100, 0, 0, 0, 0, 0, 0, 0), 64, "reserved"),
+    (
+        Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+        32,
+        "documentation",
+    ),
+    (Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0), 7, "private"),
+    (Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 10, "link-local"),
+    (Ipv6Addr::new(0xff00, 0, 0, 0, 0, 0, 0, 0), 8, "multicast"),
+    // Global unicast, anything outside of it is unallocated
+    (Ipv6Addr::new(0x2000, 0, 0, 0, 0, 0, 0, 0), 3, ""),
+];
+
+// What the databases and the special ranges tell about an address
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Enrichment {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organisation: Option<String>,
+}
+
+impl Enrichment {
+    pub fn is_empty(&self) -> bool {
+        *self == Enrichment::default()
+    }
+}
+
+// Shortest form first: the label, or "US Mountain View AS15169 Google LLC"
+impl fmt::Display for Enrichment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(label) = self.label {
+            return write!(f, "{}", label);
+        }
+        let mut parts = Vec::new();
+        if let Some(code) = &self.country_code {
+            parts.push(code.clone());
+        }
+        if let Some(city) = &self.city {
+            parts.push(city.clone());
+        }
+        if let Some(asn) = self.asn {
+            parts.push(format!("AS{}", asn));
+        }
+        if let Some(organisation) = &self.organisation {
+            parts.push(organisation.clone());
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+// Names the special range the address falls in, unallocated IPv6 space is a bogon
+pub fn special_label(address: IpAddr) -> Option<&'static str> {
+    match address {
+        IpAddr::V4(address) => SPECIAL_V4
+            .iter()
+            .find(|(network, prefix, _)| {
+                in_network(u32::from(address), u32::from(*network), *prefix)
+            })
+            .map(|(_, _, label)| *label),
+        IpAddr::V6(address) => {
+            if let Some(mapped) = address.to_ipv4_mapped() {
+                return special_label(IpAddr::V4(mapped));
+            }
+            match SPECIAL_V6.iter().find(|(network, prefix, _)| {
+                in_network(u128::from(address), u128::from(*network), *prefix)
+            }) {
+                Some((_, _, "")) => None,
+                Some((_, _, label)) => Some(label),
+                None => Some("bogon"),
+            }
+        }
+    }
+}
+
+fn in_network<T>(address: T, network: T, prefix: u32) -> bool
+where
+    T: Copy + Eq + std::ops::BitXor<Output = T> + std::ops::Shr<u32, Output = T> + Default,
+{
+    let bits = std::mem::size_of::<T>() as u32 * 8;
+    prefix == 0 || (address ^ network) >> (bits - prefix) == T::default()
+}
+
+// City (or Country) and ASN databases, either may be missing
+#[derive(Default)]
+pub struct GeoIp {
+    city: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+    // Only labels the special ranges until databases are opened
+    pub fn new() -> Self {
+        GeoIp::default()
+    }
+
+    // Opens a database, its metadata tells which kind it is
+    pub fn open(&mut self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        let reader =
+            Reader::open_readfile(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let kind = reader.metadata.database_type.clone();
+        if kind.contains("ASN") {
+            self.asn = Some(reader);
+        } else if kind.contains("City") || kind.contains("Country") {
+            self.city = Some(reader);
+        } else {
+            return Err(format!(
+                "{}: unsupported database type {}, expected City, Country or ASN",
+                path.display(),
+                kind
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn lookup(&self, address: IpAddr) -> Enrichment {
+        let mut enrichment = Enrichment {
+            label: special_label(address),
+            ..Enrichment::default()
+        };
+        if enrichment.label.is_some() {
+            return enrichment;
+        }
+        // The databases hold IPv4 mapped into IPv6 under their IPv4 form
+        let address = match address {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+            address => address,
+        };
+
+        if let Some(Ok(city)) = self
+            .city
+            .as_ref()
+            .map(|reader| reader.lookup::<geoip2::City>(address))
+        {
+            let country = city.country.or(city.registered_country);
+            if let Some(country) = country {
+                enrichment.country_code = country.iso_code.map(String::from);
+                enrichment.country = english(country.names);
+            }
+            enrichment.city = city.city.and_then(|city| english(city.names));
+        }
+        if let Some(Ok(asn)) = self
+            .asn
+            .as_ref()
+            .map(|reader| reader.lookup::<geoip2::Asn>(address))
+        {
+            enrichment.asn = asn.autonomous_system_number;
+            enrichment.organisation = asn.autonomous_system_organization.map(String::from);
+        }
+        enrichment
+    }
+
+    // The address followed by what is known about it, "8.8.8.8 (US AS15169 Google LLC)"
+    pub fn annotate(&self, address: IpAddr) -> String {
+        let enrichment = self.lookup(address);
+        if enrichment.is_empty() {
+            address.to_string()
+        } else {
+            format!("{} ({})", address, enrichment)
+        }
+    }
+}
+
+fn english(names: Option<std::collections::BTreeMap<&str, &str>>) -> Option<String> {
+    names?.get("en").map(|name| name.to_string())
+}
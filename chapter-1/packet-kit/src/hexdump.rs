@@ -0,0 +1,202 @@
+use std::fmt::Write;
+
+use crate::layout::{byte_owners, LayerSpans};
+
+// Bytes per line of the dump
+pub const HEXDUMP_WIDTH: usize = 16;
+
+// ANSI colours of the layers in order, a normal and a bright shade each
+const LAYER_COLORS: &[(u8, u8)] = &[(36, 96), (33, 93), (35, 95), (32, 92), (34, 94), (31, 91)];
+
+// Layer of each byte and whether it takes the alternate shade. The shade flips at
+// every field boundary inside a layer so neighbouring fields stand apart.
+pub fn byte_shades(owners: &[Option<(usize, usize)>]) -> Vec<Option<(usize, bool)>> {
+    let mut previous: Option<(usize, usize, bool)> = None;
+    owners
+        .iter()
+        .map(|owner| {
+            owner.map(|(layer, field)| {
+                let alternate = match previous {
+                    Some((previous_layer, previous_field, alternate))
+                        if previous_layer == layer =>
+                    {
+                        if previous_field == field {
+                            alternate
+                        } else {
+                            !alternate
+                        }
+                    }
+                    _ => false,
+                };
+                previous = Some((layer, field, alternate));
+                (layer, alternate)
+            })
+        })
+        .collect()
+}
+
+fn paint(text: &str, shade: Option<(usize, bool)>, color: bool) -> String {
+    match shade {
+        Some((layer, alternate)) if color => {
+            let (normal, bright) = LAYER_COLORS[layer % LAYER_COLORS.len()];
+            let code = if alternate { bright } else { normal };
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        }
+        _ => text.to_string(),
+    }
+}
+
+// Offsets, hexadecimal and ASCII columns with every layer in its own colour, then a
+// legend giving the field behind each byte range and the value decoded from it.
+// Without colour the legend alone tells the fields apart.
+pub fn annotated_hexdump(data: &[u8], layers: &[LayerSpans], color: bool) -> String {
+    let shades = byte_shades(&byte_owners(data.len(), layers));
+    let mut out = String::new();
+
+    for (line, chunk) in data.chunks(HEXDUMP_WIDTH).enumerate() {
+        let start = line * HEXDUMP_WIDTH;
+        let _ = write!(out, "{:04x}  ", start);
+        for (index, byte) in chunk.iter().enumerate() {
+            out += &paint(&format!("{:02x}", byte), shades[start + index], color);
+            out.push(' ');
+        }
+        out += &"   ".repeat(HEXDUMP_WIDTH - chunk.len());
+        out.push(' ');
+        for (index, byte) in chunk.iter().enumerate() {
+            let character = if byte.is_ascii_graphic() || *byte == b' ' {
+                *byte as char
+            } else {
+                '.'
+            };
+            out += &paint(&character.to_string(), shades[start + index], color);
+        }
+        out.push('\n');
+    }
+
+    // Layers carried by tunnels are indented under them
+    for layer in layers {
+        let indent = "  ".repeat(layer.depth);
+        let _ = writeln!(out, "\n{}{}", indent, layer.name);
+        for field in &layer.fields {
+            let range = format!(
+                "{:04x}-{:04x}",
+                field.offset,
+                (field.offset + field.length).saturating_sub(1)
+            );
+            let shade = shades.get(field.offset).copied().flatten();
+            let _ = writeln!(
+                out,
+                "{}  {}  {}: {}",
+                indent,
+                paint(&range, shade, color),
+                field.name,
+                field.value
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::layout::layout;
+    use crate::pcap::LINKTYPE_RAW;
+    use crate::{Ipv4Builder, UdpBuilder};
+
+    // 20 bytes of IPv4, 8 of UDP and 17 of payload the dissectors leave alone
+    fn datagram() -> Vec<u8> {
+        Ipv4Builder::new()
+            .src(Ipv4Addr::new(192, 0, 2, 1))
+            .dst(Ipv4Addr::new(198, 51, 100, 7))
+            .id(0x1234)
+            .payload(UdpBuilder::new(40000, 9).payload(b"hello, packet-kit"))
+            .build()
+    }
+
+    #[test]
+    fn lays_out_every_byte() {
+        let packet = datagram();
+        let layers = layout(LINKTYPE_RAW, &packet);
+        let names: Vec<&str> = layers.iter().map(|layer| layer.name).collect();
+        assert_eq!(names, ["IPv4", "UDP", "Payload"]);
+
+        let owners = byte_owners(packet.len(), &layers);
+        let owner = |offset: usize| {
+            let (layer, field) = owners[offset].unwrap();
+            (layers[layer].name, layers[layer].fields[field].name)
+        };
+        // Where fields share a byte the last, most specific, one owns it
+        assert_eq!(owner(0), ("IPv4", "Header Length"));
+        assert_eq!(owner(6), ("IPv4", "Fragment Offset"));
+        assert_eq!(owner(9), ("IPv4", "Protocol"));
+        assert_eq!(owner(19), ("IPv4", "Destination"));
+        assert_eq!(owner(20), ("UDP", "Source Port"));
+        assert_eq!(owner(27), ("UDP", "Checksum"));
+        assert_eq!(owner(28), ("Payload", "Data"));
+        assert_eq!(owner(44), ("Payload", "Data"));
+
+        // The shade flips at every field boundary and starts over with each layer
+        let shades = byte_shades(&owners);
+        let expected = [
+            (0, false),
+            (0, true),
+            (0, false),
+            (0, false),
+            (0, true),
+            (0, true),
+            (0, false),
+            (0, false),
+        ];
+        assert_eq!(&shades[..8], expected.map(Some));
+        assert_eq!(
+            shades[20..24],
+            [(1, false), (1, false), (1, true), (1, true)].map(Some)
+        );
+        assert_eq!(shades[28], Some((2, false)));
+
+        // Bytes past the decoded layers belong to none
+        let owners = byte_owners(packet.len(), &layers[..2]);
+        assert_eq!(owners[27], Some((1, 3)));
+        assert_eq!(owners[28], None);
+    }
+
+    #[test]
+    fn renders_rows_and_legend() {
+        let packet = datagram();
+        let layers = layout(LINKTYPE_RAW, &packet);
+        let dump = annotated_hexdump(&packet, &layers, false);
+        let lines: Vec<&str> = dump.lines().collect();
+        // The short last row is padded so its ASCII column lines up
+        assert_eq!(
+            lines[..3],
+            [
+                "0000  45 00 00 2d 12 34 00 00 40 11 7c 50 c0 00 02 01  E..-.4..@.|P....",
+                "0010  c6 33 64 07 9c 40 00 09 00 19 f2 67 68 65 6c 6c  .3d..@.....ghell",
+                "0020  6f 2c 20 70 61 63 6b 65 74 2d 6b 69 74           o, packet-kit",
+            ]
+        );
+        for line in [
+            "IPv4",
+            "  0000-0000  Header Length: 20 bytes",
+            "  0006-0007  Fragment Offset: 0",
+            "  0010-0013  Destination: 198.51.100.7",
+            "UDP",
+            "  0014-0015  Source Port: 40000",
+            "  001a-001b  Checksum: 0xf267",
+            "Payload",
+            "  001c-002c  Data: 17 bytes",
+        ] {
+            assert!(lines.contains(&line), "{}", line);
+        }
+
+        // Each layer in its colour, the legend ranges in the shade of their first byte
+        let dump = annotated_hexdump(&packet, &layers, true);
+        assert!(dump.starts_with("0000  \x1b[36m45\x1b[0m \x1b[96m00\x1b[0m "));
+        assert!(dump.contains(" \x1b[33m9c\x1b[0m \x1b[33m40\x1b[0m \x1b[93m00\x1b[0m "));
+        assert!(dump.contains("\x1b[35mh\x1b[0m\x1b[35me\x1b[0m"));
+        assert!(dump.contains("  \x1b[93m0016-0017\x1b[0m  Destination Port: 9\n"));
+    }
+}
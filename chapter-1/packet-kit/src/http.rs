@@ -0,0 +1,110 @@
+use serde::Serialize;
+
+use crate::layout::FieldSpan;
+
+// Ports HTTP servers usually listen on
+pub const HTTP_PORTS: &[u16] = &[80, 8000, 8008, 8080];
+
+const METHODS: &[&str] = &[
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+// The start line and headers of an HTTP/1.x request or response, as far as the
+// segment holds them. The body is left alone.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct HttpMessage {
+    pub start_line: String,
+    // Headers in the order they were sent
+    pub headers: Vec<(String, String)>,
+    // Bytes of the start line and headers, the blank line after them included
+    pub header_length: usize,
+    // Offset and length of every line, line ending included
+    #[serde(skip)]
+    lines: Vec<(usize, usize)>,
+}
+
+impl HttpMessage {
+    // Whether the data starts like a request or a response
+    pub fn looks_like_http(data: &[u8]) -> bool {
+        if data.starts_with(b"HTTP/1.") {
+            return true;
+        }
+        METHODS.iter().any(|method| {
+            data.len() > method.len()
+                && data.starts_with(method.as_bytes())
+                && data[method.len()] == b' '
+        })
+    }
+
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if !Self::looks_like_http(data) {
+            return None;
+        }
+        let mut text = Vec::new();
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while let Some(end) = data[offset..].windows(2).position(|pair| pair == b"\r\n") {
+            let line = &data[offset..offset + end];
+            lines.push((offset, end + 2));
+            offset += end + 2;
+            if line.is_empty() {
+                break;
+            }
+            text.push(std::str::from_utf8(line).ok()?);
+        }
+        let (start_line, headers) = text.split_first()?;
+        // A response starts with the version, a request ends with it
+        let version = |line: &str| line.starts_with("HTTP/1.") || line.ends_with(" HTTP/1.1");
+        if !version(start_line) && !start_line.ends_with(" HTTP/1.0") {
+            return None;
+        }
+
+        let mut message = HttpMessage {
+            start_line: start_line.to_string(),
+            headers: Vec::new(),
+            header_length: offset,
+            lines: vec![lines[0]],
+        };
+        for (line, extent) in headers.iter().zip(&lines[1..]) {
+            if let Some((name, value)) = line.split_once(':') {
+                message
+                    .headers
+                    .push((name.trim().to_string(), value.trim().to_string()));
+                message.lines.push(*extent);
+            }
+        }
+        Some(message)
+    }
+
+    pub fn is_request(&self) -> bool {
+        !self.start_line.starts_with("HTTP/")
+    }
+
+    // Value of a header, names compared case insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Where each line sits in the message
+    pub fn spans(&self) -> Vec<FieldSpan> {
+        let name = if self.is_request() {
+            "Request Line"
+        } else {
+            "Status Line"
+        };
+        let (offset, length) = self.lines[0];
+        let mut spans = vec![FieldSpan::new(name, offset, length, &self.start_line)];
+        for ((header, value), (offset, length)) in self.headers.iter().zip(&self.lines[1..]) {
+            spans.push(FieldSpan::new(
+                "Header",
+                *offset,
+                *length,
+                format!("{}: {}", header, value),
+            ));
+        }
+        spans
+    }
+}
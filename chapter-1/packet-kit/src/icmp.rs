@@ -0,0 +1,156 @@
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+use crate::layout::FieldSpan;
+use crate::{checksum, pseudo_header_checksum, Layer, PROTOCOL_ICMP, PROTOCOL_ICMPV6};
+
+pub const ICMP_HEADER_SIZE: usize = 8;
+
+// Builds an ICMP (RFC 792) or ICMPv6 (RFC 4443) message
+#[derive(Clone)]
+pub struct IcmpBuilder {
+    v6: bool,
+    type_: u8,
+    code: u8,
+    // The four bytes following the checksum, their meaning depends on the type
+    rest: [u8; 4],
+    payload: Vec<u8>,
+}
+
+impl IcmpBuilder {
+    pub fn new(type_: u8, code: u8) -> Self {
+        IcmpBuilder {
+            v6: false,
+            type_,
+            code,
+            rest: [0; 4],
+            payload: Vec::new(),
+        }
+    }
+
+    // ICMPv6 messages include a pseudo header in their checksum
+    pub fn new_v6(type_: u8, code: u8) -> Self {
+        IcmpBuilder {
+            v6: true,
+            ..IcmpBuilder::new(type_, code)
+        }
+    }
+
+    pub fn echo_request(id: u16, sequence: u16) -> Self {
+        IcmpBuilder::new(8, 0).echo(id, sequence)
+    }
+
+    pub fn echo_reply(id: u16, sequence: u16) -> Self {
+        IcmpBuilder::new(0, 0).echo(id, sequence)
+    }
+
+    pub fn echo_request_v6(id: u16, sequence: u16) -> Self {
+        IcmpBuilder::new_v6(128, 0).echo(id, sequence)
+    }
+
+    pub fn echo_reply_v6(id: u16, sequence: u16) -> Self {
+        IcmpBuilder::new_v6(129, 0).echo(id, sequence)
+    }
+
+    fn echo(mut self, id: u16, sequence: u16) -> Self {
+        self.rest[..2].copy_from_slice(&id.to_be_bytes());
+        self.rest[2..].copy_from_slice(&sequence.to_be_bytes());
+        self
+    }
+
+    pub fn rest_of_header(mut self, rest: u32) -> Self {
+        self.rest = rest.to_be_bytes();
+        self
+    }
+
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.payload = payload.to_vec();
+        self
+    }
+}
+
+impl Layer for IcmpBuilder {
+    fn protocol(&self) -> u8 {
+        if self.v6 {
+            PROTOCOL_ICMPV6
+        } else {
+            PROTOCOL_ICMP
+        }
+    }
+
+    fn build(&self, src: IpAddr, dst: IpAddr) -> Vec<u8> {
+        let mut message = Vec::with_capacity(ICMP_HEADER_SIZE + self.payload.len());
+        message.push(self.type_);
+        message.push(self.code);
+        message.extend_from_slice(&[0, 0]);
+        message.extend_from_slice(&self.rest);
+        message.extend_from_slice(&self.payload);
+
+        let sum = if self.v6 {
+            pseudo_header_checksum(src, dst, PROTOCOL_ICMPV6, &message)
+        } else {
+            checksum(&message)
+        };
+        message[2..4].copy_from_slice(&sum.to_be_bytes());
+        message
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct IcmpHeader {
+    #[serde(rename = "type")]
+    pub type_: u8,
+    pub code: u8,
+    pub checksum: u16,
+    pub rest: [u8; 4],
+}
+
+impl IcmpHeader {
+    // Parses the header and returns it along with the message body
+    pub fn parse(buffer: &[u8]) -> Option<(Self, &[u8])> {
+        if buffer.len() < ICMP_HEADER_SIZE {
+            return None;
+        }
+        let header = IcmpHeader {
+            type_: buffer[0],
+            code: buffer[1],
+            checksum: u16::from_be_bytes([buffer[2], buffer[3]]),
+            rest: [buffer[4], buffer[5], buffer[6], buffer[7]],
+        };
+        Some((header, &buffer[ICMP_HEADER_SIZE..]))
+    }
+
+    // Identifier of echo requests and replies
+    pub fn id(&self) -> u16 {
+        u16::from_be_bytes([self.rest[0], self.rest[1]])
+    }
+
+    // Sequence number of echo requests and replies
+    pub fn sequence(&self) -> u16 {
+        u16::from_be_bytes([self.rest[2], self.rest[3]])
+    }
+
+    // Where each field sits in the header, echo messages split the rest of it
+    pub fn spans(&self) -> Vec<FieldSpan> {
+        let mut spans = vec![
+            FieldSpan::new("Type", 0, 1, self.type_),
+            FieldSpan::new("Code", 1, 1, self.code),
+            FieldSpan::new("Checksum", 2, 2, format!("0x{:04x}", self.checksum)),
+        ];
+        match self.type_ {
+            // Echo reply and request, over IPv4 then IPv6
+            0 | 8 | 128 | 129 => {
+                spans.push(FieldSpan::new("Identifier", 4, 2, self.id()));
+                spans.push(FieldSpan::new("Sequence Number", 6, 2, self.sequence()));
+            }
+            _ => spans.push(FieldSpan::new(
+                "Rest of Header",
+                4,
+                4,
+                format!("{:02x?}", self.rest),
+            )),
+        }
+        spans
+    }
+}
@@ -0,0 +1,342 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use serde::Serialize;
+
+use crate::layout::FieldSpan;
+use crate::{checksum, Layer, PROTOCOL_IPV4};
+
+// Size of the IPv4 header without options
+pub const IPV4_HEADER_SIZE: usize = 20;
+
+// What the 4-bit header length leaves for options
+pub const MAX_OPTIONS_SIZE: usize = 40;
+
+// Flags sharing the 16 bits of the fragment offset
+pub const DONT_FRAGMENT: u8 = 0b010;
+pub const MORE_FRAGMENTS: u8 = 0b001;
+
+// Builds an IPv4 packet around the payload layer (RFC 791)
+pub struct Ipv4Builder {
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    tos: u8,
+    id: u16,
+    flags: u8,
+    // Offset of the fragment in 8-byte units
+    fragment_offset: u16,
+    ttl: u8,
+    protocol: Option<u8>,
+    options: Vec<u8>,
+    payload: Option<Box<dyn Layer>>,
+}
+
+impl Default for Ipv4Builder {
+    fn default() -> Self {
+        Ipv4Builder::new()
+    }
+}
+
+impl Ipv4Builder {
+    pub fn new() -> Self {
+        Ipv4Builder {
+            src: Ipv4Addr::UNSPECIFIED,
+            dst: Ipv4Addr::UNSPECIFIED,
+            tos: 0,
+            id: 0,
+            flags: 0,
+            fragment_offset: 0,
+            ttl: 64,
+            protocol: None,
+            options: Vec::new(),
+            payload: None,
+        }
+    }
+
+    pub fn src(mut self, src: Ipv4Addr) -> Self {
+        self.src = src;
+        self
+    }
+
+    pub fn dst(mut self, dst: Ipv4Addr) -> Self {
+        self.dst = dst;
+        self
+    }
+
+    pub fn tos(mut self, tos: u8) -> Self {
+        self.tos = tos;
+        self
+    }
+
+    pub fn id(mut self, id: u16) -> Self {
+        self.id = id;
+        self
+    }
+
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn dont_fragment(mut self) -> Self {
+        self.flags |= DONT_FRAGMENT;
+        self
+    }
+
+    pub fn more_fragments(mut self) -> Self {
+        self.flags |= MORE_FRAGMENTS;
+        self
+    }
+
+    pub fn fragment_offset(mut self, offset: u16) -> Self {
+        self.fragment_offset = offset & 0x1fff;
+        self
+    }
+
+    // Overrides the protocol number taken from the payload layer
+    pub fn protocol(mut self, protocol: u8) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    // Raw options, padded with end of option list bytes to a multiple of 4 bytes.
+    // Panics past 40 bytes, which the header length can't count.
+    pub fn options(mut self, options: &[u8]) -> Self {
+        assert!(
+            options.len() <= MAX_OPTIONS_SIZE,
+            "IPv4 options take at most {} bytes, not {}",
+            MAX_OPTIONS_SIZE,
+            options.len()
+        );
+        self.options = options.to_vec();
+        self.options.resize(options.len().div_ceil(4) * 4, 0);
+        self
+    }
+
+    pub fn payload<L: Layer + 'static>(mut self, payload: L) -> Self {
+        self.payload = Some(Box::new(payload));
+        self
+    }
+
+    // Serialises the packet, filling the header length, total length and checksum
+    pub fn build(&self) -> Vec<u8> {
+        let payload = match &self.payload {
+            Some(payload) => payload.build(IpAddr::V4(self.src), IpAddr::V4(self.dst)),
+            None => Vec::new(),
+        };
+        let protocol = self
+            .protocol
+            .or(self.payload.as_ref().map(|payload| payload.protocol()))
+            .unwrap_or(0);
+
+        let header_length = IPV4_HEADER_SIZE + self.options.len();
+        let total_length = header_length + payload.len();
+        let mut packet = Vec::with_capacity(total_length);
+        // Version 4, the header length counts 32-bit words
+        packet.push(0x40 | (header_length / 4) as u8);
+        packet.push(self.tos);
+        packet.extend_from_slice(&(total_length as u16).to_be_bytes());
+        packet.extend_from_slice(&self.id.to_be_bytes());
+        let offset = (self.flags as u16) << 13 | self.fragment_offset;
+        packet.extend_from_slice(&offset.to_be_bytes());
+        packet.push(self.ttl);
+        packet.push(protocol);
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(&self.src.octets());
+        packet.extend_from_slice(&self.dst.octets());
+        packet.extend_from_slice(&self.options);
+
+        let sum = checksum(&packet);
+        packet[10..12].copy_from_slice(&sum.to_be_bytes());
+        packet.extend_from_slice(&payload);
+        packet
+    }
+}
+
+// An IPv4 packet can be tunnelled inside another IP packet (IP in IP, RFC 2003)
+impl Layer for Ipv4Builder {
+    fn protocol(&self) -> u8 {
+        PROTOCOL_IPV4
+    }
+
+    fn build(&self, _src: IpAddr, _dst: IpAddr) -> Vec<u8> {
+        Ipv4Builder::build(self)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Ipv4Header {
+    pub version: u8,
+    // Header length in bytes
+    pub header_length: usize,
+    pub tos: u8,
+    pub total_length: u16,
+    pub id: u16,
+    pub flags: u8,
+    pub fragment_offset: u16,
+    pub ttl: u8,
+    pub protocol: u8,
+    pub checksum: u16,
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub options: Vec<u8>,
+}
+
+impl Ipv4Header {
+    // Parses the header and returns it along with the payload it announces
+    pub fn parse(buffer: &[u8]) -> Option<(Self, &[u8])> {
+        if buffer.len() < IPV4_HEADER_SIZE || buffer[0] >> 4 != 4 {
+            return None;
+        }
+        let header_length = (buffer[0] & 0x0f) as usize * 4;
+        let total_length = u16::from_be_bytes([buffer[2], buffer[3]]);
+        if header_length < IPV4_HEADER_SIZE || buffer.len() < header_length {
+            return None;
+        }
+        // Captures may be truncated or padded, the payload never goes past either end
+        let end = (total_length as usize).clamp(header_length, buffer.len());
+        let offset = u16::from_be_bytes([buffer[6], buffer[7]]);
+
+        let header = Ipv4Header {
+            version: 4,
+            header_length,
+            tos: buffer[1],
+            total_length,
+            id: u16::from_be_bytes([buffer[4], buffer[5]]),
+            flags: (offset >> 13) as u8,
+            fragment_offset: offset & 0x1fff,
+            ttl: buffer[8],
+            protocol: buffer[9],
+            checksum: u16::from_be_bytes([buffer[10], buffer[11]]),
+            src: Ipv4Addr::new(buffer[12], buffer[13], buffer[14], buffer[15]),
+            dst: Ipv4Addr::new(buffer[16], buffer[17], buffer[18], buffer[19]),
+            options: buffer[IPV4_HEADER_SIZE..header_length].to_vec(),
+        };
+        Some((header, &buffer[header_length..end]))
+    }
+
+    // Where each field sits in the header
+    pub fn spans(&self) -> Vec<FieldSpan> {
+        let mut spans = vec![
+            FieldSpan::new("Version", 0, 1, self.version),
+            FieldSpan::new(
+                "Header Length",
+                0,
+                1,
+                format!("{} bytes", self.header_length),
+            ),
+            FieldSpan::new("Type of Service", 1, 1, format!("0x{:02x}", self.tos)),
+            FieldSpan::new("Total Length", 2, 2, self.total_length),
+            FieldSpan::new("Identification", 4, 2, format!("0x{:04x}", self.id)),
+            FieldSpan::new("Flags", 6, 1, format!("0b{:03b}", self.flags)),
+            FieldSpan::new("Fragment Offset", 6, 2, self.fragment_offset),
+            FieldSpan::new("TTL", 8, 1, self.ttl),
+            FieldSpan::new("Protocol", 9, 1, self.protocol),
+            FieldSpan::new("Header Checksum", 10, 2, format!("0x{:04x}", self.checksum)),
+            FieldSpan::new("Source", 12, 4, self.src),
+            FieldSpan::new("Destination", 16, 4, self.dst),
+        ];
+        if !self.options.is_empty() {
+            spans.push(FieldSpan::new(
+                "Options",
+                IPV4_HEADER_SIZE,
+                self.options.len(),
+                format!("{} bytes", self.options.len()),
+            ));
+        }
+        spans
+    }
+
+    // A header carrying its own checksum sums to zero
+    pub fn checksum_valid(buffer: &[u8]) -> bool {
+        match Ipv4Header::parse(buffer) {
+            Some((header, _)) => checksum(&buffer[..header.header_length]) == 0,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UdpBuilder;
+
+    fn builder() -> Ipv4Builder {
+        Ipv4Builder::new()
+            .src(Ipv4Addr::new(192, 0, 2, 1))
+            .dst(Ipv4Addr::new(198, 51, 100, 7))
+            .tos(0x10)
+            .id(0xbeef)
+            .ttl(17)
+    }
+
+    #[test]
+    fn header_survives_a_round_trip() {
+        let packet = builder()
+            .dont_fragment()
+            .payload(UdpBuilder::new(5353, 53).payload(b"hello"))
+            .build();
+        let (header, payload) = Ipv4Header::parse(&packet).unwrap();
+        assert_eq!(header.version, 4);
+        assert_eq!(header.header_length, IPV4_HEADER_SIZE);
+        assert_eq!(header.tos, 0x10);
+        assert_eq!(header.total_length as usize, packet.len());
+        assert_eq!(header.id, 0xbeef);
+        assert_eq!(header.flags, DONT_FRAGMENT);
+        assert_eq!(header.fragment_offset, 0);
+        assert_eq!(header.ttl, 17);
+        assert_eq!(header.protocol, crate::PROTOCOL_UDP);
+        assert_eq!(header.src, Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(header.dst, Ipv4Addr::new(198, 51, 100, 7));
+        assert_eq!(payload.len(), 8 + 5);
+        assert!(Ipv4Header::checksum_valid(&packet));
+    }
+
+    #[test]
+    fn options_are_padded_and_counted_in_the_header_length() {
+        // Record route with room for one address, 7 bytes padded to 8
+        let packet = builder()
+            .options(&[7, 7, 4, 0, 0, 0, 0])
+            .payload(vec![1, 2, 3])
+            .build();
+        let (header, payload) = Ipv4Header::parse(&packet).unwrap();
+        assert_eq!(header.header_length, IPV4_HEADER_SIZE + 8);
+        assert_eq!(header.options, [7, 7, 4, 0, 0, 0, 0, 0]);
+        assert_eq!(header.protocol, crate::PROTOCOL_RAW);
+        assert_eq!(payload, [1, 2, 3]);
+        assert!(Ipv4Header::checksum_valid(&packet));
+    }
+
+    #[test]
+    fn fragments_keep_their_flags_and_offset() {
+        let packet = builder()
+            .more_fragments()
+            .fragment_offset(185)
+            .protocol(crate::PROTOCOL_UDP)
+            .build();
+        let (header, _) = Ipv4Header::parse(&packet).unwrap();
+        assert_eq!(header.flags, MORE_FRAGMENTS);
+        assert_eq!(header.fragment_offset, 185);
+        assert_eq!(header.protocol, crate::PROTOCOL_UDP);
+    }
+
+    #[test]
+    fn forty_bytes_of_options_fill_the_header() {
+        let packet = builder().options(&[1; MAX_OPTIONS_SIZE]).build();
+        assert_eq!(packet[0], 0x4f);
+        let (header, _) = Ipv4Header::parse(&packet).unwrap();
+        assert_eq!(header.header_length, 60);
+    }
+
+    #[test]
+    #[should_panic(expected = "IPv4 options take at most 40 bytes")]
+    fn options_past_forty_bytes_are_rejected() {
+        builder().options(&[1; MAX_OPTIONS_SIZE + 1]);
+    }
+
+    #[test]
+    fn corrupted_header_fails_its_checksum() {
+        let mut packet = builder().build();
+        packet[8] ^= 1;
+        assert!(!Ipv4Header::checksum_valid(&packet));
+    }
+}
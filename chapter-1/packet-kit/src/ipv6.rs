@@ -0,0 +1,168 @@
+use std::net::{IpAddr, Ipv6Addr};
+
+use serde::Serialize;
+
+use crate::layout::FieldSpan;
+use crate::{Layer, PROTOCOL_IPV6};
+
+// Size of the fixed IPv6 header
+pub const IPV6_HEADER_SIZE: usize = 40;
+
+// Builds an IPv6 packet around the payload layer (RFC 8200)
+pub struct Ipv6Builder {
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+    traffic_class: u8,
+    flow_label: u32,
+    hop_limit: u8,
+    next_header: Option<u8>,
+    payload: Option<Box<dyn Layer>>,
+}
+
+impl Default for Ipv6Builder {
+    fn default() -> Self {
+        Ipv6Builder::new()
+    }
+}
+
+impl Ipv6Builder {
+    pub fn new() -> Self {
+        Ipv6Builder {
+            src: Ipv6Addr::UNSPECIFIED,
+            dst: Ipv6Addr::UNSPECIFIED,
+            traffic_class: 0,
+            flow_label: 0,
+            hop_limit: 64,
+            next_header: None,
+            payload: None,
+        }
+    }
+
+    pub fn src(mut self, src: Ipv6Addr) -> Self {
+        self.src = src;
+        self
+    }
+
+    pub fn dst(mut self, dst: Ipv6Addr) -> Self {
+        self.dst = dst;
+        self
+    }
+
+    pub fn traffic_class(mut self, traffic_class: u8) -> Self {
+        self.traffic_class = traffic_class;
+        self
+    }
+
+    // Only the low 20 bits are kept
+    pub fn flow_label(mut self, flow_label: u32) -> Self {
+        self.flow_label = flow_label & 0xfffff;
+        self
+    }
+
+    pub fn hop_limit(mut self, hop_limit: u8) -> Self {
+        self.hop_limit = hop_limit;
+        self
+    }
+
+    // Overrides the next header taken from the payload layer
+    pub fn next_header(mut self, next_header: u8) -> Self {
+        self.next_header = Some(next_header);
+        self
+    }
+
+    pub fn payload<L: Layer + 'static>(mut self, payload: L) -> Self {
+        self.payload = Some(Box::new(payload));
+        self
+    }
+
+    // Serialises the packet, filling the payload length
+    pub fn build(&self) -> Vec<u8> {
+        let payload = match &self.payload {
+            Some(payload) => payload.build(IpAddr::V6(self.src), IpAddr::V6(self.dst)),
+            None => Vec::new(),
+        };
+        let next_header = self
+            .next_header
+            .or(self.payload.as_ref().map(|payload| payload.protocol()))
+            // No next header
+            .unwrap_or(59);
+
+        let mut packet = Vec::with_capacity(IPV6_HEADER_SIZE + payload.len());
+        let first_word = 6u32 << 28 | (self.traffic_class as u32) << 20 | self.flow_label;
+        packet.extend_from_slice(&first_word.to_be_bytes());
+        packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        packet.push(next_header);
+        packet.push(self.hop_limit);
+        packet.extend_from_slice(&self.src.octets());
+        packet.extend_from_slice(&self.dst.octets());
+        packet.extend_from_slice(&payload);
+        packet
+    }
+}
+
+// An IPv6 packet can be tunnelled inside another IP packet (6in4, RFC 4213)
+impl Layer for Ipv6Builder {
+    fn protocol(&self) -> u8 {
+        PROTOCOL_IPV6
+    }
+
+    fn build(&self, _src: IpAddr, _dst: IpAddr) -> Vec<u8> {
+        Ipv6Builder::build(self)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Ipv6Header {
+    pub version: u8,
+    pub traffic_class: u8,
+    pub flow_label: u32,
+    pub payload_length: u16,
+    pub next_header: u8,
+    pub hop_limit: u8,
+    pub src: Ipv6Addr,
+    pub dst: Ipv6Addr,
+}
+
+impl Ipv6Header {
+    // Parses the fixed header and returns it along with the payload it announces.
+    // Extension headers are left in the payload.
+    pub fn parse(buffer: &[u8]) -> Option<(Self, &[u8])> {
+        if buffer.len() < IPV6_HEADER_SIZE || buffer[0] >> 4 != 6 {
+            return None;
+        }
+        let first_word = u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]);
+        let payload_length = u16::from_be_bytes([buffer[4], buffer[5]]);
+        let end = (IPV6_HEADER_SIZE + payload_length as usize).min(buffer.len());
+
+        let header = Ipv6Header {
+            version: 6,
+            traffic_class: (first_word >> 20) as u8,
+            flow_label: first_word & 0xfffff,
+            payload_length,
+            next_header: buffer[6],
+            hop_limit: buffer[7],
+            src: Ipv6Addr::from(<[u8; 16]>::try_from(&buffer[8..24]).unwrap()),
+            dst: Ipv6Addr::from(<[u8; 16]>::try_from(&buffer[24..40]).unwrap()),
+        };
+        Some((header, &buffer[IPV6_HEADER_SIZE..end]))
+    }
+
+    // Where each field sits in the header
+    pub fn spans(&self) -> Vec<FieldSpan> {
+        vec![
+            FieldSpan::new("Version", 0, 1, self.version),
+            FieldSpan::new(
+                "Traffic Class",
+                0,
+                2,
+                format!("0x{:02x}", self.traffic_class),
+            ),
+            FieldSpan::new("Flow Label", 1, 3, format!("0x{:05x}", self.flow_label)),
+            FieldSpan::new("Payload Length", 4, 2, self.payload_length),
+            FieldSpan::new("Next Header", 6, 1, self.next_header),
+            FieldSpan::new("Hop Limit", 7, 1, self.hop_limit),
+            FieldSpan::new("Source", 8, 16, self.src),
+            FieldSpan::new("Destination", 24, 16, self.dst),
+        ]
+    }
+}
@@ -0,0 +1,63 @@
+use serde::Serialize;
+
+use crate::dissect;
+
+// Bytes of a header field and the value the parser read from them.
+// Fields sharing a byte, such as the IPv4 version and header length, overlap.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FieldSpan {
+    pub name: &'static str,
+    // Offset of the first byte, from the start of the header until the layout shifts it
+    pub offset: usize,
+    pub length: usize,
+    pub value: String,
+}
+
+impl FieldSpan {
+    pub fn new(name: &'static str, offset: usize, length: usize, value: impl ToString) -> Self {
+        FieldSpan {
+            name,
+            offset,
+            length,
+            value: value.to_string(),
+        }
+    }
+
+    fn end(&self) -> usize {
+        self.offset + self.length
+    }
+}
+
+// The fields of one decoded layer, offsets are from the start of the frame
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LayerSpans {
+    pub name: &'static str,
+    // Tunnels the layer is carried in, 0 for the outer packet
+    pub depth: usize,
+    pub fields: Vec<FieldSpan>,
+}
+
+// Maps every header field of the frame to its bytes, layer by layer. Tunnels are
+// followed into the packets they carry, which are laid out the same way one level deeper.
+// Decoding stops at the first layer that doesn't parse, what's left is payload.
+pub fn layout(linktype: u32, frame: &[u8]) -> Vec<LayerSpans> {
+    dissect::decode(linktype, frame).spans()
+}
+
+// Layer and field index owning each byte of the frame, None past the decoded layers.
+// Where fields overlap the last one wins, it's the most specific.
+pub fn byte_owners(length: usize, layers: &[LayerSpans]) -> Vec<Option<(usize, usize)>> {
+    let mut owners = vec![None; length];
+    for (layer_index, layer) in layers.iter().enumerate() {
+        for (field_index, field) in layer.fields.iter().enumerate() {
+            for owner in owners
+                .iter_mut()
+                .take(field.end().min(length))
+                .skip(field.offset)
+            {
+                *owner = Some((layer_index, field_index));
+            }
+        }
+    }
+    owners
+}
@@ -0,0 +1,179 @@
+// Layered packet builders and the matching header parsers.
+//
+// Every builder fills in lengths and checksums when it is serialised, the
+// transport layers only need the addresses of the IP layer carrying them:
+//
+//     let packet = Ipv4Builder::new()
+//         .src(Ipv4Addr::new(10, 0, 0, 1))
+//         .dst(Ipv4Addr::new(10, 0, 0, 2))
+//         .ttl(64)
+//         .payload(TcpBuilder::new(40000, 80).syn().mss(1460))
+//         .build();
+
+use std::net::IpAddr;
+
+pub mod arp;
+#[cfg(target_os = "linux")]
+pub mod capture;
+#[cfg(target_os = "linux")]
+pub mod cli;
+pub mod dissect;
+pub mod ethernet;
+pub mod geoip;
+pub mod hexdump;
+pub mod http;
+pub mod icmp;
+pub mod ipv4;
+pub mod ipv6;
+pub mod layout;
+pub mod names;
+pub mod output;
+pub mod pcap;
+#[cfg(target_os = "linux")]
+pub mod privilege;
+pub mod tcp;
+pub mod tunnel;
+pub mod udp;
+
+pub use arp::ArpPacket;
+pub use dissect::{DecodedLayer, DecodedPacket, Dissection, Dissector, Registry};
+pub use ethernet::{EthernetHeader, MacAddr};
+pub use geoip::{Enrichment, GeoIp};
+pub use hexdump::annotated_hexdump;
+pub use http::HttpMessage;
+pub use icmp::{IcmpBuilder, IcmpHeader};
+pub use ipv4::{Ipv4Builder, Ipv4Header};
+pub use ipv6::{Ipv6Builder, Ipv6Header};
+pub use layout::{layout, FieldSpan, LayerSpans};
+pub use output::Output;
+pub use pcap::{PcapPacket, PcapReader, PcapWriter};
+#[cfg(target_os = "linux")]
+pub use privilege::Capability;
+pub use tcp::{TcpBuilder, TcpHeader};
+pub use udp::{UdpBuilder, UdpHeader};
+
+// IP protocol numbers of the layers below
+// Refer to ---> https://www.iana.org/assignments/protocol-numbers/protocol-numbers.xhtml
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_IPV4: u8 = 4;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+pub const PROTOCOL_IPV6: u8 = 41;
+pub const PROTOCOL_GRE: u8 = 47;
+pub const PROTOCOL_ICMPV6: u8 = 58;
+// The older IP-in-IP number, some stacks still send it
+pub const PROTOCOL_IPIP: u8 = 94;
+pub const PROTOCOL_ETHERIP: u8 = 97;
+pub const PROTOCOL_MPLS_IN_IP: u8 = 137;
+// Reserved protocol number used for raw payloads that don't name their protocol
+pub const PROTOCOL_RAW: u8 = 255;
+
+// A layer that can be carried by an IP packet
+pub trait Layer {
+    // IP protocol number announced by the carrying header
+    fn protocol(&self) -> u8;
+
+    // Serialises the layer, the addresses of the carrying header feed the pseudo header checksums
+    fn build(&self, src: IpAddr, dst: IpAddr) -> Vec<u8>;
+}
+
+// Raw bytes are carried as they are
+impl Layer for Vec<u8> {
+    fn protocol(&self) -> u8 {
+        PROTOCOL_RAW
+    }
+
+    fn build(&self, _src: IpAddr, _dst: IpAddr) -> Vec<u8> {
+        self.clone()
+    }
+}
+
+impl Layer for &'static [u8] {
+    fn protocol(&self) -> u8 {
+        PROTOCOL_RAW
+    }
+
+    fn build(&self, _src: IpAddr, _dst: IpAddr) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+// Computes the internet checksum (RFC 1071) of the given data
+pub fn checksum(data: &[u8]) -> u16 {
+    !fold(sum_words(data, 0))
+}
+
+// Computes the checksum of a transport segment along with the pseudo header
+// made of the addresses, protocol and length (RFC 793 and RFC 8200 section 8.1)
+pub fn pseudo_header_checksum(src: IpAddr, dst: IpAddr, protocol: u8, segment: &[u8]) -> u16 {
+    let mut sum = 0;
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            sum = sum_words(&src.octets(), sum);
+            sum = sum_words(&dst.octets(), sum);
+        }
+        _ => {
+            sum = sum_words(&to_ipv6(src).octets(), sum);
+            sum = sum_words(&to_ipv6(dst).octets(), sum);
+        }
+    }
+    sum += protocol as u64;
+    sum += segment.len() as u64;
+    !fold(sum_words(segment, sum))
+}
+
+// Updates a checksum for bytes changed from `old` to `new` without summing the rest of
+// the data (RFC 1624 equation 3). Both are the same even length and start on a word boundary.
+pub fn update_checksum(checksum: u16, old: &[u8], new: &[u8]) -> u16 {
+    // Adding the complement of a word subtracts it
+    let removed: Vec<u8> = old.iter().map(|byte| !byte).collect();
+    !fold(sum_words(new, sum_words(&removed, !checksum as u64)))
+}
+
+// Mixed address families only happen on malformed input, IPv4 is then mapped into IPv6
+fn to_ipv6(address: IpAddr) -> std::net::Ipv6Addr {
+    match address {
+        IpAddr::V4(address) => address.to_ipv6_mapped(),
+        IpAddr::V6(address) => address,
+    }
+}
+
+// Adds the 16-bit words of the data, an odd trailing byte is padded with zero
+fn sum_words(data: &[u8], mut sum: u64) -> u64 {
+    for chunk in data.chunks(2) {
+        let word = if chunk.len() == 2 {
+            u16::from_be_bytes([chunk[0], chunk[1]])
+        } else {
+            u16::from_be_bytes([chunk[0], 0])
+        };
+        sum += word as u64;
+    }
+    sum
+}
+
+// Folds the carries back into the low 16 bits (one's complement addition)
+fn fold(mut sum: u64) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updated_checksum_matches_a_full_one() {
+        let mut data: Vec<u8> = (0..=255u8).map(|byte| byte.wrapping_mul(37)).collect();
+        let before = checksum(&data);
+        let old = data[40..46].to_vec();
+        data[40..46].copy_from_slice(&[0xff, 0x00, 0x12, 0x34, 0x00, 0x00]);
+        assert_eq!(
+            update_checksum(before, &old, &data[40..46]),
+            checksum(&data)
+        );
+        // Nothing changed, nothing to update
+        assert_eq!(update_checksum(before, &old, &old), before);
+    }
+}
@@ -0,0 +1,219 @@
+// Names of protocol numbers and ICMP messages, as shown in reports
+
+// Refer to ---> https://www.iana.org/assignments/icmp-parameters/icmp-parameters.xhtml
+pub const ICMP_TYPE_CODE_MAP: &[((u8, u8), &str)] = &[
+    ((0, 0), "Echo Reply"),
+    ((3, 0), "Destination Unreachable - Net is unreachable"),
+    ((3, 1), "Destination Unreachable - Host is unreachable"),
+    ((3, 2), "Destination Unreachable - Protocol is unreachable"),
+    ((3, 3), "Destination Unreachable - Port is unreachable"),
+    ((3, 4), "Destination Unreachable - Fragmentation is needed and Don't Fragment was set"),
+    ((3, 5), "Destination Unreachable - Source route failed"),
+    ((3, 6), "Destination Unreachable - Destination network is unknown"),
+    ((3, 7), "Destination Unreachable - Destination host is unknown"),
+    ((3, 8), "Destination Unreachable - Source host is isolated"),
+    ((3, 9), "Destination Unreachable - Communication with destination network is administratively prohibited"),
+    ((3, 10), "Destination Unreachable - Communication with destination host is administratively prohibited"),
+    ((3, 11), "Destination Unreachable - Destination network is unreachable for type of service"),
+    ((3, 12), "Destination Unreachable - Destination host is unreachable for type of service"),
+    ((3, 13), "Destination Unreachable - Communication is administratively prohibited"),
+    ((3, 14), "Destination Unreachable - Host precedence violation"),
+    ((3, 15), "Destination Unreachable - Precedence cutoff is in effect"),
+    ((4, 0), "Source Quench"),
+    ((5, 0), "Redirect"),
+    ((8, 0), "Echo"),
+    ((9, 0), "Router Advertisement"),
+    ((10, 0), "Router Selection"),
+    ((11, 0), "Time Exceeded"),
+    ((12, 0), "Parameter Problem"),
+    ((13, 0), "Timestamp"),
+    ((14, 0), "Timestamp Reply"),
+    ((15, 0), "Information Request"),
+    ((16, 0), "Information Reply"),
+    ((17, 0), "Address Mask Request"),
+    ((18, 0), "Address Mask Reply"),
+    ((30, 0), "Traceroute"),
+    ((40, 0), "Photuris"),
+    ((41, 0), "ICMP for IPv6"),
+    ((42, 0), "No Next Header for IPv6"),
+    ((43, 0), "Destination Unreachable for IPv6"),
+    ((44, 0), "Packet Too Big for IPv6"),
+    ((45, 0), "Time Exceeded for IPv6"),
+    ((46, 0), "Parameter Problem for IPv6"),
+    ((47, 0), "Echo Request for IPv6"),
+    ((48, 0), "Echo Reply for IPv6"),
+    ((49, 0), "Multicast Listener Query for IPv6"),
+    ((50, 0), "Multicast Listener Report for IPv6"),
+    ((51, 0), "Multicast Listener Done for IPv6"),
+    ((58, 0), "Router Solicitation for IPv6"),
+    ((59, 0), "Router Advertisement for IPv6"),
+    ((60, 0), "Neighbor Solicitation for IPv6"),
+    ((61, 0), "Neighbor Advertisement for IPv6"),
+    ((62, 0), "Redirect Message for IPv6"),
+];
+
+// Refer to ---> https://www.iana.org/assignments/protocol-numbers/protocol-numbers.xhtml
+pub fn protocol_name(protocol: u8) -> &'static str {
+    match protocol {
+        0 => "HOPOPT",
+        1 => "ICMP",
+        2 => "IGMP",
+        3 => "GGP",
+        4 => "IPv4",
+        5 => "ST",
+        6 => "TCP",
+        7 => "CBT",
+        8 => "EGP",
+        9 => "IGP",
+        10 => "BBN-RCC-MON",
+        11 => "NVP-II",
+        12 => "PUP",
+        13 => "ARGUS",
+        14 => "EMCON",
+        15 => "XNET",
+        16 => "CHAOS",
+        17 => "UDP",
+        18 => "MUX",
+        19 => "DCN-MEAS",
+        20 => "HMP",
+        21 => "PRM",
+        22 => "XNS-IDP",
+        23 => "TRUNK-1",
+        24 => "TRUNK-2",
+        25 => "LEAF-1",
+        26 => "LEAF-2",
+        27 => "RDP",
+        28 => "IRTP",
+        29 => "ISO-TP4",
+        30 => "NETBLT",
+        31 => "MFE-NSP",
+        32 => "MERIT-INP",
+        33 => "DCCP",
+        34 => "3PC",
+        35 => "IDPR",
+        36 => "XTP",
+        37 => "DDP",
+        38 => "IDPR-CMTP",
+        39 => "TP++",
+        40 => "IL",
+        41 => "IPv6",
+        42 => "SDRP",
+        43 => "IPv6-Route",
+        44 => "IPv6-Frag",
+        45 => "IDRP",
+        46 => "RSVP",
+        47 => "GRE",
+        48 => "DSR",
+        49 => "BNA",
+        50 => "ESP",
+        51 => "AH",
+        52 => "I-NLSP",
+        53 => "SWIPE (deprecated)",
+        54 => "NARP",
+        55 => "MOBILE",
+        56 => "TLSP",
+        57 => "SKIP",
+        58 => "IPv6-ICMP",
+        59 => "IPv6-NoNxt",
+        60 => "IPv6-Opts",
+        61 => "any host internal protocol",
+        62 => "CFTP",
+        63 => "any local network",
+        64 => "SAT-EXPAK",
+        65 => "KRYPTOLAN",
+        66 => "RVD",
+        67 => "IPPC",
+        68 => "any distributed file system",
+        69 => "SAT-MON",
+        70 => "VISA",
+        71 => "IPCV",
+        72 => "CPNX",
+        73 => "CPHB",
+        74 => "WSN",
+        75 => "PVP",
+        76 => "BR-SAT-MON",
+        77 => "SUN-ND",
+        78 => "WB-MON",
+        79 => "WB-EXPAK",
+        80 => "ISO-IP",
+        81 => "VMTP",
+        82 => "SECURE-VMTP",
+        83 => "VINES",
+        84 => "IPTM",
+        85 => "NSFNET-IGP",
+        86 => "DGP",
+        87 => "TCF",
+        88 => "EIGRP",
+        89 => "OSPFIGP",
+        90 => "Sprite-RPC",
+        91 => "LARP",
+        92 => "MTP",
+        93 => "AX.25",
+        94 => "IPIP",
+        95 => "MICP (deprecated)",
+        96 => "SCC-SP",
+        97 => "ETHERIP",
+        98 => "ENCAP",
+        99 => "any private encryption scheme",
+        100 => "GMTP",
+        101 => "IFMP",
+        102 => "PNNI",
+        103 => "PIM",
+        104 => "ARIS",
+        105 => "SCPS",
+        106 => "QNX",
+        107 => "A/N",
+        108 => "IPComp",
+        109 => "SNP",
+        110 => "Compaq-Peer",
+        111 => "IPX-in-IP",
+        112 => "VRRP",
+        113 => "PGM",
+        114 => "any 0-hop protocol",
+        115 => "L2TP",
+        116 => "DDX",
+        117 => "IATP",
+        118 => "STP",
+        119 => "SRP",
+        120 => "UTI",
+        121 => "SMP",
+        122 => "SM (deprecated)",
+        123 => "PTP",
+        124 => "ISIS over IPv4",
+        125 => "FIRE",
+        126 => "CRTP",
+        127 => "CRUDP",
+        128 => "SSCOPMCE",
+        129 => "IPLT",
+        130 => "SPS",
+        131 => "PIPE",
+        132 => "SCTP",
+        133 => "FC",
+        134 => "RSVP-E2E-IGNORE",
+        135 => "Mobility Header",
+        136 => "UDPLite",
+        137 => "MPLS-in-IP",
+        138 => "manet",
+        139 => "HIP",
+        140 => "Shim6",
+        141 => "WESP",
+        142 => "ROHC",
+        143 => "Ethernet",
+        144 => "AGGFRAG",
+        145 => "NSH",
+        146..=252 => "Unassigned",
+        253 => "Use for experimentation and testing",
+        254 => "Use for experimentation and testing",
+        255 => "Reserved",
+    }
+}
+
+// Name of an ICMP message, the type and code when the map doesn't know it
+pub fn icmp_type_name(type_: u8, code: u8) -> String {
+    for &((t, c), name) in ICMP_TYPE_CODE_MAP {
+        if t == type_ && c == code {
+            return name.to_string();
+        }
+    }
+    format!("Type: {}, Code: {}", type_, code)
+}
@@ -0,0 +1,48 @@
+// How the decoders print each packet, picked with --output
+use std::io::IsTerminal;
+
+use serde::Serialize;
+
+use crate::hexdump::annotated_hexdump;
+use crate::layout::layout;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+    Text,
+    // One indented object per packet
+    Json,
+    // One object per line
+    Ndjson,
+    // The bytes along with the header field each of them belongs to
+    Hex,
+}
+
+impl Output {
+    pub fn from_option(option: &str) -> Option<Self> {
+        match option {
+            "text" => Some(Output::Text),
+            "json" => Some(Output::Json),
+            "ndjson" => Some(Output::Ndjson),
+            "hex" => Some(Output::Hex),
+            _ => None,
+        }
+    }
+
+    // Prints the record in the JSON format picked, nothing for the other formats
+    pub fn print_json<T: Serialize>(self, record: &T) {
+        let json = match self {
+            Output::Json => serde_json::to_string_pretty(record),
+            Output::Ndjson => serde_json::to_string(record),
+            Output::Text | Output::Hex => return,
+        };
+        println!("{}", json.expect("records serialise to JSON"));
+    }
+}
+
+// Prints the frame as an annotated hex dump, coloured by layer when printing to a terminal
+pub fn print_hex(linktype: u32, frame: &[u8]) {
+    let layers = layout(linktype, frame);
+    let color = std::io::stdout().is_terminal();
+    print!("{}", annotated_hexdump(frame, &layers, color));
+    println!();
+}
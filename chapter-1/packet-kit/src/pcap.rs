@@ -0,0 +1,471 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::ethernet::{EthernetHeader, ETHERTYPE_IPV4, ETHERTYPE_IPV6};
+
+// Link types of the capture, refer to ---> https://www.tcpdump.org/linktypes.html
+pub const LINKTYPE_NULL: u32 = 0;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_IPV4: u32 = 228;
+pub const LINKTYPE_IPV6: u32 = 229;
+
+// Magic numbers of the global header, nanosecond captures use their own
+const MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const MAGIC_NANOS: u32 = 0xa1b23c4d;
+
+const GLOBAL_HEADER_SIZE: usize = 24;
+const RECORD_HEADER_SIZE: usize = 16;
+
+// pcapng block types (RFC draft-ietf-opsawg-pcapng), the section header doubles as magic number
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+// Written in the byte order of the capturing host
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+// Interface description option giving the timestamp resolution
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+// Larger blocks are taken for a corrupt capture
+const PCAPNG_MAX_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+// Longer records are taken for a corrupt capture, whatever the snap length claims
+const MAX_RECORD_SIZE: u32 = 262144;
+
+// A packet record of the capture
+#[derive(Clone, Debug)]
+pub struct PcapPacket {
+    // Time since the epoch the packet was captured at
+    pub timestamp: Duration,
+    // Length of the packet on the wire, data may hold less when the snap length cut it
+    pub original_length: u32,
+    // Link type of the interface the packet was captured on
+    pub linktype: u32,
+    pub data: Vec<u8>,
+}
+
+impl PcapPacket {
+    // The IPv4 packet carried by the frame, as a raw IPv4 socket would hand it over
+    pub fn ipv4(&self) -> Option<&[u8]> {
+        let packet = &self.data[network_offset(self.linktype, &self.data)?..];
+        (packet.len() >= 20 && packet[0] >> 4 == 4).then_some(packet)
+    }
+}
+
+// An interface of a pcapng section
+#[derive(Clone, Copy)]
+struct Interface {
+    linktype: u32,
+    snaplen: u32,
+    // Timestamp units per second
+    resolution: u64,
+}
+
+enum Format {
+    Pcap { nanos: bool },
+    // Interfaces are described again in every section
+    Pcapng { interfaces: Vec<Interface> },
+}
+
+// Reads the classic libpcap format, in either byte order and timestamp precision, and
+// pcapng captures. The link type and snap length are those of the first interface.
+pub struct PcapReader<R: Read> {
+    reader: R,
+    swapped: bool,
+    format: Format,
+    pub linktype: u32,
+    pub snaplen: u32,
+}
+
+impl PcapReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        PcapReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+fn invalid(message: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; GLOBAL_HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let (swapped, nanos) = match magic {
+            MAGIC_MICROS => (false, false),
+            MAGIC_NANOS => (false, true),
+            _ if magic.swap_bytes() == MAGIC_MICROS => (true, false),
+            _ if magic.swap_bytes() == MAGIC_NANOS => (true, true),
+            PCAPNG_SECTION_HEADER => return PcapReader::new_pcapng(reader, &header),
+            _ => return Err(invalid("Not a pcap capture")),
+        };
+
+        let mut capture = PcapReader {
+            reader,
+            swapped,
+            format: Format::Pcap { nanos },
+            linktype: 0,
+            snaplen: 0,
+        };
+        capture.snaplen = capture.read_u32(&header[16..20]);
+        // The upper bits carry the FCS length, only the low 16 bits name the link type
+        capture.linktype = capture.read_u32(&header[20..24]) & 0xffff;
+        Ok(capture)
+    }
+
+    // Picks up after the start of the section header, then reads up to the first
+    // interface description so that the link type is known
+    fn new_pcapng(reader: R, header: &[u8; GLOBAL_HEADER_SIZE]) -> io::Result<Self> {
+        let mut capture = PcapReader {
+            reader,
+            swapped: false,
+            format: Format::Pcapng {
+                interfaces: Vec::new(),
+            },
+            linktype: 0,
+            snaplen: 0,
+        };
+        capture.swapped = byte_order(&header[8..12])?;
+        let length = capture.read_u32(&header[4..8]) as usize;
+        capture.skip_block(length, GLOBAL_HEADER_SIZE)?;
+
+        loop {
+            let Some((block_type, body)) = capture.read_block()? else {
+                return Err(invalid("pcapng capture without any interface"));
+            };
+            if block_type == PCAPNG_INTERFACE_DESCRIPTION {
+                capture.add_interface(&body)?;
+                return Ok(capture);
+            }
+        }
+    }
+
+    fn read_u32(&self, bytes: &[u8]) -> u32 {
+        let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        if self.swapped {
+            value.swap_bytes()
+        } else {
+            value
+        }
+    }
+
+    fn read_u16(&self, bytes: &[u8]) -> u16 {
+        let value = u16::from_le_bytes([bytes[0], bytes[1]]);
+        if self.swapped {
+            value.swap_bytes()
+        } else {
+            value
+        }
+    }
+
+    // Whether timestamps are finer than microseconds
+    pub fn nanos(&self) -> bool {
+        match &self.format {
+            Format::Pcap { nanos } => *nanos,
+            Format::Pcapng { interfaces } => interfaces
+                .iter()
+                .any(|interface| interface.resolution > 1_000_000),
+        }
+    }
+
+    // Returns the next packet, or None once the capture is over
+    pub fn next_packet(&mut self) -> io::Result<Option<PcapPacket>> {
+        if matches!(self.format, Format::Pcapng { .. }) {
+            return self.next_pcapng_packet();
+        }
+
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        // A capture cut in the middle of a record header ends there
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        let seconds = self.read_u32(&header[0..4]);
+        let fraction = self.read_u32(&header[4..8]);
+        let captured_length = self.read_u32(&header[8..12]);
+        let original_length = self.read_u32(&header[12..16]);
+        if captured_length > MAX_RECORD_SIZE {
+            return Err(invalid(format!(
+                "Record of {} bytes exceeds the maximum record size",
+                captured_length
+            )));
+        }
+
+        let mut data = vec![0u8; captured_length as usize];
+        self.reader.read_exact(&mut data)?;
+        let fraction = if self.nanos() {
+            Duration::from_nanos(fraction as u64)
+        } else {
+            Duration::from_micros(fraction as u64)
+        };
+        Ok(Some(PcapPacket {
+            timestamp: Duration::from_secs(seconds as u64) + fraction,
+            original_length,
+            linktype: self.linktype,
+            data,
+        }))
+    }
+
+    fn next_pcapng_packet(&mut self) -> io::Result<Option<PcapPacket>> {
+        while let Some((block_type, body)) = self.read_block()? {
+            match block_type {
+                PCAPNG_INTERFACE_DESCRIPTION => self.add_interface(&body)?,
+                PCAPNG_ENHANCED_PACKET if body.len() >= 20 => {
+                    let interface = self.read_u32(&body[0..4]) as usize;
+                    let high = self.read_u32(&body[4..8]) as u64;
+                    let low = self.read_u32(&body[8..12]) as u64;
+                    let captured_length = self.read_u32(&body[12..16]) as usize;
+                    let original_length = self.read_u32(&body[16..20]);
+                    let data = body
+                        .get(20..20 + captured_length)
+                        .ok_or_else(|| invalid("Enhanced packet block shorter than its packet"))?;
+                    let Interface {
+                        linktype,
+                        resolution,
+                        ..
+                    } = *self.interface(interface)?;
+                    let timestamp = high << 32 | low;
+                    return Ok(Some(PcapPacket {
+                        timestamp: Duration::from_secs(timestamp / resolution)
+                            + Duration::from_nanos(
+                                ((timestamp % resolution) as u128 * 1_000_000_000
+                                    / resolution as u128) as u64,
+                            ),
+                        original_length,
+                        linktype,
+                        data: data.to_vec(),
+                    }));
+                }
+                // No timestamp, the packet was captured on the first interface and cut to its snap length
+                PCAPNG_SIMPLE_PACKET if body.len() >= 4 => {
+                    let original_length = self.read_u32(&body[0..4]);
+                    let Interface {
+                        linktype, snaplen, ..
+                    } = *self.interface(0)?;
+                    // A zero snap length means packets weren't cut
+                    let snaplen = if snaplen == 0 { u32::MAX } else { snaplen };
+                    let captured_length =
+                        (original_length.min(snaplen) as usize).min(body.len() - 4);
+                    return Ok(Some(PcapPacket {
+                        timestamp: Duration::ZERO,
+                        original_length,
+                        linktype,
+                        data: body[4..4 + captured_length].to_vec(),
+                    }));
+                }
+                // Name resolution, statistics and custom blocks are left alone
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    fn interface(&self, index: usize) -> io::Result<&Interface> {
+        let interfaces = match &self.format {
+            Format::Pcapng { interfaces } => interfaces.as_slice(),
+            Format::Pcap { .. } => &[],
+        };
+        interfaces
+            .get(index)
+            .ok_or_else(|| invalid(format!("Packet on undescribed interface {}", index)))
+    }
+
+    fn add_interface(&mut self, body: &[u8]) -> io::Result<()> {
+        if body.len() < 8 {
+            return Err(invalid("Interface description block too short"));
+        }
+        let linktype = self.read_u16(&body[0..2]) as u32;
+        let snaplen = self.read_u32(&body[4..8]);
+        let mut resolution = 1_000_000;
+
+        // Options are code, length and a value padded to 4 bytes
+        let mut offset = 8;
+        while offset + 4 <= body.len() {
+            let code = self.read_u16(&body[offset..offset + 2]);
+            let length = self.read_u16(&body[offset + 2..offset + 4]) as usize;
+            let value = body
+                .get(offset + 4..offset + 4 + length)
+                .ok_or_else(|| invalid("Interface option longer than its block"))?;
+            if code == 0 {
+                break;
+            }
+            if code == PCAPNG_OPTION_TSRESOL && length == 1 {
+                // The high bit picks a power of two rather than of ten
+                let exponent = (value[0] & 0x7f) as u32;
+                resolution = if value[0] & 0x80 != 0 {
+                    1u64.checked_shl(exponent)
+                } else {
+                    10u64.checked_pow(exponent)
+                }
+                .ok_or_else(|| invalid("Timestamp resolution out of range"))?;
+            }
+            offset += 4 + length.next_multiple_of(4);
+        }
+
+        if let Format::Pcapng { interfaces } = &mut self.format {
+            if interfaces.is_empty() {
+                self.linktype = linktype;
+                self.snaplen = snaplen;
+            }
+            interfaces.push(Interface {
+                linktype,
+                snaplen,
+                resolution,
+            });
+        }
+        Ok(())
+    }
+
+    // Reads a whole block and returns its type and body, None at the end of the capture
+    fn read_block(&mut self) -> io::Result<Option<(u32, Vec<u8>)>> {
+        let mut header = [0u8; 8];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        let block_type = self.read_u32(&header[0..4]);
+
+        // A new section may come from a host of the other byte order
+        if block_type == PCAPNG_SECTION_HEADER {
+            let mut magic = [0u8; 4];
+            self.reader.read_exact(&mut magic)?;
+            self.swapped = byte_order(&magic)?;
+            let length = self.read_u32(&header[4..8]) as usize;
+            self.skip_block(length, 12)?;
+            if let Format::Pcapng { interfaces } = &mut self.format {
+                interfaces.clear();
+            }
+            return Ok(Some((block_type, Vec::new())));
+        }
+
+        let length = self.read_u32(&header[4..8]) as usize;
+        if length < 12 || !length.is_multiple_of(4) || length > PCAPNG_MAX_BLOCK_SIZE {
+            return Err(invalid(format!("Invalid pcapng block length {}", length)));
+        }
+        // The body, then the length again
+        let mut body = vec![0u8; length - 8];
+        self.reader.read_exact(&mut body)?;
+        body.truncate(length - 12);
+        Ok(Some((block_type, body)))
+    }
+
+    // Skips the rest of a block of which `read` bytes were consumed
+    fn skip_block(&mut self, length: usize, read: usize) -> io::Result<()> {
+        if length < read + 4 || !length.is_multiple_of(4) || length > PCAPNG_MAX_BLOCK_SIZE {
+            return Err(invalid(format!("Invalid pcapng block length {}", length)));
+        }
+        io::copy(
+            &mut self.reader.by_ref().take((length - read) as u64),
+            &mut io::sink(),
+        )?;
+        Ok(())
+    }
+}
+
+// Whether a pcapng section was written in the other byte order
+fn byte_order(magic: &[u8]) -> io::Result<bool> {
+    match u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]]) {
+        PCAPNG_BYTE_ORDER_MAGIC => Ok(false),
+        magic if magic.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => Ok(true),
+        _ => Err(invalid("Not a pcapng section header")),
+    }
+}
+
+// Writes the classic libpcap format in the byte order of this host
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    nanos: bool,
+}
+
+impl PcapWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        linktype: u32,
+        snaplen: u32,
+        nanos: bool,
+    ) -> io::Result<Self> {
+        PcapWriter::new(
+            BufWriter::new(File::create(path)?),
+            linktype,
+            snaplen,
+            nanos,
+        )
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    pub fn new(mut writer: W, linktype: u32, snaplen: u32, nanos: bool) -> io::Result<Self> {
+        let magic = if nanos { MAGIC_NANOS } else { MAGIC_MICROS };
+        let mut header = Vec::with_capacity(GLOBAL_HEADER_SIZE);
+        header.extend_from_slice(&magic.to_ne_bytes());
+        // Version 2.4
+        header.extend_from_slice(&2u16.to_ne_bytes());
+        header.extend_from_slice(&4u16.to_ne_bytes());
+        // Timezone offset and timestamp accuracy, always zero
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&snaplen.to_ne_bytes());
+        header.extend_from_slice(&linktype.to_ne_bytes());
+        writer.write_all(&header)?;
+        Ok(PcapWriter { writer, nanos })
+    }
+
+    pub fn write_packet(&mut self, packet: &PcapPacket) -> io::Result<()> {
+        let fraction = if self.nanos {
+            packet.timestamp.subsec_nanos()
+        } else {
+            packet.timestamp.subsec_micros()
+        };
+        let mut header = Vec::with_capacity(RECORD_HEADER_SIZE);
+        header.extend_from_slice(&(packet.timestamp.as_secs() as u32).to_ne_bytes());
+        header.extend_from_slice(&fraction.to_ne_bytes());
+        header.extend_from_slice(&(packet.data.len() as u32).to_ne_bytes());
+        header.extend_from_slice(&packet.original_length.to_ne_bytes());
+        self.writer.write_all(&header)?;
+        self.writer.write_all(&packet.data)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = io::Result<PcapPacket>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
+
+// Returns the offset of the IP packet within a frame of the given link type
+pub fn network_offset(linktype: u32, frame: &[u8]) -> Option<usize> {
+    let offset = match linktype {
+        LINKTYPE_ETHERNET => {
+            let (header, payload) = EthernetHeader::parse(frame)?;
+            if header.ethertype != ETHERTYPE_IPV4 && header.ethertype != ETHERTYPE_IPV6 {
+                return None;
+            }
+            frame.len() - payload.len()
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => 0,
+        // BSD loopback: the address family in host byte order
+        LINKTYPE_NULL => 4,
+        // Linux cooked capture: 16 bytes ending with the protocol type
+        LINKTYPE_LINUX_SLL => {
+            let protocol = u16::from_be_bytes([*frame.get(14)?, *frame.get(15)?]);
+            if protocol != ETHERTYPE_IPV4 && protocol != ETHERTYPE_IPV6 {
+                return None;
+            }
+            16
+        }
+        _ => return None,
+    };
+    (frame.len() > offset).then_some(offset)
+}
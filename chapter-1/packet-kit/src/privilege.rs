@@ -0,0 +1,166 @@
+// Privileges of the raw-socket tools. Raw and packet sockets need CAP_NET_RAW, which root
+// holds and `setcap` can grant to a single binary. The tools check for it, say how to get
+// it when it's missing and give every privilege up once their sockets are open: a socket
+// keeps working whatever happens to the process that opened it.
+
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io;
+
+// Capabilities the tools deal with, numbered as in linux/capability.h
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    // Interfaces, routes and network namespaces
+    NetAdmin = 12,
+    // Raw and packet sockets
+    NetRaw = 13,
+}
+
+impl Capability {
+    // Name as setcap(8) spells it
+    fn setcap_name(self) -> &'static str {
+        match self {
+            Capability::NetAdmin => "cap_net_admin",
+            Capability::NetRaw => "cap_net_raw",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Capability::NetAdmin => write!(f, "CAP_NET_ADMIN"),
+            Capability::NetRaw => write!(f, "CAP_NET_RAW"),
+        }
+    }
+}
+
+// capset(2) takes a header and two 32-bit words per set since version 3
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+// Effective capability set of the process, as /proc/self/status lists it
+fn effective_capabilities() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let set = status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))?;
+    u64::from_str_radix(set.trim(), 16).ok()
+}
+
+// Whether the process holds the capability. Without /proc, root is taken to hold them all.
+pub fn has_capability(capability: Capability) -> bool {
+    match effective_capabilities() {
+        Some(set) => set & (1 << capability as u64) != 0,
+        None => unsafe { libc::geteuid() == 0 },
+    }
+}
+
+// Says how to get the capability: the command line to run again with sudo and the
+// setcap call granting it for good. The fallback tells what works without it.
+pub fn requirement(capability: Capability, fallback: Option<&str>) -> String {
+    let command_line: Vec<String> = std::env::args().collect();
+    let binary = std::env::current_exe()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| command_line[0].clone());
+    let mut message = format!(
+        "{} is missing, either run as root:\n  sudo {}\nor grant it to the binary once:\n  sudo setcap {}+ep {}",
+        capability,
+        command_line.join(" "),
+        capability.setcap_name(),
+        binary
+    );
+    if let Some(fallback) = fallback {
+        message.push_str(&format!("\nWithout privileges: {}", fallback));
+    }
+    message
+}
+
+// Turns the error of opening a privileged socket into a message saying what to do about it
+pub fn explain(
+    what: &str,
+    err: &io::Error,
+    capability: Capability,
+    fallback: Option<&str>,
+) -> String {
+    if err.kind() == io::ErrorKind::PermissionDenied {
+        format!("{}: {}\n{}", what, err, requirement(capability, fallback))
+    } else {
+        format!("{}: {}", what, err)
+    }
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+// The user who ran sudo, from the variables sudo leaves behind
+fn sudo_user() -> Option<(libc::uid_t, libc::gid_t, Option<String>)> {
+    let id = |name| std::env::var(name).ok()?.parse().ok();
+    let uid = id("SUDO_UID").filter(|uid| *uid != 0)?;
+    Some((uid, id("SUDO_GID")?, std::env::var("SUDO_USER").ok()))
+}
+
+// Gives up root and every capability, to be called once the privileged sockets are open.
+// Under sudo the process becomes the user who ran it, otherwise it keeps its real ids:
+// root stays root without capabilities, a setuid or setcap binary goes back to its user.
+pub fn drop_privileges() -> io::Result<()> {
+    let sudo = match unsafe { libc::getuid() } {
+        0 => sudo_user(),
+        _ => None,
+    };
+    let (uid, gid) = match &sudo {
+        Some((uid, gid, _)) => (*uid, *gid),
+        None => unsafe { (libc::getuid(), libc::getgid()) },
+    };
+
+    unsafe {
+        match &sudo {
+            // The groups of the user, instead of those of root
+            Some((_, _, Some(name))) => {
+                let name = CString::new(name.as_str()).map_err(io::Error::other)?;
+                check(libc::initgroups(name.as_ptr(), gid))?;
+            }
+            Some((_, _, None)) => check(libc::setgroups(0, std::ptr::null()))?,
+            None => {}
+        }
+        check(libc::setresgid(gid, gid, gid))?;
+        check(libc::setresuid(uid, uid, uid))?;
+    }
+
+    // Leaving root clears the capabilities, staying root or running a setcap binary doesn't
+    let header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data = [CapUserData::default(); 2];
+    check(unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) } as libc::c_int)?;
+    // Nothing executed from now on gets them back, setuid bits and file capabilities included
+    check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+
+    match effective_capabilities() {
+        Some(0) | None => Ok(()),
+        Some(set) => Err(io::Error::other(format!(
+            "capabilities {:#x} are still held after dropping them",
+            set
+        ))),
+    }
+}
@@ -0,0 +1,402 @@
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+use crate::layout::FieldSpan;
+use crate::{pseudo_header_checksum, Layer, PROTOCOL_TCP};
+
+// Size of the TCP header without options
+pub const TCP_HEADER_SIZE: usize = 20;
+
+// What the 4-bit data offset leaves for options
+pub const MAX_OPTIONS_SIZE: usize = 40;
+
+// Control bits, the NS bit (RFC 3540) sits in the low bit of the data offset byte
+pub const NS: u16 = 0b1_0000_0000;
+pub const CWR: u16 = 0b1000_0000;
+pub const ECE: u16 = 0b0100_0000;
+pub const URG: u16 = 0b0010_0000;
+pub const ACK: u16 = 0b0001_0000;
+pub const PSH: u16 = 0b0000_1000;
+pub const RST: u16 = 0b0000_0100;
+pub const SYN: u16 = 0b0000_0010;
+pub const FIN: u16 = 0b0000_0001;
+
+// Flag names in the order they appear in the header
+const FLAG_NAMES: &[(&str, u16)] = &[
+    ("CWR", CWR),
+    ("ECE", ECE),
+    ("URG", URG),
+    ("ACK", ACK),
+    ("PSH", PSH),
+    ("RST", RST),
+    ("SYN", SYN),
+    ("FIN", FIN),
+];
+
+// Option kinds (RFC 9293 section 3.1, RFC 7323, RFC 2018)
+pub const OPTION_END: u8 = 0;
+pub const OPTION_NOP: u8 = 1;
+pub const OPTION_MSS: u8 = 2;
+pub const OPTION_WINDOW_SCALE: u8 = 3;
+pub const OPTION_SACK_PERMITTED: u8 = 4;
+pub const OPTION_TIMESTAMP: u8 = 8;
+
+// Names of the flags set, comma separated
+pub fn flag_names(flags: u16) -> String {
+    let names: Vec<&str> = FLAG_NAMES
+        .iter()
+        .filter(|(_, flag)| flags & flag != 0)
+        .map(|(name, _)| *name)
+        .collect();
+    names.join(",")
+}
+
+// Builds a TCP segment (RFC 9293)
+#[derive(Clone)]
+pub struct TcpBuilder {
+    src_port: u16,
+    dst_port: u16,
+    sequence_number: u32,
+    acknowledgment_number: u32,
+    flags: u16,
+    window_size: u16,
+    urgent_pointer: u16,
+    options: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl TcpBuilder {
+    pub fn new(src_port: u16, dst_port: u16) -> Self {
+        TcpBuilder {
+            src_port,
+            dst_port,
+            sequence_number: 0,
+            acknowledgment_number: 0,
+            flags: 0,
+            window_size: 1024,
+            urgent_pointer: 0,
+            options: Vec::new(),
+            payload: Vec::new(),
+        }
+    }
+
+    pub fn sequence_number(mut self, sequence_number: u32) -> Self {
+        self.sequence_number = sequence_number;
+        self
+    }
+
+    // Sets the acknowledgment number along with the ACK flag
+    pub fn ack(mut self, acknowledgment_number: u32) -> Self {
+        self.acknowledgment_number = acknowledgment_number;
+        self.flags |= ACK;
+        self
+    }
+
+    // Replaces every control bit
+    pub fn flags(mut self, flags: u16) -> Self {
+        self.flags = flags & 0x1ff;
+        self
+    }
+
+    pub fn syn(mut self) -> Self {
+        self.flags |= SYN;
+        self
+    }
+
+    pub fn fin(mut self) -> Self {
+        self.flags |= FIN;
+        self
+    }
+
+    pub fn rst(mut self) -> Self {
+        self.flags |= RST;
+        self
+    }
+
+    pub fn psh(mut self) -> Self {
+        self.flags |= PSH;
+        self
+    }
+
+    // Sets the urgent pointer along with the URG flag
+    pub fn urg(mut self, urgent_pointer: u16) -> Self {
+        self.urgent_pointer = urgent_pointer;
+        self.flags |= URG;
+        self
+    }
+
+    pub fn window_size(mut self, window_size: u16) -> Self {
+        self.window_size = window_size;
+        self
+    }
+
+    // Appends option bytes. Panics once the options go past 40 bytes, which the data
+    // offset can't count.
+    fn push_options(mut self, options: &[u8]) -> Self {
+        assert!(
+            self.options.len() + options.len() <= MAX_OPTIONS_SIZE,
+            "TCP options take at most {} bytes, not {}",
+            MAX_OPTIONS_SIZE,
+            self.options.len() + options.len()
+        );
+        self.options.extend_from_slice(options);
+        self
+    }
+
+    // Appends an option of the given kind, its length byte is filled in
+    pub fn option(self, kind: u8, data: &[u8]) -> Self {
+        let mut option = vec![kind, 0];
+        option.extend_from_slice(data);
+        // An option too long for its length byte doesn't fit in the header either
+        option[1] = u8::try_from(option.len()).unwrap_or(u8::MAX);
+        self.push_options(&option)
+    }
+
+    pub fn nop(self) -> Self {
+        self.push_options(&[OPTION_NOP])
+    }
+
+    pub fn mss(self, mss: u16) -> Self {
+        self.option(OPTION_MSS, &mss.to_be_bytes())
+    }
+
+    pub fn window_scale(self, shift: u8) -> Self {
+        self.option(OPTION_WINDOW_SCALE, &[shift])
+    }
+
+    pub fn sack_permitted(self) -> Self {
+        self.option(OPTION_SACK_PERMITTED, &[])
+    }
+
+    pub fn timestamp(self, value: u32, echo_reply: u32) -> Self {
+        let mut data = value.to_be_bytes().to_vec();
+        data.extend_from_slice(&echo_reply.to_be_bytes());
+        self.option(OPTION_TIMESTAMP, &data)
+    }
+
+    // Raw option bytes appended as they are
+    pub fn raw_options(self, options: &[u8]) -> Self {
+        self.push_options(options)
+    }
+
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.payload = payload.to_vec();
+        self
+    }
+}
+
+impl Layer for TcpBuilder {
+    fn protocol(&self) -> u8 {
+        PROTOCOL_TCP
+    }
+
+    // Serialises the segment, the options are padded to a multiple of 4 bytes
+    fn build(&self, src: IpAddr, dst: IpAddr) -> Vec<u8> {
+        let header_length = TCP_HEADER_SIZE + self.options.len().div_ceil(4) * 4;
+        let mut segment = Vec::with_capacity(header_length + self.payload.len());
+        segment.extend_from_slice(&self.src_port.to_be_bytes());
+        segment.extend_from_slice(&self.dst_port.to_be_bytes());
+        segment.extend_from_slice(&self.sequence_number.to_be_bytes());
+        segment.extend_from_slice(&self.acknowledgment_number.to_be_bytes());
+        // The data offset counts the header and options in 32-bit words
+        segment.push(((header_length / 4) as u8) << 4 | (self.flags >> 8) as u8);
+        segment.push(self.flags as u8);
+        segment.extend_from_slice(&self.window_size.to_be_bytes());
+        segment.extend_from_slice(&[0, 0]);
+        segment.extend_from_slice(&self.urgent_pointer.to_be_bytes());
+        segment.extend_from_slice(&self.options);
+        segment.resize(header_length, OPTION_END);
+        segment.extend_from_slice(&self.payload);
+
+        let sum = pseudo_header_checksum(src, dst, PROTOCOL_TCP, &segment);
+        segment[16..18].copy_from_slice(&sum.to_be_bytes());
+        segment
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TcpHeader {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub sequence_number: u32,
+    pub acknowledgment_number: u32,
+    // Header length in bytes
+    pub data_offset: usize,
+    pub flags: u16,
+    pub window_size: u16,
+    pub checksum: u16,
+    pub urgent_pointer: u16,
+    pub options: Vec<u8>,
+}
+
+impl TcpHeader {
+    // Parses the header and returns it along with the segment payload
+    pub fn parse(buffer: &[u8]) -> Option<(Self, &[u8])> {
+        if buffer.len() < TCP_HEADER_SIZE {
+            return None;
+        }
+        let data_offset = (buffer[12] >> 4) as usize * 4;
+        if data_offset < TCP_HEADER_SIZE || buffer.len() < data_offset {
+            return None;
+        }
+        let header = TcpHeader {
+            source_port: u16::from_be_bytes([buffer[0], buffer[1]]),
+            destination_port: u16::from_be_bytes([buffer[2], buffer[3]]),
+            sequence_number: u32::from_be_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]),
+            acknowledgment_number: u32::from_be_bytes([
+                buffer[8], buffer[9], buffer[10], buffer[11],
+            ]),
+            data_offset,
+            flags: u16::from_be_bytes([buffer[12] & 0x01, buffer[13]]),
+            window_size: u16::from_be_bytes([buffer[14], buffer[15]]),
+            checksum: u16::from_be_bytes([buffer[16], buffer[17]]),
+            urgent_pointer: u16::from_be_bytes([buffer[18], buffer[19]]),
+            options: buffer[TCP_HEADER_SIZE..data_offset].to_vec(),
+        };
+        Some((header, &buffer[data_offset..]))
+    }
+
+    // Where each field sits in the header
+    pub fn spans(&self) -> Vec<FieldSpan> {
+        let mut spans = vec![
+            FieldSpan::new("Source Port", 0, 2, self.source_port),
+            FieldSpan::new("Destination Port", 2, 2, self.destination_port),
+            FieldSpan::new("Sequence Number", 4, 4, self.sequence_number),
+            FieldSpan::new("Acknowledgment Number", 8, 4, self.acknowledgment_number),
+            FieldSpan::new("Data Offset", 12, 1, format!("{} bytes", self.data_offset)),
+            FieldSpan::new("Flags", 12, 2, format!("0x{:03x}", self.flags)),
+            FieldSpan::new("Window Size", 14, 2, self.window_size),
+            FieldSpan::new("Checksum", 16, 2, format!("0x{:04x}", self.checksum)),
+            FieldSpan::new("Urgent Pointer", 18, 2, self.urgent_pointer),
+        ];
+        if !self.options.is_empty() {
+            spans.push(FieldSpan::new(
+                "Options",
+                TCP_HEADER_SIZE,
+                self.options.len(),
+                format!("{} bytes", self.options.len()),
+            ));
+        }
+        spans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::{Ipv4Builder, Ipv4Header};
+
+    const SRC: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const DST: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+    fn build(tcp: TcpBuilder) -> Vec<u8> {
+        tcp.build(IpAddr::V4(SRC), IpAddr::V4(DST))
+    }
+
+    #[test]
+    fn header_survives_a_round_trip() {
+        let segment = build(
+            TcpBuilder::new(40000, 80)
+                .sequence_number(0x01020304)
+                .ack(0x0a0b0c0d)
+                .psh()
+                .window_size(29200)
+                .urg(7)
+                .payload(b"GET / HTTP/1.1\r\n\r\n"),
+        );
+        let (header, payload) = TcpHeader::parse(&segment).unwrap();
+        assert_eq!(header.source_port, 40000);
+        assert_eq!(header.destination_port, 80);
+        assert_eq!(header.sequence_number, 0x01020304);
+        assert_eq!(header.acknowledgment_number, 0x0a0b0c0d);
+        assert_eq!(header.data_offset, TCP_HEADER_SIZE);
+        assert_eq!(header.flags, ACK | PSH | URG);
+        assert_eq!(header.window_size, 29200);
+        assert_eq!(header.urgent_pointer, 7);
+        assert_eq!(payload, b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(
+            pseudo_header_checksum(IpAddr::V4(SRC), IpAddr::V4(DST), PROTOCOL_TCP, &segment),
+            0
+        );
+    }
+
+    #[test]
+    fn options_are_padded_to_whole_words() {
+        let segment = build(
+            TcpBuilder::new(40000, 443)
+                .syn()
+                .mss(1460)
+                .sack_permitted()
+                .timestamp(1, 0)
+                .nop()
+                .window_scale(7),
+        );
+        let (header, payload) = TcpHeader::parse(&segment).unwrap();
+        assert_eq!(header.flags, SYN);
+        assert_eq!(header.data_offset, TCP_HEADER_SIZE + 20);
+        assert_eq!(
+            header.options,
+            [2, 4, 5, 180, 4, 2, 8, 10, 0, 0, 0, 1, 0, 0, 0, 0, 1, 3, 3, 7]
+        );
+        assert!(payload.is_empty());
+
+        let segment = build(TcpBuilder::new(1, 2).window_scale(2));
+        let (header, _) = TcpHeader::parse(&segment).unwrap();
+        assert_eq!(header.options, [3, 3, 2, OPTION_END]);
+    }
+
+    #[test]
+    fn ns_flag_sits_next_to_the_data_offset() {
+        let segment = build(TcpBuilder::new(1, 2).flags(NS | ECE | CWR | FIN));
+        assert_eq!(segment[12], 0x51);
+        let (header, _) = TcpHeader::parse(&segment).unwrap();
+        assert_eq!(header.flags, NS | ECE | CWR | FIN);
+    }
+
+    #[test]
+    fn segment_checksum_covers_the_carrying_packet() {
+        let packet = Ipv4Builder::new()
+            .src(SRC)
+            .dst(DST)
+            .payload(TcpBuilder::new(40000, 22).syn())
+            .build();
+        let (ip, segment) = Ipv4Header::parse(&packet).unwrap();
+        assert_eq!(ip.protocol, PROTOCOL_TCP);
+        assert_eq!(
+            pseudo_header_checksum(IpAddr::V4(SRC), IpAddr::V4(DST), PROTOCOL_TCP, segment),
+            0
+        );
+        assert_ne!(
+            pseudo_header_checksum(IpAddr::V4(DST), IpAddr::V4(DST), PROTOCOL_TCP, segment),
+            0
+        );
+    }
+
+    #[test]
+    fn forty_bytes_of_options_fill_the_header() {
+        let segment = build(TcpBuilder::new(1, 2).raw_options(&[OPTION_NOP; MAX_OPTIONS_SIZE]));
+        assert_eq!(segment[12] >> 4, 15);
+        let (header, _) = TcpHeader::parse(&segment).unwrap();
+        assert_eq!(header.data_offset, 60);
+    }
+
+    #[test]
+    #[should_panic(expected = "TCP options take at most 40 bytes")]
+    fn options_past_forty_bytes_are_rejected() {
+        TcpBuilder::new(1, 2)
+            .timestamp(1, 2)
+            .timestamp(3, 4)
+            .timestamp(5, 6)
+            .timestamp(7, 8)
+            .nop();
+    }
+
+    #[test]
+    #[should_panic(expected = "TCP options take at most 40 bytes, not 256")]
+    fn option_longer_than_its_length_byte_is_rejected() {
+        TcpBuilder::new(1, 2).option(254, &[0; 254]);
+    }
+}
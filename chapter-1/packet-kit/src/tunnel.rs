@@ -0,0 +1,584 @@
+// Tunnel headers and what they carry, so that inner packets go through the same decoding
+// as outer ones. IP-in-IP and 6in4 have no header of their own, the inner IP header
+// directly follows the outer one.
+
+use crate::ethernet::{
+    ETHERTYPE_IPV4, ETHERTYPE_IPV6, ETHERTYPE_MPLS, ETHERTYPE_MPLS_MULTICAST, ETHERTYPE_TEB,
+};
+use crate::layout::FieldSpan;
+use crate::{
+    PROTOCOL_ETHERIP, PROTOCOL_GRE, PROTOCOL_IPIP, PROTOCOL_IPV4, PROTOCOL_IPV6,
+    PROTOCOL_MPLS_IN_IP,
+};
+
+// UDP ports of the tunnels carried over UDP
+pub const PORT_VXLAN: u16 = 4789;
+pub const PORT_GENEVE: u16 = 6081;
+pub const PORT_GRE_IN_UDP: u16 = 4754;
+pub const PORT_MPLS_IN_UDP: u16 = 6635;
+
+// Tunnels nested deeper than this are left undecoded, crafted packets could nest forever
+pub const MAX_ENCAPSULATION: usize = 8;
+
+const GRE_HEADER_SIZE: usize = 4;
+const GRE_CHECKSUM: u8 = 0x80;
+const GRE_KEY: u8 = 0x20;
+const GRE_SEQUENCE: u8 = 0x10;
+const VXLAN_HEADER_SIZE: usize = 8;
+const GENEVE_HEADER_SIZE: usize = 8;
+const MPLS_LABEL_SIZE: usize = 4;
+const ETHERIP_HEADER_SIZE: usize = 2;
+
+// What follows a tunnel header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Inner {
+    Ethernet,
+    // IPv4 or IPv6, the version nibble tells them apart
+    Ip,
+    Mpls,
+}
+
+impl Inner {
+    // Inner kind announced by an EtherType, as GRE and GENEVE do
+    pub fn from_ethertype(ethertype: u16) -> Option<Self> {
+        match ethertype {
+            ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => Some(Inner::Ip),
+            ETHERTYPE_TEB => Some(Inner::Ethernet),
+            ETHERTYPE_MPLS | ETHERTYPE_MPLS_MULTICAST => Some(Inner::Mpls),
+            _ => None,
+        }
+    }
+}
+
+// A decoded tunnel header and the packet it carries
+pub struct Tunnel<'a> {
+    pub name: &'static str,
+    // Fields of the header, empty for tunnels without one
+    pub spans: Vec<FieldSpan>,
+    pub inner: Inner,
+    pub payload: &'a [u8],
+}
+
+// Generic Routing Encapsulation (RFC 2784 and the key and sequence number of RFC 2890)
+pub fn gre(buffer: &[u8]) -> Option<Tunnel<'_>> {
+    let header = buffer.get(..GRE_HEADER_SIZE)?;
+    // Version 1 is the PPTP flavour carrying PPP
+    if header[1] & 0x07 != 0 {
+        return None;
+    }
+    let flags = header[0];
+    let protocol = u16::from_be_bytes([header[2], header[3]]);
+    let mut spans = vec![
+        FieldSpan::new(
+            "Flags",
+            0,
+            2,
+            format!("0x{:04x}", u16::from_be_bytes([flags, header[1]])),
+        ),
+        FieldSpan::new("Protocol Type", 2, 2, format!("0x{:04x}", protocol)),
+    ];
+
+    let mut offset = GRE_HEADER_SIZE;
+    let mut word = |name: &'static str| -> Option<()> {
+        let value = buffer.get(offset..offset + 4)?;
+        let value = u32::from_be_bytes(value.try_into().unwrap());
+        let value = match name {
+            "Checksum" => format!("0x{:04x}", value >> 16),
+            _ => value.to_string(),
+        };
+        spans.push(FieldSpan::new(name, offset, 4, value));
+        offset += 4;
+        Some(())
+    };
+    if flags & GRE_CHECKSUM != 0 {
+        word("Checksum")?;
+    }
+    if flags & GRE_KEY != 0 {
+        word("Key")?;
+    }
+    if flags & GRE_SEQUENCE != 0 {
+        word("Sequence Number")?;
+    }
+    Some(Tunnel {
+        name: "GRE",
+        spans,
+        inner: Inner::from_ethertype(protocol)?,
+        payload: &buffer[offset..],
+    })
+}
+
+// Virtual eXtensible LAN (RFC 7348), always carries Ethernet
+pub fn vxlan(buffer: &[u8]) -> Option<Tunnel<'_>> {
+    let header = buffer.get(..VXLAN_HEADER_SIZE)?;
+    // The I flag says the VNI is valid
+    if header[0] & 0x08 == 0 {
+        return None;
+    }
+    let vni = u32::from_be_bytes([0, header[4], header[5], header[6]]);
+    Some(Tunnel {
+        name: "VXLAN",
+        spans: vec![
+            FieldSpan::new("Flags", 0, 1, format!("0x{:02x}", header[0])),
+            FieldSpan::new("VNI", 4, 3, vni),
+        ],
+        inner: Inner::Ethernet,
+        payload: &buffer[VXLAN_HEADER_SIZE..],
+    })
+}
+
+// Generic Network Virtualization Encapsulation (RFC 8926), options are skipped
+pub fn geneve(buffer: &[u8]) -> Option<Tunnel<'_>> {
+    let header = buffer.get(..GENEVE_HEADER_SIZE)?;
+    if header[0] >> 6 != 0 {
+        return None;
+    }
+    let options = (header[0] & 0x3f) as usize * 4;
+    let protocol = u16::from_be_bytes([header[2], header[3]]);
+    let vni = u32::from_be_bytes([0, header[4], header[5], header[6]]);
+    let end = GENEVE_HEADER_SIZE + options;
+    let mut spans = vec![
+        FieldSpan::new("Options Length", 0, 1, format!("{} bytes", options)),
+        FieldSpan::new("Flags", 1, 1, format!("0x{:02x}", header[1])),
+        FieldSpan::new("Protocol Type", 2, 2, format!("0x{:04x}", protocol)),
+        FieldSpan::new("VNI", 4, 3, vni),
+    ];
+    if options > 0 {
+        spans.push(FieldSpan::new(
+            "Options",
+            GENEVE_HEADER_SIZE,
+            options,
+            format!("{} bytes", options),
+        ));
+    }
+    Some(Tunnel {
+        name: "GENEVE",
+        spans,
+        inner: Inner::from_ethertype(protocol)?,
+        payload: buffer.get(end..)?,
+    })
+}
+
+// EtherIP (RFC 3378), Ethernet frames behind a version number
+pub fn etherip(buffer: &[u8]) -> Option<Tunnel<'_>> {
+    let header = buffer.get(..ETHERIP_HEADER_SIZE)?;
+    let version = header[0] >> 4;
+    if version != 3 {
+        return None;
+    }
+    Some(Tunnel {
+        name: "EtherIP",
+        spans: vec![FieldSpan::new("Version", 0, 1, version)],
+        inner: Inner::Ethernet,
+        payload: &buffer[ETHERIP_HEADER_SIZE..],
+    })
+}
+
+// An MPLS label stack (RFC 3032). Nothing names the payload, IP is told apart by its
+// version nibble and anything else is taken for the control word of an Ethernet pseudowire.
+pub fn mpls(buffer: &[u8]) -> Option<Tunnel<'_>> {
+    let mut spans = Vec::new();
+    let mut offset = 0;
+    loop {
+        let entry = buffer.get(offset..offset + MPLS_LABEL_SIZE)?;
+        let entry = u32::from_be_bytes(entry.try_into().unwrap());
+        let bottom = entry & 0x100 != 0;
+        spans.push(FieldSpan::new(
+            "Label",
+            offset,
+            MPLS_LABEL_SIZE,
+            format!(
+                "{} (TC {}, TTL {}{})",
+                entry >> 12,
+                (entry >> 9) & 0x07,
+                entry & 0xff,
+                if bottom { ", bottom" } else { "" }
+            ),
+        ));
+        offset += MPLS_LABEL_SIZE;
+        if bottom {
+            break;
+        }
+    }
+
+    let (inner, offset) = match buffer.get(offset)? >> 4 {
+        4 | 6 => (Inner::Ip, offset),
+        0 => {
+            spans.push(FieldSpan::new("Control Word", offset, 4, "pseudowire"));
+            (Inner::Ethernet, offset + 4)
+        }
+        _ => return None,
+    };
+    Some(Tunnel {
+        name: "MPLS",
+        spans,
+        inner,
+        payload: buffer.get(offset..)?,
+    })
+}
+
+// The tunnel carried by an IP packet of the given protocol, if any
+pub fn over_ip(protocol: u8, payload: &[u8]) -> Option<Tunnel<'_>> {
+    match protocol {
+        PROTOCOL_IPV4 | PROTOCOL_IPIP | PROTOCOL_IPV6 => Some(Tunnel {
+            name: if protocol == PROTOCOL_IPV6 {
+                "6in4"
+            } else {
+                "IP-in-IP"
+            },
+            spans: Vec::new(),
+            inner: Inner::Ip,
+            payload,
+        }),
+        PROTOCOL_GRE => gre(payload),
+        PROTOCOL_ETHERIP => etherip(payload),
+        PROTOCOL_MPLS_IN_IP => mpls(payload),
+        _ => None,
+    }
+}
+
+// The tunnel carried by a UDP datagram sent to the given port, if any
+pub fn over_udp(port: u16, payload: &[u8]) -> Option<Tunnel<'_>> {
+    match port {
+        PORT_VXLAN => vxlan(payload),
+        PORT_GENEVE => geneve(payload),
+        PORT_GRE_IN_UDP => gre(payload),
+        PORT_MPLS_IN_UDP => mpls(payload),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+    use crate::dissect;
+    use crate::ethernet::{EthernetHeader, MacAddr};
+    use crate::pcap::{LINKTYPE_ETHERNET, LINKTYPE_RAW};
+    use crate::{Ipv4Builder, Ipv6Builder, UdpBuilder};
+
+    // The UDP datagram every tunnel carries
+    fn datagram() -> UdpBuilder {
+        UdpBuilder::new(1000, 53).payload(b"inner")
+    }
+
+    fn inner_ipv4() -> Vec<u8> {
+        Ipv4Builder::new()
+            .src(Ipv4Addr::new(10, 0, 0, 1))
+            .dst(Ipv4Addr::new(10, 0, 0, 2))
+            .payload(datagram())
+            .build()
+    }
+
+    fn inner_frame() -> Vec<u8> {
+        EthernetHeader::new(
+            MacAddr::BROADCAST,
+            MacAddr([2, 0, 0, 0, 0, 1]),
+            ETHERTYPE_IPV4,
+        )
+        .build(&inner_ipv4())
+    }
+
+    // Carries the tunnel in an Ethernet frame, as an IP protocol or over UDP
+    fn over_ipv4(protocol: u8, tunnel: Vec<u8>) -> Vec<u8> {
+        let packet = Ipv4Builder::new()
+            .protocol(protocol)
+            .payload(tunnel)
+            .build();
+        EthernetHeader::new(MacAddr::BROADCAST, MacAddr::BROADCAST, ETHERTYPE_IPV4).build(&packet)
+    }
+
+    fn over_udp_port(port: u16, tunnel: &[u8]) -> Vec<u8> {
+        let packet = Ipv4Builder::new()
+            .payload(UdpBuilder::new(50000, port).payload(tunnel))
+            .build();
+        EthernetHeader::new(MacAddr::BROADCAST, MacAddr::BROADCAST, ETHERTYPE_IPV4).build(&packet)
+    }
+
+    fn fields(tunnel: &Tunnel) -> Vec<(&'static str, usize, usize, String)> {
+        tunnel
+            .spans
+            .iter()
+            .map(|span| (span.name, span.offset, span.length, span.value.clone()))
+            .collect()
+    }
+
+    fn field(
+        name: &'static str,
+        offset: usize,
+        length: usize,
+        value: &str,
+    ) -> (&'static str, usize, usize, String) {
+        (name, offset, length, value.to_string())
+    }
+
+    // Name, depth and frame offset of every decoded layer
+    fn layers(linktype: u32, frame: &[u8]) -> Vec<(&'static str, usize, usize)> {
+        dissect::decode(linktype, frame)
+            .layers()
+            .map(|layer| (layer.name, layer.depth, layer.offset))
+            .collect()
+    }
+
+    #[test]
+    fn gre_with_checksum_key_and_sequence_number() {
+        let mut buffer = vec![0xb0, 0x00, 0x08, 0x00];
+        buffer.extend_from_slice(&[0x12, 0x34, 0, 0, 0, 0, 0, 42, 0, 0, 0, 7]);
+        buffer.extend_from_slice(&inner_ipv4());
+
+        let tunnel = gre(&buffer).unwrap();
+        assert_eq!(
+            fields(&tunnel),
+            [
+                field("Flags", 0, 2, "0xb000"),
+                field("Protocol Type", 2, 2, "0x0800"),
+                field("Checksum", 4, 4, "0x1234"),
+                field("Key", 8, 4, "42"),
+                field("Sequence Number", 12, 4, "7"),
+            ]
+        );
+        assert_eq!(tunnel.inner, Inner::Ip);
+        assert_eq!(tunnel.payload, inner_ipv4());
+
+        // A missing optional word, PPTP's version 1 and unknown payloads aren't decoded
+        assert!(gre(&buffer[..10]).is_none());
+        assert!(gre(&[0x30, 0x01, 0x88, 0x0b]).is_none());
+        assert!(gre(&[0x00, 0x00, 0x12, 0x34]).is_none());
+
+        assert_eq!(
+            layers(LINKTYPE_ETHERNET, &over_ipv4(PROTOCOL_GRE, buffer)),
+            [
+                ("Ethernet", 0, 0),
+                ("IPv4", 0, 14),
+                ("GRE", 0, 34),
+                ("IPv4", 1, 50),
+                ("UDP", 1, 70),
+            ]
+        );
+    }
+
+    #[test]
+    fn vxlan_carries_ethernet() {
+        let mut buffer = vec![0x08, 0, 0, 0, 0x00, 0x12, 0x34, 0];
+        buffer.extend_from_slice(&inner_frame());
+
+        let tunnel = vxlan(&buffer).unwrap();
+        assert_eq!(
+            fields(&tunnel),
+            [field("Flags", 0, 1, "0x08"), field("VNI", 4, 3, "4660")]
+        );
+        assert_eq!(tunnel.inner, Inner::Ethernet);
+        assert_eq!(tunnel.payload, inner_frame());
+        // Without the I flag the VNI isn't valid
+        buffer[0] = 0;
+        assert!(vxlan(&buffer).is_none());
+        buffer[0] = 0x08;
+
+        let frame = over_udp_port(PORT_VXLAN, &buffer);
+        assert_eq!(
+            layers(LINKTYPE_ETHERNET, &frame),
+            [
+                ("Ethernet", 0, 0),
+                ("IPv4", 0, 14),
+                ("UDP", 0, 34),
+                ("VXLAN", 0, 42),
+                ("Ethernet", 1, 50),
+                ("IPv4", 1, 64),
+                ("UDP", 1, 84),
+            ]
+        );
+        // The ports of the inner datagram win over those of the tunnel
+        assert_eq!(
+            dissect::decode(LINKTYPE_ETHERNET, &frame).ports(),
+            Some((1000, 53))
+        );
+    }
+
+    #[test]
+    fn geneve_skips_its_options() {
+        // Two words of options, then an Ethernet frame
+        let mut buffer = vec![0x02, 0x00, 0x65, 0x58, 0, 0, 7, 0];
+        buffer.extend_from_slice(&[0x01, 0x02, 0x03, 0x01, 0xaa, 0xbb, 0xcc, 0xdd]);
+        buffer.extend_from_slice(&inner_frame());
+
+        let tunnel = geneve(&buffer).unwrap();
+        assert_eq!(
+            fields(&tunnel),
+            [
+                field("Options Length", 0, 1, "8 bytes"),
+                field("Flags", 1, 1, "0x00"),
+                field("Protocol Type", 2, 2, "0x6558"),
+                field("VNI", 4, 3, "7"),
+                field("Options", 8, 8, "8 bytes"),
+            ]
+        );
+        assert_eq!(tunnel.inner, Inner::Ethernet);
+        assert_eq!(tunnel.payload, inner_frame());
+        // Options running past the end and other versions aren't decoded
+        assert!(geneve(&buffer[..12]).is_none());
+        buffer[0] |= 0x40;
+        assert!(geneve(&buffer).is_none());
+        buffer[0] &= !0x40;
+
+        assert_eq!(
+            layers(LINKTYPE_ETHERNET, &over_udp_port(PORT_GENEVE, &buffer)),
+            [
+                ("Ethernet", 0, 0),
+                ("IPv4", 0, 14),
+                ("UDP", 0, 34),
+                ("GENEVE", 0, 42),
+                ("Ethernet", 1, 58),
+                ("IPv4", 1, 72),
+                ("UDP", 1, 92),
+            ]
+        );
+    }
+
+    #[test]
+    fn etherip_carries_ethernet() {
+        let mut buffer = vec![0x30, 0x00];
+        buffer.extend_from_slice(&inner_frame());
+
+        let tunnel = etherip(&buffer).unwrap();
+        assert_eq!(fields(&tunnel), [field("Version", 0, 1, "3")]);
+        assert_eq!(tunnel.inner, Inner::Ethernet);
+        assert_eq!(tunnel.payload, inner_frame());
+        assert!(etherip(&[0x20, 0x00]).is_none());
+
+        assert_eq!(
+            layers(LINKTYPE_ETHERNET, &over_ipv4(PROTOCOL_ETHERIP, buffer)),
+            [
+                ("Ethernet", 0, 0),
+                ("IPv4", 0, 14),
+                ("EtherIP", 0, 34),
+                ("Ethernet", 1, 36),
+                ("IPv4", 1, 50),
+                ("UDP", 1, 70),
+            ]
+        );
+    }
+
+    #[test]
+    fn mpls_stacks_and_the_control_word() {
+        // Label 100 with TTL 64, then label 200 at the bottom of the stack with TC 5 and TTL 63
+        let mut buffer = ((100u32 << 12) | 64).to_be_bytes().to_vec();
+        buffer.extend_from_slice(&((200u32 << 12) | (5 << 9) | 0x100 | 63).to_be_bytes());
+        buffer.extend_from_slice(&inner_ipv4());
+
+        let tunnel = mpls(&buffer).unwrap();
+        assert_eq!(
+            fields(&tunnel),
+            [
+                field("Label", 0, 4, "100 (TC 0, TTL 64)"),
+                field("Label", 4, 4, "200 (TC 5, TTL 63, bottom)"),
+            ]
+        );
+        assert_eq!(tunnel.inner, Inner::Ip);
+        assert_eq!(tunnel.payload, inner_ipv4());
+        // A stack running off the end without its bottom label
+        assert!(mpls(&buffer[..4]).is_none());
+
+        let frame = EthernetHeader::new(MacAddr::BROADCAST, MacAddr::BROADCAST, ETHERTYPE_MPLS)
+            .build(&buffer);
+        assert_eq!(
+            layers(LINKTYPE_ETHERNET, &frame),
+            [
+                ("Ethernet", 0, 0),
+                ("MPLS", 0, 14),
+                ("IPv4", 1, 22),
+                ("UDP", 1, 42),
+            ]
+        );
+
+        // An Ethernet pseudowire starts with a control word
+        let mut pseudowire = ((300u32 << 12) | 0x100 | 255).to_be_bytes().to_vec();
+        pseudowire.extend_from_slice(&[0, 0, 0, 1]);
+        pseudowire.extend_from_slice(&inner_frame());
+        let tunnel = mpls(&pseudowire).unwrap();
+        assert_eq!(
+            fields(&tunnel),
+            [
+                field("Label", 0, 4, "300 (TC 0, TTL 255, bottom)"),
+                field("Control Word", 4, 4, "pseudowire"),
+            ]
+        );
+        assert_eq!(tunnel.inner, Inner::Ethernet);
+        assert_eq!(tunnel.payload, inner_frame());
+        assert_eq!(
+            layers(
+                LINKTYPE_ETHERNET,
+                &over_udp_port(PORT_MPLS_IN_UDP, &pseudowire)
+            ),
+            [
+                ("Ethernet", 0, 0),
+                ("IPv4", 0, 14),
+                ("UDP", 0, 34),
+                ("MPLS", 0, 42),
+                ("Ethernet", 1, 50),
+                ("IPv4", 1, 64),
+                ("UDP", 1, 84),
+            ]
+        );
+
+        // Neither IP nor a control word
+        pseudowire[4] = 0x10;
+        assert!(mpls(&pseudowire).is_none());
+    }
+
+    #[test]
+    fn ip_in_ip_and_6in4_have_no_header() {
+        let inner = inner_ipv4();
+        for protocol in [PROTOCOL_IPV4, PROTOCOL_IPIP] {
+            let tunnel = over_ip(protocol, &inner).unwrap();
+            assert_eq!(tunnel.name, "IP-in-IP");
+            assert!(tunnel.spans.is_empty());
+            assert_eq!(tunnel.inner, Inner::Ip);
+            assert_eq!(tunnel.payload, inner);
+        }
+        let packet = Ipv4Builder::new()
+            .payload(
+                Ipv4Builder::new()
+                    .src(Ipv4Addr::new(10, 0, 0, 1))
+                    .dst(Ipv4Addr::new(10, 0, 0, 2))
+                    .payload(datagram()),
+            )
+            .build();
+        assert_eq!(
+            layers(LINKTYPE_RAW, &packet),
+            [
+                ("IPv4", 0, 0),
+                ("IP-in-IP", 0, 20),
+                ("IPv4", 1, 20),
+                ("UDP", 1, 40),
+            ]
+        );
+
+        let inner = Ipv6Builder::new()
+            .src(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))
+            .dst(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2))
+            .payload(datagram());
+        let tunnel_payload = inner.build();
+        let tunnel = over_ip(PROTOCOL_IPV6, &tunnel_payload).unwrap();
+        assert_eq!(tunnel.name, "6in4");
+        assert_eq!(tunnel.inner, Inner::Ip);
+        let packet = Ipv4Builder::new().payload(inner).build();
+        let decoded = dissect::decode(LINKTYPE_RAW, &packet);
+        assert_eq!(
+            layers(LINKTYPE_RAW, &packet),
+            [
+                ("IPv4", 0, 0),
+                ("6in4", 0, 20),
+                ("IPv6", 1, 20),
+                ("UDP", 1, 60),
+            ]
+        );
+        assert_eq!(
+            decoded.addresses(),
+            Some((
+                "2001:db8::1".parse().unwrap(),
+                "2001:db8::2".parse().unwrap()
+            ))
+        );
+        assert!(over_ip(crate::PROTOCOL_UDP, &packet).is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+use crate::layout::FieldSpan;
+use crate::{pseudo_header_checksum, Layer, PROTOCOL_UDP};
+
+pub const UDP_HEADER_SIZE: usize = 8;
+
+// Builds a UDP datagram (RFC 768)
+#[derive(Clone)]
+pub struct UdpBuilder {
+    src_port: u16,
+    dst_port: u16,
+    payload: Vec<u8>,
+}
+
+impl UdpBuilder {
+    pub fn new(src_port: u16, dst_port: u16) -> Self {
+        UdpBuilder {
+            src_port,
+            dst_port,
+            payload: Vec::new(),
+        }
+    }
+
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.payload = payload.to_vec();
+        self
+    }
+}
+
+impl Layer for UdpBuilder {
+    fn protocol(&self) -> u8 {
+        PROTOCOL_UDP
+    }
+
+    fn build(&self, src: IpAddr, dst: IpAddr) -> Vec<u8> {
+        let length = UDP_HEADER_SIZE + self.payload.len();
+        let mut datagram = Vec::with_capacity(length);
+        datagram.extend_from_slice(&self.src_port.to_be_bytes());
+        datagram.extend_from_slice(&self.dst_port.to_be_bytes());
+        datagram.extend_from_slice(&(length as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(&self.payload);
+
+        // A computed checksum of zero is sent as all ones, zero means no checksum
+        let sum = match pseudo_header_checksum(src, dst, PROTOCOL_UDP, &datagram) {
+            0 => 0xffff,
+            sum => sum,
+        };
+        datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+        datagram
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct UdpHeader {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub length: u16,
+    pub checksum: u16,
+}
+
+impl UdpHeader {
+    // Parses the header and returns it along with the payload it announces
+    pub fn parse(buffer: &[u8]) -> Option<(Self, &[u8])> {
+        if buffer.len() < UDP_HEADER_SIZE {
+            return None;
+        }
+        let length = u16::from_be_bytes([buffer[4], buffer[5]]);
+        let end = (length as usize).clamp(UDP_HEADER_SIZE, buffer.len());
+        let header = UdpHeader {
+            source_port: u16::from_be_bytes([buffer[0], buffer[1]]),
+            destination_port: u16::from_be_bytes([buffer[2], buffer[3]]),
+            length,
+            checksum: u16::from_be_bytes([buffer[6], buffer[7]]),
+        };
+        Some((header, &buffer[UDP_HEADER_SIZE..end]))
+    }
+
+    // Where each field sits in the header
+    pub fn spans(&self) -> Vec<FieldSpan> {
+        vec![
+            FieldSpan::new("Source Port", 0, 2, self.source_port),
+            FieldSpan::new("Destination Port", 2, 2, self.destination_port),
+            FieldSpan::new("Length", 4, 2, self.length),
+            FieldSpan::new("Checksum", 6, 2, format!("0x{:04x}", self.checksum)),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::*;
+
+    #[test]
+    fn datagram_survives_a_round_trip() {
+        let (src, dst) = (
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+        );
+        let datagram = UdpBuilder::new(5353, 53).payload(b"hello").build(src, dst);
+        let (header, payload) = UdpHeader::parse(&datagram).unwrap();
+        assert_eq!(header.source_port, 5353);
+        assert_eq!(header.destination_port, 53);
+        assert_eq!(header.length as usize, datagram.len());
+        assert_eq!(payload, b"hello");
+        assert_ne!(header.checksum, 0);
+        assert_eq!(pseudo_header_checksum(src, dst, PROTOCOL_UDP, &datagram), 0);
+    }
+}
@@ -0,0 +1,529 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use packet_kit::capture;
+use packet_kit::ethernet::ETHERNET_HEADER_SIZE;
+use packet_kit::ipv4::{IPV4_HEADER_SIZE, MORE_FRAGMENTS};
+use packet_kit::ipv6::IPV6_HEADER_SIZE;
+use packet_kit::pcap::{network_offset, LINKTYPE_ETHERNET};
+use packet_kit::privilege::{self, Capability};
+use packet_kit::{
+    checksum, pseudo_header_checksum, update_checksum, Ipv4Header, Ipv6Header, MacAddr, PcapReader,
+    PROTOCOL_ICMPV6, PROTOCOL_TCP, PROTOCOL_UDP,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+
+// Multipliers outside this range would overflow the schedule of the packets
+const MULTIPLIERS: RangeInclusive<f64> = 0.001..=1000.0;
+
+// How the capture is paced
+#[derive(Clone, Copy)]
+enum Pace {
+    // Gaps between packets divided by the multiplier, 1.0 keeps the original timing
+    Multiplier(f64),
+    TopSpeed,
+}
+
+// Changes applied to every packet before it is sent
+#[derive(Default)]
+struct Rewrite {
+    src_mac: Option<MacAddr>,
+    dst_mac: Option<MacAddr>,
+    // Old address to new address, applied to sources and destinations alike
+    addresses: HashMap<IpAddr, IpAddr>,
+    // Old port to new port, applied to TCP and UDP sources and destinations alike
+    ports: HashMap<u16, u16>,
+}
+
+impl Rewrite {
+    fn is_empty(&self) -> bool {
+        self.src_mac.is_none()
+            && self.dst_mac.is_none()
+            && self.addresses.is_empty()
+            && self.ports.is_empty()
+    }
+
+    // Rewrites the frame in place, then fixes up every checksum covering a changed field
+    fn apply(&self, linktype: u32, frame: &mut [u8]) {
+        if linktype == LINKTYPE_ETHERNET && frame.len() >= ETHERNET_HEADER_SIZE {
+            if let Some(mac) = self.dst_mac {
+                frame[0..6].copy_from_slice(&mac.0);
+            }
+            if let Some(mac) = self.src_mac {
+                frame[6..12].copy_from_slice(&mac.0);
+            }
+        }
+        if self.addresses.is_empty() && self.ports.is_empty() {
+            return;
+        }
+        let offset = match network_offset(linktype, frame) {
+            Some(offset) => offset,
+            None => return,
+        };
+        let packet = &mut frame[offset..];
+        match packet[0] >> 4 {
+            4 => self.apply_ipv4(packet),
+            6 => self.apply_ipv6(packet),
+            _ => {}
+        }
+    }
+
+    fn apply_ipv4(&self, packet: &mut [u8]) {
+        let (header, payload) = match Ipv4Header::parse(packet) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+        let header_length = header.header_length;
+        let payload_length = payload.len();
+        let original = (IpAddr::V4(header.src), IpAddr::V4(header.dst));
+        // Addresses only change within their family
+        let rewritten = match (self.address(original.0), self.address(original.1)) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                packet[12..16].copy_from_slice(&src.octets());
+                packet[16..20].copy_from_slice(&dst.octets());
+                (IpAddr::V4(src), IpAddr::V4(dst))
+            }
+            _ => original,
+        };
+        packet[10..12].copy_from_slice(&[0, 0]);
+        let sum = checksum(&packet[..header_length]);
+        packet[10..12].copy_from_slice(&sum.to_be_bytes());
+
+        // Only the first fragment carries the transport header, the checksum covers every fragment
+        let fragmented = header.fragment_offset != 0 || header.flags & MORE_FRAGMENTS != 0;
+        let complete = header.total_length as usize == header_length + payload_length;
+        let segment = &mut packet[header_length..header_length + payload_length];
+        if header.fragment_offset == 0 {
+            self.apply_transport(
+                header.protocol,
+                original,
+                rewritten,
+                segment,
+                complete && !fragmented,
+            );
+        }
+    }
+
+    fn apply_ipv6(&self, packet: &mut [u8]) {
+        let (header, payload) = match Ipv6Header::parse(packet) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+        let payload_length = payload.len();
+        let original = (IpAddr::V6(header.src), IpAddr::V6(header.dst));
+        let rewritten = match (self.address(original.0), self.address(original.1)) {
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                packet[8..24].copy_from_slice(&src.octets());
+                packet[24..40].copy_from_slice(&dst.octets());
+                (IpAddr::V6(src), IpAddr::V6(dst))
+            }
+            _ => original,
+        };
+
+        // Extension headers aren't walked, only segments right after the fixed header are fixed up
+        let complete = header.payload_length as usize == payload_length;
+        let segment = &mut packet[IPV6_HEADER_SIZE..IPV6_HEADER_SIZE + payload_length];
+        self.apply_transport(header.next_header, original, rewritten, segment, complete);
+    }
+
+    // Maps the ports and fixes up the checksum, which covers the addresses through the pseudo
+    // header. It's recomputed when the whole segment was captured. First fragments and
+    // segments cut short by the snap length only have the changed fields accounted for.
+    fn apply_transport(
+        &self,
+        protocol: u8,
+        original: (IpAddr, IpAddr),
+        rewritten: (IpAddr, IpAddr),
+        segment: &mut [u8],
+        complete: bool,
+    ) {
+        let checksum_offset = match protocol {
+            PROTOCOL_TCP if segment.len() >= 20 => 16,
+            PROTOCOL_UDP if segment.len() >= 8 => 6,
+            PROTOCOL_ICMPV6 if segment.len() >= 4 => 2,
+            _ => return,
+        };
+        let ports = segment[..4].to_vec();
+        if protocol != PROTOCOL_ICMPV6 {
+            for range in [0..2, 2..4] {
+                let port = u16::from_be_bytes([segment[range.start], segment[range.start + 1]]);
+                if let Some(port) = self.ports.get(&port) {
+                    segment[range].copy_from_slice(&port.to_be_bytes());
+                }
+            }
+        }
+
+        let (src, dst) = rewritten;
+        let sum = u16::from_be_bytes([segment[checksum_offset], segment[checksum_offset + 1]]);
+        // A zero UDP checksum over IPv4 means the sender didn't compute one
+        if protocol == PROTOCOL_UDP && sum == 0 && src.is_ipv4() {
+            return;
+        }
+        let sum = if complete {
+            segment[checksum_offset..checksum_offset + 2].copy_from_slice(&[0, 0]);
+            pseudo_header_checksum(src, dst, protocol, segment)
+        } else {
+            let sum = update_checksum(sum, &octets(original.0), &octets(src));
+            let sum = update_checksum(sum, &octets(original.1), &octets(dst));
+            update_checksum(sum, &ports, &segment[..4])
+        };
+        let sum = match sum {
+            0 if protocol == PROTOCOL_UDP => 0xffff,
+            sum => sum,
+        };
+        segment[checksum_offset..checksum_offset + 2].copy_from_slice(&sum.to_be_bytes());
+    }
+
+    fn address(&self, address: IpAddr) -> IpAddr {
+        self.addresses.get(&address).copied().unwrap_or(address)
+    }
+}
+
+fn octets(address: IpAddr) -> Vec<u8> {
+    match address {
+        IpAddr::V4(address) => address.octets().to_vec(),
+        IpAddr::V6(address) => address.octets().to_vec(),
+    }
+}
+
+// Sends whole frames out of an interface, or IP packets through the routing table
+enum Injector {
+    Link(Socket),
+    Network {
+        ipv4: Socket,
+        // Hosts without IPv6 still replay the IPv4 packets
+        ipv6: io::Result<Socket>,
+    },
+}
+
+impl Injector {
+    // Opens an AF_PACKET socket bound to the interface, it only sends
+    fn link(iface: &str) -> io::Result<Self> {
+        Ok(Injector::Link(capture::open(0, Some(iface))?))
+    }
+
+    // Opens the raw sockets of both families up front, IPPROTO_RAW implies the IP header
+    // is included
+    fn network() -> io::Result<Self> {
+        let ipv4 = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::from(255)))?;
+        let ipv6 = Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::from(255)))
+            .and_then(|socket| socket.set_header_included_v6(true).map(|_| socket));
+        Ok(Injector::Network { ipv4, ipv6 })
+    }
+
+    fn send(&mut self, linktype: u32, frame: &[u8]) -> io::Result<usize> {
+        let (ipv4, ipv6) = match self {
+            Injector::Link(socket) => return socket.send(frame),
+            Injector::Network { ipv4, ipv6 } => (ipv4, ipv6),
+        };
+        let offset = network_offset(linktype, frame)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Not an IP packet"))?;
+        let packet = &frame[offset..];
+        match packet[0] >> 4 {
+            4 if packet.len() >= IPV4_HEADER_SIZE => {
+                let dst = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+                ipv4.send_to(packet, &SocketAddr::new(IpAddr::V4(dst), 0).into())
+            }
+            6 if packet.len() >= IPV6_HEADER_SIZE => {
+                let socket = ipv6
+                    .as_ref()
+                    .map_err(|err| io::Error::new(err.kind(), err.to_string()))?;
+                let dst = Ipv6Addr::from(<[u8; 16]>::try_from(&packet[24..40]).unwrap());
+                socket.send_to(packet, &SocketAddr::new(IpAddr::V6(dst), 0).into())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not an IP packet",
+            )),
+        }
+    }
+}
+
+// Replays the capture once, returns the packets and bytes sent along with the packets skipped
+fn replay(
+    path: &str,
+    injector: &mut Injector,
+    pace: Pace,
+    rewrite: &Rewrite,
+) -> io::Result<(usize, usize, usize)> {
+    let mut capture = PcapReader::open(path)?;
+    let (mut sent, mut bytes, mut skipped) = (0, 0, 0);
+    let mut clock: Option<(Duration, Instant)> = None;
+    while let Some(mut packet) = capture.next_packet()? {
+        if let Pace::Multiplier(multiplier) = pace {
+            let (first, started) = *clock.get_or_insert((packet.timestamp, Instant::now()));
+            // Captures aren't always in order, late packets go out right away
+            let due = started + packet.timestamp.saturating_sub(first).div_f64(multiplier);
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+        }
+
+        // pcapng files carry a link type per interface, only Ethernet frames go out whole
+        let linktype = packet.linktype;
+        if matches!(injector, Injector::Link(_)) && linktype != LINKTYPE_ETHERNET {
+            eprintln!(
+                "Skipping packet {}: link type {} can't be sent on an interface, \
+                 drop -i to send the IP packets",
+                sent + skipped + 1,
+                linktype
+            );
+            skipped += 1;
+            continue;
+        }
+        rewrite.apply(linktype, &mut packet.data);
+        match injector.send(linktype, &packet.data) {
+            Ok(length) => {
+                sent += 1;
+                bytes += length;
+            }
+            Err(err) => {
+                eprintln!("Skipping packet {}: {}", sent + skipped + 1, err);
+                skipped += 1;
+            }
+        }
+    }
+    Ok((sent, bytes, skipped))
+}
+
+// Parses an old=new pair
+fn parse_mapping<T: std::str::FromStr>(value: &str) -> Option<(T, T)> {
+    let (old, new) = value.split_once('=')?;
+    Some((old.parse().ok()?, new.parse().ok()?))
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [-i <iface>] [--multiplier <x> | --topspeed] [--loop <n>] \
+         [--srcmac <mac>] [--dstmac <mac>] [--ip <old>=<new>]... [--port <old>=<new>]... \
+         <capture.pcap>",
+        program
+    );
+    eprintln!(
+        "  The multiplier goes from {} to {}",
+        MULTIPLIERS.start(),
+        MULTIPLIERS.end()
+    );
+    std::process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let mut args = std::env::args();
+    let program = args.next().unwrap();
+
+    let mut iface = None;
+    let mut pace = Pace::Multiplier(1.0);
+    // Zero loops forever
+    let mut loops = 1;
+    let mut rewrite = Rewrite::default();
+    let mut path = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage(&program));
+        match arg.as_str() {
+            "-i" => iface = Some(value()),
+            "--multiplier" => match value().parse::<f64>() {
+                Ok(multiplier) if MULTIPLIERS.contains(&multiplier) => {
+                    pace = Pace::Multiplier(multiplier)
+                }
+                _ => usage(&program),
+            },
+            "--topspeed" => pace = Pace::TopSpeed,
+            "--loop" => loops = value().parse::<usize>().unwrap_or_else(|_| usage(&program)),
+            "--srcmac" => {
+                rewrite.src_mac = Some(value().parse().unwrap_or_else(|_| usage(&program)))
+            }
+            "--dstmac" => {
+                rewrite.dst_mac = Some(value().parse().unwrap_or_else(|_| usage(&program)))
+            }
+            "--ip" => {
+                let (old, new): (IpAddr, IpAddr) =
+                    parse_mapping(&value()).unwrap_or_else(|| usage(&program));
+                if old.is_ipv4() != new.is_ipv4() {
+                    eprintln!("Can't map {} to {}: address families differ", old, new);
+                    std::process::exit(1);
+                }
+                rewrite.addresses.insert(old, new);
+            }
+            "--port" => {
+                let (old, new) = parse_mapping(&value()).unwrap_or_else(|| usage(&program));
+                rewrite.ports.insert(old, new);
+            }
+            _ if arg.starts_with('-') => usage(&program),
+            _ if path.is_none() => path = Some(arg),
+            _ => usage(&program),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage(&program));
+
+    let injector = match &iface {
+        Some(iface) => Injector::link(iface),
+        None => Injector::network(),
+    };
+    let mut injector = injector.unwrap_or_else(|err| {
+        eprintln!(
+            "{}",
+            privilege::explain(
+                "Can't open the injection socket",
+                &err,
+                Capability::NetRaw,
+                None
+            )
+        );
+        std::process::exit(1);
+    });
+    // Nothing past the sockets needs root, the capture is reopened on every loop as the user
+    if let Err(err) = privilege::drop_privileges() {
+        eprintln!("Can't drop privileges: {}", err);
+        std::process::exit(1);
+    }
+    if !rewrite.is_empty() {
+        println!(
+            "Rewriting {} addresses and {} ports, checksums are fixed up",
+            rewrite.addresses.len(),
+            rewrite.ports.len()
+        );
+    }
+
+    let started = Instant::now();
+    let (mut sent, mut bytes, mut skipped) = (0, 0, 0);
+    let mut round = 0;
+    while loops == 0 || round < loops {
+        let (round_sent, round_bytes, round_skipped) =
+            replay(&path, &mut injector, pace, &rewrite)?;
+        sent += round_sent;
+        bytes += round_bytes;
+        skipped += round_skipped;
+        round += 1;
+        if loops != 1 {
+            println!("Loop {}: sent {} packets", round, round_sent);
+        }
+    }
+
+    let elapsed = started.elapsed().as_secs_f64();
+    println!(
+        "Sent {} packets ({} bytes) in {:.2}s, {:.1} packets per second, {} skipped",
+        sent,
+        bytes,
+        elapsed,
+        sent as f64 / elapsed.max(f64::EPSILON),
+        skipped
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use packet_kit::pcap::LINKTYPE_RAW;
+    use packet_kit::{IcmpBuilder, Ipv4Builder, Ipv6Builder, TcpBuilder, UdpBuilder};
+
+    use super::*;
+
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const SERVER: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 2);
+
+    fn rewrite() -> Rewrite {
+        Rewrite {
+            addresses: HashMap::from([
+                (IpAddr::V4(CLIENT), IpAddr::V4(Ipv4Addr::new(10, 9, 8, 7))),
+                (
+                    IpAddr::V6(Ipv6Addr::LOCALHOST),
+                    IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0xabcd, 1)),
+                ),
+            ]),
+            ports: HashMap::from([(5353, 53), (80, 8080)]),
+            ..Rewrite::default()
+        }
+    }
+
+    fn datagram() -> Vec<u8> {
+        let payload: Vec<u8> = (0..64).collect();
+        Ipv4Builder::new()
+            .src(CLIENT)
+            .dst(SERVER)
+            .id(7)
+            .payload(UdpBuilder::new(5353, 9).payload(&payload))
+            .build()
+    }
+
+    // Checksum of the transport segment of an IPv4 packet with its pseudo header
+    fn transport_checksum(packet: &[u8]) -> u16 {
+        let (header, segment) = Ipv4Header::parse(packet).unwrap();
+        pseudo_header_checksum(
+            IpAddr::V4(header.src),
+            IpAddr::V4(header.dst),
+            header.protocol,
+            segment,
+        )
+    }
+
+    #[test]
+    fn whole_segments_are_recomputed() {
+        let mut packet = Ipv4Builder::new()
+            .src(CLIENT)
+            .dst(SERVER)
+            .payload(TcpBuilder::new(40000, 80).syn().payload(b"hello"))
+            .build();
+        rewrite().apply(LINKTYPE_RAW, &mut packet);
+        assert_eq!(packet[12..16], [10, 9, 8, 7]);
+        assert_eq!(packet[22..24], 8080u16.to_be_bytes());
+        assert!(Ipv4Header::checksum_valid(&packet));
+        assert_eq!(transport_checksum(&packet), 0);
+    }
+
+    #[test]
+    fn icmpv6_checksums_cover_the_new_addresses() {
+        let mut packet = Ipv6Builder::new()
+            .src(Ipv6Addr::LOCALHOST)
+            .dst(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2))
+            .payload(IcmpBuilder::echo_request_v6(1, 2))
+            .build();
+        rewrite().apply(LINKTYPE_RAW, &mut packet);
+        let (header, segment) = Ipv6Header::parse(&packet).unwrap();
+        assert_eq!(
+            header.src,
+            Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0xabcd, 1)
+        );
+        assert_eq!(
+            pseudo_header_checksum(
+                IpAddr::V6(header.src),
+                IpAddr::V6(header.dst),
+                PROTOCOL_ICMPV6,
+                segment
+            ),
+            0
+        );
+    }
+
+    #[test]
+    fn first_fragments_carry_the_checksum_of_the_whole_datagram() {
+        let mut whole = datagram();
+        // The UDP header and the first 16 bytes of data, more fragments to follow
+        let mut fragment = whole[..IPV4_HEADER_SIZE + 24].to_vec();
+        let length = fragment.len() as u16;
+        fragment[2..4].copy_from_slice(&length.to_be_bytes());
+        fragment[6] |= MORE_FRAGMENTS << 5;
+
+        rewrite().apply(LINKTYPE_RAW, &mut whole);
+        rewrite().apply(LINKTYPE_RAW, &mut fragment);
+        assert!(Ipv4Header::checksum_valid(&fragment));
+        assert_eq!(fragment[20..22], 53u16.to_be_bytes());
+        assert_eq!(fragment[20..28], whole[20..28]);
+    }
+
+    #[test]
+    fn truncated_segments_carry_the_checksum_of_the_whole_one() {
+        let mut whole = datagram();
+        let mut truncated = whole[..IPV4_HEADER_SIZE + 12].to_vec();
+
+        rewrite().apply(LINKTYPE_RAW, &mut whole);
+        rewrite().apply(LINKTYPE_RAW, &mut truncated);
+        assert_eq!(transport_checksum(&whole), 0);
+        assert_eq!(truncated[20..28], whole[20..28]);
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use packet_kit::dissect::{DecodedPacket, Key, Registry};
+use packet_kit::pcap::LINKTYPE_RAW;
+use packet_kit::{checksum, PcapPacket};
+
+use crate::checksums;
+use crate::cryptopan::CryptoPan;
+use crate::dns::{self, Dns, DNS_PORT, LLMNR_PORT, MDNS_PORT};
+
+// Cipher inputs of the pseudonyms start with these, so that they never meet the
+// inputs of Crypto-PAn or of each other
+const MAC_DOMAIN: u128 = u128::from_be_bytes(*b"MAC\0\0\0\0\0\0\0\0\0\0\0\0\0");
+const LABEL_DOMAIN: u128 = u128::from_be_bytes(*b"DNS\0\0\0\0\0\0\0\0\0\0\0\0\0");
+
+// Headers carrying cookies, blanked when scrubbing HTTP
+const COOKIE_HEADERS: &[&str] = &["cookie", "set-cookie"];
+
+// What happens to the bytes after the innermost transport header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Keep,
+    // Overwritten with zeros, the record keeps its length
+    Zero,
+    // Cut from the record after that many bytes, the original length stays
+    Truncate(usize),
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            None if value == "keep" => Ok(Action::Keep),
+            None if value == "zero" => Ok(Action::Zero),
+            None if value == "truncate" => Ok(Action::Truncate(0)),
+            Some(("truncate", bytes)) => bytes
+                .parse()
+                .map(Action::Truncate)
+                .map_err(|_| format!("Invalid byte count {:?}", bytes)),
+            _ => Err(format!(
+                "Unknown action {:?}, expected keep, zero or truncate[:<bytes>]",
+                value
+            )),
+        }
+    }
+}
+
+// Counts of what was rewritten, for the final report
+#[derive(Default)]
+pub struct Report {
+    pub packets: usize,
+    pub addresses: HashSet<IpAddr>,
+    pub macs: HashSet<[u8; 6]>,
+    pub dns_messages: usize,
+    pub cookies: usize,
+    pub checksums: usize,
+    pub zeroed: usize,
+    pub truncated: usize,
+}
+
+pub struct Anonymiser {
+    pub cryptopan: CryptoPan,
+    registry: Registry,
+    // Payload action of each protocol, lowercase layer names such as "dns" or "tcp"
+    pub actions: HashMap<String, Action>,
+    pub default_action: Action,
+    // Keep the vendor half of MAC addresses
+    pub keep_oui: bool,
+    pub scrub_dns: bool,
+    pub scrub_cookies: bool,
+    pub report: Report,
+}
+
+impl Anonymiser {
+    pub fn new(cryptopan: CryptoPan) -> Self {
+        // DNS gets decoded so that its names can be scrubbed
+        let mut registry = Registry::new();
+        let udp = [DNS_PORT, MDNS_PORT, LLMNR_PORT].map(Key::UdpPort);
+        registry.register(&udp, Dns { tcp: false });
+        registry.register(&[Key::TcpPort(DNS_PORT)], Dns { tcp: true });
+
+        let mut actions = HashMap::new();
+        // Names and addresses in DNS messages get scrubbed, the rest is worth keeping
+        actions.insert(String::from("dns"), Action::Keep);
+        Anonymiser {
+            cryptopan,
+            registry,
+            actions,
+            default_action: Action::Truncate(0),
+            keep_oui: false,
+            scrub_dns: true,
+            scrub_cookies: true,
+            report: Report::default(),
+        }
+    }
+
+    // Anonymises the packet in place. Lengths never change, payloads are only cut from
+    // the record once the checksums covering them were recomputed.
+    pub fn apply(&mut self, packet: &mut PcapPacket) {
+        let frame = &mut packet.data;
+        let decoded = self.registry.decode(packet.linktype, frame);
+        self.report.packets += 1;
+
+        self.rewrite_addresses(&decoded, frame);
+        for layer in decoded.layers() {
+            let end = (layer.offset + layer.length).min(frame.len());
+            match layer.name {
+                "DNS" if self.scrub_dns => {
+                    // Over TCP the message comes after its length
+                    let start = layer.offset + layer.field("Length").map_or(0, |_| 2);
+                    dns::scrub(&mut frame[start..end], self);
+                    self.report.dns_messages += 1;
+                }
+                "HTTP" if self.scrub_cookies => {
+                    for field in &layer.fields {
+                        let header = field.value.to_ascii_lowercase();
+                        let cookie = COOKIE_HEADERS
+                            .iter()
+                            .any(|name| header.starts_with(&format!("{}:", name)));
+                        if field.name == "Header" && cookie {
+                            blank_header_value(
+                                &mut frame[field.offset..field.offset + field.length],
+                            );
+                            self.report.cookies += 1;
+                        }
+                    }
+                }
+                "ICMP" | "ICMPv6" if quotes_packet(layer.name, layer.field("Type")) => {
+                    self.rewrite_quoted(&mut frame[end..]);
+                }
+                _ => {}
+            }
+        }
+
+        let mut cut = frame.len();
+        if let Some((protocol, start)) = payload(&decoded) {
+            let action = self
+                .actions
+                .get(&protocol.to_ascii_lowercase())
+                .copied()
+                .unwrap_or(self.default_action);
+            match action {
+                Action::Keep => {}
+                Action::Zero if start < frame.len() => {
+                    frame[start..].fill(0);
+                    self.report.zeroed += 1;
+                }
+                Action::Truncate(bytes) if start + bytes < frame.len() => {
+                    cut = start + bytes;
+                    self.report.truncated += 1;
+                }
+                _ => {}
+            }
+        }
+
+        // Addresses changed, so decode again for the pseudo headers
+        let decoded = self.registry.decode(packet.linktype, frame);
+        self.report.checksums += checksums::recompute(&decoded, frame);
+        frame.truncate(cut);
+    }
+
+    // Rewrites the addresses of every layer, tunnelled packets included
+    fn rewrite_addresses(&mut self, decoded: &DecodedPacket, frame: &mut [u8]) {
+        for layer in decoded.layers() {
+            for field in &layer.fields {
+                let bytes = &mut frame[field.offset..field.offset + field.length];
+                match (layer.name, field.name) {
+                    ("IPv4", "Source" | "Destination") | ("ARP", "Sender IP" | "Target IP") => {
+                        let address: [u8; 4] = bytes.try_into().unwrap();
+                        let address = self.address(IpAddr::V4(Ipv4Addr::from(address)));
+                        bytes.copy_from_slice(&ip_octets(address));
+                    }
+                    ("IPv6", "Source" | "Destination") => {
+                        let address: [u8; 16] = bytes.try_into().unwrap();
+                        let address = self.address(IpAddr::V6(Ipv6Addr::from(address)));
+                        bytes.copy_from_slice(&ip_octets(address));
+                    }
+                    ("Ethernet", "Source" | "Destination")
+                    | ("ARP", "Sender MAC" | "Target MAC") => {
+                        let mac: [u8; 6] = bytes.try_into().unwrap();
+                        bytes.copy_from_slice(&self.mac(mac));
+                    }
+                    // Other link types pad their addresses to 8 bytes
+                    ("Linux cooked capture", "Address")
+                        if layer.field("Address Length") == Some("6") =>
+                    {
+                        let mac: [u8; 6] = bytes[..6].try_into().unwrap();
+                        bytes[..6].copy_from_slice(&self.mac(mac));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // ICMP errors quote the header of the packet that caused them, addresses included.
+    // The quoted transport header is usually cut short, its checksum stays as it was.
+    fn rewrite_quoted(&mut self, quoted: &mut [u8]) {
+        let decoded = self.registry.decode(LINKTYPE_RAW, quoted);
+        let first = decoded.layers().next().map(|layer| layer.name);
+        match first {
+            Some("IPv4" | "IPv6") => {
+                self.rewrite_addresses(&decoded, quoted);
+            }
+            // Quotes are often shorter than the quoted packet claims, which the IP
+            // dissectors turn down
+            _ => match quoted.first().map(|byte| byte >> 4) {
+                Some(4) if quoted.len() >= 20 => {
+                    for range in [12..16, 16..20] {
+                        let address: [u8; 4] = quoted[range.clone()].try_into().unwrap();
+                        let address = self.address(IpAddr::V4(Ipv4Addr::from(address)));
+                        quoted[range].copy_from_slice(&ip_octets(address));
+                    }
+                    let header_length = ((quoted[0] & 0x0f) as usize * 4).min(quoted.len());
+                    quoted[10..12].copy_from_slice(&[0, 0]);
+                    let sum = checksum(&quoted[..header_length]);
+                    quoted[10..12].copy_from_slice(&sum.to_be_bytes());
+                }
+                Some(6) if quoted.len() >= 40 => {
+                    for range in [8..24, 24..40] {
+                        let address: [u8; 16] = quoted[range.clone()].try_into().unwrap();
+                        let address = self.address(IpAddr::V6(Ipv6Addr::from(address)));
+                        quoted[range].copy_from_slice(&ip_octets(address));
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+
+    // Addresses naming no host, such as broadcast and multicast groups, are kept
+    pub fn address(&mut self, address: IpAddr) -> IpAddr {
+        let shared = match address {
+            IpAddr::V4(address) => {
+                address.is_unspecified()
+                    || address.is_loopback()
+                    || address.is_broadcast()
+                    || address.is_multicast()
+            }
+            IpAddr::V6(address) => {
+                address.is_unspecified() || address.is_loopback() || address.is_multicast()
+            }
+        };
+        if shared {
+            return address;
+        }
+        self.report.addresses.insert(address);
+        self.cryptopan.anonymise(address)
+    }
+
+    // A locally administered address standing for the original, the same one every time.
+    // Group addresses and the zero address are kept.
+    pub fn mac(&mut self, mac: [u8; 6]) -> [u8; 6] {
+        if mac[0] & 0x01 != 0 || mac == [0; 6] {
+            return mac;
+        }
+        self.report.macs.insert(mac);
+        let mut block = [0u8; 8];
+        block[2..].copy_from_slice(&mac);
+        let output = self
+            .cryptopan
+            .encrypt(MAC_DOMAIN | u64::from_be_bytes(block) as u128)
+            .to_be_bytes();
+        let mut pseudonym = [0u8; 6];
+        pseudonym.copy_from_slice(&output[..6]);
+        if self.keep_oui {
+            pseudonym[..3].copy_from_slice(&mac[..3]);
+        } else {
+            pseudonym[0] = (pseudonym[0] & 0xfc) | 0x02;
+        }
+        pseudonym
+    }
+
+    // Lowercase letters standing for a DNS label, the same for every spelling of it
+    pub fn pseudonym(&self, label: &[u8]) -> Vec<u8> {
+        let mut state = self.cryptopan.encrypt(LABEL_DOMAIN | label.len() as u128);
+        for chunk in label.chunks(16) {
+            let mut block = [0u8; 16];
+            for (byte, original) in block.iter_mut().zip(chunk) {
+                *byte = original.to_ascii_lowercase();
+            }
+            state = self.cryptopan.encrypt(state ^ u128::from_be_bytes(block));
+        }
+        let mut pseudonym = Vec::with_capacity(label.len());
+        for index in 0..label.len() {
+            if index > 0 && index.is_multiple_of(16) {
+                state = self.cryptopan.encrypt(state);
+            }
+            pseudonym.push(b'a' + state.to_be_bytes()[index % 16] % 26);
+        }
+        pseudonym
+    }
+}
+
+fn ip_octets(address: IpAddr) -> Vec<u8> {
+    match address {
+        IpAddr::V4(address) => address.octets().to_vec(),
+        IpAddr::V6(address) => address.octets().to_vec(),
+    }
+}
+
+// Error messages carrying the start of the offending packet
+fn quotes_packet(name: &str, message_type: Option<&str>) -> bool {
+    let Some(message_type) = message_type.and_then(|value| value.parse::<u8>().ok()) else {
+        return false;
+    };
+    match name {
+        // Destination unreachable, source quench, redirect, time exceeded, parameter problem
+        "ICMP" => matches!(message_type, 3 | 4 | 5 | 11 | 12),
+        // Destination unreachable, packet too big, time exceeded, parameter problem
+        _ => matches!(message_type, 1..=4),
+    }
+}
+
+// Where the payload starts and the protocol naming it: the layer decoded inside the
+// innermost transport header, or that header itself. Frames without IP name their
+// payload after the last layer decoded.
+fn payload(decoded: &DecodedPacket) -> Option<(&'static str, usize)> {
+    let mut anchor = None;
+    for layer in decoded.layers() {
+        match layer.name {
+            "IPv4" | "IPv6" | "TCP" | "UDP" | "ICMP" | "ICMPv6" => {
+                anchor = Some((layer.name, layer.offset + layer.length));
+            }
+            name => {
+                if let Some((_, start)) = anchor {
+                    if layer.inner.is_none() && layer.offset >= start {
+                        anchor = Some((name, start));
+                    }
+                }
+            }
+        }
+    }
+    anchor.or_else(|| {
+        let innermost = decoded.innermost()?;
+        Some((innermost.name, decoded.payload_offset))
+    })
+}
+
+// Blanks what follows the colon of a header line, the line ending stays
+fn blank_header_value(line: &mut [u8]) {
+    let Some(colon) = line.iter().position(|byte| *byte == b':') else {
+        return;
+    };
+    let end = line.len().saturating_sub(2);
+    for byte in line[colon + 1..end].iter_mut() {
+        if *byte != b' ' {
+            *byte = b'x';
+        }
+    }
+}
@@ -0,0 +1,158 @@
+// Recomputes the checksums of every layer after the frame was rewritten, innermost
+// first since the checksums of tunnels over UDP cover the packets they carry
+
+use std::net::IpAddr;
+
+use packet_kit::dissect::{DecodedLayer, DecodedPacket};
+use packet_kit::ipv4::MORE_FRAGMENTS;
+use packet_kit::ipv6::IPV6_HEADER_SIZE;
+use packet_kit::{
+    checksum, pseudo_header_checksum, Ipv4Header, Ipv6Header, PROTOCOL_ICMPV6, PROTOCOL_TCP,
+    PROTOCOL_UDP,
+};
+
+// Where the payload of the IP layer ends in the frame, as its header announces it, and
+// whether it's one fragment of a larger packet
+fn ip_payload(layer: &DecodedLayer, frame: &[u8]) -> Option<(usize, bool)> {
+    let packet = frame.get(layer.offset..)?;
+    match layer.name {
+        "IPv4" => {
+            let (header, _) = Ipv4Header::parse(packet)?;
+            let fragmented = header.fragment_offset != 0 || header.flags & MORE_FRAGMENTS != 0;
+            Some((layer.offset + header.total_length as usize, fragmented))
+        }
+        "IPv6" => {
+            let (header, _) = Ipv6Header::parse(packet)?;
+            let length = IPV6_HEADER_SIZE + header.payload_length as usize;
+            Some((layer.offset + length, false))
+        }
+        _ => None,
+    }
+}
+
+fn store(frame: &mut [u8], offset: usize, sum: u16) {
+    frame[offset..offset + 2].copy_from_slice(&sum.to_be_bytes());
+}
+
+// Returns how many checksums were recomputed. Segments cut short by the snap length or
+// spread over fragments are left alone, their checksums can't be computed.
+pub fn recompute(decoded: &DecodedPacket, frame: &mut [u8]) -> usize {
+    let layers: Vec<&DecodedLayer> = decoded.layers().collect();
+    let mut recomputed = 0;
+    for (index, layer) in layers.iter().enumerate().rev() {
+        let start = layer.offset;
+        if layer.name == "IPv4" {
+            let end = start + layer.length;
+            store(frame, start + 10, 0);
+            let sum = checksum(&frame[start..end]);
+            store(frame, start + 10, sum);
+            recomputed += 1;
+            continue;
+        }
+
+        let (protocol, checksum_offset) = match layer.name {
+            "TCP" => (PROTOCOL_TCP, 16),
+            "UDP" => (PROTOCOL_UDP, 6),
+            "ICMP" => (0, 2),
+            "ICMPv6" => (PROTOCOL_ICMPV6, 2),
+            _ => continue,
+        };
+        // The IP layer carrying the segment
+        let Some(ip) = layers[..index]
+            .iter()
+            .rev()
+            .find(|layer| layer.name == "IPv4" || layer.name == "IPv6")
+        else {
+            continue;
+        };
+        let (Some((src, dst)), Some((end, fragmented))) = (ip.addresses, ip_payload(ip, frame))
+        else {
+            continue;
+        };
+        if end > frame.len() || end < start + checksum_offset + 2 || fragmented {
+            continue;
+        }
+        let original = u16::from_be_bytes([
+            frame[start + checksum_offset],
+            frame[start + checksum_offset + 1],
+        ]);
+        // A zero UDP checksum over IPv4 means the sender didn't compute one
+        if protocol == PROTOCOL_UDP && original == 0 && matches!(src, IpAddr::V4(_)) {
+            continue;
+        }
+
+        store(frame, start + checksum_offset, 0);
+        let segment = &frame[start..end];
+        let sum = match layer.name {
+            "ICMP" => checksum(segment),
+            _ => match pseudo_header_checksum(src, dst, protocol, segment) {
+                0 if protocol == PROTOCOL_UDP => 0xffff,
+                sum => sum,
+            },
+        };
+        store(frame, start + checksum_offset, sum);
+        recomputed += 1;
+    }
+    recomputed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use packet_kit::dissect::decode;
+    use packet_kit::pcap::LINKTYPE_RAW;
+    use packet_kit::{Ipv4Builder, UdpBuilder};
+
+    use super::*;
+
+    fn datagram(builder: Ipv4Builder) -> Vec<u8> {
+        builder
+            .src(Ipv4Addr::new(192, 0, 2, 1))
+            .dst(Ipv4Addr::new(192, 0, 2, 2))
+            .payload(UdpBuilder::new(1024, 9).payload(b"payload"))
+            .build()
+    }
+
+    // Rewrites the source address behind the checksums' back, then recomputes them
+    fn rewrite(mut frame: Vec<u8>) -> (usize, Vec<u8>) {
+        frame[15] = 99;
+        let decoded = decode(LINKTYPE_RAW, &frame);
+        let recomputed = recompute(&decoded, &mut frame);
+        (recomputed, frame)
+    }
+
+    #[test]
+    fn whole_datagrams_are_recomputed() {
+        let (recomputed, frame) = rewrite(datagram(Ipv4Builder::new()));
+        assert_eq!(recomputed, 2);
+        assert!(Ipv4Header::checksum_valid(&frame));
+        let (header, segment) = Ipv4Header::parse(&frame).unwrap();
+        assert_eq!(
+            pseudo_header_checksum(
+                IpAddr::V4(header.src),
+                IpAddr::V4(header.dst),
+                PROTOCOL_UDP,
+                segment
+            ),
+            0
+        );
+    }
+
+    #[test]
+    fn fragments_keep_their_transport_checksum() {
+        let original = datagram(Ipv4Builder::new().more_fragments());
+        let (recomputed, frame) = rewrite(original.clone());
+        assert_eq!(recomputed, 1);
+        assert_eq!(frame[26..28], original[26..28]);
+    }
+
+    #[test]
+    fn truncated_datagrams_keep_their_transport_checksum() {
+        let mut original = datagram(Ipv4Builder::new());
+        original.truncate(original.len() - 2);
+        let (recomputed, frame) = rewrite(original.clone());
+        assert_eq!(recomputed, 1);
+        assert_eq!(frame[26..28], original[26..28]);
+    }
+}
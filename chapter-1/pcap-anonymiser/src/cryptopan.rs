@@ -0,0 +1,124 @@
+// Prefix-preserving address anonymisation (Crypto-PAn, Xu et al. 2002): two addresses
+// sharing an n-bit prefix map to two addresses sharing an n-bit prefix, and the same
+// key always gives the same mapping. IPv6 addresses go through the same construction
+// over 128 bits.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+
+pub const KEY_SIZE: usize = 32;
+
+pub struct CryptoPan {
+    cipher: Aes128,
+    // Bits of the cipher input past the prefix being anonymised
+    pad: u128,
+}
+
+impl CryptoPan {
+    // The first half of the key is the AES key, the second half encrypted makes the pad
+    pub fn new(key: &[u8; KEY_SIZE]) -> Self {
+        let cipher = Aes128::new(GenericArray::from_slice(&key[..16]));
+        let mut pad = GenericArray::clone_from_slice(&key[16..]);
+        cipher.encrypt_block(&mut pad);
+        CryptoPan {
+            cipher,
+            pad: u128::from_be_bytes(pad.into()),
+        }
+    }
+
+    pub fn encrypt(&self, block: u128) -> u128 {
+        let mut block = GenericArray::from(block.to_be_bytes());
+        self.cipher.encrypt_block(&mut block);
+        u128::from_be_bytes(block.into())
+    }
+
+    // Anonymises the first `bits` bits of the address, held in the high bits
+    fn anonymise_bits(&self, address: u128, bits: u32) -> u128 {
+        let mut flips = 0;
+        for position in 0..bits {
+            // The original prefix before this bit, the pad after it
+            let prefix = u128::MAX.checked_shl(128 - position).unwrap_or(0);
+            let input = (address & prefix) | (self.pad & !prefix);
+            flips |= (self.encrypt(input) >> 127) << (127 - position);
+        }
+        address ^ flips
+    }
+
+    pub fn anonymise_v4(&self, address: Ipv4Addr) -> Ipv4Addr {
+        let address = (u32::from(address) as u128) << 96;
+        Ipv4Addr::from((self.anonymise_bits(address, 32) >> 96) as u32)
+    }
+
+    pub fn anonymise_v6(&self, address: Ipv6Addr) -> Ipv6Addr {
+        Ipv6Addr::from(self.anonymise_bits(u128::from(address), 128))
+    }
+
+    pub fn anonymise(&self, address: IpAddr) -> IpAddr {
+        match address {
+            IpAddr::V4(address) => IpAddr::V4(self.anonymise_v4(address)),
+            IpAddr::V6(address) => IpAddr::V6(self.anonymise_v6(address)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Key and addresses of the sample trace shipped with the reference implementation
+    const KEY: [u8; KEY_SIZE] = [
+        21, 34, 23, 141, 51, 164, 207, 128, 19, 10, 91, 22, 73, 144, 125, 16, 216, 152, 143, 131,
+        121, 121, 101, 39, 98, 87, 76, 45, 42, 132, 34, 2,
+    ];
+    const SAMPLE: [(&str, &str); 10] = [
+        ("128.11.68.132", "135.242.180.132"),
+        ("129.118.74.4", "134.136.186.123"),
+        ("130.132.252.244", "133.68.164.234"),
+        ("141.223.7.43", "141.167.8.160"),
+        ("141.233.145.108", "141.129.237.235"),
+        ("152.163.225.39", "151.140.114.167"),
+        ("156.29.3.236", "147.225.12.42"),
+        ("165.247.96.84", "162.9.99.234"),
+        ("166.107.77.190", "160.132.178.185"),
+        ("192.102.249.13", "252.138.62.131"),
+    ];
+
+    #[test]
+    fn matches_the_reference_implementation() {
+        let pan = CryptoPan::new(&KEY);
+        for (address, anonymised) in SAMPLE {
+            assert_eq!(
+                pan.anonymise_v4(address.parse().unwrap()),
+                anonymised.parse::<Ipv4Addr>().unwrap(),
+                "{}",
+                address
+            );
+        }
+    }
+
+    #[test]
+    fn shared_prefixes_are_preserved() {
+        let pan = CryptoPan::new(&KEY);
+        let shared = |a: u128, b: u128| (a ^ b).leading_zeros();
+        let pairs: [(IpAddr, IpAddr); 3] = [
+            ("10.1.2.3".parse().unwrap(), "10.1.200.7".parse().unwrap()),
+            ("192.0.2.1".parse().unwrap(), "64.0.2.1".parse().unwrap()),
+            (
+                "2001:db8::1".parse().unwrap(),
+                "2001:db8:0:1::1".parse().unwrap(),
+            ),
+        ];
+        for (a, b) in pairs {
+            let bits = |address: IpAddr| match address {
+                IpAddr::V4(address) => (u32::from(address) as u128) << 96,
+                IpAddr::V6(address) => u128::from(address),
+            };
+            let (x, y) = (pan.anonymise(a), pan.anonymise(b));
+            assert_eq!(shared(bits(a), bits(b)), shared(bits(x), bits(y)));
+            assert_ne!(a, x);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+// DNS messages, found by port and scrubbed in place. Names keep their length so that
+// compression pointers and every length field stay valid.
+
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use packet_kit::dissect::{Dissection, Dissector, Next};
+use packet_kit::FieldSpan;
+
+use crate::anonymise::Anonymiser;
+
+pub const DNS_PORT: u16 = 53;
+pub const MDNS_PORT: u16 = 5353;
+pub const LLMNR_PORT: u16 = 5355;
+
+const HEADER_SIZE: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_NS: u16 = 2;
+const TYPE_CNAME: u16 = 5;
+const TYPE_SOA: u16 = 6;
+const TYPE_PTR: u16 = 12;
+const TYPE_MX: u16 = 15;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+
+// Labels that only give the structure of a name away
+const STRUCTURAL_LABELS: &[&[u8]] = &[b"arpa", b"in-addr", b"ip6", b"local", b"_tcp", b"_udp"];
+
+// DNS over UDP, or over TCP where every message starts with its length
+pub struct Dns {
+    pub tcp: bool,
+}
+
+impl Dissector for Dns {
+    fn name(&self) -> &'static str {
+        "DNS"
+    }
+
+    fn dissect<'a>(&self, data: &'a [u8]) -> Option<Dissection<'a>> {
+        let prefix = if self.tcp { 2 } else { 0 };
+        let header = data.get(prefix..prefix + HEADER_SIZE)?;
+        let field = |offset: usize| u16::from_be_bytes([header[offset], header[offset + 1]]);
+        let mut fields = Vec::new();
+        if self.tcp {
+            fields.push(FieldSpan::new("Length", 0, 2, field(0)));
+        }
+        fields.extend([
+            FieldSpan::new("Transaction ID", prefix, 2, format!("0x{:04x}", field(0))),
+            FieldSpan::new("Flags", prefix + 2, 2, format!("0x{:04x}", field(2))),
+            FieldSpan::new("Questions", prefix + 4, 2, field(4)),
+            FieldSpan::new("Answers", prefix + 6, 2, field(6)),
+            FieldSpan::new("Authority", prefix + 8, 2, field(8)),
+            FieldSpan::new("Additional", prefix + 10, 2, field(10)),
+        ]);
+        let kind = if field(2) & 0x8000 == 0 {
+            "Query"
+        } else {
+            "Response"
+        };
+        // The layer covers the whole message
+        let end = data.len();
+        Some(Dissection::new(kind, fields, &data[end..], Next::End))
+    }
+}
+
+// Scrubs a message in place: names get pseudonyms, A and AAAA records are anonymised
+// like the packet addresses, and TXT strings are blanked. Returns whether the whole
+// message could be walked.
+pub fn scrub(message: &mut [u8], anonymiser: &mut Anonymiser) -> bool {
+    if message.len() < HEADER_SIZE {
+        return false;
+    }
+    let count = |offset: usize| u16::from_be_bytes([message[offset], message[offset + 1]]);
+    let questions = count(4);
+    let records = count(6) as usize + count(8) as usize + count(10) as usize;
+    let mut scrubbed = HashSet::new();
+
+    let mut offset = HEADER_SIZE;
+    for _ in 0..questions {
+        let Some(end) = scrub_name(message, offset, anonymiser, &mut scrubbed) else {
+            return false;
+        };
+        // Type and class
+        offset = end + 4;
+    }
+    for _ in 0..records {
+        let Some(end) = scrub_name(message, offset, anonymiser, &mut scrubbed) else {
+            return false;
+        };
+        let Some(header) = message.get(end..end + 10) else {
+            return false;
+        };
+        let record_type = u16::from_be_bytes([header[0], header[1]]);
+        let length = u16::from_be_bytes([header[8], header[9]]) as usize;
+        let start = end + 10;
+        if start + length > message.len() {
+            return false;
+        }
+        scrub_rdata(
+            message,
+            record_type,
+            start,
+            length,
+            anonymiser,
+            &mut scrubbed,
+        );
+        offset = start + length;
+    }
+    true
+}
+
+fn scrub_rdata(
+    message: &mut [u8],
+    record_type: u16,
+    start: usize,
+    length: usize,
+    anonymiser: &mut Anonymiser,
+    scrubbed: &mut HashSet<usize>,
+) {
+    match record_type {
+        TYPE_A if length == 4 => {
+            let address: [u8; 4] = message[start..start + 4].try_into().unwrap();
+            if let IpAddr::V4(address) = anonymiser.address(IpAddr::V4(Ipv4Addr::from(address))) {
+                message[start..start + 4].copy_from_slice(&address.octets());
+            }
+        }
+        TYPE_AAAA if length == 16 => {
+            let address: [u8; 16] = message[start..start + 16].try_into().unwrap();
+            if let IpAddr::V6(address) = anonymiser.address(IpAddr::V6(Ipv6Addr::from(address))) {
+                message[start..start + 16].copy_from_slice(&address.octets());
+            }
+        }
+        TYPE_NS | TYPE_CNAME | TYPE_PTR => {
+            scrub_name(message, start, anonymiser, scrubbed);
+        }
+        // Preference, then the exchange
+        TYPE_MX if length > 2 => {
+            scrub_name(message, start + 2, anonymiser, scrubbed);
+        }
+        // Priority, weight and port, then the target
+        TYPE_SRV if length > 6 => {
+            scrub_name(message, start + 6, anonymiser, scrubbed);
+        }
+        // Primary name server, then the mailbox of the administrator
+        TYPE_SOA => {
+            if let Some(end) = scrub_name(message, start, anonymiser, scrubbed) {
+                scrub_name(message, end, anonymiser, scrubbed);
+            }
+        }
+        TYPE_TXT => {
+            let mut offset = start;
+            while offset < start + length {
+                let string = message[offset] as usize;
+                let end = (offset + 1 + string).min(start + length);
+                message[offset + 1..end].fill(b'x');
+                offset = end;
+            }
+        }
+        _ => {}
+    }
+}
+
+// Gives every label of the name a pseudonym, following compression pointers but never
+// scrubbing a label twice. Returns where the name ends at its original position.
+fn scrub_name(
+    message: &mut [u8],
+    mut offset: usize,
+    anonymiser: &mut Anonymiser,
+    scrubbed: &mut HashSet<usize>,
+) -> Option<usize> {
+    let mut end = None;
+    // Pointers only go backwards in sane messages, a loop gives up here
+    for _ in 0..128 {
+        let length = *message.get(offset)? as usize;
+        match length {
+            0 => return Some(end.unwrap_or(offset + 1)),
+            _ if length & 0xc0 == 0xc0 => {
+                let pointer = u16::from_be_bytes([length as u8, *message.get(offset + 1)?]);
+                end.get_or_insert(offset + 2);
+                offset = (pointer & 0x3fff) as usize;
+            }
+            _ if length & 0xc0 != 0 => return None,
+            _ => {
+                let label = message.get_mut(offset + 1..offset + 1 + length)?;
+                let structural = STRUCTURAL_LABELS
+                    .iter()
+                    .any(|known| known.eq_ignore_ascii_case(label));
+                if !structural && scrubbed.insert(offset) {
+                    let pseudonym = anonymiser.pseudonym(label);
+                    label.copy_from_slice(&pseudonym);
+                }
+                offset += 1 + length;
+            }
+        }
+    }
+    None
+}
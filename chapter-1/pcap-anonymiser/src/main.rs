@@ -0,0 +1,153 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use packet_kit::{PcapReader, PcapWriter};
+
+mod anonymise;
+mod checksums;
+mod cryptopan;
+mod dns;
+
+use anonymise::{Action, Anonymiser};
+use cryptopan::{CryptoPan, KEY_SIZE};
+
+// Snap length written when the capture doesn't give one
+const DEFAULT_SNAPLEN: u32 = 262144;
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} --key <file> -w <output.pcap> [--payload <protocol>=<keep|zero|truncate[:<bytes>]>]... \
+         [--keep-oui] [--keep-dns-names] [--keep-cookies] <capture.pcap|capture.pcapng>",
+        program
+    );
+    eprintln!("  Protocols are layer names such as tcp, udp, icmp, dns or http, and default");
+    eprintln!("  for the others. Payloads are cut after the transport header unless told");
+    eprintln!("  otherwise, DNS messages are kept with their names and addresses scrubbed.");
+    std::process::exit(1);
+}
+
+// Reads the key from the file, 64 hex digits. A missing file gets a new random key,
+// the same key anonymises later captures consistently.
+fn load_key(path: &str) -> io::Result<[u8; KEY_SIZE]> {
+    if !Path::new(path).exists() {
+        let mut key = [0u8; KEY_SIZE];
+        fs::File::open("/dev/urandom")?.read_exact(&mut key)?;
+        let hex: String = key.iter().map(|byte| format!("{:02x}", byte)).collect();
+        fs::write(path, format!("{}\n", hex))?;
+        eprintln!(
+            "Generated a new key in {}, keep it to anonymise later captures alike",
+            path
+        );
+        return Ok(key);
+    }
+
+    let text = fs::read_to_string(path)?;
+    let text = text.trim();
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: expected {} hex digits", path, KEY_SIZE * 2),
+        )
+    };
+    if text.len() != KEY_SIZE * 2 || !text.is_ascii() {
+        return Err(invalid());
+    }
+    let mut key = [0u8; KEY_SIZE];
+    for (index, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[index * 2..index * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(key)
+}
+
+fn main() -> io::Result<()> {
+    let mut args = std::env::args();
+    let program = args.next().unwrap();
+
+    let mut key_path = None;
+    let mut output = None;
+    let mut input = None;
+    let mut actions = Vec::new();
+    let (mut keep_oui, mut keep_dns_names, mut keep_cookies) = (false, false, false);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage(&program));
+        match arg.as_str() {
+            "--key" => key_path = Some(value()),
+            "-w" => output = Some(value()),
+            "--payload" => {
+                let value = value();
+                let Some((protocol, action)) = value.split_once('=') else {
+                    usage(&program);
+                };
+                match action.parse::<Action>() {
+                    Ok(action) => actions.push((protocol.to_ascii_lowercase(), action)),
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--keep-oui" => keep_oui = true,
+            "--keep-dns-names" => keep_dns_names = true,
+            "--keep-cookies" => keep_cookies = true,
+            _ if arg.starts_with('-') => usage(&program),
+            _ if input.is_none() => input = Some(arg),
+            _ => usage(&program),
+        }
+    }
+    let (Some(key_path), Some(output), Some(input)) = (key_path, output, input) else {
+        usage(&program);
+    };
+
+    let mut anonymiser = Anonymiser::new(CryptoPan::new(&load_key(&key_path)?));
+    anonymiser.keep_oui = keep_oui;
+    anonymiser.scrub_dns = !keep_dns_names;
+    anonymiser.scrub_cookies = !keep_cookies;
+    for (protocol, action) in actions {
+        if protocol == "default" {
+            anonymiser.default_action = action;
+        } else {
+            anonymiser.actions.insert(protocol, action);
+        }
+    }
+
+    let mut capture = PcapReader::open(&input)?;
+    let linktype = capture.linktype;
+    let snaplen = match capture.snaplen {
+        0 => DEFAULT_SNAPLEN,
+        snaplen => snaplen,
+    };
+    // pcapng comments, interface names and the like don't make it into the output
+    let mut writer = PcapWriter::create(&output, linktype, snaplen, capture.nanos())?;
+
+    let mut skipped = 0;
+    while let Some(mut packet) = capture.next_packet()? {
+        // A classic capture holds a single link type
+        if packet.linktype != linktype {
+            skipped += 1;
+            continue;
+        }
+        anonymiser.apply(&mut packet);
+        writer.write_packet(&packet)?;
+    }
+    writer.flush()?;
+
+    let report = &anonymiser.report;
+    println!("Anonymised {} packets into {}", report.packets, output);
+    println!("  {} IP addresses", report.addresses.len());
+    println!("  {} MAC addresses", report.macs.len());
+    println!("  {} DNS messages scrubbed", report.dns_messages);
+    println!("  {} cookie headers blanked", report.cookies);
+    println!(
+        "  {} payloads zeroed, {} truncated",
+        report.zeroed, report.truncated
+    );
+    println!("  {} checksums recomputed", report.checksums);
+    if skipped > 0 {
+        println!(
+            "  {} packets skipped, captured on interfaces of another link type",
+            skipped
+        );
+    }
+    Ok(())
+}
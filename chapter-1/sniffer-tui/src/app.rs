@@ -0,0 +1,241 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::Duration;
+
+use packet_kit::GeoIp;
+
+use crate::capture::Captured;
+use crate::decode::{decode, Summary};
+
+// Packets kept for display, the oldest ones are dropped past this count
+const MAX_PACKETS: usize = 50_000;
+
+pub struct Packet {
+    // Position in the capture, starting at 1
+    pub number: usize,
+    // Time since the first packet
+    pub time: Duration,
+    pub data: Vec<u8>,
+    pub summary: Summary,
+}
+
+// One condition of the display filter
+enum Term {
+    Protocol(String),
+    Host(IpAddr),
+    Port(u16),
+    Text(String),
+    Not(Box<Term>),
+}
+
+impl Term {
+    fn matches(&self, summary: &Summary) -> bool {
+        match self {
+            Term::Protocol(name) => match name.as_str() {
+                "ip" => matches!(summary.src, Some(IpAddr::V4(_))),
+                "ipv6" => matches!(summary.src, Some(IpAddr::V6(_))),
+                _ => summary.protocol.eq_ignore_ascii_case(name),
+            },
+            Term::Host(host) => summary.src == Some(*host) || summary.dst == Some(*host),
+            Term::Port(port) => summary.src_port == Some(*port) || summary.dst_port == Some(*port),
+            Term::Text(text) => {
+                let (src, dst) = summary.endpoints();
+                summary.info.to_lowercase().contains(text)
+                    || src.contains(text)
+                    || dst.contains(text)
+            }
+            Term::Not(term) => !term.matches(summary),
+        }
+    }
+}
+
+// Parses a filter such as "tcp port 443 not host 10.0.0.1", every term must match
+fn parse_filter(filter: &str) -> Result<Vec<Term>, String> {
+    let mut terms = Vec::new();
+    let mut negate = false;
+    let mut words = filter.split_whitespace();
+    while let Some(word) = words.next() {
+        let word = word.to_lowercase();
+        let term = match word.as_str() {
+            "not" | "!" => {
+                negate = !negate;
+                continue;
+            }
+            "ip" | "ipv6" | "tcp" | "udp" | "icmp" | "icmpv6" | "arp" => Term::Protocol(word),
+            "host" => {
+                let host = words.next().ok_or("host needs an address")?;
+                Term::Host(
+                    host.parse()
+                        .map_err(|_| format!("Invalid address '{}'", host))?,
+                )
+            }
+            "port" => {
+                let port = words.next().ok_or("port needs a number")?;
+                Term::Port(
+                    port.parse()
+                        .map_err(|_| format!("Invalid port '{}'", port))?,
+                )
+            }
+            _ => Term::Text(word),
+        };
+        terms.push(if negate {
+            Term::Not(Box::new(term))
+        } else {
+            term
+        });
+        negate = false;
+    }
+    Ok(terms)
+}
+
+#[derive(PartialEq, Eq)]
+pub enum Mode {
+    Browse,
+    // Typing in the filter bar
+    EditFilter,
+}
+
+pub struct App {
+    pub packets: VecDeque<Packet>,
+    // Packets captured so far, including the dropped ones
+    pub captured: usize,
+    first_timestamp: Option<Duration>,
+    // Packet number of the selection, None follows the newest packet
+    pub selected: Option<usize>,
+    pub mode: Mode,
+    pub filter: String,
+    // Text of the filter bar while editing
+    pub input: String,
+    pub filter_error: Option<String>,
+    terms: Vec<Term>,
+    // Packets and bytes seen per address
+    talkers: HashMap<IpAddr, (u64, u64)>,
+    protocols: HashMap<&'static str, u64>,
+    pub source: String,
+    pub status: Option<String>,
+    // Annotates the talkers and the addresses of the selected packet
+    pub geoip: GeoIp,
+}
+
+impl App {
+    pub fn new(source: String, filter: &str, geoip: GeoIp) -> Result<Self, String> {
+        Ok(App {
+            packets: VecDeque::new(),
+            captured: 0,
+            first_timestamp: None,
+            selected: None,
+            mode: Mode::Browse,
+            filter: filter.to_string(),
+            input: String::new(),
+            filter_error: None,
+            terms: parse_filter(filter)?,
+            talkers: HashMap::new(),
+            protocols: HashMap::new(),
+            source,
+            status: None,
+            geoip,
+        })
+    }
+
+    pub fn push(&mut self, frame: Captured) {
+        let first = *self.first_timestamp.get_or_insert(frame.timestamp);
+        let summary = decode(frame.linktype, &frame.data);
+        self.captured += 1;
+
+        *self.protocols.entry(summary.protocol).or_insert(0) += 1;
+        for address in [summary.src, summary.dst].into_iter().flatten() {
+            let talker = self.talkers.entry(address).or_insert((0, 0));
+            talker.0 += 1;
+            talker.1 += frame.data.len() as u64;
+        }
+
+        self.packets.push_back(Packet {
+            number: self.captured,
+            time: frame.timestamp.saturating_sub(first),
+            data: frame.data,
+            summary,
+        });
+        if self.packets.len() > MAX_PACKETS {
+            self.packets.pop_front();
+        }
+    }
+
+    // Packets passing the display filter
+    pub fn visible(&self) -> Vec<&Packet> {
+        self.packets
+            .iter()
+            .filter(|packet| self.terms.iter().all(|term| term.matches(&packet.summary)))
+            .collect()
+    }
+
+    // Position of the selection among the visible packets
+    pub fn selected_index(&self, visible: &[&Packet]) -> Option<usize> {
+        if visible.is_empty() {
+            return None;
+        }
+        match self.selected {
+            Some(number) => Some(
+                visible
+                    .iter()
+                    .position(|packet| packet.number >= number)
+                    .unwrap_or(visible.len() - 1),
+            ),
+            None => Some(visible.len() - 1),
+        }
+    }
+
+    // Moves the selection by the given number of visible packets
+    pub fn move_selection(&mut self, delta: isize) {
+        let visible = self.visible();
+        let index = match self.selected_index(&visible) {
+            Some(index) => index,
+            None => return,
+        };
+        let index = index.saturating_add_signed(delta).min(visible.len() - 1);
+        self.selected = Some(visible[index].number);
+    }
+
+    pub fn select_first(&mut self) {
+        self.selected = self.visible().first().map(|packet| packet.number);
+    }
+
+    // Goes back to following the newest packet
+    pub fn follow(&mut self) {
+        self.selected = None;
+    }
+
+    pub fn apply_filter(&mut self) {
+        match parse_filter(&self.input) {
+            Ok(terms) => {
+                self.terms = terms;
+                self.filter = self.input.clone();
+                self.filter_error = None;
+            }
+            Err(err) => self.filter_error = Some(err),
+        }
+    }
+
+    // Addresses sorted by bytes, the busiest first
+    pub fn top_talkers(&self, count: usize) -> Vec<(IpAddr, u64, u64)> {
+        let mut talkers: Vec<(IpAddr, u64, u64)> = self
+            .talkers
+            .iter()
+            .map(|(address, (packets, bytes))| (*address, *packets, *bytes))
+            .collect();
+        talkers.sort_by_key(|(address, _, bytes)| (Reverse(*bytes), *address));
+        talkers.truncate(count);
+        talkers
+    }
+
+    // Packet count per protocol, the most common first
+    pub fn protocol_distribution(&self) -> Vec<(&'static str, u64)> {
+        let mut protocols: Vec<(&'static str, u64)> = self
+            .protocols
+            .iter()
+            .map(|(name, count)| (*name, *count))
+            .collect();
+        protocols.sort_by_key(|(name, count)| (Reverse(*count), *name));
+        protocols
+    }
+}
@@ -0,0 +1,93 @@
+use std::io;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use packet_kit::capture::LiveCapture;
+use packet_kit::PcapReader;
+
+// A frame handed over to the interface, along with the link type of the source
+pub struct Captured {
+    // Time since the epoch the frame was captured at
+    pub timestamp: Duration,
+    pub linktype: u32,
+    pub data: Vec<u8>,
+}
+
+// Captures every frame seen on the interface, or on all of them
+pub fn live(iface: Option<&str>) -> io::Result<LiveCapture> {
+    let capture = LiveCapture::open(iface)?;
+    capture.set_read_timeout(Some(Duration::from_millis(200)))?;
+    Ok(capture)
+}
+
+// Hands the frames read from the socket over to the interface until it's done
+pub fn sniff(
+    mut capture: LiveCapture,
+    frames: Sender<Captured>,
+    done: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+    while !done.load(Ordering::Relaxed) {
+        let Some(frame) = capture.next_frame(&mut buffer)? else {
+            continue;
+        };
+        let raw_buffer: &[u8] =
+            unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, frame.length) };
+
+        let frame = Captured {
+            timestamp: frame.timestamp,
+            linktype: frame.linktype,
+            data: raw_buffer.to_vec(),
+        };
+        if frames.send(frame).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Plays a capture back, gaps between packets are divided by the multiplier.
+// Without a multiplier the whole capture is loaded at once.
+pub fn playback(
+    path: &str,
+    multiplier: Option<f64>,
+    frames: Sender<Captured>,
+    done: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let mut capture = PcapReader::open(path)?;
+    let mut clock: Option<(Duration, Instant)> = None;
+
+    while let Some(packet) = capture.next_packet()? {
+        if let Some(multiplier) = multiplier {
+            let (first, started) = *clock.get_or_insert((packet.timestamp, Instant::now()));
+            let due = started + packet.timestamp.saturating_sub(first).div_f64(multiplier);
+            // Sleep in short steps so quitting doesn't wait for a long gap
+            while Instant::now() < due {
+                if done.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                thread::sleep(
+                    due.saturating_duration_since(Instant::now())
+                        .min(Duration::from_millis(200)),
+                );
+            }
+        }
+        if done.load(Ordering::Relaxed) {
+            break;
+        }
+        let frame = Captured {
+            timestamp: packet.timestamp,
+            // pcapng files carry a link type per interface
+            linktype: packet.linktype,
+            data: packet.data,
+        };
+        if frames.send(frame).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use std::net::IpAddr;
+
+use packet_kit::dissect::{self, DecodedPacket};
+
+// What the packet list shows about a packet, plus the decoded layers the detail panes draw
+pub struct Summary {
+    pub src: Option<IpAddr>,
+    pub dst: Option<IpAddr>,
+    pub src_port: Option<u16>,
+    pub dst_port: Option<u16>,
+    // Highest layer that was decoded
+    pub protocol: &'static str,
+    pub info: String,
+    pub decoded: DecodedPacket,
+}
+
+impl Summary {
+    // Source and destination columns, a dash when there's no IP layer
+    pub fn endpoints(&self) -> (String, String) {
+        match (self.src, self.dst) {
+            (Some(src), Some(dst)) => (src.to_string(), dst.to_string()),
+            _ => (String::from("-"), String::from("-")),
+        }
+    }
+}
+
+// Decodes every layer we know about, stopping at the first one that doesn't parse.
+// Tunnels are followed, the columns then describe the innermost packet.
+pub fn decode(linktype: u32, frame: &[u8]) -> Summary {
+    let decoded = dissect::decode(linktype, frame);
+    let (src, dst) = decoded.addresses().unzip();
+    let (src_port, dst_port) = decoded.ports().unzip();
+    let (protocol, info) = match decoded.innermost() {
+        Some(layer) => (layer.name, layer.summary.clone()),
+        None => ("Unknown", format!("{} bytes", frame.len())),
+    };
+    Summary {
+        src,
+        dst,
+        src_port,
+        dst_port,
+        protocol,
+        info,
+        decoded,
+    }
+}
@@ -0,0 +1,204 @@
+use std::io;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use packet_kit::privilege::{self, Capability};
+use packet_kit::GeoIp;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+
+mod app;
+mod capture;
+mod decode;
+mod ui;
+
+use app::{App, Mode};
+
+// Longest time spent ingesting packets between two redraws
+const INGEST_BUDGET: Duration = Duration::from_millis(50);
+// Playback multipliers outside this range would overflow the schedule of the packets
+const MULTIPLIERS: RangeInclusive<f64> = 0.001..=1000.0;
+
+// Where packets come from
+enum Source {
+    Live(Option<String>),
+    // Capture file and playback speed multiplier, None loads it at once
+    File(String, Option<f64>),
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [-i <iface>] [-f <filter>] [--geoip <database.mmdb>]...\n       \
+         {} -r <capture.pcap> [--multiplier <x> | --topspeed] [-f <filter>] [--geoip <database.mmdb>]...",
+        program, program
+    );
+    eprintln!(
+        "  The multiplier goes from {} to {}",
+        MULTIPLIERS.start(),
+        MULTIPLIERS.end()
+    );
+    eprintln!("  GeoLite2 City, Country or ASN databases annotate the addresses");
+    std::process::exit(1);
+}
+
+// Handles a key press, returns false once the user wants to quit
+fn handle_key(app: &mut App, code: KeyCode) -> bool {
+    match app.mode {
+        Mode::Browse => match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('/') => {
+                app.input = app.filter.clone();
+                app.mode = Mode::EditFilter;
+            }
+            KeyCode::Up | KeyCode::Char('k') => app.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => app.move_selection(1),
+            KeyCode::PageUp => app.move_selection(-20),
+            KeyCode::PageDown => app.move_selection(20),
+            KeyCode::Home | KeyCode::Char('g') => app.select_first(),
+            KeyCode::End | KeyCode::Char('G') => app.follow(),
+            _ => {}
+        },
+        Mode::EditFilter => match code {
+            KeyCode::Enter => {
+                app.apply_filter();
+                if app.filter_error.is_none() {
+                    app.mode = Mode::Browse;
+                }
+            }
+            KeyCode::Esc => {
+                app.filter_error = None;
+                app.mode = Mode::Browse;
+            }
+            KeyCode::Backspace => {
+                app.input.pop();
+            }
+            KeyCode::Char(c) => app.input.push(c),
+            _ => {}
+        },
+    }
+    true
+}
+
+fn main() -> io::Result<()> {
+    let mut args = std::env::args();
+    let program = args.next().unwrap();
+
+    let mut iface = None;
+    let mut path = None;
+    let mut multiplier = Some(1.0);
+    let mut filter = String::new();
+    let mut geoip = GeoIp::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage(&program));
+        match arg.as_str() {
+            "-i" => iface = Some(value()),
+            "-r" => path = Some(value()),
+            "-f" => filter = value(),
+            "--multiplier" => match value().parse::<f64>() {
+                Ok(x) if MULTIPLIERS.contains(&x) => multiplier = Some(x),
+                _ => usage(&program),
+            },
+            "--topspeed" => multiplier = None,
+            "--geoip" => {
+                if let Err(err) = geoip.open(value()) {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            }
+            _ => usage(&program),
+        }
+    }
+    let source = match (path, iface) {
+        (Some(_), Some(_)) => usage(&program),
+        (Some(path), None) => Source::File(path, multiplier),
+        (None, iface) => Source::Live(iface),
+    };
+
+    let mut app = App::new(
+        match &source {
+            Source::Live(Some(iface)) => iface.clone(),
+            Source::Live(None) => String::from("all interfaces"),
+            Source::File(path, _) => path.clone(),
+        },
+        &filter,
+        geoip,
+    )
+    .unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+
+    // Open the socket before taking over the terminal so errors are readable
+    let done = Arc::new(AtomicBool::new(false));
+    let (frames, received) = mpsc::channel();
+    let capture_thread = match source {
+        Source::Live(iface) => {
+            let live = capture::live(iface.as_deref()).unwrap_or_else(|err| {
+                eprintln!(
+                    "{}",
+                    privilege::explain(
+                        "Can't capture live traffic",
+                        &err,
+                        Capability::NetRaw,
+                        Some("browse a capture file with -r <capture.pcap>")
+                    )
+                );
+                std::process::exit(1);
+            });
+            // Decoding and drawing run without them, parsers included
+            if let Err(err) = privilege::drop_privileges() {
+                eprintln!("Can't drop privileges: {}", err);
+                std::process::exit(1);
+            }
+            let done = done.clone();
+            thread::spawn(move || capture::sniff(live, frames, done))
+        }
+        Source::File(path, multiplier) => {
+            // Fail early on a missing or malformed file
+            packet_kit::PcapReader::open(&path)?;
+            let done = done.clone();
+            thread::spawn(move || capture::playback(&path, multiplier, frames, done))
+        }
+    };
+
+    let mut terminal = ratatui::init();
+    let result = (|| -> io::Result<()> {
+        let mut capture_thread = Some(capture_thread);
+        loop {
+            let started = Instant::now();
+            while started.elapsed() < INGEST_BUDGET {
+                match received.try_recv() {
+                    Ok(frame) => app.push(frame),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        if let Some(handle) = capture_thread.take() {
+                            app.status = Some(match handle.join() {
+                                Ok(Ok(())) => String::from("Capture finished"),
+                                Ok(Err(err)) => format!("Capture failed: {}", err),
+                                Err(_) => String::from("Capture thread panicked"),
+                            });
+                        }
+                        break;
+                    }
+                }
+            }
+
+            terminal.draw(|frame| ui::draw(frame, &app))?;
+
+            if event::poll(Duration::from_millis(100))? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press && !handle_key(&mut app, key.code) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    })();
+    ratatui::restore();
+
+    done.store(true, Ordering::Relaxed);
+    result
+}
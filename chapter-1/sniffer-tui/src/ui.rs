@@ -0,0 +1,307 @@
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState};
+use ratatui::Frame;
+
+use packet_kit::hexdump::byte_shades;
+use packet_kit::layout::byte_owners;
+use packet_kit::GeoIp;
+
+use crate::app::{App, Mode, Packet};
+
+// Bytes per line of the hex dump pane
+const HEX_WIDTH: usize = 16;
+
+// Colours of the layers in the hex pane, a normal and an alternate shade each
+const LAYER_COLORS: &[(Color, Color)] = &[
+    (Color::Cyan, Color::LightCyan),
+    (Color::Yellow, Color::LightYellow),
+    (Color::Magenta, Color::LightMagenta),
+    (Color::Green, Color::LightGreen),
+    (Color::Blue, Color::LightBlue),
+    (Color::Red, Color::LightRed),
+];
+
+fn protocol_color(protocol: &str) -> Color {
+    match protocol {
+        "TCP" => Color::LightBlue,
+        "UDP" => Color::LightCyan,
+        "ICMP" | "ICMPv6" => Color::LightMagenta,
+        "ARP" => Color::Yellow,
+        _ => Color::Gray,
+    }
+}
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let [filter_area, middle, bottom, help] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(8),
+        Constraint::Length(14),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [list_area, stats_area] =
+        Layout::horizontal([Constraint::Percentage(70), Constraint::Percentage(30)]).areas(middle);
+    let [talkers_area, protocols_area] =
+        Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .areas(stats_area);
+    let [tree_area, hex_area] =
+        Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)]).areas(bottom);
+
+    let visible = app.visible();
+    let selected = app.selected_index(&visible);
+    let packet = selected.map(|index| visible[index]);
+
+    draw_filter(frame, app, filter_area);
+    draw_packets(frame, app, &visible, selected, list_area);
+    draw_talkers(frame, app, talkers_area);
+    draw_protocols(frame, app, protocols_area);
+    draw_tree(frame, packet, &app.geoip, tree_area);
+    draw_hex(frame, packet, hex_area);
+
+    let help_text = match app.mode {
+        Mode::Browse => "q quit  / filter  ↑↓ PgUp PgDn select  Home first  End follow newest",
+        Mode::EditFilter => "Enter apply  Esc cancel",
+    };
+    let status = match &app.status {
+        Some(status) => format!("{}  |  {}", help_text, status),
+        None => help_text.to_string(),
+    };
+    frame.render_widget(
+        Paragraph::new(status).style(Style::default().fg(Color::DarkGray)),
+        help,
+    );
+}
+
+fn draw_filter(frame: &mut Frame, app: &App, area: Rect) {
+    let (text, style) = match app.mode {
+        Mode::EditFilter => (
+            format!("{}█", app.input),
+            Style::default().fg(Color::Yellow),
+        ),
+        Mode::Browse => (app.filter.clone(), Style::default()),
+    };
+    let title = match &app.filter_error {
+        Some(err) => format!(" Filter: {} ", err),
+        None => String::from(" Filter (e.g. tcp port 443 not host 10.0.0.1) "),
+    };
+    let block = Block::default().borders(Borders::ALL).title(title);
+    frame.render_widget(Paragraph::new(text).style(style).block(block), area);
+}
+
+fn draw_packets(
+    frame: &mut Frame,
+    app: &App,
+    visible: &[&Packet],
+    selected: Option<usize>,
+    area: Rect,
+) {
+    // Only the rows around the selection are built, the list can hold many packets
+    let height = area.height.saturating_sub(3) as usize;
+    let end = match selected {
+        Some(index) => (index + 1).max(height.min(visible.len())),
+        None => 0,
+    };
+    let start = end.saturating_sub(height);
+
+    let rows = visible[start..end].iter().map(|packet| {
+        let (src, dst) = packet.summary.endpoints();
+        Row::new(vec![
+            Cell::from(packet.number.to_string()),
+            Cell::from(format!("{:.6}", packet.time.as_secs_f64())),
+            Cell::from(src),
+            Cell::from(dst),
+            Cell::from(packet.summary.protocol),
+            Cell::from(packet.data.len().to_string()),
+            Cell::from(packet.summary.info.clone()),
+        ])
+        .style(Style::default().fg(protocol_color(packet.summary.protocol)))
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(7),
+            Constraint::Length(12),
+            Constraint::Length(18),
+            Constraint::Length(18),
+            Constraint::Length(8),
+            Constraint::Length(6),
+            Constraint::Min(10),
+        ],
+    )
+    .header(
+        Row::new(vec![
+            "No.",
+            "Time",
+            "Source",
+            "Destination",
+            "Proto",
+            "Len",
+            "Info",
+        ])
+        .style(Style::default().add_modifier(Modifier::BOLD)),
+    )
+    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+    .block(Block::default().borders(Borders::ALL).title(format!(
+        " {}: {} shown, {} captured{} ",
+        app.source,
+        visible.len(),
+        app.captured,
+        if app.selected.is_none() {
+            ", following"
+        } else {
+            ""
+        }
+    )));
+
+    let mut state = TableState::default().with_selected(selected.map(|index| index - start));
+    frame.render_stateful_widget(table, area, &mut state);
+}
+
+fn draw_talkers(frame: &mut Frame, app: &App, area: Rect) {
+    let rows = app
+        .top_talkers(area.height.saturating_sub(3) as usize)
+        .into_iter()
+        .map(|(address, packets, bytes)| {
+            Row::new(vec![
+                address.to_string(),
+                app.geoip.lookup(address).to_string(),
+                packets.to_string(),
+                bytes.to_string(),
+            ])
+        });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(15),
+            Constraint::Min(10),
+            Constraint::Length(8),
+            Constraint::Length(10),
+        ],
+    )
+    .header(
+        Row::new(vec!["Address", "Location", "Packets", "Bytes"])
+            .style(Style::default().add_modifier(Modifier::BOLD)),
+    )
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" Top talkers "),
+    );
+    frame.render_widget(table, area);
+}
+
+fn draw_protocols(frame: &mut Frame, app: &App, area: Rect) {
+    let total = app.captured.max(1) as f64;
+    let rows = app
+        .protocol_distribution()
+        .into_iter()
+        .map(|(protocol, count)| {
+            Row::new(vec![
+                Cell::from(protocol).style(Style::default().fg(protocol_color(protocol))),
+                Cell::from(count.to_string()),
+                Cell::from(format!("{:.1}%", count as f64 * 100.0 / total)),
+            ])
+        });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Min(10),
+            Constraint::Length(8),
+            Constraint::Length(7),
+        ],
+    )
+    .header(
+        Row::new(vec!["Protocol", "Packets", "Share"])
+            .style(Style::default().add_modifier(Modifier::BOLD)),
+    )
+    .block(Block::default().borders(Borders::ALL).title(" Protocols "));
+    frame.render_widget(table, area);
+}
+
+fn draw_tree(frame: &mut Frame, packet: Option<&Packet>, geoip: &GeoIp, area: Rect) {
+    let mut lines = Vec::new();
+    if let Some(packet) = packet {
+        lines.push(Line::from(format!(
+            "Frame {}: {} bytes",
+            packet.number,
+            packet.data.len()
+        )));
+        for layer in packet.summary.decoded.layers() {
+            let indent = "  ".repeat(layer.depth);
+            lines.push(Line::from(Span::styled(
+                format!("{}▾ {}: {}", indent, layer.name, layer.summary),
+                Style::default().add_modifier(Modifier::BOLD),
+            )));
+            lines.extend(
+                layer.fields.iter().map(|field| {
+                    Line::from(format!("{}    {}: {}", indent, field.name, field.value))
+                }),
+            );
+            // What the GeoIP databases know about the addresses of IP layers
+            if let Some((src, dst)) = layer.addresses {
+                for (name, address) in [("Source", src), ("Destination", dst)] {
+                    let enrichment = geoip.lookup(address);
+                    if !enrichment.is_empty() {
+                        lines.push(Line::from(format!(
+                            "{}    {} GeoIP: {}",
+                            indent, name, enrichment
+                        )));
+                    }
+                }
+            }
+        }
+    }
+    let block = Block::default().borders(Borders::ALL).title(" Layers ");
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+// Hex and ASCII columns, the bytes of each layer in its colour and the fields of a
+// layer in alternating shades
+fn draw_hex(frame: &mut Frame, packet: Option<&Packet>, area: Rect) {
+    let rows = area.height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = match packet {
+        Some(packet) => {
+            let layers = packet.summary.decoded.spans();
+            let shades = byte_shades(&byte_owners(packet.data.len(), &layers));
+            let style = |index: usize| match shades[index] {
+                Some((layer, alternate)) => {
+                    let (normal, bright) = LAYER_COLORS[layer % LAYER_COLORS.len()];
+                    Style::default().fg(if alternate { bright } else { normal })
+                }
+                None => Style::default(),
+            };
+            packet
+                .data
+                .chunks(HEX_WIDTH)
+                .take(rows)
+                .enumerate()
+                .map(|(line, chunk)| {
+                    let start = line * HEX_WIDTH;
+                    let mut spans = vec![Span::styled(
+                        format!("{:04x}  ", start),
+                        Style::default().fg(Color::DarkGray),
+                    )];
+                    for (index, byte) in chunk.iter().enumerate() {
+                        spans.push(Span::styled(format!("{:02x}", byte), style(start + index)));
+                        spans.push(Span::raw(" "));
+                    }
+                    spans.push(Span::raw(" ".repeat((HEX_WIDTH - chunk.len()) * 3 + 1)));
+                    for (index, byte) in chunk.iter().enumerate() {
+                        let character = if byte.is_ascii_graphic() || *byte == b' ' {
+                            *byte as char
+                        } else {
+                            '.'
+                        };
+                        spans.push(Span::styled(character.to_string(), style(start + index)));
+                    }
+                    Line::from(spans)
+                })
+                .collect()
+        }
+        None => Vec::new(),
+    };
+    let block = Block::default().borders(Borders::ALL).title(" Hex ");
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
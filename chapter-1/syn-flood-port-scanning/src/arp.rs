@@ -0,0 +1,293 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use packet_kit::arp::{ArpPacket, ARP_REPLY};
+use packet_kit::capture;
+use packet_kit::ethernet::ETHERTYPE_ARP;
+use packet_kit::{EthernetHeader, MacAddr};
+use socket2::Socket;
+
+use crate::stealth;
+use crate::timing::Timing;
+
+// A MAC flipping back to an address it lost within this long means two hosts claim it
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(60);
+// A MAC claiming this many addresses is likely answering for others
+const MANY_ADDRESSES: usize = 5;
+
+// Hardware address of an interface, as listed under /sys/class/net
+fn interface_mac(iface: &str) -> io::Result<MacAddr> {
+    fs::read_to_string(format!("/sys/class/net/{}/address", iface))
+        .ok()
+        .and_then(|mac| mac.trim().parse().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No Ethernet interface named {}", iface),
+            )
+        })
+}
+
+// Opens a packet socket sending and receiving ARP frames on the interface, along with
+// the MAC of the interface
+pub fn open_interface(iface: &str) -> io::Result<(Socket, MacAddr)> {
+    let mac = interface_mac(iface)?;
+    Ok((capture::open(ETHERTYPE_ARP, Some(iface))?, mac))
+}
+
+// Reads the next ARP packet, None when the read timed out or the frame isn't one
+fn recv_arp(socket: &Socket, buffer: &mut [MaybeUninit<u8>]) -> io::Result<Option<ArpPacket>> {
+    let length = match socket.recv(buffer) {
+        Ok(length) => length,
+        Err(ref err)
+            if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut =>
+        {
+            return Ok(None)
+        }
+        Err(err) => return Err(err),
+    };
+    let raw_buffer: &[u8] =
+        unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, length) };
+    Ok(EthernetHeader::parse(raw_buffer)
+        .filter(|(header, _)| header.ethertype == ETHERTYPE_ARP)
+        .and_then(|(_, payload)| ArpPacket::parse(payload)))
+}
+
+// Collects the replies sent to our address, keyed by the replying address
+fn receive_replies(
+    socket: Socket,
+    source: Ipv4Addr,
+    replies: Arc<Mutex<HashMap<Ipv4Addr, (MacAddr, Instant)>>>,
+    done: Arc<AtomicBool>,
+) -> io::Result<()> {
+    socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+
+    let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+    while !done.load(Ordering::Relaxed) {
+        let arp = match recv_arp(&socket, &mut buffer)? {
+            Some(arp) if arp.operation == ARP_REPLY && arp.target_ip == source => arp,
+            _ => continue,
+        };
+        let mut replies = replies.lock().unwrap();
+        match replies.get(&arp.sender_ip) {
+            Some((mac, _)) if *mac != arp.sender_mac => println!(
+                "Warning: {} answered from both {} and {}",
+                arp.sender_ip, mac, arp.sender_mac
+            ),
+            Some(_) => {}
+            None => {
+                replies.insert(arp.sender_ip, (arp.sender_mac, Instant::now()));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Broadcasts ARP requests for every target on the interface's link and returns the
+// hosts that answered along with their MAC and round trip time, in the order of the targets.
+// Unlike probes sent over IP, hosts can't firewall ARP away and still be reachable.
+pub fn sweep(
+    socket: Socket,
+    mac: MacAddr,
+    targets: &[IpAddr],
+    timing: &Timing,
+) -> io::Result<Vec<(Ipv4Addr, MacAddr, Duration)>> {
+    let targets: Vec<Ipv4Addr> = targets
+        .iter()
+        .filter_map(|target| match target {
+            IpAddr::V4(target) => Some(*target),
+            IpAddr::V6(_) => {
+                eprintln!("Skipping {}: ARP only resolves IPv4 addresses", target);
+                None
+            }
+        })
+        .collect();
+    let Some(first) = targets.first() else {
+        return Ok(Vec::new());
+    };
+
+    let source = stealth::source_address(*first)?;
+    let replies = Arc::new(Mutex::new(HashMap::new()));
+    let done = Arc::new(AtomicBool::new(false));
+
+    let reply_thread = thread::spawn({
+        let socket = socket.try_clone()?;
+        let replies = replies.clone();
+        let done = done.clone();
+        move || {
+            if let Err(err) = receive_replies(socket, source, replies, done) {
+                eprintln!("Error capturing ARP packets: {}", err);
+            }
+        }
+    });
+
+    let mut sent = HashMap::new();
+    let mut rtt = timing.rtt_estimator();
+    for round in 0..=timing.max_retries {
+        let pending: Vec<Ipv4Addr> = {
+            let replies = replies.lock().unwrap();
+            targets
+                .iter()
+                .copied()
+                .filter(|target| !replies.contains_key(target))
+                .collect()
+        };
+        if pending.is_empty() {
+            break;
+        }
+
+        for target in pending {
+            timing.limiter.acquire();
+            let request = ArpPacket::request(mac, source, target).build();
+            let frame = EthernetHeader::new(MacAddr::BROADCAST, mac, ETHERTYPE_ARP).build(&request);
+            sent.insert(target, Instant::now());
+            socket.send(&frame)?;
+        }
+        thread::sleep(rtt.timeout());
+
+        // Karn's algorithm: only replies to requests sent once give a usable sample
+        if round == 0 {
+            for (host, (_, received)) in replies.lock().unwrap().iter() {
+                if let Some(sent) = sent.get(host) {
+                    rtt.update(received.saturating_duration_since(*sent));
+                }
+            }
+        } else {
+            rtt.back_off();
+        }
+    }
+
+    done.store(true, Ordering::Relaxed);
+    reply_thread.join().unwrap();
+
+    let replies = replies.lock().unwrap();
+    Ok(targets
+        .iter()
+        .filter_map(|target| {
+            let (mac, received) = replies.get(target)?;
+            Some((
+                *target,
+                *mac,
+                received.saturating_duration_since(sent[target]),
+            ))
+        })
+        .collect())
+}
+
+// Runs the sweep and prints the hosts that are up
+pub fn run(socket: Socket, mac: MacAddr, targets: &[IpAddr], timing: &Timing) -> io::Result<()> {
+    let up = sweep(socket, mac, targets, timing)?;
+    for (host, mac, rtt) in &up {
+        println!(
+            "Host {} is up ({:.2}ms latency), MAC {}",
+            host,
+            rtt.as_secs_f64() * 1000.0,
+            mac
+        );
+    }
+    println!("{} of {} hosts up", up.len(), targets.len());
+    Ok(())
+}
+
+// What the monitor knows about an address
+struct Binding {
+    mac: MacAddr,
+    // The MAC the address had before and when it changed
+    previous: Option<(MacAddr, Instant)>,
+}
+
+// IP to MAC bindings learnt from the ARP traffic, the MITM indicators printed as they show up
+#[derive(Default)]
+struct BindingTable {
+    bindings: HashMap<Ipv4Addr, Binding>,
+    // Addresses claimed by each MAC
+    claims: HashMap<MacAddr, HashSet<Ipv4Addr>>,
+    // MACs already reported for claiming many addresses
+    reported: HashSet<MacAddr>,
+}
+
+impl BindingTable {
+    fn learn(&mut self, arp: &ArpPacket) {
+        // Probes checking an address is free (RFC 5227) don't claim anything
+        if arp.sender_ip.is_unspecified() {
+            return;
+        }
+        let (ip, mac) = (arp.sender_ip, arp.sender_mac);
+        let kind = if arp.is_gratuitous() {
+            "Gratuitous ARP"
+        } else {
+            "ARP"
+        };
+
+        match self.bindings.get_mut(&ip) {
+            None => {
+                println!("New binding: {} is at {}", ip, mac);
+                self.bindings.insert(
+                    ip,
+                    Binding {
+                        mac,
+                        previous: None,
+                    },
+                );
+            }
+            Some(binding) if binding.mac == mac => {}
+            Some(binding) => {
+                match binding.previous {
+                    // Flapping between two MACs, both hosts keep answering for the address
+                    Some((previous, changed))
+                        if previous == mac && changed.elapsed() < DUPLICATE_WINDOW =>
+                    {
+                        println!(
+                            "ALERT duplicate IP: {} is claimed by both {} and {}",
+                            ip, binding.mac, mac
+                        )
+                    }
+                    _ => println!(
+                        "ALERT {} changed binding: {} moved from {} to {}",
+                        kind, ip, binding.mac, mac
+                    ),
+                }
+                if let Some(claims) = self.claims.get_mut(&binding.mac) {
+                    claims.remove(&ip);
+                }
+                binding.previous = Some((binding.mac, Instant::now()));
+                binding.mac = mac;
+            }
+        }
+
+        let claims = self.claims.entry(mac).or_default();
+        claims.insert(ip);
+        if claims.len() >= MANY_ADDRESSES && self.reported.insert(mac) {
+            let mut addresses: Vec<&Ipv4Addr> = claims.iter().collect();
+            addresses.sort();
+            let addresses: Vec<String> = addresses.iter().map(|ip| ip.to_string()).collect();
+            println!(
+                "ALERT {} claims {} addresses: {}",
+                mac,
+                addresses.len(),
+                addresses.join(", ")
+            );
+        }
+    }
+}
+
+// Listens to the ARP traffic of the interface and reports bindings that change,
+// addresses claimed by two hosts and hosts claiming many addresses
+pub fn watch(iface: &str, socket: Socket) -> io::Result<()> {
+    let mut table = BindingTable::default();
+
+    let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+    println!("Watching ARP traffic on {}", iface);
+    loop {
+        if let Some(arp) = recv_arp(&socket, &mut buffer)? {
+            table.learn(&arp);
+        }
+    }
+}
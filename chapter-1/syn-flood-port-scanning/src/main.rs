@@ -3,310 +3,138 @@ use std::io;
 use std::mem::MaybeUninit;
 use std::net::{IpAddr, Ipv4Addr};
 use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
-use std::time::Duration;
-
-use socket2::{Domain, Protocol, Socket, Type};
+use std::time::{Duration, Instant, SystemTime};
+
+use packet_kit::privilege::{self, Capability};
+use packet_kit::{GeoIp, IcmpHeader, Ipv4Header, TcpHeader, PROTOCOL_ICMP};
+use serde::{Serialize, Serializer};
+use socket2::Socket;
+
+mod arp;
+mod os;
+mod ping;
+mod report;
+mod service;
+mod sockets;
+mod stateless;
+mod stealth;
+mod targets;
+mod timing;
+mod udp;
+
+use ping::EchoSocket;
+use report::ScanRecord;
+use sockets::RawSockets;
+use timing::{RttEstimator, Timing};
 
 // TODO
 const _SNAPLEN: i32 = 320;
 const _PROMISC: bool = true;
-const TIMEOUT: Duration = Duration::from_secs(3);
 
 // TCP Flags
-const _CWR: u16 = 0b10000000;
-const _ECE: u16 = 0b01000000;
-const _URG: u16 = 0b00100000;
+const CWR: u16 = 0b10000000;
+const ECE: u16 = 0b01000000;
+const URG: u16 = 0b00100000;
 const ACK: u16 = 0b00010000;
 const PSH: u16 = 0b00001000;
-const _RST: u16 = 0b00000100;
-const _SYN: u16 = 0b00000010;
+const RST: u16 = 0b00000100;
+const SYN: u16 = 0b00000010;
 const FIN: u16 = 0b00000001;
 
-// Constants for TCP and IP headers size
-const TCP_HEADER_SIZE: usize = 20;
-const IPV4_HEADER_SIZE: usize = 20;
-#[allow(dead_code)]
-struct TCP {
-    source_port: u16,
-    destination_port: u16,
-    sequence_number: u32,
-    acknowledgment_number: u32,
-    data_offset: u8,
-    reserved: u8,
-    flags: u16,
-    window_size: u16,
-    checksum: u16,
-    urgent_pointer: u16,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PortState {
+    Open,
+    Closed,
+    Filtered,
+    Unfiltered,
+    OpenFiltered,
 }
 
-impl TCP {
-    fn new(buffer: &[u8]) -> Self {
-        // Parse the TCP header fields from the buffer
-        let source_port = u16::from_be_bytes([buffer[0], buffer[1]]);
-        let destination_port = u16::from_be_bytes([buffer[2], buffer[3]]);
-        let sequence_number = u32::from_be_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]);
-        let acknowledgment_number =
-            u32::from_be_bytes([buffer[8], buffer[9], buffer[10], buffer[11]]);
-        let data_offset = (buffer[12] >> 4) * 4; // The top 4 bits represent the data offset
-        let reserved = buffer[12] & 0b00001111;
-        let flags = u16::from_be_bytes([buffer[13], buffer[14]]);
-        let window_size = u16::from_be_bytes([buffer[15], buffer[16]]);
-        let checksum = u16::from_be_bytes([buffer[17], buffer[18]]);
-        let urgent_pointer = u16::from_be_bytes([buffer[19], buffer[20]]);
-
-        TCP {
-            source_port,
-            destination_port,
-            sequence_number,
-            acknowledgment_number,
-            data_offset,
-            reserved,
-            flags,
-            window_size,
-            checksum,
-            urgent_pointer,
-        }
+impl std::fmt::Display for PortState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let state = match self {
+            PortState::Open => "open",
+            PortState::Closed => "closed",
+            PortState::Filtered => "filtered",
+            PortState::Unfiltered => "unfiltered",
+            PortState::OpenFiltered => "open|filtered",
+        };
+        write!(f, "{}", state)
     }
 }
 
-#[allow(dead_code)]
-struct IP {
-    ver_ihl: u8,
-    tos: u8,
-    len: u16,
-    id: u16,
-    offset: u16,
-    ttl: u8,
-    protocol_num: u8,
-    sum: u16,
-    src: u32,
-    dst: u32,
+// Reports carry the state the way it's printed
+impl Serialize for PortState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
 }
 
-#[allow(dead_code)]
-impl IP {
-    fn new(buff: &[u8]) -> Option<Self> {
-        if buff.len() >= 20 {
-            let header = IP {
-                ver_ihl: buff[0],
-                tos: buff[1],
-                len: u16::from_be_bytes([buff[2], buff[3]]),
-                id: u16::from_be_bytes([buff[4], buff[5]]),
-                offset: u16::from_be_bytes([buff[6], buff[7]]),
-                ttl: buff[8],
-                protocol_num: buff[9],
-                sum: u16::from_be_bytes([buff[10], buff[11]]),
-                src: u32::from_be_bytes([buff[12], buff[13], buff[14], buff[15]]),
-                dst: u32::from_be_bytes([buff[16], buff[17], buff[18], buff[19]]),
-            };
-
-            Some(header)
-        } else {
-            None
-        }
-    }
+// State of a port, the reason behind it and the round trip time of the reply that decided it
+type PortResult = (PortState, &'static str, Option<Duration>);
 
-    fn protocol(&self) -> String {
-        // Refer to ---> https://www.iana.org/assignments/protocol-numbers/protocol-numbers.xhtml
-        match self.protocol_num {
-            0 => String::from("HOPOPT"),
-            1 => String::from("ICMP"),
-            2 => String::from("IGMP"),
-            3 => String::from("GGP"),
-            4 => String::from("IPv4"),
-            5 => String::from("ST"),
-            6 => String::from("TCP"),
-            7 => String::from("CBT"),
-            8 => String::from("EGP"),
-            9 => String::from("IGP"),
-            10 => String::from("BBN-RCC-MON"),
-            11 => String::from("NVP-II"),
-            12 => String::from("PUP"),
-            13 => String::from("ARGUS"),
-            14 => String::from("EMCON"),
-            15 => String::from("XNET"),
-            16 => String::from("CHAOS"),
-            17 => String::from("UDP"),
-            18 => String::from("MUX"),
-            19 => String::from("DCN-MEAS"),
-            20 => String::from("HMP"),
-            21 => String::from("PRM"),
-            22 => String::from("XNS-IDP"),
-            23 => String::from("TRUNK-1"),
-            24 => String::from("TRUNK-2"),
-            25 => String::from("LEAF-1"),
-            26 => String::from("LEAF-2"),
-            27 => String::from("RDP"),
-            28 => String::from("IRTP"),
-            29 => String::from("ISO-TP4"),
-            30 => String::from("NETBLT"),
-            31 => String::from("MFE-NSP"),
-            32 => String::from("MERIT-INP"),
-            33 => String::from("DCCP"),
-            34 => String::from("3PC"),
-            35 => String::from("IDPR"),
-            36 => String::from("XTP"),
-            37 => String::from("DDP"),
-            38 => String::from("IDPR-CMTP"),
-            39 => String::from("TP++"),
-            40 => String::from("IL"),
-            41 => String::from("IPv6"),
-            42 => String::from("SDRP"),
-            43 => String::from("IPv6-Route"),
-            44 => String::from("IPv6-Frag"),
-            45 => String::from("IDRP"),
-            46 => String::from("RSVP"),
-            47 => String::from("GRE"),
-            48 => String::from("DSR"),
-            49 => String::from("BNA"),
-            50 => String::from("ESP"),
-            51 => String::from("AH"),
-            52 => String::from("I-NLSP"),
-            53 => String::from("SWIPE (deprecated)"),
-            54 => String::from("NARP"),
-            55 => String::from("MOBILE"),
-            56 => String::from("TLSP"),
-            57 => String::from("SKIP"),
-            58 => String::from("IPv6-ICMP"),
-            59 => String::from("IPv6-NoNxt"),
-            60 => String::from("IPv6-Opts"),
-            61 => String::from("any host internal protocol"),
-            62 => String::from("CFTP"),
-            63 => String::from("any local network"),
-            64 => String::from("SAT-EXPAK"),
-            65 => String::from("KRYPTOLAN"),
-            66 => String::from("RVD"),
-            67 => String::from("IPPC"),
-            68 => String::from("any distributed file system"),
-            69 => String::from("SAT-MON"),
-            70 => String::from("VISA"),
-            71 => String::from("IPCV"),
-            72 => String::from("CPNX"),
-            73 => String::from("CPHB"),
-            74 => String::from("WSN"),
-            75 => String::from("PVP"),
-            76 => String::from("BR-SAT-MON"),
-            77 => String::from("SUN-ND"),
-            78 => String::from("WB-MON"),
-            79 => String::from("WB-EXPAK"),
-            80 => String::from("ISO-IP"),
-            81 => String::from("VMTP"),
-            82 => String::from("SECURE-VMTP"),
-            83 => String::from("VINES"),
-            84 => String::from("IPTM"),
-            85 => String::from("NSFNET-IGP"),
-            86 => String::from("DGP"),
-            87 => String::from("TCF"),
-            88 => String::from("EIGRP"),
-            89 => String::from("OSPFIGP"),
-            90 => String::from("Sprite-RPC"),
-            91 => String::from("LARP"),
-            92 => String::from("MTP"),
-            93 => String::from("AX.25"),
-            94 => String::from("IPIP"),
-            95 => String::from("MICP (deprecated)"),
-            96 => String::from("SCC-SP"),
-            97 => String::from("ETHERIP"),
-            98 => String::from("ENCAP"),
-            100 => String::from("GMTP"),
-            101 => String::from("IFMP"),
-            102 => String::from("PNNI"),
-            103 => String::from("PIM"),
-            104 => String::from("ARIS"),
-            105 => String::from("SCPS"),
-            106 => String::from("QNX"),
-            107 => String::from("A/N"),
-            108 => String::from("IPComp"),
-            109 => String::from("SNP"),
-            110 => String::from("Compaq-Peer"),
-            111 => String::from("IPX-in-IP"),
-            112 => String::from("VRRP"),
-            113 => String::from("PGM"),
-            114 => String::from("any 0-hop protocol"),
-            115 => String::from("L2TP"),
-            116 => String::from("DDX"),
-            117 => String::from("IATP"),
-            118 => String::from("STP"),
-            119 => String::from("SRP"),
-            120 => String::from("UTI"),
-            121 => String::from("SMP"),
-            122 => String::from("SM (deprecated)"),
-            123 => String::from("PTP"),
-            124 => String::from("ISIS over IPv4"),
-            125 => String::from("FIRE"),
-            126 => String::from("CRTP"),
-            127 => String::from("CRUDP"),
-            128 => String::from("SSCOPMCE"),
-            129 => String::from("IPLT"),
-            130 => String::from("SPS"),
-            131 => String::from("PIPE"),
-            132 => String::from("SCTP"),
-            133 => String::from("FC"),
-            134 => String::from("RSVP-E2E-IGNORE"),
-            135 => String::from("Mobility Header"),
-            136 => String::from("UDPLite"),
-            137 => String::from("MPLS-in-IP"),
-            138 => String::from("manet"),
-            139 => String::from("HIP"),
-            140 => String::from("Shim6"),
-            141 => String::from("WESP"),
-            142 => String::from("ROHC"),
-            143 => String::from("Ethernet"),
-            144 => String::from("AGGFRAG"),
-            145 => String::from("NSH"),
-            146..=252 => String::from("Unassigned"),
-            253 => String::from("Use for experimentation and testing"),
-            254 => String::from("Use for experimentation and testing"),
-            255 => String::from("Reserved"),
-            _ => format!("{}", self.protocol_num),
-        }
-    }
+impl std::str::FromStr for PortState {
+    type Err = String;
 
-    fn src_address(&self) -> String {
-        Ipv4Addr::from(self.src).to_string()
-    }
-
-    fn dst_address(&self) -> String {
-        Ipv4Addr::from(self.dst).to_string()
+    fn from_str(state: &str) -> Result<Self, Self::Err> {
+        match state {
+            "open" => Ok(PortState::Open),
+            "closed" => Ok(PortState::Closed),
+            "filtered" => Ok(PortState::Filtered),
+            "unfiltered" => Ok(PortState::Unfiltered),
+            "open|filtered" => Ok(PortState::OpenFiltered),
+            _ => Err(format!("Unknown port state '{}'", state)),
+        }
     }
+}
 
-    fn offset(&self) -> String {
-        self.offset.to_string()
+// Parses an ICMP destination unreachable message quoting one of our probes.
+// Returns the destination port of the quoted probe along with the ICMP code.
+fn parse_unreachable(packet: &[u8], target: Ipv4Addr, protocol: u8) -> Option<(u16, u8)> {
+    let (ip_header, message) = Ipv4Header::parse(packet)?;
+    if ip_header.protocol != PROTOCOL_ICMP {
+        return None;
     }
-
-    fn ttl(&self) -> String {
-        self.ttl.to_string()
+    let (icmp_header, quoted) = IcmpHeader::parse(message)?;
+    if icmp_header.type_ != 3 {
+        return None;
     }
 
-    fn ver(&self) -> String {
-        self.ver_ihl.to_string()
+    // The ICMP error quotes the IP header and the first 8 bytes of our probe
+    let (quoted_ip, quoted_probe) = Ipv4Header::parse(quoted)?;
+    if quoted_ip.protocol != protocol || quoted_ip.dst != target {
+        return None;
     }
+    let port = quoted_probe.get(2..4)?;
+    Some((u16::from_be_bytes([port[0], port[1]]), icmp_header.code))
+}
 
-    fn len(&self) -> String {
-        self.len.to_string()
+// Names the ICMP unreachable codes that mean a firewall or router dropped the probe
+fn filtered_reason(code: u8) -> Option<&'static str> {
+    match code {
+        1 => Some("host-unreach"),
+        2 => Some("proto-unreach"),
+        3 => Some("port-unreach"),
+        9 | 10 | 13 => Some("admin-prohibited"),
+        _ => None,
     }
 }
 
 fn sniff(
-    socket: SocketAddr,
+    sniffer: Socket,
     _iface: &str,
     target: &str,
-    results: Arc<Mutex<HashMap<String, usize>>>,
+    results: Arc<Mutex<HashMap<u16, usize>>>,
+    done: Arc<AtomicBool>,
+    ready: mpsc::Sender<()>,
 ) -> io::Result<()> {
-    let socket_protocol = if cfg!(target_os = "windows") {
-        0
-    } else {
-        6 // TCP
-    };
-    let sniffer = Socket::new(
-        Domain::IPV4,
-        Type::RAW,
-        Some(Protocol::from(socket_protocol)),
-    )?;
-    sniffer.bind(&socket.into())?;
+    sniffer.set_read_timeout(Some(Duration::from_millis(200)))?;
+    let _ = ready.send(());
 
     // TODO: set interface
     // Available only on MacOS: https://docs.rs/socket2/latest/socket2/struct.Socket.html#method.device_index_v4
@@ -316,110 +144,579 @@ fn sniff(
     let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
 
     println!("Capturing packets");
-    loop {
+    while !done.load(Ordering::Relaxed) {
         // Receive a TCP packet
-        let _length = sniffer.recv_from(&mut buffer).unwrap();
+        let length = match sniffer.recv_from(&mut buffer) {
+            Ok((length, _)) => length,
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(err) => return Err(err),
+        };
         let raw_buffer: &[u8] =
-            unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, buffer.len()) };
+            unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, length) };
 
-        // Create an IP header from the first 20 bytes
-        let ip_header = match IP::new(&raw_buffer[..20]) {
-            Some(header) => header,
-            None => return Ok(()),
+        let (ip_header, segment) = match Ipv4Header::parse(raw_buffer) {
+            Some(parsed) => parsed,
+            None => continue,
         };
-        if ip_header.dst_address() != target {
+        if ip_header.dst.to_string() != target {
             continue;
         }
 
-        if raw_buffer.len() < IPV4_HEADER_SIZE + TCP_HEADER_SIZE {
-            eprintln!("Invalid packet: too short");
-            continue;
-        }
-
-        let tcp_header =
-            TCP::new(&raw_buffer[IPV4_HEADER_SIZE..IPV4_HEADER_SIZE + TCP_HEADER_SIZE + 1]);
-
-        // Check if the flags match the specified combinations
-        let ack = (tcp_header.flags & ACK) != 0;
-        let fin = (tcp_header.flags & FIN) != 0;
-        let psh = (tcp_header.flags & PSH) != 0;
+        let tcp_header = match TcpHeader::parse(segment) {
+            Some((header, _)) => header,
+            None => {
+                eprintln!("Invalid packet: too short");
+                continue;
+            }
+        };
 
-        if !(ack && fin || ack || ack && psh) {
+        // Every segment after the handshake carries ACK, with or without FIN and PSH
+        if tcp_header.flags & ACK == 0 {
             continue;
         }
 
         // Add the source port
         let mut results = results.lock().unwrap();
         results
-            .entry(tcp_header.destination_port.to_string())
+            .entry(tcp_header.destination_port)
             .and_modify(|e| *e += 1)
             .or_insert(1);
     }
+    Ok(())
 }
 
-fn main() -> io::Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: {} <target_ip> <port_numbers>", args[0]);
-        std::process::exit(1);
+// Scans the ports with full TCP connections, watching the traffic that follows the handshake.
+// Ports are shared between the workers of the timing template. Returns a record per port.
+fn connect_scan(
+    target: IpAddr,
+    ports: &[u16],
+    iface: &str,
+    timing: &Timing,
+    sockets: Option<&RawSockets>,
+) -> io::Result<Vec<ScanRecord>> {
+    let results = Arc::new(Mutex::new(HashMap::new()));
+    let done = Arc::new(AtomicBool::new(false));
+    let (ready, sniffer_ready) = mpsc::channel();
+
+    // The raw socket only sees IPv4 traffic, IPv6 ports are judged by the connection alone,
+    // as are all of them without the privileges to open it
+    let sniffer = match sockets {
+        Some(sockets) if target.is_ipv4() => Some(sockets.tcp()?),
+        _ => None,
+    };
+    let sniff_thread = sniffer.map(|sniffer| {
+        thread::spawn({
+            let iface = iface.to_string();
+            let target = target.to_string();
+            let results = results.clone();
+            let done = done.clone();
+            move || {
+                if let Err(err) = sniff(sniffer, &iface, &target, results, done, ready) {
+                    eprintln!("Error capturing packets: {}", err);
+                }
+            }
+        })
+    });
+    if sniff_thread.is_some() {
+        // Gives up waiting when the sniffer failed and dropped its end of the channel
+        let _ = sniffer_ready.recv();
     }
 
-    let target = match args.get(1) {
-        Some(target) => target,
-        None => "eth0",
-    };
+    let next = AtomicUsize::new(0);
+    let rtt = Mutex::new(timing.rtt_estimator());
+    let records = Mutex::new(Vec::with_capacity(ports.len()));
+    thread::scope(|scope| {
+        for _ in 0..timing.parallelism.min(ports.len()) {
+            scope.spawn(|| {
+                while let Some(port) = ports.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let record = connect_port(target, *port, timing, &rtt);
+                    records.lock().unwrap().push(record);
+                }
+            });
+        }
+    });
 
-    let ports: Vec<&str> = match args.get(2) {
-        Some(ports) => ports.split(',').collect(),
-        None => vec!["eth0"],
-    };
+    // Leaves time for the packets following the last handshakes to be captured
+    thread::sleep(rtt.lock().unwrap().timeout());
 
-    let iface = match args.get(3) {
-        Some(iface) => iface,
-        None => "eth0",
-    };
+    done.store(true, Ordering::Relaxed);
+    if let Some(sniff_thread) = sniff_thread {
+        sniff_thread.join().unwrap();
+    }
 
-    let results = Arc::new(Mutex::new(HashMap::new()));
+    // Open ports whose handshake traffic was captured get a stronger reason
+    let results = results.lock().unwrap();
+    let mut records = records.into_inner().unwrap();
+    for record in records.iter_mut() {
+        if record.state == PortState::Open && results.get(&record.port).is_some_and(|n| *n >= 1) {
+            record.reason = String::from("syn-ack");
+        }
+    }
+    records.sort_by_key(|record| record.port);
+    Ok(records)
+}
 
-    let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 12345);
+// Connects to a port, retrying with a backed off timeout while the attempts time out
+fn connect_port(
+    target: IpAddr,
+    port: u16,
+    timing: &Timing,
+    rtt: &Mutex<RttEstimator>,
+) -> ScanRecord {
+    let target_addr = SocketAddr::new(target, port);
+    for attempt in 0..=timing.max_retries {
+        timing.limiter.acquire();
+        println!("Trying {}", target_addr);
+        // Opens a TCP connection to a remote host with a timeout.
+        let timeout = rtt.lock().unwrap().timeout();
+        let started = Instant::now();
+        let (state, reason) = match TcpStream::connect_timeout(&target_addr, timeout) {
+            Ok(stream) => {
+                drop(stream);
+                (PortState::Open, "connected")
+            }
+            Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                (PortState::Closed, "conn-refused")
+            }
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                rtt.lock().unwrap().back_off();
+                continue;
+            }
+            Err(_) => (PortState::Filtered, "unreachable"),
+        };
+
+        let elapsed = started.elapsed();
+        // Karn's algorithm: retried connections don't give a usable sample
+        if attempt == 0 {
+            rtt.lock().unwrap().update(elapsed);
+        }
+        let mut record = ScanRecord::new(target, port, "tcp", state, reason);
+        record.rtt = Some(elapsed);
+        return record;
+    }
+    ScanRecord::new(target, port, "tcp", PortState::Filtered, "no-response")
+}
 
-    let sniff_thread = thread::spawn({
-        let iface = iface.to_string();
-        let target = target.to_string();
-        let results = results.clone();
-        move || {
-            if let Err(err) = sniff(socket, &iface, &target, results) {
-                eprintln!("Error capturing packets: {}", err);
+// Identifies the service and version listening on each open port
+fn report_services(
+    detector: &service::ServiceDetector,
+    records: &mut [ScanRecord],
+    timing: &Timing,
+) {
+    for record in records
+        .iter_mut()
+        .filter(|record| record.state == PortState::Open)
+    {
+        match detector.detect(record.host, record.port, timing) {
+            Some(service) => {
+                println!("Port {}/tcp service: {}", record.port, service);
+                record.service = Some(service);
             }
+            None => println!("Port {}/tcp service: unrecognized", record.port),
         }
+    }
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [-sU | -sS | -sF | -sN | -sX | -sA | -sW | -sM | --scanflags <flags>] \
+         [-p <ports> | --top-ports <n>] [-iL <file>] [--exclude <targets>] \
+         [--excludefile <file>] [-e <iface>] [-sV] [-O] [-sn [-PR]] \
+         [-T<0-5> | -T <template>] [--max-rate <pps>] [--max-retries <n>] \
+         [-oJ | -oC | -oX | -oG <file>] [-oA <basename>] [--checkpoint <file>] \
+         [--stateless [--seed <n>]] [--geoip <database.mmdb>]... <targets>... [port_numbers]\n       \
+         {} --resume <checkpoint>\n       \
+         {} --passive\n       \
+         {} --arp-watch [-e <iface>]",
+        program, program, program, program
+    );
+    eprintln!(
+        "  Raw probes need root or CAP_NET_RAW, without them -sS falls back to a connect scan \
+         and -sn to ICMP datagram sockets"
+    );
+    std::process::exit(1);
+}
+
+// Writes every requested report of the scan
+fn write_reports(
+    outputs: &[(String, report::Format)],
+    records: &[ScanRecord],
+    command_line: &[String],
+    started: SystemTime,
+    geoip: &GeoIp,
+) -> io::Result<()> {
+    for (path, format) in outputs {
+        report::write_report(path, *format, records, command_line, started, geoip)?;
+        println!("Wrote {} records to {}", records.len(), path);
+    }
+    Ok(())
+}
+
+fn drop_privileges() {
+    if let Err(err) = privilege::drop_privileges() {
+        eprintln!("Can't drop privileges: {}", err);
+        std::process::exit(1);
+    }
+}
+
+// Opens what needs CAP_NET_RAW, saying how to get it when that fails, and gives up the
+// privileges right after
+fn open_privileged<T>(
+    what: &str,
+    fallback: Option<&str>,
+    open: impl FnOnce() -> io::Result<T>,
+) -> T {
+    let opened = open().unwrap_or_else(|err| {
+        eprintln!(
+            "{}",
+            privilege::explain(what, &err, Capability::NetRaw, fallback)
+        );
+        std::process::exit(1);
     });
+    drop_privileges();
+    opened
+}
 
-    thread::sleep(Duration::from_secs(1));
+fn parse_timing(template: &str, program: &str) -> Timing {
+    Timing::from_template(template).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        usage(program)
+    })
+}
 
-    for port in ports {
-        let target_addr = format!("{}:{}", target, port);
-        println!("Trying {}", target_addr);
-        // Opens a TCP connection to a remote host with a timeout.
-        if let Ok(stream) =
-            TcpStream::connect_timeout(&target_addr.parse::<SocketAddr>().unwrap(), TIMEOUT)
-        {
-            println!("Couldn't connect to the remote host...");
-            drop(stream);
+fn main() -> io::Result<()> {
+    let mut args: Vec<String> = std::env::args().collect();
+    let program = args.remove(0);
+
+    // A resumed scan runs again with the command line saved in the checkpoint
+    let progress = match args.as_slice() {
+        [option, path] if option == "--resume" => Some(report::Checkpoint::load(path)?),
+        _ if args.iter().any(|arg| arg == "--resume") => usage(&program),
+        _ => None,
+    };
+    if let Some(progress) = &progress {
+        args = progress.args.clone();
+        println!(
+            "Resuming scan, {} hosts already done: {}",
+            progress.done.len(),
+            args.join(" ")
+        );
+    }
+    let command_line = args.clone();
+    let started = SystemTime::now();
+
+    let mut stealth_scan = match stealth::ScanKind::from_args(&mut args) {
+        Ok(kind) => kind,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut udp_scan = false;
+    let mut version_detection = false;
+    let mut os_detection = false;
+    let mut passive = false;
+    let mut port_spec = None;
+    let mut top_ports = None;
+    let mut target_specs = Vec::new();
+    let mut exclude_specs = Vec::new();
+    let mut iface = String::from("eth0");
+    let mut outputs = Vec::new();
+    let mut checkpoint_path = None;
+    let mut ping_sweep = false;
+    let mut arp_ping = false;
+    let mut arp_watch = false;
+    let mut timing = Timing::default();
+    let mut max_rate = None;
+    let mut max_retries = None;
+    let mut stateless_scan = false;
+    let mut seed = None;
+    let mut geoip = GeoIp::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage(&program));
+        match arg.as_str() {
+            "-sU" => udp_scan = true,
+            "-sV" => version_detection = true,
+            "-O" => os_detection = true,
+            "--passive" => passive = true,
+            "-p" => port_spec = Some(value()),
+            "--top-ports" => {
+                top_ports = Some(value().parse::<usize>().unwrap_or_else(|_| usage(&program)))
+            }
+            "-iL" => target_specs.extend(targets::read_spec_file(&value())?),
+            "--exclude" => exclude_specs.extend(value().split(',').map(String::from)),
+            "--excludefile" => exclude_specs.extend(targets::read_spec_file(&value())?),
+            "-e" => iface = value(),
+            "-oJ" | "-oC" | "-oX" | "-oG" => {
+                outputs.push((value(), report::Format::from_option(&arg).unwrap()))
+            }
+            "-oA" => {
+                let basename = value();
+                for format in report::Format::ALL {
+                    outputs.push((format!("{}.{}", basename, format.extension()), format));
+                }
+            }
+            "--checkpoint" => checkpoint_path = Some(value()),
+            "-sn" => ping_sweep = true,
+            // ARP ping only discovers hosts, it implies -sn
+            "-PR" => {
+                ping_sweep = true;
+                arp_ping = true;
+            }
+            "--arp-watch" => arp_watch = true,
+            "-T" => timing = parse_timing(&value(), &program),
+            _ if arg.starts_with("-T") && arg.len() > 2 => {
+                timing = parse_timing(&arg[2..], &program)
+            }
+            "--max-rate" => {
+                max_rate = Some(value().parse::<f64>().unwrap_or_else(|_| usage(&program)))
+            }
+            "--max-retries" => {
+                max_retries = Some(value().parse::<usize>().unwrap_or_else(|_| usage(&program)))
+            }
+            "--stateless" => stateless_scan = true,
+            "--seed" => seed = Some(value().parse::<u64>().unwrap_or_else(|_| usage(&program))),
+            "--geoip" => {
+                if let Err(err) = geoip.open(value()) {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => usage(&program),
+            _ => target_specs.push(arg),
         }
     }
 
-    thread::sleep(Duration::from_secs(2));
+    // Explicit limits override the ones of the timing template, whatever the order
+    if let Some(rate) = max_rate.filter(|rate| *rate > 0.0) {
+        timing.set_max_rate(rate);
+    }
+    if let Some(retries) = max_retries {
+        timing.max_retries = retries;
+    }
 
-    let results = results.lock().unwrap();
-    for (port, confidence) in results.iter() {
-        if *confidence >= 1 {
-            println!("Port {} open (confidence: {})", port, confidence);
+    // Passive fingerprinting only listens, it doesn't take targets or ports
+    let mut fingerprints = (os_detection || passive).then(|| {
+        os::Fingerprints::new().unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        })
+    });
+    if passive {
+        let sockets = open_privileged("Can't open a raw socket", None, RawSockets::open);
+        return os::passive(fingerprints.as_ref().unwrap(), sockets.tcp()?);
+    }
+    if arp_watch {
+        let (socket, _) = open_privileged("Can't open a packet socket", None, || {
+            arp::open_interface(&iface)
+        });
+        return arp::watch(&iface, socket);
+    }
+
+    // Without -p or --top-ports the last positional argument holds the ports
+    let protocol = if udp_scan { "udp" } else { "tcp" };
+    let ports = match (port_spec, top_ports) {
+        (Some(spec), _) => targets::parse_ports(&spec, protocol),
+        (None, Some(count)) => Ok(targets::top_ports(count, protocol)),
+        // A ping sweep doesn't probe ports, every positional argument is a target
+        (None, None) if ping_sweep => Ok(Vec::new()),
+        (None, None) if target_specs.len() >= 2 => {
+            targets::parse_ports(&target_specs.pop().unwrap(), protocol)
         }
+        (None, None) => usage(&program),
+    };
+    let ports = ports.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+
+    if target_specs.is_empty() {
+        usage(&program);
     }
+    let targets = targets::parse_targets(&target_specs, &exclude_specs).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
 
-    if results.len() == 0 {
-        println!("All scanned ports on {} are closed", target);
+    println!(
+        "Timing: {} ({} probes per second, {} retries)",
+        timing.name,
+        timing.limiter.rate(),
+        timing.max_retries
+    );
+    if arp_ping {
+        let (socket, mac) = open_privileged(
+            "Can't open a packet socket",
+            Some("drop -PR to ping over ICMP"),
+            || arp::open_interface(&iface),
+        );
+        return arp::run(socket, mac, &targets, &timing);
     }
-    sniff_thread.join().unwrap();
-    Ok(())
+    if stateless_scan && (udp_scan || stealth_scan.is_some()) {
+        eprintln!("--stateless only sends SYN probes, it can't be combined with other scan types");
+        std::process::exit(1);
+    }
+
+    // Every other engine shares the same raw sockets. Without the privileges to open them,
+    // the scan makes do with what ordinary sockets can tell.
+    let sockets = if privilege::has_capability(Capability::NetRaw) {
+        Some(open_privileged(
+            "Can't open the raw sockets",
+            None,
+            RawSockets::open,
+        ))
+    } else {
+        drop_privileges();
+        None
+    };
+    if ping_sweep {
+        let echo = match &sockets {
+            Some(sockets) => EchoSocket::raw(sockets.icmp()?),
+            None => EchoSocket::datagram().unwrap_or_else(|err| {
+                eprintln!(
+                    "{}",
+                    privilege::explain(
+                        "Can't open an ICMP datagram socket",
+                        &err,
+                        Capability::NetRaw,
+                        Some("allow ping sockets with sysctl net.ipv4.ping_group_range=\"0 2147483647\"")
+                    )
+                );
+                std::process::exit(1);
+            }),
+        };
+        return ping::run(echo, &targets, &timing);
+    }
+    if sockets.is_none() {
+        let requirement = privilege::requirement(Capability::NetRaw, None);
+        if stateless_scan || matches!(stealth_scan, Some(stealth::ScanKind::Syn)) {
+            eprintln!(
+                "SYN probes need a raw socket, running a connect scan instead\n{}",
+                requirement
+            );
+            stateless_scan = false;
+            stealth_scan = None;
+        } else if stealth_scan.is_some() {
+            eprintln!("Crafted probes need a raw socket\n{}", requirement);
+            std::process::exit(1);
+        }
+        if udp_scan {
+            eprintln!(
+                "Port unreachables can't be seen without a raw socket, closed ports show as open|filtered\n{}",
+                requirement
+            );
+        }
+        if fingerprints.take().is_some() {
+            eprintln!(
+                "Skipping OS detection, its probes need a raw socket\n{}",
+                requirement
+            );
+        }
+    }
+
+    // The stateless engine interleaves every host in a single pass, the per-host steps don't apply
+    if let (true, Some(sockets)) = (stateless_scan, &sockets) {
+        if version_detection || os_detection || checkpoint_path.is_some() {
+            eprintln!("Ignoring -sV, -O and --checkpoint, they need a scan running host by host");
+        }
+        let records = stateless::run(
+            &targets,
+            &ports,
+            &timing,
+            seed.unwrap_or_else(stateless::random_seed),
+            sockets,
+        )?;
+        // Only answering ports are known, silent hosts have nothing to print
+        for host_records in records.chunk_by(|a, b| a.host == b.host) {
+            report::print_records(host_records[0].host, host_records, &geoip);
+        }
+        return write_reports(&outputs, &records, &command_line, started, &geoip);
+    }
+
+    // UDP probes already name the services that answer, -sV only covers TCP
+    let detector = (version_detection && !udp_scan).then(|| {
+        service::ServiceDetector::new().unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        })
+    });
+
+    let mut checkpoint = match &checkpoint_path {
+        Some(path) => Some(report::Checkpoint::open(
+            path,
+            &command_line,
+            progress.is_some(),
+        )?),
+        None => None,
+    };
+    let (done, mut all_records) = match progress {
+        Some(progress) => (progress.done, progress.records),
+        None => Default::default(),
+    };
+
+    for target in targets {
+        if done.contains(&target) {
+            continue;
+        }
+        let mut records = match (target, udp_scan, stealth_scan, &sockets) {
+            (IpAddr::V4(target), true, _, _) => {
+                udp::run(target, &ports, &timing, sockets.as_ref())?
+            }
+            (IpAddr::V4(target), false, Some(kind), Some(sockets)) => {
+                stealth::run(kind, target, &ports, &timing, sockets)?
+            }
+            (IpAddr::V6(_), true, _, _) | (IpAddr::V6(_), false, Some(_), _) => {
+                eprintln!("Skipping {}: raw probes are only crafted for IPv4", target);
+                continue;
+            }
+            // Crafted probes fell back to a connect scan without the raw sockets
+            (_, false, _, _) => connect_scan(target, &ports, &iface, &timing, sockets.as_ref())?,
+        };
+        report::print_records(target, &records, &geoip);
+
+        if let Some(detector) = &detector {
+            report_services(detector, &mut records, &timing);
+        }
+        // The reset probe needs a port known to answer, filtered ones stay silent
+        match (target, &fingerprints, udp_scan, &sockets) {
+            (IpAddr::V4(target), Some(fingerprints), false, Some(sockets)) => {
+                let port = |state| {
+                    records
+                        .iter()
+                        .find(|record| record.state == state)
+                        .map(|record| record.port)
+                };
+                os::run(
+                    fingerprints,
+                    target,
+                    port(PortState::Open),
+                    port(PortState::Closed),
+                    &timing,
+                    sockets,
+                )?;
+            }
+            (IpAddr::V6(_), Some(_), false, _) => {
+                eprintln!(
+                    "Skipping OS detection of {}: probes are only crafted for IPv4",
+                    target
+                )
+            }
+            _ => {}
+        }
+
+        if let Some(checkpoint) = checkpoint.as_mut() {
+            checkpoint.save(target, &records)?;
+        }
+        all_records.extend(records);
+    }
+
+    write_reports(&outputs, &all_records, &command_line, started, &geoip)
 }
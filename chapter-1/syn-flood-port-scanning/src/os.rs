@@ -0,0 +1,1104 @@
+use std::collections::HashMap;
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use packet_kit::{
+    IcmpBuilder, IcmpHeader, Ipv4Header, Layer, TcpBuilder, TcpHeader, PROTOCOL_ICMP, PROTOCOL_TCP,
+};
+use socket2::Socket;
+
+use crate::sockets::RawSockets;
+
+use crate::stealth::source_address;
+use crate::timing::Timing;
+use crate::{ACK, CWR, ECE, PSH, RST, SYN, URG};
+
+// Bundled signatures, see the header of the file for the format
+const OS_FINGERPRINTS: &str = include_str!("os-fingerprints.txt");
+
+// Delay between two probes, keeps the IP ID sequence readable
+const OS_PROBE_INTERVAL: Duration = Duration::from_millis(100);
+// Time to wait for late replies once every probe went out
+const OS_WAIT: Duration = Duration::from_secs(2);
+
+// Initial TTLs used by common stacks, the observed TTL is rounded up to one of them
+const INITIAL_TTLS: [u8; 4] = [32, 64, 128, 255];
+
+// Weight of each active test in the final score
+const ACTIVE_WEIGHTS: &[(&str, u32)] = &[
+    ("ops", 20),
+    ("win", 15),
+    ("ttl", 15),
+    ("ws", 10),
+    ("ipid", 10),
+    ("icmp_ttl", 10),
+    ("df", 5),
+    ("rst_ttl", 5),
+    ("rst_win", 5),
+    ("rst_df", 5),
+];
+// Guesses scoring below this percentage aren't reported
+const ACTIVE_THRESHOLD: u32 = 70;
+
+// SYN probes sent to the open port as (window, options), modelled on the nmap T1 probes.
+// The first one offers every option, its reply is the one matched against the signatures.
+const SYN_PROBES: &[(u16, &[u8])] = &[
+    (
+        1,
+        &[
+            3, 3, 10, 1, 2, 4, 0x05, 0xb4, 8, 10, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 4, 2,
+        ],
+    ),
+    (
+        63,
+        &[
+            2, 4, 0x05, 0x78, 3, 3, 0, 4, 2, 8, 10, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 0,
+        ],
+    ),
+    (
+        4,
+        &[
+            8, 10, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 1, 1, 3, 3, 5, 1, 2, 4, 0x02, 0x80,
+        ],
+    ),
+    (
+        4,
+        &[4, 2, 8, 10, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 3, 3, 10, 0],
+    ),
+    (
+        16,
+        &[
+            2, 4, 0x02, 0x18, 4, 2, 8, 10, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 3, 3, 10, 0,
+        ],
+    ),
+    (
+        512,
+        &[
+            2, 4, 0x01, 0x09, 4, 2, 8, 10, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0,
+        ],
+    ),
+];
+
+// Features of a SYN or SYN/ACK packet, named after the fields of a p0f signature
+struct Observation {
+    source: Ipv4Addr,
+    destination_port: u16,
+    flags: u16,
+    ip_id: u16,
+    df: bool,
+    ttl: u8,
+    ip_options: usize,
+    mss: Option<u16>,
+    window: u16,
+    scale: Option<u8>,
+    layout: Vec<String>,
+    quirks: Vec<&'static str>,
+    payload: bool,
+}
+
+impl Observation {
+    // Decodes an IPv4 packet carrying a TCP segment
+    fn new(packet: &[u8]) -> Option<Self> {
+        let (ip_header, segment) = Ipv4Header::parse(packet)?;
+        if ip_header.protocol != PROTOCOL_TCP {
+            return None;
+        }
+        let (tcp_header, payload) = TcpHeader::parse(segment)?;
+
+        // The flags are reserved, DF and MF from the most significant bit
+        let df = ip_header.flags & 0b010 != 0;
+        let mut quirks = Vec::new();
+        // IP level quirks
+        if df {
+            quirks.push("df");
+            if ip_header.id != 0 {
+                quirks.push("id+");
+            }
+        } else if ip_header.id == 0 {
+            quirks.push("id-");
+        }
+        if ip_header.tos & 0b11 != 0 || tcp_header.flags & (ECE | CWR) != 0 {
+            quirks.push("ecn");
+        }
+        if ip_header.flags & 0b100 != 0 {
+            quirks.push("0+");
+        }
+        // TCP level quirks
+        if tcp_header.sequence_number == 0 {
+            quirks.push("seq-");
+        }
+        if tcp_header.flags & ACK == 0 && tcp_header.acknowledgment_number != 0 {
+            quirks.push("ack+");
+        }
+        if tcp_header.flags & ACK != 0 && tcp_header.acknowledgment_number == 0 {
+            quirks.push("ack-");
+        }
+        if tcp_header.flags & URG == 0 && tcp_header.urgent_pointer != 0 {
+            quirks.push("uptr+");
+        }
+        if tcp_header.flags & URG != 0 {
+            quirks.push("urgf+");
+        }
+        if tcp_header.flags & PSH != 0 {
+            quirks.push("pushf+");
+        }
+
+        let mut observation = Observation {
+            source: ip_header.src,
+            destination_port: tcp_header.destination_port,
+            flags: tcp_header.flags,
+            ip_id: ip_header.id,
+            df,
+            ttl: ip_header.ttl,
+            ip_options: ip_header.options.len(),
+            mss: None,
+            window: tcp_header.window_size,
+            scale: None,
+            layout: Vec::new(),
+            quirks,
+            payload: !payload.is_empty(),
+        };
+        observation.parse_options(&tcp_header.options);
+        Some(observation)
+    }
+
+    // Records the option layout along with the MSS, window scale and timestamp quirks
+    fn parse_options(&mut self, options: &[u8]) {
+        let mut i = 0;
+        while i < options.len() {
+            let kind = options[i];
+            match kind {
+                0 => {
+                    let padding = &options[i + 1..];
+                    self.layout.push(format!("eol+{}", padding.len()));
+                    if padding.iter().any(|byte| *byte != 0) {
+                        self.quirks.push("opt+");
+                    }
+                    return;
+                }
+                1 => {
+                    self.layout.push(String::from("nop"));
+                    i += 1;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let length = options.get(i + 1).copied().unwrap_or(0) as usize;
+            if length < 2 || i + length > options.len() {
+                self.quirks.push("bad");
+                return;
+            }
+            let data = &options[i + 2..i + length];
+            let name = match (kind, length) {
+                (2, 4) => {
+                    self.mss = Some(u16::from_be_bytes([data[0], data[1]]));
+                    String::from("mss")
+                }
+                (3, 3) => {
+                    self.scale = Some(data[0]);
+                    if data[0] > 14 {
+                        self.quirks.push("exws");
+                    }
+                    String::from("ws")
+                }
+                (4, 2) => String::from("sok"),
+                (5, _) => String::from("sack"),
+                (8, 10) => {
+                    if data[0..4] == [0, 0, 0, 0] {
+                        self.quirks.push("ts1-");
+                    }
+                    // The echoed timestamp must stay empty until the peer sent one
+                    if self.flags & ACK == 0 && data[4..8] != [0, 0, 0, 0] {
+                        self.quirks.push("ts2+");
+                    }
+                    String::from("ts")
+                }
+                (2, _) | (3, _) | (4, _) | (8, _) => {
+                    self.quirks.push("bad");
+                    return;
+                }
+                _ => format!("?{}", kind),
+            };
+            self.layout.push(name);
+            i += length;
+        }
+    }
+
+    // Rounds the observed TTL up to the closest common initial TTL
+    fn initial_ttl(&self) -> u8 {
+        INITIAL_TTLS
+            .iter()
+            .copied()
+            .find(|ttl| *ttl >= self.ttl)
+            .unwrap_or(255)
+    }
+
+    fn distance(&self) -> u8 {
+        self.initial_ttl() - self.ttl
+    }
+
+    fn has_timestamps(&self) -> bool {
+        self.layout.iter().any(|option| option == "ts")
+    }
+}
+
+// Formats the observation as a p0f signature, ready to be pasted in the signature file
+impl std::fmt::Display for Observation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mss = self.mss.map_or(String::from("*"), |mss| mss.to_string());
+        write!(
+            f,
+            "4:{}:{}:{}:{},{}:{}:{}:{}",
+            self.initial_ttl(),
+            self.ip_options,
+            mss,
+            self.window,
+            self.scale.unwrap_or(0),
+            self.layout.join(","),
+            self.quirks.join(","),
+            if self.payload { "+" } else { "0" }
+        )
+    }
+}
+
+enum WindowSize {
+    Any,
+    Value(u16),
+    Mss(u32),
+    Mtu(u32),
+    Modulo(u16),
+}
+
+// One "sig" line of the [tcp:request] or [tcp:response] sections
+struct TcpSignature {
+    version: Option<u8>,
+    ttl: u8,
+    // A trailing - on the TTL accepts any lower initial TTL
+    ttl_or_lower: bool,
+    ip_options: usize,
+    mss: Option<u16>,
+    window: WindowSize,
+    scale: Option<u8>,
+    layout: Vec<String>,
+    quirks: Vec<String>,
+    payload: Option<bool>,
+}
+
+impl TcpSignature {
+    fn parse(sig: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = sig.split(':').collect();
+        if fields.len() != 8 {
+            return Err(String::from("a signature holds 8 fields"));
+        }
+        let number = |field: &str| {
+            field
+                .parse::<u32>()
+                .map_err(|_| format!("invalid number '{}'", field))
+        };
+        let optional = |field: &str| match field {
+            "*" => Ok(None),
+            _ => number(field).map(Some),
+        };
+        let list = |field: &str| -> Vec<String> {
+            field
+                .split(',')
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect()
+        };
+
+        let (window, scale) = fields[4]
+            .split_once(',')
+            .ok_or_else(|| String::from("missing window scale"))?;
+        let window = if window == "*" {
+            WindowSize::Any
+        } else if let Some(factor) = window.strip_prefix("mss*") {
+            WindowSize::Mss(number(factor)?)
+        } else if let Some(factor) = window.strip_prefix("mtu*") {
+            WindowSize::Mtu(number(factor)?)
+        } else if let Some(modulo) = window.strip_prefix('%') {
+            WindowSize::Modulo(number(modulo)?.max(1) as u16)
+        } else {
+            WindowSize::Value(number(window)? as u16)
+        };
+
+        Ok(TcpSignature {
+            version: optional(fields[0])?.map(|version| version as u8),
+            ttl: number(fields[1].trim_end_matches('-'))? as u8,
+            ttl_or_lower: fields[1].ends_with('-'),
+            ip_options: number(fields[2])? as usize,
+            mss: optional(fields[3])?.map(|mss| mss as u16),
+            window,
+            scale: optional(scale)?.map(|scale| scale as u8),
+            layout: list(fields[5]),
+            quirks: list(fields[6]),
+            payload: match fields[7] {
+                "0" => Some(false),
+                "+" => Some(true),
+                "*" => None,
+                _ => return Err(format!("invalid payload class '{}'", fields[7])),
+            },
+        })
+    }
+
+    // A fuzzy match tolerates a different TTL and the DF and IP ID quirks that middleboxes rewrite
+    fn matches(&self, observation: &Observation, fuzzy: bool) -> bool {
+        if self.version.is_some_and(|version| version != 4)
+            || self.ip_options != observation.ip_options
+            || self.mss.is_some_and(|mss| Some(mss) != observation.mss)
+            || self
+                .scale
+                .is_some_and(|scale| scale != observation.scale.unwrap_or(0))
+            || self.layout != observation.layout
+            || self
+                .payload
+                .is_some_and(|payload| payload != observation.payload)
+        {
+            return false;
+        }
+
+        let ttl = observation.initial_ttl();
+        if !fuzzy && ttl != self.ttl && !(self.ttl_or_lower && ttl <= self.ttl) {
+            return false;
+        }
+
+        let ignored = |quirk: &str| fuzzy && matches!(quirk, "df" | "id+" | "id-");
+        let mut expected: Vec<&str> = self.quirks.iter().map(String::as_str).collect();
+        let mut observed: Vec<&str> = observation.quirks.clone();
+        expected.retain(|quirk| !ignored(quirk));
+        observed.retain(|quirk| !ignored(quirk));
+        expected.sort_unstable();
+        observed.sort_unstable();
+        if expected != observed {
+            return false;
+        }
+
+        let window = observation.window as u32;
+        let mss = observation.mss.unwrap_or(0) as u32;
+        match self.window {
+            WindowSize::Any => true,
+            WindowSize::Value(value) => observation.window == value,
+            // Stacks sending timestamps often base the window on the MSS minus the option length
+            WindowSize::Mss(factor) => {
+                mss > 0
+                    && (window == mss * factor
+                        || (observation.has_timestamps()
+                            && mss
+                                .checked_sub(12)
+                                .is_some_and(|mss| window == mss * factor)))
+            }
+            WindowSize::Mtu(factor) => mss > 0 && window == (mss + 40) * factor,
+            WindowSize::Modulo(modulo) => observation.window.is_multiple_of(modulo),
+        }
+    }
+}
+
+// A p0f label such as s:unix:Linux:3.x
+struct Label {
+    generic: bool,
+    name: String,
+    flavor: String,
+}
+
+impl Label {
+    fn parse(label: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = label.splitn(4, ':').collect();
+        if fields.len() != 4 || !matches!(fields[0], "s" | "g") {
+            return Err(format!("invalid label '{}'", label));
+        }
+        Ok(Label {
+            generic: fields[0] == "g",
+            name: fields[2].to_string(),
+            flavor: fields[3].to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for Label {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.flavor.is_empty() {
+            write!(f, " {}", self.flavor)?;
+        }
+        if self.generic {
+            write!(f, " (generic)")?;
+        }
+        Ok(())
+    }
+}
+
+// One entry of the [active] section
+struct ActiveSignature {
+    label: String,
+    tests: Vec<(String, String)>,
+}
+
+impl ActiveSignature {
+    // Returns the share of the observed tests matching the signature, as a percentage
+    fn score(&self, observed: &HashMap<&'static str, String>) -> Option<u32> {
+        let mut total = 0;
+        let mut matched = 0;
+        for (test, expected) in &self.tests {
+            let (value, weight) = match (
+                observed.get(test.as_str()),
+                ACTIVE_WEIGHTS.iter().find(|(name, _)| name == test),
+            ) {
+                (Some(value), Some((_, weight))) => (value, *weight),
+                _ => continue,
+            };
+            total += weight;
+            if expected
+                .split('|')
+                .any(|alternative| accepts(alternative, value))
+            {
+                matched += weight;
+            }
+        }
+        (total > 0).then(|| matched * 100 / total)
+    }
+}
+
+// Compares an observed value with an expected value or an a-b range
+fn accepts(expected: &str, value: &str) -> bool {
+    if let (Some((start, end)), Ok(value)) = (expected.split_once('-'), value.parse::<u32>()) {
+        if let (Ok(start), Ok(end)) = (start.parse::<u32>(), end.parse::<u32>()) {
+            return (start..=end).contains(&value);
+        }
+    }
+    expected == value
+}
+
+pub struct Fingerprints {
+    requests: Vec<(Label, TcpSignature)>,
+    responses: Vec<(Label, TcpSignature)>,
+    active: Vec<ActiveSignature>,
+}
+
+impl Fingerprints {
+    pub fn new() -> Result<Self, String> {
+        let mut fingerprints = Fingerprints {
+            requests: Vec::new(),
+            responses: Vec::new(),
+            active: Vec::new(),
+        };
+        let mut section = "";
+        let mut label: Option<String> = None;
+        for (number, line) in OS_FINGERPRINTS.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| format!("os-fingerprints.txt:{}: {}", number + 1, message);
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                if !matches!(name, "tcp:request" | "tcp:response" | "active") {
+                    return Err(error("unknown section"));
+                }
+                section = match name {
+                    "tcp:request" => "request",
+                    "tcp:response" => "response",
+                    _ => "active",
+                };
+                label = None;
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| error("expected key = value"))?;
+            match (section, key) {
+                ("", _) => return Err(error("entry outside of a section")),
+                (_, "label") => {
+                    label = Some(value.to_string());
+                    if section == "active" {
+                        fingerprints.active.push(ActiveSignature {
+                            label: value.to_string(),
+                            tests: Vec::new(),
+                        });
+                    }
+                }
+                ("request" | "response", "sig") => {
+                    let current = label.as_deref().ok_or_else(|| error("sig before label"))?;
+                    let entry = (
+                        Label::parse(current).map_err(|err| error(&err))?,
+                        TcpSignature::parse(value).map_err(|err| error(&err))?,
+                    );
+                    if section == "request" {
+                        fingerprints.requests.push(entry);
+                    } else {
+                        fingerprints.responses.push(entry);
+                    }
+                }
+                ("active", _) => {
+                    if !ACTIVE_WEIGHTS.iter().any(|(name, _)| *name == key) {
+                        return Err(error("unknown active test"));
+                    }
+                    fingerprints
+                        .active
+                        .last_mut()
+                        .ok_or_else(|| error("test before label"))?
+                        .tests
+                        .push((key.to_string(), value.to_string()));
+                }
+                _ => return Err(error("unknown key")),
+            }
+        }
+        Ok(fingerprints)
+    }
+
+    // Finds the label of a SYN or SYN/ACK, specific signatures win over generic ones
+    // and exact matches over fuzzy ones. Returns the label and whether the match is fuzzy.
+    fn classify(&self, observation: &Observation) -> Option<(&Label, bool)> {
+        let signatures = if observation.flags & ACK != 0 {
+            &self.responses
+        } else {
+            &self.requests
+        };
+        for fuzzy in [false, true] {
+            let mut candidates = signatures
+                .iter()
+                .filter(|(_, signature)| signature.matches(observation, fuzzy));
+            let first = candidates.next();
+            let specific = first
+                .filter(|(label, _)| !label.generic)
+                .or_else(|| candidates.find(|(label, _)| !label.generic));
+            if let Some((label, _)) = specific.or(first) {
+                return Some((label, fuzzy));
+            }
+        }
+        None
+    }
+}
+
+// Describes a passive match for the output
+fn describe(fingerprints: &Fingerprints, observation: &Observation) -> String {
+    match fingerprints.classify(observation) {
+        Some((label, false)) => format!("{} (distance {})", label, observation.distance()),
+        Some((label, true)) => format!("{} (fuzzy, distance {})", label, observation.distance()),
+        None => format!("unknown (sig {})", observation),
+    }
+}
+
+fn recv_packet(socket: &Socket, buffer: &mut [MaybeUninit<u8>]) -> io::Result<Option<Vec<u8>>> {
+    match socket.recv_from(buffer) {
+        Ok((length, _)) => {
+            let packet: &[u8] =
+                unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, length) };
+            Ok(Some(packet.to_vec()))
+        }
+        Err(ref err)
+            if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut =>
+        {
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// Fingerprints the SYN and SYN/ACK packets reaching this host and keeps an inventory
+// of the hosts seen. A line is printed whenever a host shows up or its label changes.
+pub fn passive(fingerprints: &Fingerprints, sniffer: Socket) -> io::Result<()> {
+    let mut inventory: HashMap<Ipv4Addr, String> = HashMap::new();
+
+    let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+    println!("Fingerprinting incoming SYN and SYN/ACK packets");
+    loop {
+        let packet = match recv_packet(&sniffer, &mut buffer)? {
+            Some(packet) => packet,
+            None => continue,
+        };
+        let observation = match Observation::new(&packet) {
+            Some(observation) if observation.flags & (SYN | RST) == SYN => observation,
+            _ => continue,
+        };
+
+        let kind = if observation.flags & ACK != 0 {
+            "SYN/ACK"
+        } else {
+            "SYN"
+        };
+        let description = describe(fingerprints, &observation);
+        if inventory.get(&observation.source) != Some(&description) {
+            println!("Host {} {}: {}", observation.source, kind, description);
+            inventory.insert(observation.source, description);
+        }
+    }
+}
+
+// Collects the TCP replies sent by the target, keyed by the port they were sent to
+fn sniff_replies(
+    sniffer: Socket,
+    target: Ipv4Addr,
+    replies: Arc<Mutex<HashMap<u16, Vec<u8>>>>,
+    done: Arc<AtomicBool>,
+) -> io::Result<()> {
+    sniffer.set_read_timeout(Some(Duration::from_millis(200)))?;
+
+    let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+    while !done.load(Ordering::Relaxed) {
+        let packet = match recv_packet(&sniffer, &mut buffer)? {
+            Some(packet) => packet,
+            None => continue,
+        };
+        if let Some(observation) = Observation::new(&packet) {
+            if observation.source == target {
+                replies
+                    .lock()
+                    .unwrap()
+                    .entry(observation.destination_port)
+                    .or_insert(packet);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Sends an ICMP echo request and returns the TTL of the reply
+fn ping(target: Ipv4Addr, timing: &Timing, socket: Socket) -> io::Result<Option<u8>> {
+    socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+
+    let id = std::process::id() as u16;
+    let request = IcmpBuilder::echo_request(id, 1)
+        .payload(b"dark-web-rust os probe")
+        .build(IpAddr::V4(Ipv4Addr::UNSPECIFIED), IpAddr::V4(target));
+    timing.limiter.acquire();
+    socket.send_to(&request, &SocketAddr::new(IpAddr::V4(target), 0).into())?;
+
+    let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+    for _ in 0..OS_WAIT.as_millis() / 200 {
+        let packet = match recv_packet(&socket, &mut buffer)? {
+            Some(packet) => packet,
+            None => continue,
+        };
+        let (ip_header, message) = match Ipv4Header::parse(&packet) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+        if ip_header.src != target || ip_header.protocol != PROTOCOL_ICMP {
+            continue;
+        }
+        let icmp_header = match IcmpHeader::parse(message) {
+            Some((header, _)) => header,
+            None => continue,
+        };
+        if icmp_header.type_ == 0 && icmp_header.id() == id {
+            return Ok(Some(ip_header.ttl));
+        }
+    }
+    Ok(None)
+}
+
+// Names the IP ID sequence generated by the target: zero, incremental,
+// random increments or random
+fn classify_ip_ids(ids: &[u16]) -> Option<&'static str> {
+    if ids.len() < 2 {
+        return None;
+    }
+    if ids.iter().all(|id| *id == 0) {
+        return Some("Z");
+    }
+    let increments: Vec<u16> = ids.windows(2).map(|w| w[1].wrapping_sub(w[0])).collect();
+    if increments.iter().all(|increment| *increment < 10) {
+        Some("I")
+    } else if increments.iter().all(|increment| *increment < 20000) {
+        Some("RI")
+    } else {
+        Some("RD")
+    }
+}
+
+// Probes the target and reports the signatures its replies match.
+// The SYN probes go to an open port, the closed port is expected to answer with a reset.
+pub fn run(
+    fingerprints: &Fingerprints,
+    target: Ipv4Addr,
+    open: Option<u16>,
+    closed: Option<u16>,
+    timing: &Timing,
+    sockets: &RawSockets,
+) -> io::Result<()> {
+    let src = source_address(target)?;
+    // Each probe gets its own source port to tell the replies apart
+    let base_port = 40000 + (std::process::id() % 10000) as u16;
+    let closed_port = base_port + SYN_PROBES.len() as u16;
+
+    let sender = sockets.tcp()?;
+    let replies = Arc::new(Mutex::new(HashMap::new()));
+    let done = Arc::new(AtomicBool::new(false));
+
+    let reply_thread = thread::spawn({
+        let sniffer = sender.try_clone()?;
+        let replies = replies.clone();
+        let done = done.clone();
+        move || {
+            if let Err(err) = sniff_replies(sniffer, target, replies, done) {
+                eprintln!("Error capturing packets: {}", err);
+            }
+        }
+    });
+
+    let destination = SocketAddr::new(IpAddr::V4(target), 0).into();
+    if let Some(port) = open {
+        for (i, (window, options)) in SYN_PROBES.iter().enumerate() {
+            let sequence_number = (std::process::id() as u32) << 8 | i as u32;
+            let probe = TcpBuilder::new(base_port + i as u16, port)
+                .sequence_number(sequence_number)
+                .syn()
+                .window_size(*window)
+                .raw_options(options)
+                .build(IpAddr::V4(src), IpAddr::V4(target));
+            timing.limiter.acquire();
+            sender.send_to(&probe, &destination)?;
+            thread::sleep(OS_PROBE_INTERVAL);
+        }
+    }
+    match closed {
+        Some(port) => {
+            let probe = TcpBuilder::new(closed_port, port)
+                .sequence_number(1)
+                .syn()
+                .build(IpAddr::V4(src), IpAddr::V4(target));
+            timing.limiter.acquire();
+            sender.send_to(&probe, &destination)?;
+        }
+        // A filtered port would stay silent and an open one answer with a SYN/ACK
+        None => println!(
+            "No closed port found on {}, the RST tests of OS detection are skipped",
+            target
+        ),
+    }
+
+    let icmp_ttl = ping(target, timing, sockets.icmp()?)?;
+    thread::sleep(OS_WAIT);
+    done.store(true, Ordering::Relaxed);
+    reply_thread.join().unwrap();
+
+    let replies = replies.lock().unwrap();
+    let syn_acks: Vec<Observation> = (0..SYN_PROBES.len() as u16)
+        .filter_map(|i| replies.get(&(base_port + i)))
+        .filter_map(|packet| Observation::new(packet))
+        .filter(|observation| observation.flags & (SYN | ACK) == SYN | ACK)
+        .collect();
+    let reset = replies
+        .get(&closed_port)
+        .and_then(|packet| Observation::new(packet))
+        .filter(|observation| observation.flags & RST != 0);
+
+    let mut observed: HashMap<&'static str, String> = HashMap::new();
+    if let Some(syn_ack) = syn_acks.first() {
+        println!("OS fingerprint of {}: {}", target, syn_ack);
+        println!(
+            "SYN/ACK signature of {}: {}",
+            target,
+            describe(fingerprints, syn_ack)
+        );
+        observed.insert("ttl", syn_ack.initial_ttl().to_string());
+        observed.insert("df", (syn_ack.df as u8).to_string());
+        observed.insert("win", syn_ack.window.to_string());
+        observed.insert("ops", syn_ack.layout.join(","));
+        if let Some(scale) = syn_ack.scale {
+            observed.insert("ws", scale.to_string());
+        }
+    }
+    let ids: Vec<u16> = syn_acks.iter().map(|syn_ack| syn_ack.ip_id).collect();
+    if let Some(class) = classify_ip_ids(&ids) {
+        observed.insert("ipid", class.to_string());
+    }
+    if let Some(reset) = reset {
+        observed.insert("rst_ttl", reset.initial_ttl().to_string());
+        observed.insert("rst_win", reset.window.to_string());
+        observed.insert("rst_df", (reset.df as u8).to_string());
+    }
+    if let Some(ttl) = icmp_ttl {
+        let ttl = INITIAL_TTLS.iter().find(|initial| **initial >= ttl);
+        observed.insert("icmp_ttl", ttl.unwrap_or(&255).to_string());
+    }
+
+    if observed.is_empty() {
+        println!("No replies from {}, OS detection skipped", target);
+        return Ok(());
+    }
+
+    let mut guesses: Vec<(u32, &str)> = fingerprints
+        .active
+        .iter()
+        .filter_map(|signature| Some((signature.score(&observed)?, signature.label.as_str())))
+        .filter(|(score, _)| *score >= ACTIVE_THRESHOLD)
+        .collect();
+    guesses.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    if guesses.is_empty() {
+        println!("No OS matches for {}", target);
+    }
+    for (score, label) in guesses.iter().take(3) {
+        println!("OS guess for {}: {} ({}%)", target, label, score);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use packet_kit::Ipv4Builder;
+
+    // A Linux SYN/ACK seven hops away: the window is 45 times the MSS less the timestamps
+    fn linux_syn_ack() -> Vec<u8> {
+        Ipv4Builder::new()
+            .src(Ipv4Addr::new(192, 0, 2, 10))
+            .dst(Ipv4Addr::new(192, 0, 2, 1))
+            .ttl(57)
+            .dont_fragment()
+            .payload(
+                TcpBuilder::new(80, 40000)
+                    .sequence_number(1000)
+                    .syn()
+                    .ack(2)
+                    .window_size(65160)
+                    .mss(1460)
+                    .sack_permitted()
+                    .timestamp(12345, 678)
+                    .nop()
+                    .window_scale(7),
+            )
+            .build()
+    }
+
+    fn signature(sig: &str) -> TcpSignature {
+        TcpSignature::parse(sig).unwrap()
+    }
+
+    fn label(label: &str) -> Label {
+        Label::parse(label).unwrap()
+    }
+
+    #[test]
+    fn observes_a_syn_ack() {
+        let observation = Observation::new(&linux_syn_ack()).unwrap();
+        assert_eq!(observation.source, Ipv4Addr::new(192, 0, 2, 10));
+        assert_eq!(observation.destination_port, 40000);
+        assert_eq!(observation.flags, SYN | ACK);
+        assert!(observation.df);
+        assert_eq!((observation.initial_ttl(), observation.distance()), (64, 7));
+        assert_eq!(observation.mss, Some(1460));
+        assert_eq!(observation.scale, Some(7));
+        assert_eq!(observation.layout, ["mss", "sok", "ts", "nop", "ws"]);
+        assert_eq!(observation.quirks, ["df"]);
+        assert!(!observation.payload);
+        assert_eq!(
+            observation.to_string(),
+            "4:64:0:1460:65160,7:mss,sok,ts,nop,ws:df:0"
+        );
+
+        // Truncated packets and other protocols aren't observed
+        let packet = linux_syn_ack();
+        assert!(Observation::new(&packet[..30]).is_none());
+        let udp = Ipv4Builder::new()
+            .payload(packet_kit::UdpBuilder::new(53, 53))
+            .build();
+        assert!(Observation::new(&udp).is_none());
+    }
+
+    #[test]
+    fn observes_quirks() {
+        let packet = Ipv4Builder::new()
+            .id(0)
+            .tos(1)
+            .payload(
+                TcpBuilder::new(40000, 80)
+                    .syn()
+                    .urg(5)
+                    .psh()
+                    .timestamp(0, 9)
+                    .raw_options(&[0, 0, 1]),
+            )
+            .build();
+        let observation = Observation::new(&packet).unwrap();
+        // The padding after the end of the options counts, a non-zero byte in it is a quirk
+        assert_eq!(observation.layout, ["ts", "eol+5"]);
+        assert_eq!(
+            observation.quirks,
+            ["id-", "ecn", "seq-", "urgf+", "pushf+", "ts1-", "ts2+", "opt+"]
+        );
+    }
+
+    #[test]
+    fn parses_signatures() {
+        let parsed = signature("*:64-:0:1460:mss*44,7:mss,sok,ts,nop,ws:df,id+:*");
+        assert_eq!(parsed.version, None);
+        assert_eq!((parsed.ttl, parsed.ttl_or_lower), (64, true));
+        assert_eq!(parsed.mss, Some(1460));
+        assert!(matches!(parsed.window, WindowSize::Mss(44)));
+        assert_eq!(parsed.scale, Some(7));
+        assert_eq!(parsed.layout, ["mss", "sok", "ts", "nop", "ws"]);
+        assert_eq!(parsed.quirks, ["df", "id+"]);
+        assert_eq!(parsed.payload, None);
+
+        assert!(matches!(
+            signature("4:128:0:*:*,*:::0").window,
+            WindowSize::Any
+        ));
+        assert!(matches!(
+            signature("4:64:0:*:mtu*4,*:::0").window,
+            WindowSize::Mtu(4)
+        ));
+        assert!(matches!(
+            signature("4:64:0:*:%512,*:::+").window,
+            WindowSize::Modulo(512)
+        ));
+        assert!(matches!(
+            signature("4:64:0:*:8192,*:::0").window,
+            WindowSize::Value(8192)
+        ));
+
+        for invalid in [
+            "4:64:0:*:8192,0:mss:df",
+            "4:64:0:*:8192:mss:df:0",
+            "4:64:0:big:8192,0:mss:df:0",
+            "4:64:0:*:8192,0:mss:df:?",
+        ] {
+            assert!(TcpSignature::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn matches_signatures() {
+        let observation = Observation::new(&linux_syn_ack()).unwrap();
+        for sig in [
+            "*:64:0:*:mss*45,7:mss,sok,ts,nop,ws:df:0",
+            "4:64:0:1460:65160,*:mss,sok,ts,nop,ws:df:*",
+            "*:128-:0:*:%8,7:mss,sok,ts,nop,ws:df:0",
+            "*:64:0:*:*,7:mss,sok,ts,nop,ws:df:0",
+        ] {
+            assert!(signature(sig).matches(&observation, false), "{}", sig);
+        }
+        for sig in [
+            "6:64:0:*:*,7:mss,sok,ts,nop,ws:df:0",
+            "*:64:0:1380:*,7:mss,sok,ts,nop,ws:df:0",
+            "*:64:0:*:mss*44,7:mss,sok,ts,nop,ws:df:0",
+            "*:64:0:*:*,8:mss,sok,ts,nop,ws:df:0",
+            "*:64:0:*:*,7:mss,nop,ws,sok,ts:df:0",
+            "*:64:0:*:*,7:mss,sok,ts,nop,ws:df,id+:0",
+            "*:64:0:*:*,7:mss,sok,ts,nop,ws:df:+",
+            "*:32-:0:*:*,7:mss,sok,ts,nop,ws:df:0",
+        ] {
+            assert!(!signature(sig).matches(&observation, false), "{}", sig);
+        }
+
+        // Fuzzy matches let the TTL and the quirks middleboxes rewrite through
+        let sig = signature("*:128:0:*:*,7:mss,sok,ts,nop,ws:id+:0");
+        assert!(!sig.matches(&observation, false));
+        assert!(sig.matches(&observation, true));
+        let sig = signature("*:128:0:*:*,7:mss,sok,ts,nop,ws:df,ecn:0");
+        assert!(!sig.matches(&observation, true));
+    }
+
+    #[test]
+    fn loads_the_bundled_fingerprints() {
+        let fingerprints = Fingerprints::new().unwrap();
+        assert!(!fingerprints.requests.is_empty());
+        assert!(!fingerprints.responses.is_empty());
+        assert!(fingerprints
+            .active
+            .iter()
+            .all(|signature| !signature.tests.is_empty()));
+
+        let observation = Observation::new(&linux_syn_ack()).unwrap();
+        assert_eq!(
+            describe(&fingerprints, &observation),
+            "Linux 4.x-6.x (distance 7)"
+        );
+    }
+
+    #[test]
+    fn classify_prefers_specific_and_exact_matches() {
+        let observation = Observation::new(&linux_syn_ack()).unwrap();
+        let generic = || {
+            (
+                label("g:unix:Linux:"),
+                signature("*:64:0:*:*,*:mss,sok,ts,nop,ws:df:0"),
+            )
+        };
+        let specific = || {
+            (
+                label("s:unix:Linux:4.x-6.x"),
+                signature("*:64:0:*:mss*45,7:mss,sok,ts,nop,ws:df:0"),
+            )
+        };
+        let fuzzy = || {
+            (
+                label("s:unix:Linux:fuzzy"),
+                signature("*:128:0:*:mss*45,7:mss,sok,ts,nop,ws:df:0"),
+            )
+        };
+        let classify = |responses: Vec<(Label, TcpSignature)>| {
+            let fingerprints = Fingerprints {
+                requests: Vec::new(),
+                responses,
+                active: Vec::new(),
+            };
+            fingerprints
+                .classify(&observation)
+                .map(|(label, fuzzy)| (label.to_string(), fuzzy))
+        };
+
+        // A specific signature wins over a generic one listed before it
+        assert_eq!(
+            classify(vec![generic(), specific()]),
+            Some((String::from("Linux 4.x-6.x"), false))
+        );
+        // An exact generic match wins over a fuzzy specific one
+        assert_eq!(
+            classify(vec![fuzzy(), generic()]),
+            Some((String::from("Linux (generic)"), false))
+        );
+        assert_eq!(
+            classify(vec![fuzzy()]),
+            Some((String::from("Linux fuzzy"), true))
+        );
+        // SYN/ACKs are only matched against the response signatures
+        let fingerprints = Fingerprints {
+            requests: vec![specific()],
+            responses: Vec::new(),
+            active: Vec::new(),
+        };
+        assert!(fingerprints.classify(&observation).is_none());
+    }
+
+    #[test]
+    fn classifies_ip_id_sequences() {
+        assert_eq!(classify_ip_ids(&[7]), None);
+        assert_eq!(classify_ip_ids(&[0, 0, 0]), Some("Z"));
+        assert_eq!(classify_ip_ids(&[100, 101, 103, 110]), Some("I"));
+        // The counter wraps around
+        assert_eq!(classify_ip_ids(&[65534, 1, 3]), Some("I"));
+        assert_eq!(classify_ip_ids(&[100, 5000, 19000]), Some("RI"));
+        assert_eq!(classify_ip_ids(&[40000, 1000, 30000]), Some("RD"));
+    }
+
+    #[test]
+    fn scores_active_signatures() {
+        let signature = ActiveSignature {
+            label: String::from("Linux"),
+            tests: vec![
+                (String::from("ttl"), String::from("64")),
+                (String::from("win"), String::from("64240-65535")),
+                (String::from("ipid"), String::from("Z|I")),
+                (String::from("rst_ttl"), String::from("64")),
+            ],
+        };
+        let mut observed = HashMap::new();
+        assert_eq!(signature.score(&observed), None);
+        observed.insert("ttl", String::from("64"));
+        observed.insert("win", String::from("65160"));
+        observed.insert("ipid", String::from("I"));
+        assert_eq!(signature.score(&observed), Some(100));
+        observed.insert("ttl", String::from("128"));
+        // 15 of the 40 weighted points are off
+        assert_eq!(signature.score(&observed), Some(62));
+    }
+}
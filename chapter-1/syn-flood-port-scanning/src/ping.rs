@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use packet_kit::{IcmpBuilder, IcmpHeader, Ipv4Header, Layer};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::timing::Timing;
+
+// The socket a sweep runs on
+pub struct EchoSocket {
+    socket: Socket,
+    // Raw sockets hand over the IP header of the replies, datagram sockets only the message
+    raw: bool,
+}
+
+impl EchoSocket {
+    pub fn raw(socket: Socket) -> Self {
+        EchoSocket { socket, raw: true }
+    }
+
+    // Opens an unprivileged ping socket, which Linux allows to the groups listed in
+    // net.ipv4.ping_group_range. The kernel only passes on the replies to its requests.
+    pub fn datagram() -> io::Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::ICMPV4))?;
+        socket.bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0).into())?;
+        Ok(EchoSocket { socket, raw: false })
+    }
+
+    // Identifier of the requests. Datagram sockets overwrite it with the port they're bound to.
+    fn id(&self) -> io::Result<u16> {
+        if self.raw {
+            return Ok(std::process::id() as u16);
+        }
+        let address = self.socket.local_addr()?;
+        Ok(address.as_socket().map_or(0, |address| address.port()))
+    }
+}
+
+// Builds an ICMP echo request carrying our identifier
+fn echo_request(target: Ipv4Addr, id: u16, sequence: u16) -> Vec<u8> {
+    // ICMPv4 checksums don't cover the addresses
+    IcmpBuilder::echo_request(id, sequence)
+        .payload(b"dark-web-rust ping sweep")
+        .build(IpAddr::V4(Ipv4Addr::UNSPECIFIED), IpAddr::V4(target))
+}
+
+// Collects the echo replies carrying our identifier, keyed by the replying host
+fn receive_replies(
+    socket: Socket,
+    raw: bool,
+    id: u16,
+    replies: Arc<Mutex<HashMap<Ipv4Addr, Instant>>>,
+    done: Arc<AtomicBool>,
+) -> io::Result<()> {
+    socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+
+    let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+    while !done.load(Ordering::Relaxed) {
+        let (length, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(err) => return Err(err),
+        };
+        let raw_buffer: &[u8] =
+            unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, length) };
+
+        let (source, message) = if raw {
+            match Ipv4Header::parse(raw_buffer) {
+                Some((ip_header, message)) => (ip_header.src, message),
+                None => continue,
+            }
+        } else {
+            match from.as_socket_ipv4() {
+                Some(from) => (*from.ip(), raw_buffer),
+                None => continue,
+            }
+        };
+        let icmp_header = match IcmpHeader::parse(message) {
+            Some((header, _)) => header,
+            None => continue,
+        };
+        if icmp_header.type_ == 0 && icmp_header.id() == id {
+            replies
+                .lock()
+                .unwrap()
+                .entry(source)
+                .or_insert_with(Instant::now);
+        }
+    }
+    Ok(())
+}
+
+// Sends ICMP echo requests to every target and returns the hosts that answered
+// along with their round trip time, in the order of the targets
+pub fn sweep(
+    echo: EchoSocket,
+    targets: &[IpAddr],
+    timing: &Timing,
+) -> io::Result<Vec<(Ipv4Addr, Duration)>> {
+    let targets: Vec<Ipv4Addr> = targets
+        .iter()
+        .filter_map(|target| match target {
+            IpAddr::V4(target) => Some(*target),
+            IpAddr::V6(_) => {
+                eprintln!("Skipping {}: echo requests are only sent over IPv4", target);
+                None
+            }
+        })
+        .collect();
+
+    let id = echo.id()?;
+    let EchoSocket { socket, raw } = echo;
+    let replies = Arc::new(Mutex::new(HashMap::new()));
+    let done = Arc::new(AtomicBool::new(false));
+
+    let reply_thread = thread::spawn({
+        let socket = socket.try_clone()?;
+        let replies = replies.clone();
+        let done = done.clone();
+        move || {
+            if let Err(err) = receive_replies(socket, raw, id, replies, done) {
+                eprintln!("Error capturing ICMP packets: {}", err);
+            }
+        }
+    });
+
+    let mut sent = HashMap::new();
+    let mut rtt = timing.rtt_estimator();
+    for round in 0..=timing.max_retries {
+        let pending: Vec<Ipv4Addr> = {
+            let replies = replies.lock().unwrap();
+            targets
+                .iter()
+                .copied()
+                .filter(|target| !replies.contains_key(target))
+                .collect()
+        };
+        if pending.is_empty() {
+            break;
+        }
+
+        for (sequence, target) in pending.into_iter().enumerate() {
+            timing.limiter.acquire();
+            let request = echo_request(target, id, sequence as u16);
+            sent.insert(target, Instant::now());
+            socket.send_to(&request, &SocketAddr::new(IpAddr::V4(target), 0).into())?;
+        }
+        thread::sleep(rtt.timeout());
+
+        // Karn's algorithm: only replies to requests sent once give a usable sample
+        if round == 0 {
+            for (host, received) in replies.lock().unwrap().iter() {
+                if let Some(sent) = sent.get(host) {
+                    rtt.update(received.saturating_duration_since(*sent));
+                }
+            }
+        } else {
+            rtt.back_off();
+        }
+    }
+
+    done.store(true, Ordering::Relaxed);
+    reply_thread.join().unwrap();
+
+    let replies = replies.lock().unwrap();
+    Ok(targets
+        .iter()
+        .filter_map(|target| {
+            let received = replies.get(target)?;
+            Some((*target, received.saturating_duration_since(sent[target])))
+        })
+        .collect())
+}
+
+// Runs the sweep and prints the hosts that are up
+pub fn run(echo: EchoSocket, targets: &[IpAddr], timing: &Timing) -> io::Result<()> {
+    let up = sweep(echo, targets, timing)?;
+    for (host, rtt) in &up {
+        println!(
+            "Host {} is up ({:.2}ms latency)",
+            host,
+            rtt.as_secs_f64() * 1000.0
+        );
+    }
+    println!("{} of {} hosts up", up.len(), targets.len());
+    Ok(())
+}
@@ -0,0 +1,707 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use packet_kit::{Enrichment, GeoIp};
+use serde::{Serialize, Serializer};
+
+use crate::service::Service;
+use crate::PortState;
+
+// First line of a checkpoint file
+const CHECKPOINT_HEADER: &str = "# dark-web-rust scan checkpoint";
+
+// The outcome of probing one port of one host
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScanRecord {
+    pub host: IpAddr,
+    pub port: u16,
+    #[serde(rename = "proto")]
+    pub protocol: &'static str,
+    pub state: PortState,
+    pub reason: String,
+    #[serde(flatten)]
+    pub service: Option<Service>,
+    #[serde(
+        rename = "rtt_ms",
+        serialize_with = "milliseconds",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub rtt: Option<Duration>,
+}
+
+// Round trip times are written in milliseconds, with the microseconds as decimals
+fn milliseconds<S: Serializer>(rtt: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+    match rtt {
+        Some(rtt) => serializer.serialize_f64(rtt.as_micros() as f64 / 1000.0),
+        None => serializer.serialize_none(),
+    }
+}
+
+// A line of the JSON Lines report
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    #[serde(flatten)]
+    record: &'a ScanRecord,
+    #[serde(skip_serializing_if = "Enrichment::is_empty")]
+    geoip: Enrichment,
+}
+
+impl ScanRecord {
+    pub fn new(
+        host: IpAddr,
+        port: u16,
+        protocol: &'static str,
+        state: PortState,
+        reason: &str,
+    ) -> Self {
+        ScanRecord {
+            host,
+            port,
+            protocol,
+            state,
+            reason: reason.to_string(),
+            service: crate::targets::service_name(port, protocol).map(|name| Service {
+                name: name.to_string(),
+                ..Default::default()
+            }),
+            rtt: None,
+        }
+    }
+
+    fn service_name(&self) -> &str {
+        self.service
+            .as_ref()
+            .map_or("unknown", |service| service.name.as_str())
+    }
+
+    // Product, version and extra info joined the way nmap prints them
+    fn version(&self) -> String {
+        let service = match &self.service {
+            Some(service) => service,
+            None => return String::new(),
+        };
+        let mut parts: Vec<String> = [&service.product, &service.version]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+        if let Some(info) = &service.info {
+            parts.push(format!("({})", info));
+        }
+        parts.join(" ")
+    }
+}
+
+impl std::fmt::Display for ScanRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Port {}/{} {} {} ({})",
+            self.port,
+            self.protocol,
+            self.state,
+            self.service_name(),
+            self.reason
+        )?;
+        if let Some(rtt) = self.rtt {
+            write!(f, " rtt {:.2}ms", rtt.as_secs_f64() * 1000.0)?;
+        }
+        Ok(())
+    }
+}
+
+// Prints the records of a host, closed ports are only counted
+pub fn print_records(host: IpAddr, records: &[ScanRecord], geoip: &GeoIp) {
+    let enrichment = geoip.lookup(host);
+    if !enrichment.is_empty() {
+        println!("Host {} ({})", host, enrichment);
+    }
+    let mut closed = 0;
+    for record in records {
+        if record.state == PortState::Closed {
+            closed += 1;
+            continue;
+        }
+        println!("{}", record);
+    }
+    if closed == records.len() {
+        println!("All scanned ports on {} are closed", host);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    JsonLines,
+    Csv,
+    Xml,
+    Grepable,
+}
+
+impl Format {
+    // Maps the -oJ, -oC, -oX and -oG options to a format
+    pub fn from_option(option: &str) -> Option<Self> {
+        match option {
+            "-oJ" => Some(Format::JsonLines),
+            "-oC" => Some(Format::Csv),
+            "-oX" => Some(Format::Xml),
+            "-oG" => Some(Format::Grepable),
+            _ => None,
+        }
+    }
+
+    // File extension used by -oA
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::JsonLines => "jsonl",
+            Format::Csv => "csv",
+            Format::Xml => "xml",
+            Format::Grepable => "gnmap",
+        }
+    }
+
+    pub const ALL: [Format; 4] = [
+        Format::JsonLines,
+        Format::Csv,
+        Format::Xml,
+        Format::Grepable,
+    ];
+}
+
+// Writes the records of the whole scan in the given format.
+// Records are expected grouped by host, in the order the hosts were scanned.
+pub fn write_report(
+    path: &str,
+    format: Format,
+    records: &[ScanRecord],
+    args: &[String],
+    started: SystemTime,
+    geoip: &GeoIp,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    match format {
+        Format::JsonLines => {
+            for record in records {
+                let line = JsonRecord {
+                    record,
+                    geoip: geoip.lookup(record.host),
+                };
+                serde_json::to_writer(&mut out, &line)?;
+                writeln!(out)?;
+            }
+        }
+        Format::Csv => {
+            writeln!(
+                out,
+                "host,port,proto,state,reason,service,version,rtt_ms,country,city,asn,organisation"
+            )?;
+            for record in records {
+                let enrichment = geoip.lookup(record.host);
+                let fields = [
+                    record.host.to_string(),
+                    record.port.to_string(),
+                    record.protocol.to_string(),
+                    record.state.to_string(),
+                    record.reason.clone(),
+                    record.service_name().to_string(),
+                    record.version(),
+                    record.rtt.map_or(String::new(), |rtt| {
+                        format!("{:.3}", rtt.as_secs_f64() * 1000.0)
+                    }),
+                    // Special ranges hold their label in place of the country
+                    enrichment
+                        .label
+                        .map(String::from)
+                        .or(enrichment.country_code)
+                        .unwrap_or_default(),
+                    enrichment.city.unwrap_or_default(),
+                    enrichment.asn.map_or(String::new(), |asn| asn.to_string()),
+                    enrichment.organisation.unwrap_or_default(),
+                ];
+                let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+                writeln!(out, "{}", fields.join(","))?;
+            }
+        }
+        Format::Xml => write_xml(&mut out, records, args, started)?,
+        Format::Grepable => write_grepable(&mut out, records, args, started)?,
+    }
+    out.flush()
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Splits the records into consecutive runs belonging to the same host
+fn by_host(records: &[ScanRecord]) -> impl Iterator<Item = &[ScanRecord]> {
+    records.chunk_by(|a, b| a.host == b.host)
+}
+
+fn epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// Follows the nmap XML output format (https://nmap.org/book/nmap-dtd.html) so
+// the report can be fed to the tools that consume it
+fn write_xml(
+    out: &mut impl Write,
+    records: &[ScanRecord],
+    args: &[String],
+    started: SystemTime,
+) -> io::Result<()> {
+    let finished = SystemTime::now();
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(out, "<!DOCTYPE nmaprun>")?;
+    writeln!(
+        out,
+        "<nmaprun scanner=\"dark-web-rust\" args=\"{}\" start=\"{}\" version=\"{}\" xmloutputversion=\"1.05\">",
+        xml_escape(&args.join(" ")),
+        epoch(started),
+        env!("CARGO_PKG_VERSION")
+    )?;
+
+    let mut hosts = 0;
+    for host_records in by_host(records) {
+        hosts += 1;
+        let host = host_records[0].host;
+        let addrtype = if host.is_ipv4() { "ipv4" } else { "ipv6" };
+        writeln!(out, "<host>")?;
+        writeln!(out, "<status state=\"up\" reason=\"user-set\"/>")?;
+        writeln!(
+            out,
+            "<address addr=\"{}\" addrtype=\"{}\"/>",
+            host, addrtype
+        )?;
+        writeln!(out, "<ports>")?;
+
+        let closed = host_records
+            .iter()
+            .filter(|record| record.state == PortState::Closed)
+            .count();
+        if closed > 0 {
+            writeln!(out, "<extraports state=\"closed\" count=\"{}\"/>", closed)?;
+        }
+        for record in host_records
+            .iter()
+            .filter(|record| record.state != PortState::Closed)
+        {
+            writeln!(
+                out,
+                "<port protocol=\"{}\" portid=\"{}\"><state state=\"{}\" reason=\"{}\" reason_ttl=\"0\"/>",
+                record.protocol,
+                record.port,
+                record.state,
+                xml_escape(&record.reason)
+            )?;
+            if let Some(service) = &record.service {
+                write!(out, "<service name=\"{}\"", xml_escape(&service.name))?;
+                let details = [
+                    ("product", &service.product),
+                    ("version", &service.version),
+                    ("extrainfo", &service.info),
+                    ("ostype", &service.os),
+                ];
+                for (name, value) in details {
+                    if let Some(value) = value {
+                        write!(out, " {}=\"{}\"", name, xml_escape(value))?;
+                    }
+                }
+                let probed = service.product.is_some() || service.version.is_some();
+                let (method, conf) = if probed { ("probed", 10) } else { ("table", 3) };
+                writeln!(out, " method=\"{}\" conf=\"{}\"/>", method, conf)?;
+            }
+            writeln!(out, "</port>")?;
+        }
+        writeln!(out, "</ports>")?;
+
+        // Smoothed RTT in microseconds, averaged over the probes that got an answer
+        let rtts: Vec<Duration> = host_records
+            .iter()
+            .filter_map(|record| record.rtt)
+            .collect();
+        if !rtts.is_empty() {
+            let srtt = rtts.iter().sum::<Duration>() / rtts.len() as u32;
+            writeln!(out, "<times srtt=\"{}\"/>", srtt.as_micros())?;
+        }
+        writeln!(out, "</host>")?;
+    }
+
+    writeln!(
+        out,
+        "<runstats><finished time=\"{}\" elapsed=\"{}\"/><hosts up=\"{}\" down=\"0\" total=\"{}\"/></runstats>",
+        epoch(finished),
+        finished.duration_since(started).unwrap_or_default().as_secs(),
+        hosts,
+        hosts
+    )?;
+    writeln!(out, "</nmaprun>")
+}
+
+// Follows the nmap grepable format, one line per host
+fn write_grepable(
+    out: &mut impl Write,
+    records: &[ScanRecord],
+    args: &[String],
+    started: SystemTime,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "# dark-web-rust {} scan initiated at {} as: {}",
+        env!("CARGO_PKG_VERSION"),
+        epoch(started),
+        args.join(" ")
+    )?;
+    for host_records in by_host(records) {
+        let mut ports = Vec::new();
+        let mut closed = 0;
+        for record in host_records {
+            if record.state == PortState::Closed {
+                closed += 1;
+                continue;
+            }
+            // port/state/protocol/owner/service/rpc info/version info/
+            ports.push(format!(
+                "{}/{}/{}//{}//{}/",
+                record.port,
+                record.state,
+                record.protocol,
+                record.service_name(),
+                record.version().replace('/', "|")
+            ));
+        }
+        write!(
+            out,
+            "Host: {} ()\tPorts: {}",
+            host_records[0].host,
+            ports.join(", ")
+        )?;
+        if closed > 0 {
+            write!(out, "\tIgnored State: closed ({})", closed)?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "# dark-web-rust done at {}", epoch(SystemTime::now()))
+}
+
+// Progress of a scan kept on disk so an interrupted run can pick up where it stopped.
+// The file starts with the command line, then every finished host appends its
+// records followed by a "done" line. Records of a host without a "done" line are
+// dropped on resume and the host is scanned again.
+pub struct Checkpoint {
+    file: File,
+}
+
+// What a checkpoint file holds once loaded
+pub struct Progress {
+    pub args: Vec<String>,
+    pub done: HashSet<IpAddr>,
+    pub records: Vec<ScanRecord>,
+}
+
+impl Checkpoint {
+    // Starts a new checkpoint, or appends to an existing one when resuming
+    pub fn open(path: &str, args: &[String], resume: bool) -> io::Result<Self> {
+        if resume {
+            let file = OpenOptions::new().append(true).open(path)?;
+            // Drop what an interrupted save left, the next records would run
+            // into a line cut short and the host would get its records twice
+            file.set_len(finished_length(&fs::read(path)?) as u64)?;
+            return Ok(Checkpoint { file });
+        }
+        let mut file = File::create(path)?;
+        writeln!(file, "{}", CHECKPOINT_HEADER)?;
+        writeln!(file, "args\t{}", args.join("\t"))?;
+        file.sync_data()?;
+        Ok(Checkpoint { file })
+    }
+
+    // Records a finished host, the data reaches the disk before the next host starts
+    pub fn save(&mut self, host: IpAddr, records: &[ScanRecord]) -> io::Result<()> {
+        let mut lines = String::new();
+        for record in records {
+            let service = record.service.clone().unwrap_or_default();
+            let fields = [
+                record.port.to_string(),
+                record.protocol.to_string(),
+                record.state.to_string(),
+                record.reason.clone(),
+                service.name,
+                service.product.unwrap_or_default(),
+                service.version.unwrap_or_default(),
+                service.info.unwrap_or_default(),
+                service.os.unwrap_or_default(),
+                record
+                    .rtt
+                    .map_or(String::new(), |rtt| rtt.as_micros().to_string()),
+            ];
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|field| field.replace(['\t', '\n', '\r'], " "))
+                .collect();
+            lines.push_str(&format!("record\t{}\t{}\n", host, fields.join("\t")));
+        }
+        lines.push_str(&format!("done\t{}\n", host));
+        self.file.write_all(lines.as_bytes())?;
+        self.file.sync_data()
+    }
+
+    pub fn load(path: &str) -> io::Result<Progress> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let contents = fs::read(path)?;
+        let contents = String::from_utf8_lossy(&contents[..finished_length(&contents)]);
+        let mut lines = contents.lines();
+        if lines.next() != Some(CHECKPOINT_HEADER) {
+            return Err(invalid(format!("{} is not a scan checkpoint", path)));
+        }
+
+        let mut progress = Progress {
+            args: Vec::new(),
+            done: HashSet::new(),
+            records: Vec::new(),
+        };
+        let mut pending = Vec::new();
+        for (number, line) in lines.enumerate() {
+            let error = || {
+                invalid(format!(
+                    "{}:{}: malformed checkpoint line",
+                    path,
+                    number + 2
+                ))
+            };
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields[0] {
+                "args" => progress.args = fields[1..].iter().map(|arg| arg.to_string()).collect(),
+                "record" if fields.len() == 12 => {
+                    pending.push(parse_record(&fields[1..]).ok_or_else(error)?);
+                }
+                "done" if fields.len() == 2 => {
+                    let host: IpAddr = fields[1].parse().map_err(|_| error())?;
+                    progress.done.insert(host);
+                    progress
+                        .records
+                        .extend(pending.drain(..).filter(|record| record.host == host));
+                }
+                // Lines of an unknown kind are left alone
+                _ => continue,
+            }
+        }
+        if progress.args.is_empty() {
+            return Err(invalid(format!("{} holds no command line", path)));
+        }
+        Ok(progress)
+    }
+}
+
+// Length of the checkpoint up to the last finished host. What follows belongs to
+// a host the interruption caught, with its last line possibly cut short.
+fn finished_length(contents: &[u8]) -> usize {
+    let mut length = 0;
+    let mut start = 0;
+    for end in (0..contents.len()).filter(|&end| contents[end] == b'\n') {
+        if !contents[start..].starts_with(b"record\t") {
+            length = end + 1;
+        }
+        start = end + 1;
+    }
+    length
+}
+
+fn parse_record(fields: &[&str]) -> Option<ScanRecord> {
+    let optional = |value: &str| (!value.is_empty()).then(|| value.to_string());
+    let protocol = match fields[2] {
+        "tcp" => "tcp",
+        "udp" => "udp",
+        _ => return None,
+    };
+    let service = optional(fields[5]).map(|name| Service {
+        name,
+        product: optional(fields[6]),
+        version: optional(fields[7]),
+        info: optional(fields[8]),
+        os: optional(fields[9]),
+    });
+    Some(ScanRecord {
+        host: fields[0].parse().ok()?,
+        port: fields[1].parse().ok()?,
+        protocol,
+        state: fields[3].parse().ok()?,
+        reason: fields[4].to_string(),
+        service,
+        rtt: match fields[10] {
+            "" => None,
+            micros => Some(Duration::from_micros(micros.parse().ok()?)),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}-{}.checkpoint", name, std::process::id()));
+        path.to_string_lossy().into_owned()
+    }
+
+    fn args() -> Vec<String> {
+        vec!["syn-flood-port-scanning".to_string(), "-sV".to_string()]
+    }
+
+    fn ssh(host: &str) -> ScanRecord {
+        let mut record =
+            ScanRecord::new(host.parse().unwrap(), 22, "tcp", PortState::Open, "syn-ack");
+        record.service = Some(Service {
+            name: "ssh".to_string(),
+            product: Some("OpenSSH".to_string()),
+            version: Some("9.6p1".to_string()),
+            info: Some("protocol\t2.0".to_string()),
+            os: None,
+        });
+        record.rtt = Some(Duration::from_micros(1234));
+        record
+    }
+
+    // Tabs would split the fields, they're saved as spaces
+    fn ssh_saved(host: &str) -> ScanRecord {
+        let mut record = ssh(host);
+        record.service.as_mut().unwrap().info = Some("protocol 2.0".to_string());
+        record
+    }
+
+    fn closed(host: &str) -> ScanRecord {
+        ScanRecord::new(host.parse().unwrap(), 81, "tcp", PortState::Closed, "reset")
+    }
+
+    fn append(path: &str, data: &str) {
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let record = ssh("10.0.0.1");
+        let line = JsonRecord {
+            record: &record,
+            geoip: Enrichment {
+                label: Some("private"),
+                ..Default::default()
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&line).unwrap(),
+            "{\"host\":\"10.0.0.1\",\"port\":22,\"proto\":\"tcp\",\"state\":\"open\",\
+             \"reason\":\"syn-ack\",\"service\":\"ssh\",\"product\":\"OpenSSH\",\
+             \"version\":\"9.6p1\",\"info\":\"protocol\\t2.0\",\"rtt_ms\":1.234,\
+             \"geoip\":{\"label\":\"private\"}}"
+        );
+
+        // Without a service, a reply or anything known about the address
+        let record = ScanRecord::new(
+            "2001:db8::1".parse().unwrap(),
+            161,
+            "udp",
+            PortState::OpenFiltered,
+            "no-response",
+        );
+        let line = JsonRecord {
+            record: &record,
+            geoip: Enrichment::default(),
+        };
+        assert_eq!(
+            serde_json::to_string(&line).unwrap(),
+            "{\"host\":\"2001:db8::1\",\"port\":161,\"proto\":\"udp\",\
+             \"state\":\"open|filtered\",\"reason\":\"no-response\",\"service\":\"snmp\"}"
+        );
+    }
+
+    #[test]
+    fn checkpoints_round_trip() {
+        let path = checkpoint_path("round-trip");
+        let mut checkpoint = Checkpoint::open(&path, &args(), false).unwrap();
+        checkpoint
+            .save(
+                "10.0.0.1".parse().unwrap(),
+                &[ssh("10.0.0.1"), closed("10.0.0.1")],
+            )
+            .unwrap();
+        checkpoint.save("10.0.0.2".parse().unwrap(), &[]).unwrap();
+
+        let progress = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(progress.args, args());
+        assert_eq!(
+            progress.done,
+            HashSet::from(["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()])
+        );
+        assert_eq!(
+            progress.records,
+            vec![ssh_saved("10.0.0.1"), closed("10.0.0.1")]
+        );
+    }
+
+    #[test]
+    fn checkpoints_drop_unfinished_hosts() {
+        let path = checkpoint_path("unfinished");
+        let mut checkpoint = Checkpoint::open(&path, &args(), false).unwrap();
+        checkpoint
+            .save("10.0.0.1".parse().unwrap(), &[closed("10.0.0.1")])
+            .unwrap();
+        // Records of 10.0.0.12 got saved but its "done" line was cut short,
+        // what remains of it names another host
+        append(
+            &path,
+            "record\t10.0.0.12\t81\ttcp\tclosed\treset\t\t\t\t\t\t\ndone\t10.0.0.1",
+        );
+
+        let progress = Checkpoint::load(&path).unwrap();
+        assert_eq!(progress.done, HashSet::from(["10.0.0.1".parse().unwrap()]));
+        assert_eq!(progress.records, vec![closed("10.0.0.1")]);
+
+        // Resuming appends after the last finished host
+        let mut checkpoint = Checkpoint::open(&path, &args(), true).unwrap();
+        checkpoint
+            .save("10.0.0.12".parse().unwrap(), &[ssh("10.0.0.12")])
+            .unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        let progress = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(contents.ends_with("\ndone\t10.0.0.12\n"));
+        assert!(!contents.contains("done\t10.0.0.1record"));
+        assert_eq!(
+            progress.done,
+            HashSet::from(["10.0.0.1".parse().unwrap(), "10.0.0.12".parse().unwrap()])
+        );
+        assert_eq!(
+            progress.records,
+            vec![closed("10.0.0.1"), ssh_saved("10.0.0.12")]
+        );
+    }
+
+    #[test]
+    fn checkpoints_are_recognised() {
+        let path = checkpoint_path("foreign");
+        fs::write(&path, "host,port,proto\n").unwrap();
+        assert!(Checkpoint::load(&path).is_err());
+        // Interrupted before the command line reached the disk
+        fs::write(&path, format!("{}\nargs\tsyn-flood", CHECKPOINT_HEADER)).unwrap();
+        let loaded = Checkpoint::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err());
+    }
+}
@@ -0,0 +1,541 @@
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use regex::bytes::{Regex, RegexBuilder};
+use serde::Serialize;
+
+use crate::timing::Timing;
+
+// Bundled probes and signatures, see the header of the file for the format
+const SERVICE_PROBES: &str = include_str!("service-probes.txt");
+
+// Time to wait for the server to answer a probe
+const TIMEOUT: Duration = Duration::from_secs(3);
+// Time to wait for more data once the server started answering
+const READ_GRACE: Duration = Duration::from_millis(500);
+// Largest response kept for matching
+const MAX_RESPONSE: usize = 16 * 1024;
+
+struct Match {
+    service: String,
+    pattern: Regex,
+    soft: bool,
+    product: Option<String>,
+    version: Option<String>,
+    info: Option<String>,
+    os: Option<String>,
+}
+
+struct Probe {
+    name: String,
+    payload: Vec<u8>,
+    ports: Vec<u16>,
+    matches: Vec<Match>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Service {
+    #[serde(rename = "service")]
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+}
+
+impl std::fmt::Display for Service {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(product) = &self.product {
+            write!(f, " {}", product)?;
+        }
+        if let Some(version) = &self.version {
+            write!(f, " {}", version)?;
+        }
+        if let Some(info) = &self.info {
+            write!(f, " ({})", info)?;
+        }
+        if let Some(os) = &self.os {
+            write!(f, " [{}]", os)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct ServiceDetector {
+    probes: Vec<Probe>,
+}
+
+impl ServiceDetector {
+    pub fn new() -> Result<Self, String> {
+        Ok(ServiceDetector {
+            probes: parse_probes(SERVICE_PROBES)?,
+        })
+    }
+
+    // Connects to an open port and runs probes until a signature identifies the service.
+    // Probes registered for the port run first, the NULL probe always runs before them.
+    pub fn detect(&self, target: IpAddr, port: u16, timing: &Timing) -> Option<Service> {
+        let mut probes: Vec<&Probe> = self.probes.iter().collect();
+        probes.sort_by_key(|probe| (!probe.payload.is_empty(), !probe.ports.contains(&port)));
+
+        let mut soft_match: Option<Service> = None;
+        for probe in probes {
+            // Once a softmatch named the service, only probes for that port are worth sending
+            if soft_match.is_some() && !probe.ports.contains(&port) {
+                break;
+            }
+            let response = match send_probe(target, port, &probe.payload, timing) {
+                Ok(response) if !response.is_empty() => response,
+                _ => continue,
+            };
+
+            let named = soft_match.as_ref().map(|service| service.name.as_str());
+            let (service, soft) = match probe.identify(&response, named) {
+                Some(identified) => identified,
+                None => continue,
+            };
+            if !soft {
+                return Some(service);
+            }
+            soft_match.get_or_insert(service);
+        }
+        soft_match
+    }
+}
+
+impl Probe {
+    // The first signature matching the response and whether it was a softmatch.
+    // Once a softmatch named the service, only signatures for that service count.
+    fn identify(&self, response: &[u8], named: Option<&str>) -> Option<(Service, bool)> {
+        self.matches
+            .iter()
+            .filter(|candidate| named.is_none_or(|name| name == candidate.service))
+            .find_map(|candidate| {
+                let captures = candidate.pattern.captures(response)?;
+                let service = Service {
+                    name: candidate.service.clone(),
+                    product: substitute(&candidate.product, &captures),
+                    version: substitute(&candidate.version, &captures),
+                    info: substitute(&candidate.info, &captures),
+                    os: substitute(&candidate.os, &captures),
+                };
+                Some((service, candidate.soft))
+            })
+    }
+}
+
+// Opens a fresh connection, sends the payload and reads whatever comes back
+fn send_probe(target: IpAddr, port: u16, payload: &[u8], timing: &Timing) -> io::Result<Vec<u8>> {
+    timing.limiter.acquire();
+    let address = SocketAddr::new(target, port);
+    let mut stream = TcpStream::connect_timeout(&address, timing.max_rtt_timeout)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    if !payload.is_empty() {
+        stream.write_all(payload)?;
+    }
+
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 4096];
+    while response.len() < MAX_RESPONSE {
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(length) => {
+                response.extend_from_slice(&buffer[..length]);
+                stream.set_read_timeout(Some(READ_GRACE))?;
+            }
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                break
+            }
+            // A reset right after the response still leaves us something to match
+            Err(_) if !response.is_empty() => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(response)
+}
+
+// Replaces $1 to $9 with the capture groups and tidies the separators left by empty groups
+fn substitute(template: &Option<String>, captures: &regex::bytes::Captures) -> Option<String> {
+    let template = template.as_ref()?;
+    let mut value = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek().and_then(|next| next.to_digit(10))) {
+            ('$', Some(group)) => {
+                chars.next();
+                if let Some(capture) = captures.get(group as usize) {
+                    value.push_str(&String::from_utf8_lossy(capture.as_bytes()));
+                }
+            }
+            _ => value.push(c),
+        }
+    }
+    let value = value.trim().trim_end_matches(',').trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn parse_probes(source: &str) -> Result<Vec<Probe>, String> {
+    let mut probes: Vec<Probe> = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| format!("service-probes.txt:{}: {}", number + 1, message);
+        let (directive, rest) = line
+            .split_once(' ')
+            .ok_or_else(|| error("missing arguments"))?;
+
+        match directive {
+            "Probe" => {
+                let mut fields = rest.splitn(3, ' ');
+                let (protocol, name, payload) = (fields.next(), fields.next(), fields.next());
+                if protocol != Some("TCP") {
+                    return Err(error("only TCP probes are supported"));
+                }
+                let (payload, _) = delimited(payload.unwrap_or(""), 'q')
+                    .ok_or_else(|| error("malformed probe payload"))?;
+                probes.push(Probe {
+                    name: name.unwrap_or_default().to_string(),
+                    payload: unescape(payload),
+                    ports: Vec::new(),
+                    matches: Vec::new(),
+                });
+            }
+            "ports" => {
+                let probe = probes
+                    .last_mut()
+                    .ok_or_else(|| error("ports before any probe"))?;
+                probe.ports =
+                    crate::targets::parse_ports(rest, "tcp").map_err(|err| error(&err))?;
+            }
+            "match" | "softmatch" => {
+                let probe = probes
+                    .last_mut()
+                    .ok_or_else(|| error("match before any probe"))?;
+                let (service, rest) = rest.split_once(' ').ok_or_else(|| error("missing regex"))?;
+                let (pattern, rest) =
+                    delimited(rest, 'm').ok_or_else(|| error("malformed regex"))?;
+                let (options, fields) = rest.split_once(' ').unwrap_or((rest, ""));
+                let pattern = RegexBuilder::new(pattern)
+                    .unicode(false)
+                    .dot_matches_new_line(options.contains('s'))
+                    .case_insensitive(options.contains('i'))
+                    .build()
+                    .map_err(|err| error(&err.to_string()))?;
+
+                let mut candidate = Match {
+                    service: service.to_string(),
+                    pattern,
+                    soft: directive == "softmatch",
+                    product: None,
+                    version: None,
+                    info: None,
+                    os: None,
+                };
+                let mut fields = fields.trim();
+                while !fields.is_empty() {
+                    let kind = fields.chars().next().unwrap();
+                    let (value, rest) =
+                        delimited(fields, kind).ok_or_else(|| error("malformed version field"))?;
+                    let value = Some(value.to_string());
+                    match kind {
+                        'p' => candidate.product = value,
+                        'v' => candidate.version = value,
+                        'i' => candidate.info = value,
+                        'o' => candidate.os = value,
+                        _ => return Err(error("unknown version field")),
+                    }
+                    fields = rest.trim_start();
+                }
+                probe.matches.push(candidate);
+            }
+            _ => return Err(error("unknown directive")),
+        }
+    }
+
+    if let Some(probe) = probes.iter().find(|probe| probe.matches.is_empty()) {
+        return Err(format!("Probe {} has no signatures", probe.name));
+    }
+    Ok(probes)
+}
+
+// Splits "m|body|rest" into ("body", "rest"), the character after the prefix is the delimiter
+fn delimited(input: &str, prefix: char) -> Option<(&str, &str)> {
+    let rest = input.strip_prefix(prefix)?;
+    let delimiter = rest.chars().next()?;
+    let body = &rest[delimiter.len_utf8()..];
+    let end = body.find(delimiter)?;
+    Some((&body[..end], &body[end + delimiter.len_utf8()..]))
+}
+
+fn unescape(payload: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(payload.len());
+    let mut chars = payload.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut encoded = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut encoded).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => bytes.push(b'\r'),
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('0') => bytes.push(0),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                bytes.push(u8::from_str_radix(&hex, 16).unwrap_or(0));
+            }
+            Some(other) => bytes.push(other as u8),
+            None => bytes.push(b'\\'),
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, TcpListener};
+
+    fn probe<'a>(detector: &'a ServiceDetector, name: &str) -> &'a Probe {
+        detector
+            .probes
+            .iter()
+            .find(|probe| probe.name == name)
+            .unwrap()
+    }
+
+    fn service(name: &str, product: &str, version: &str, info: &str, os: &str) -> Service {
+        let optional = |value: &str| (!value.is_empty()).then(|| value.to_string());
+        Service {
+            name: name.to_string(),
+            product: optional(product),
+            version: optional(version),
+            info: optional(info),
+            os: optional(os),
+        }
+    }
+
+    #[test]
+    fn matches_known_banners() {
+        let detector = ServiceDetector::new().unwrap();
+        let cases: &[(&str, &[u8], Service)] = &[
+            (
+                "NULL",
+                b"SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13.5\r\n",
+                service("ssh", "OpenSSH", "9.6p1", "protocol 2.0, Ubuntu-3ubuntu13.5", ""),
+            ),
+            // The empty comment group leaves no dangling separator
+            (
+                "NULL",
+                b"SSH-2.0-OpenSSH_8.4\r\n",
+                service("ssh", "OpenSSH", "8.4", "protocol 2.0", ""),
+            ),
+            (
+                "NULL",
+                b"SSH-2.0-dropbear_2022.83\r\n",
+                service("ssh", "Dropbear sshd", "2022.83", "protocol 2.0", ""),
+            ),
+            (
+                "NULL",
+                b"SSH-2.0-Go\r\n",
+                service("ssh", "Go", "", "protocol 2.0", ""),
+            ),
+            (
+                "NULL",
+                b"220 (vsFTPd 3.0.5)\r\n",
+                service("ftp", "vsftpd", "3.0.5", "", ""),
+            ),
+            (
+                "NULL",
+                b"220-FileZilla Server 1.8.1\r\n220 Please visit https://filezilla-project.org/\r\n",
+                service("ftp", "FileZilla ftpd", "1.8.1", "", "Windows"),
+            ),
+            (
+                "NULL",
+                b"220 mail.example.org ESMTP Postfix (Ubuntu)\r\n",
+                service("smtp", "Postfix smtpd", "", "host mail.example.org", ""),
+            ),
+            (
+                "NULL",
+                b"220 mx.example.org ESMTP Exim 4.96 Mon, 19 Oct 2026 10:00:00 +0000\r\n",
+                service("smtp", "Exim smtpd", "4.96", "host mx.example.org", ""),
+            ),
+            (
+                "NULL",
+                b"\x4a\x00\x00\x00\x0a8.0.36\x00\x08\x00\x00\x00",
+                service("mysql", "MySQL", "8.0.36", "", ""),
+            ),
+            (
+                "NULL",
+                b"\x59\x00\x00\x00\x0a10.6.12-MariaDB-log\x00\x2a\x00\x00\x00",
+                service("mysql", "MariaDB", "10.6.12-MariaDB-log", "", ""),
+            ),
+            (
+                "NULL",
+                b"RFB 003.008\n",
+                service("vnc", "VNC", "", "protocol 003.008", ""),
+            ),
+            (
+                "GetRequest",
+                b"HTTP/1.1 200 OK\r\nServer: nginx/1.24.0\r\nContent-Length: 0\r\n\r\n",
+                service("http", "nginx", "1.24.0", "", ""),
+            ),
+            (
+                "GetRequest",
+                b"HTTP/1.1 403 Forbidden\r\nDate: Mon, 19 Oct 2026 10:00:00 GMT\r\nServer: Apache/2.4.58 (Ubuntu)\r\n\r\n",
+                service("http", "Apache httpd", "2.4.58", "Ubuntu", ""),
+            ),
+            (
+                "GetRequest",
+                b"HTTP/1.0 200 OK\r\nServer: SimpleHTTP/0.6 Python/3.12.3\r\n\r\n",
+                service("http", "SimpleHTTPServer", "0.6", "Python 3.12.3", ""),
+            ),
+            // Unknown servers are still named by their Server header
+            (
+                "GetRequest",
+                b"HTTP/1.1 200 OK\r\nServer: gunicorn\r\n\r\n",
+                service("http", "gunicorn", "", "", ""),
+            ),
+            (
+                "RedisServer",
+                b"$3722\r\n# Server\r\nredis_version:7.2.4\r\nredis_git_sha1:00000000\r\nredis_mode:standalone\r\n",
+                service("redis", "Redis key-value store", "7.2.4", "standalone", ""),
+            ),
+            (
+                "TLSSessionReq",
+                b"\x16\x03\x03\x00\x4a\x02\x00\x00\x46\x03\x03",
+                service("ssl", "TLS", "1.2", "", ""),
+            ),
+        ];
+        for (name, response, expected) in cases {
+            assert_eq!(
+                probe(&detector, name).identify(response, None),
+                Some((expected.clone(), false)),
+                "{}",
+                String::from_utf8_lossy(response)
+            );
+        }
+    }
+
+    #[test]
+    fn softmatches_only_name_the_service() {
+        let detector = ServiceDetector::new().unwrap();
+        let null = probe(&detector, "NULL");
+        assert_eq!(
+            null.identify(b"220 Welcome to the file server\r\n", None),
+            Some((service("ftp", "", "", "", ""), true))
+        );
+        assert_eq!(
+            null.identify(b"220 relay.example.org SMTP ready\r\n", None),
+            Some((service("smtp", "", "", "", ""), true))
+        );
+        assert_eq!(null.identify(b"Hello\r\n", None), None);
+
+        // Once named ftp, an SMTP error can't make it something else
+        let lines = probe(&detector, "GenericLines");
+        let error = b"500 5.5.2 Error: bad syntax\r\n";
+        assert_eq!(
+            lines.identify(error, None),
+            Some((service("smtp", "", "", "", ""), false))
+        );
+        assert_eq!(lines.identify(error, Some("ftp")), None);
+        assert_eq!(
+            lines.identify(error, Some("smtp")),
+            Some((service("smtp", "", "", "", ""), false))
+        );
+    }
+
+    #[test]
+    fn substitutes_capture_groups() {
+        let pattern = Regex::new(r"^(\w+)/([\d.]+)(?: \((\w+)\))?").unwrap();
+        let captures = pattern.captures(b"nginx/1.24.0 (Debian)").unwrap();
+        let template = |template: &str| Some(template.to_string());
+        assert_eq!(
+            substitute(&template("$1 $2 on $3"), &captures).as_deref(),
+            Some("nginx 1.24.0 on Debian")
+        );
+        // Groups past the pattern's are left out, a lone $ is kept
+        assert_eq!(
+            substitute(&template("$2$9 $"), &captures).as_deref(),
+            Some("1.24.0 $")
+        );
+        let captures = pattern.captures(b"nginx/1.24.0").unwrap();
+        assert_eq!(
+            substitute(&template("version $2, $3"), &captures).as_deref(),
+            Some("version 1.24.0")
+        );
+        assert_eq!(substitute(&template("$3"), &captures), None);
+        assert_eq!(substitute(&None, &captures), None);
+    }
+
+    #[test]
+    fn parses_probes() {
+        let probes = parse_probes(
+            "# comment\n\
+             Probe TCP Hello q|HELO\\r\\n\\x00|\n\
+             ports 25,587\n\
+             match smtp m|^250 (\\S+)|i p/Generic smtpd/ i/host $1/\n\
+             softmatch smtp m|^2|\n",
+        )
+        .unwrap();
+        assert_eq!(probes.len(), 1);
+        assert_eq!(probes[0].name, "Hello");
+        assert_eq!(probes[0].payload, b"HELO\r\n\x00");
+        assert_eq!(probes[0].ports, vec![25, 587]);
+        assert_eq!(
+            probes[0].identify(b"250 MX.EXAMPLE.ORG", None),
+            Some((
+                service("smtp", "Generic smtpd", "", "host MX.EXAMPLE.ORG", ""),
+                false
+            ))
+        );
+
+        let error = |source: &str| parse_probes(source).err().unwrap();
+        assert_eq!(
+            error("match ssh m|^SSH|"),
+            "service-probes.txt:1: match before any probe"
+        );
+        assert_eq!(
+            error("Probe UDP DNS q||\nmatch dns m|.|"),
+            "service-probes.txt:1: only TCP probes are supported"
+        );
+        assert_eq!(
+            error("Probe TCP NULL q||\nmatch ssh m|^SSH| x/extra/"),
+            "service-probes.txt:2: unknown version field"
+        );
+        assert_eq!(
+            error("Probe TCP NULL q||\nmatch ssh m|^SSH"),
+            "service-probes.txt:2: malformed regex"
+        );
+        assert_eq!(error("Probe TCP NULL q||"), "Probe NULL has no signatures");
+    }
+
+    #[test]
+    fn detects_a_listening_service() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"SSH-2.0-OpenSSH_9.6p1\r\n").unwrap();
+        });
+        let detector = ServiceDetector::new().unwrap();
+        let detected = detector.detect(IpAddr::V4(Ipv4Addr::LOCALHOST), port, &Timing::default());
+        server.join().unwrap();
+        assert_eq!(
+            detected,
+            Some(service("ssh", "OpenSSH", "9.6p1", "protocol 2.0", ""))
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use std::io;
+use std::mem::MaybeUninit;
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+// The raw sockets every probe engine shares. They are opened once, before the privileges
+// are dropped, and each scan works on clones: a raw socket sees every packet of its
+// protocol, the engines already keep only the replies meant for them.
+pub struct RawSockets {
+    tcp: Socket,
+    icmp: Socket,
+}
+
+impl RawSockets {
+    pub fn open() -> io::Result<Self> {
+        Ok(RawSockets {
+            tcp: Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::TCP))?,
+            icmp: Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?,
+        })
+    }
+
+    // Sends crafted TCP segments and receives every segment reaching the host
+    pub fn tcp(&self) -> io::Result<Socket> {
+        fresh_clone(&self.tcp)
+    }
+
+    pub fn icmp(&self) -> io::Result<Socket> {
+        fresh_clone(&self.icmp)
+    }
+}
+
+// Clones the socket after throwing away what queued up since the previous scan used it
+fn fresh_clone(socket: &Socket) -> io::Result<Socket> {
+    let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+    socket.set_nonblocking(true)?;
+    let drained = loop {
+        match socket.recv(&mut buffer) {
+            Ok(_) => continue,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+            Err(err) => break Err(err),
+        }
+    };
+    socket.set_nonblocking(false)?;
+    drained?;
+    socket.try_clone()
+}
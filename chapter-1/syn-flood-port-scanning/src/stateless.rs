@@ -0,0 +1,367 @@
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hasher;
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use packet_kit::{Ipv4Header, Layer, TcpBuilder, TcpHeader};
+use siphasher::sip::SipHasher24;
+use socket2::Socket;
+
+use crate::report::ScanRecord;
+use crate::sockets::RawSockets;
+use crate::stealth::source_address;
+use crate::timing::Timing;
+use crate::{PortState, ACK, RST, SYN};
+
+// Replies remembered to drop the SYN/ACKs hosts resend, the oldest are forgotten past
+// this count. A resend arriving later is merged with the first reply once the scan ends.
+const RECENT_REPLIES: usize = 64 * 1024;
+
+// Rounds of the Feistel network, 4 rounds make a pseudo-random permutation (Luby-Rackoff)
+const FEISTEL_ROUNDS: u64 = 4;
+
+// Bijection of [0, range) built from a balanced Feistel network over the smallest
+// power of four covering the range. Results falling outside the range are encrypted
+// again (cycle walking) until they land inside, which keeps the mapping one-to-one.
+pub struct Permutation {
+    range: u64,
+    half_bits: u32,
+    seed: u64,
+}
+
+impl Permutation {
+    pub fn new(range: u64, seed: u64) -> Self {
+        let bits = 64 - range.saturating_sub(1).leading_zeros();
+        Permutation {
+            range,
+            half_bits: bits.div_ceil(2).max(1),
+            seed,
+        }
+    }
+
+    // Maps an index of the walk to the index of the (target, port) pair to probe
+    pub fn shuffle(&self, index: u64) -> u64 {
+        let mut value = self.encrypt(index);
+        while value >= self.range {
+            value = self.encrypt(value);
+        }
+        value
+    }
+
+    fn encrypt(&self, value: u64) -> u64 {
+        let mask = (1u64 << self.half_bits) - 1;
+        let (mut left, mut right) = (value >> self.half_bits, value & mask);
+        for round in 0..FEISTEL_ROUNDS {
+            let mut hasher = SipHasher24::new_with_keys(self.seed, round);
+            hasher.write_u64(right);
+            let mixed = left ^ (hasher.finish() & mask);
+            left = right;
+            right = mixed;
+        }
+        (left << self.half_bits) | right
+    }
+}
+
+// Keyed hash of the connection 4-tuple carried in the SYN sequence number.
+// A reply acknowledging cookie + 1 can only come from a probe we sent, so replies are
+// validated without remembering anything about the probes.
+#[derive(Clone, Copy)]
+struct Cookie {
+    k0: u64,
+    k1: u64,
+}
+
+impl Cookie {
+    fn new() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Cookie {
+            k0: now.as_nanos() as u64,
+            k1: (std::process::id() as u64) << 32 ^ now.as_secs(),
+        }
+    }
+
+    fn sequence(&self, src: Ipv4Addr, dst: Ipv4Addr, src_port: u16, dst_port: u16) -> u32 {
+        let mut hasher = SipHasher24::new_with_keys(self.k0, self.k1);
+        hasher.write(&src.octets());
+        hasher.write(&dst.octets());
+        hasher.write_u16(src_port);
+        hasher.write_u16(dst_port);
+        hasher.finish() as u32
+    }
+
+    // Whether the acknowledgment number of a reply answers a probe we sent, `src` and
+    // `dst` are those of the probe
+    fn acknowledged(
+        &self,
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        src_port: u16,
+        dst_port: u16,
+        acknowledgment_number: u32,
+    ) -> bool {
+        acknowledgment_number == self.sequence(src, dst, src_port, dst_port).wrapping_add(1)
+    }
+}
+
+// The last RECENT_REPLIES (host, port) pairs replies came from
+#[derive(Default)]
+struct RecentReplies {
+    seen: HashSet<(Ipv4Addr, u16)>,
+    order: VecDeque<(Ipv4Addr, u16)>,
+}
+
+impl RecentReplies {
+    // Remembers the pair, false when it was already remembered
+    fn insert(&mut self, pair: (Ipv4Addr, u16)) -> bool {
+        if !self.seen.insert(pair) {
+            return false;
+        }
+        self.order.push_back(pair);
+        if self.order.len() > RECENT_REPLIES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+// Picks a seed for the scan order when none was given
+pub fn random_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+// Listens for SYN/ACK and RST replies whose acknowledgment number matches the cookie
+fn receive_replies(
+    sniffer: Socket,
+    src_port: u16,
+    cookie: Cookie,
+    records: mpsc::Sender<ScanRecord>,
+    done: Arc<AtomicBool>,
+) -> io::Result<()> {
+    sniffer.set_read_timeout(Some(Duration::from_millis(200)))?;
+    // Hosts resend their SYN/ACK until they give up, only the first one is reported
+    let mut seen = RecentReplies::default();
+
+    let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+    while !done.load(Ordering::Relaxed) {
+        let length = match sniffer.recv_from(&mut buffer) {
+            Ok((length, _)) => length,
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(err) => return Err(err),
+        };
+        let raw_buffer: &[u8] =
+            unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, length) };
+
+        let (ip_header, segment) = match Ipv4Header::parse(raw_buffer) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+        let tcp_header = match TcpHeader::parse(segment) {
+            Some((header, _)) => header,
+            None => continue,
+        };
+        if tcp_header.destination_port != src_port || tcp_header.flags & ACK == 0 {
+            continue;
+        }
+
+        // The reply travels the other way, our address is its destination
+        let host = ip_header.src;
+        if !cookie.acknowledged(
+            ip_header.dst,
+            host,
+            src_port,
+            tcp_header.source_port,
+            tcp_header.acknowledgment_number,
+        ) {
+            continue;
+        }
+
+        let (state, reason) = if tcp_header.flags & SYN != 0 {
+            (PortState::Open, "syn-ack")
+        } else if tcp_header.flags & RST != 0 {
+            (PortState::Closed, "reset")
+        } else {
+            continue;
+        };
+        if !seen.insert((host, tcp_header.source_port)) {
+            continue;
+        }
+        if state == PortState::Open {
+            println!(
+                "Discovered open port {}/tcp on {}",
+                tcp_header.source_port, host
+            );
+        }
+        let record = ScanRecord::new(
+            IpAddr::V4(host),
+            tcp_header.source_port,
+            "tcp",
+            state,
+            reason,
+        );
+        if records.send(record).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// SYN scans every (target, port) pair in an order drawn from the seed. The sender keeps no
+// per-probe state, memory stays bounded by the target list whatever the size of the scan.
+pub fn run(
+    targets: &[IpAddr],
+    ports: &[u16],
+    timing: &Timing,
+    seed: u64,
+    sockets: &RawSockets,
+) -> io::Result<Vec<ScanRecord>> {
+    let targets: Vec<Ipv4Addr> = targets
+        .iter()
+        .filter_map(|target| match target {
+            IpAddr::V4(target) => Some(*target),
+            IpAddr::V6(_) => {
+                eprintln!("Skipping {}: raw probes are only crafted for IPv4", target);
+                None
+            }
+        })
+        .collect();
+    let range = targets.len() as u64 * ports.len() as u64;
+    if range == 0 {
+        return Ok(Vec::new());
+    }
+
+    // One route lookup per target, not per probe
+    let sources = targets
+        .iter()
+        .map(|target| source_address(*target))
+        .collect::<io::Result<Vec<Ipv4Addr>>>()?;
+
+    // Keep clear of the ephemeral range the kernel hands out to connect()
+    let src_port = 20000 + (std::process::id() % 10000) as u16;
+    let cookie = Cookie::new();
+    let sender = sockets.tcp()?;
+    let done = Arc::new(AtomicBool::new(false));
+    let (records, received) = mpsc::channel();
+
+    let reply_thread = thread::spawn({
+        let sniffer = sender.try_clone()?;
+        let done = done.clone();
+        move || {
+            if let Err(err) = receive_replies(sniffer, src_port, cookie, records, done) {
+                eprintln!("Error capturing packets: {}", err);
+            }
+        }
+    });
+
+    println!(
+        "Scanning {} hosts, {} ports per host, seed {}",
+        targets.len(),
+        ports.len(),
+        seed
+    );
+    let permutation = Permutation::new(range, seed);
+    let started = Instant::now();
+    for index in 0..range {
+        // Consecutive probes hit different hosts, no single host sees a burst
+        let pair = permutation.shuffle(index);
+        let host = (pair % targets.len() as u64) as usize;
+        let (target, src) = (targets[host], sources[host]);
+        let port = ports[(pair / targets.len() as u64) as usize];
+
+        timing.limiter.acquire();
+        let sequence_number = cookie.sequence(src, target, src_port, port);
+        let probe = TcpBuilder::new(src_port, port)
+            .sequence_number(sequence_number)
+            .syn()
+            .build(IpAddr::V4(src), IpAddr::V4(target));
+        sender.send_to(&probe, &SocketAddr::new(IpAddr::V4(target), 0).into())?;
+    }
+    println!(
+        "Sent {} probes in {:.2}s, waiting for late replies",
+        range,
+        started.elapsed().as_secs_f64()
+    );
+    thread::sleep(timing.rtt_estimator().timeout());
+
+    done.store(true, Ordering::Relaxed);
+    reply_thread.join().unwrap();
+
+    let mut records: Vec<ScanRecord> = received.try_iter().collect();
+    records.sort_by_key(|record| (record.host, record.port));
+    records.dedup_by_key(|record| (record.host, record.port));
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permutation_is_a_bijection() {
+        for range in 1..=300 {
+            for seed in [0, 1, 0x0123_4567_89ab_cdef] {
+                let permutation = Permutation::new(range, seed);
+                let mut shuffled: Vec<u64> =
+                    (0..range).map(|index| permutation.shuffle(index)).collect();
+                shuffled.sort_unstable();
+                assert!(
+                    shuffled.into_iter().eq(0..range),
+                    "range {} seed {}",
+                    range,
+                    seed
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn permutation_depends_on_the_seed() {
+        let (a, b) = (Permutation::new(1000, 1), Permutation::new(1000, 2));
+        assert!((0..1000).any(|index| a.shuffle(index) != b.shuffle(index)));
+    }
+
+    #[test]
+    fn cookies_validate_replies_to_our_probes_only() {
+        let cookie = Cookie { k0: 1, k1: 2 };
+        let (src, dst) = (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2));
+        let sequence = cookie.sequence(src, dst, 20000, 80);
+        assert!(cookie.acknowledged(src, dst, 20000, 80, sequence.wrapping_add(1)));
+        assert!(!cookie.acknowledged(src, dst, 20000, 80, sequence));
+        assert!(!cookie.acknowledged(src, dst, 20000, 443, sequence.wrapping_add(1)));
+        assert!(!cookie.acknowledged(dst, src, 20000, 80, sequence.wrapping_add(1)));
+        let other = Cookie { k0: 1, k1: 3 };
+        assert!(!other.acknowledged(src, dst, 20000, 80, sequence.wrapping_add(1)));
+    }
+
+    #[test]
+    fn recent_replies_stay_bounded() {
+        let mut seen = RecentReplies::default();
+        let first = (Ipv4Addr::new(10, 0, 0, 1), 1);
+        assert!(seen.insert(first));
+        assert!(!seen.insert(first));
+        for port in 0..RECENT_REPLIES as u32 {
+            let address = Ipv4Addr::from(0x0b00_0000 + port);
+            seen.insert((address, 80));
+        }
+        assert_eq!(seen.seen.len(), RECENT_REPLIES);
+        assert_eq!(seen.order.len(), RECENT_REPLIES);
+        assert!(seen.insert(first));
+    }
+}
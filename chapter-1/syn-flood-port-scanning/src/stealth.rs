@@ -0,0 +1,546 @@
+use std::collections::HashMap;
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use packet_kit::{Ipv4Header, Layer, TcpBuilder, TcpHeader, PROTOCOL_TCP};
+use socket2::Socket;
+
+use crate::report::ScanRecord;
+use crate::sockets::RawSockets;
+use crate::timing::Timing;
+use crate::{
+    filtered_reason, parse_unreachable, PortResult, PortState, ACK, CWR, ECE, FIN, PSH, RST, SYN,
+    URG,
+};
+
+// Flag names accepted by --scanflags, in the order they appear in the header
+const FLAG_NAMES: &[(&str, u16)] = &[
+    ("CWR", CWR),
+    ("ECE", ECE),
+    ("URG", URG),
+    ("ACK", ACK),
+    ("PSH", PSH),
+    ("RST", RST),
+    ("SYN", SYN),
+    ("FIN", FIN),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanKind {
+    Syn,
+    Fin,
+    Null,
+    Xmas,
+    Ack,
+    Window,
+    Maimon,
+    Custom(u16),
+}
+
+impl ScanKind {
+    // Removes the scan type options from the command line and returns the selected scan
+    pub fn from_args(args: &mut Vec<String>) -> Result<Option<Self>, String> {
+        let mut kind = None;
+        let mut i = 0;
+        while i < args.len() {
+            let selected = match args[i].as_str() {
+                "-sS" => ScanKind::Syn,
+                "-sF" => ScanKind::Fin,
+                "-sN" => ScanKind::Null,
+                "-sX" => ScanKind::Xmas,
+                "-sA" => ScanKind::Ack,
+                "-sW" => ScanKind::Window,
+                "-sM" => ScanKind::Maimon,
+                "--scanflags" => {
+                    args.remove(i);
+                    if i >= args.len() {
+                        return Err(String::from("--scanflags requires a flag list"));
+                    }
+                    ScanKind::Custom(parse_flags(&args[i])?)
+                }
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            args.remove(i);
+            kind = Some(selected);
+        }
+        Ok(kind)
+    }
+
+    pub fn flags(&self) -> u16 {
+        match self {
+            ScanKind::Syn => SYN,
+            ScanKind::Fin => FIN,
+            ScanKind::Null => 0,
+            ScanKind::Xmas => FIN | PSH | URG,
+            ScanKind::Ack | ScanKind::Window => ACK,
+            ScanKind::Maimon => FIN | ACK,
+            ScanKind::Custom(flags) => *flags,
+        }
+    }
+
+    // Interprets the reply to a probe following RFC 793 section 3.9 ("SEGMENT ARRIVES")
+    fn interpret(&self, reply: Option<&TcpHeader>) -> (PortState, &'static str) {
+        let reply = match reply {
+            Some(reply) => reply,
+            // Only SYN, ACK and Window probes must be answered by every listening or closed port
+            None => match self {
+                ScanKind::Syn | ScanKind::Ack | ScanKind::Window => {
+                    return (PortState::Filtered, "no-response")
+                }
+                ScanKind::Custom(flags) if flags & (SYN | ACK) != 0 => {
+                    return (PortState::Filtered, "no-response")
+                }
+                _ => return (PortState::OpenFiltered, "no-response"),
+            },
+        };
+
+        if reply.flags & SYN != 0 && reply.flags & ACK != 0 {
+            return (PortState::Open, "syn-ack");
+        }
+        if reply.flags & RST == 0 {
+            return (PortState::Filtered, "unexpected-reply");
+        }
+        match self {
+            ScanKind::Ack => (PortState::Unfiltered, "reset"),
+            // Some stacks advertise a non zero window in the RST sent by open ports
+            ScanKind::Window if reply.window_size > 0 => (PortState::Open, "reset-window"),
+            _ => (PortState::Closed, "reset"),
+        }
+    }
+}
+
+impl std::fmt::Display for ScanKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ScanKind::Syn => write!(f, "SYN"),
+            ScanKind::Fin => write!(f, "FIN"),
+            ScanKind::Null => write!(f, "NULL"),
+            ScanKind::Xmas => write!(f, "Xmas"),
+            ScanKind::Ack => write!(f, "ACK"),
+            ScanKind::Window => write!(f, "Window"),
+            ScanKind::Maimon => write!(f, "Maimon"),
+            ScanKind::Custom(flags) => write!(f, "custom ({})", flag_names(*flags)),
+        }
+    }
+}
+
+// Parses a flag list such as "SYNFIN", "URG,PSH" or a numeric value such as "0x29"
+fn parse_flags(spec: &str) -> Result<u16, String> {
+    if let Some(hex) = spec.strip_prefix("0x") {
+        return u16::from_str_radix(hex, 16).map_err(|err| err.to_string());
+    }
+    if let Ok(flags) = spec.parse::<u16>() {
+        return Ok(flags);
+    }
+
+    let mut flags = 0;
+    let mut rest = spec.trim().to_uppercase();
+    while !rest.is_empty() {
+        rest = rest.trim_start_matches([',', '|', '+', ' ']).to_string();
+        let (name, flag) = FLAG_NAMES
+            .iter()
+            .find(|(name, _)| rest.starts_with(name))
+            .ok_or_else(|| format!("Unknown TCP flag in '{}'", spec))?;
+        flags |= flag;
+        rest = rest[name.len()..].to_string();
+    }
+    Ok(flags)
+}
+
+fn flag_names(flags: u16) -> String {
+    let names: Vec<&str> = FLAG_NAMES
+        .iter()
+        .filter(|(_, flag)| flags & flag != 0)
+        .map(|(name, _)| *name)
+        .collect();
+    if names.is_empty() {
+        String::from("none")
+    } else {
+        names.join("|")
+    }
+}
+
+// Crafts a bare TCP header carrying the given flags.
+// The kernel prepends the IP header since the raw socket isn't using IP_HDRINCL.
+fn craft_probe(
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    src_port: u16,
+    dst_port: u16,
+    sequence_number: u32,
+    flags: u16,
+) -> Vec<u8> {
+    TcpBuilder::new(src_port, dst_port)
+        .sequence_number(sequence_number)
+        .flags(flags)
+        .build(IpAddr::V4(src), IpAddr::V4(dst))
+}
+
+// Finds the local address the kernel uses to reach the target
+pub fn source_address(target: Ipv4Addr) -> io::Result<Ipv4Addr> {
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))?;
+    socket.connect(SocketAddr::new(IpAddr::V4(target), 9))?;
+    match socket.local_addr()?.ip() {
+        IpAddr::V4(addr) => Ok(addr),
+        IpAddr::V6(_) => Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "No IPv4 route to the target",
+        )),
+    }
+}
+
+// Returns the TCP header of a segment the target sent back to our source port
+fn parse_reply(packet: &[u8], target: Ipv4Addr, src_port: u16) -> Option<TcpHeader> {
+    let (ip_header, segment) = Ipv4Header::parse(packet)?;
+    if ip_header.src != target {
+        return None;
+    }
+    let (tcp_header, _) = TcpHeader::parse(segment)?;
+    (tcp_header.destination_port == src_port).then_some(tcp_header)
+}
+
+// Collects the TCP replies sent back to our source port
+fn sniff_replies(
+    sniffer: Socket,
+    target: Ipv4Addr,
+    src_port: u16,
+    replies: Arc<Mutex<HashMap<u16, (TcpHeader, Instant)>>>,
+    done: Arc<AtomicBool>,
+) -> io::Result<()> {
+    sniffer.set_read_timeout(Some(Duration::from_millis(200)))?;
+
+    let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+    while !done.load(Ordering::Relaxed) {
+        let length = match sniffer.recv_from(&mut buffer) {
+            Ok((length, _)) => length,
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(err) => return Err(err),
+        };
+        let raw_buffer: &[u8] =
+            unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, length) };
+
+        let tcp_header = match parse_reply(raw_buffer, target, src_port) {
+            Some(header) => header,
+            None => continue,
+        };
+        replies
+            .lock()
+            .unwrap()
+            .insert(tcp_header.source_port, (tcp_header, Instant::now()));
+    }
+    Ok(())
+}
+
+// Collects ICMP unreachable errors triggered by our probes
+fn sniff_icmp(
+    sniffer: Socket,
+    target: Ipv4Addr,
+    unreachable: Arc<Mutex<HashMap<u16, (&'static str, Instant)>>>,
+    done: Arc<AtomicBool>,
+) -> io::Result<()> {
+    sniffer.set_read_timeout(Some(Duration::from_millis(200)))?;
+
+    let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+    while !done.load(Ordering::Relaxed) {
+        let length = match sniffer.recv_from(&mut buffer) {
+            Ok((length, _)) => length,
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(err) => return Err(err),
+        };
+        let raw_buffer: &[u8] =
+            unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, length) };
+
+        if let Some((port, code)) = parse_unreachable(raw_buffer, target, PROTOCOL_TCP) {
+            if let Some(reason) = filtered_reason(code) {
+                unreachable
+                    .lock()
+                    .unwrap()
+                    .entry(port)
+                    .or_insert((reason, Instant::now()));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Sends a probe with the scan's flags to every port and interprets the replies.
+// The round trip time is measured from the last probe sent to the port.
+fn scan(
+    kind: ScanKind,
+    target: Ipv4Addr,
+    ports: &[u16],
+    timing: &Timing,
+    sockets: &RawSockets,
+) -> io::Result<HashMap<u16, PortResult>> {
+    let src = source_address(target)?;
+    // Keep clear of the ephemeral range the kernel hands out to connect()
+    let src_port = 20000 + (std::process::id() % 10000) as u16;
+
+    let sender = sockets.tcp()?;
+    let replies = Arc::new(Mutex::new(HashMap::new()));
+    let unreachable = Arc::new(Mutex::new(HashMap::new()));
+    let done = Arc::new(AtomicBool::new(false));
+
+    let reply_thread = thread::spawn({
+        let sniffer = sender.try_clone()?;
+        let replies = replies.clone();
+        let done = done.clone();
+        move || {
+            if let Err(err) = sniff_replies(sniffer, target, src_port, replies, done) {
+                eprintln!("Error capturing packets: {}", err);
+            }
+        }
+    });
+
+    let icmp_thread = thread::spawn({
+        let sniffer = sockets.icmp()?;
+        let unreachable = unreachable.clone();
+        let done = done.clone();
+        move || {
+            if let Err(err) = sniff_icmp(sniffer, target, unreachable, done) {
+                eprintln!("Error capturing ICMP packets: {}", err);
+            }
+        }
+    });
+
+    let mut sent = HashMap::new();
+    let mut rtt = timing.rtt_estimator();
+    for round in 0..=timing.max_retries {
+        let pending: Vec<u16> = {
+            let replies = replies.lock().unwrap();
+            let unreachable = unreachable.lock().unwrap();
+            ports
+                .iter()
+                .copied()
+                .filter(|port| !replies.contains_key(port) && !unreachable.contains_key(port))
+                .collect()
+        };
+        if pending.is_empty() {
+            break;
+        }
+
+        for port in pending {
+            timing.limiter.acquire();
+            println!("Trying {}:{} ({} scan)", target, port, kind);
+            let probe = craft_probe(src, target, src_port, port, round as u32, kind.flags());
+            sent.insert(port, Instant::now());
+            sender.send_to(&probe, &SocketAddr::new(IpAddr::V4(target), 0).into())?;
+        }
+        // Wait for late replies before probing the silent ports again
+        thread::sleep(rtt.timeout());
+
+        // Karn's algorithm: only replies to probes sent once give a usable sample
+        if round == 0 {
+            for (port, (_, received)) in replies.lock().unwrap().iter() {
+                if let Some(sent) = sent.get(port) {
+                    rtt.update(received.saturating_duration_since(*sent));
+                }
+            }
+        } else {
+            rtt.back_off();
+        }
+    }
+
+    done.store(true, Ordering::Relaxed);
+    reply_thread.join().unwrap();
+    icmp_thread.join().unwrap();
+
+    let replies = replies.lock().unwrap();
+    let unreachable = unreachable.lock().unwrap();
+    let mut results = HashMap::new();
+    for port in ports {
+        let rtt = |received: &Instant| {
+            sent.get(port)
+                .map(|sent: &Instant| received.saturating_duration_since(*sent))
+        };
+        let result = match (replies.get(port), unreachable.get(port)) {
+            (Some((reply, received)), _) => {
+                let (state, reason) = kind.interpret(Some(reply));
+                (state, reason, rtt(received))
+            }
+            (None, Some((reason, received))) => (PortState::Filtered, *reason, rtt(received)),
+            (None, None) => {
+                let (state, reason) = kind.interpret(None);
+                (state, reason, None)
+            }
+        };
+        results.insert(*port, result);
+    }
+    Ok(results)
+}
+
+// Scans the ports and returns a record per port
+pub fn run(
+    kind: ScanKind,
+    target: Ipv4Addr,
+    ports: &[u16],
+    timing: &Timing,
+    sockets: &RawSockets,
+) -> io::Result<Vec<ScanRecord>> {
+    let results = scan(kind, target, ports, timing, sockets)?;
+
+    let mut records: Vec<ScanRecord> = results
+        .into_iter()
+        .map(|(port, (state, reason, rtt))| ScanRecord {
+            rtt,
+            ..ScanRecord::new(IpAddr::V4(target), port, "tcp", state, reason)
+        })
+        .collect();
+    records.sort_by_key(|record| record.port);
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use packet_kit::Ipv4Builder;
+
+    const TARGET: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 10);
+    const US: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+    // What the target sends back from port 80 to our source port
+    fn reply(flags: u16, window_size: u16) -> Vec<u8> {
+        Ipv4Builder::new()
+            .src(TARGET)
+            .dst(US)
+            .payload(
+                TcpBuilder::new(80, 20000)
+                    .flags(flags)
+                    .window_size(window_size),
+            )
+            .build()
+    }
+
+    #[test]
+    fn parses_scan_flags() {
+        let cases: &[(&str, u16)] = &[
+            ("SYN", SYN),
+            ("SYNFIN", SYN | FIN),
+            ("URG,PSH", URG | PSH),
+            ("urg|psh+fin", URG | PSH | FIN),
+            ("ECEACK", ECE | ACK),
+            ("CWR ECE URG ACK PSH RST SYN FIN", 0xff),
+            ("0x29", URG | PSH | FIN),
+            ("18", SYN | ACK),
+        ];
+        for (spec, flags) in cases {
+            assert_eq!(parse_flags(spec), Ok(*flags), "{}", spec);
+        }
+        assert_eq!(
+            parse_flags("SYNX"),
+            Err(String::from("Unknown TCP flag in 'SYNX'"))
+        );
+        assert!(parse_flags("0xfg").is_err());
+
+        assert_eq!(flag_names(URG | PSH | FIN), "URG|PSH|FIN");
+        assert_eq!(flag_names(0), "none");
+    }
+
+    #[test]
+    fn selects_the_scan_kind() {
+        let mut args: Vec<String> = ["-sX", "192.0.2.10", "--scanflags", "SYNFIN", "22"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        // The last scan type given wins and only the targets and ports remain
+        assert_eq!(
+            ScanKind::from_args(&mut args),
+            Ok(Some(ScanKind::Custom(SYN | FIN)))
+        );
+        assert_eq!(args, ["192.0.2.10", "22"]);
+        assert_eq!(ScanKind::from_args(&mut args), Ok(None));
+
+        let mut args = vec![String::from("--scanflags")];
+        assert!(ScanKind::from_args(&mut args).is_err());
+        assert_eq!(ScanKind::Custom(SYN | FIN).to_string(), "custom (SYN|FIN)");
+    }
+
+    #[test]
+    fn keeps_the_replies_to_our_port() {
+        let reset = reply(RST | ACK, 0);
+        let header = parse_reply(&reset, TARGET, 20000).unwrap();
+        assert_eq!((header.source_port, header.flags), (80, (RST | ACK)));
+        assert!(parse_reply(&reset, TARGET, 20001).is_none());
+        assert!(parse_reply(&reset, US, 20000).is_none());
+        assert!(parse_reply(&reset[..30], TARGET, 20000).is_none());
+
+        // IP options push the TCP header further
+        let with_options = Ipv4Builder::new()
+            .src(TARGET)
+            .dst(US)
+            .options(&[1, 1, 1, 1, 1, 1, 1, 0])
+            .payload(TcpBuilder::new(80, 20000).flags(SYN | ACK))
+            .build();
+        let header = parse_reply(&with_options, TARGET, 20000).unwrap();
+        assert_eq!((header.source_port, header.flags), (80, SYN | ACK));
+    }
+
+    #[test]
+    fn interprets_replies_per_rfc_793() {
+        use PortState::*;
+        let kinds = [
+            ScanKind::Syn,
+            ScanKind::Fin,
+            ScanKind::Null,
+            ScanKind::Xmas,
+            ScanKind::Ack,
+            ScanKind::Window,
+            ScanKind::Maimon,
+            ScanKind::Custom(SYN | FIN),
+            ScanKind::Custom(PSH),
+        ];
+        // The expected state for each kind above: no reply, a SYN/ACK, a reset with a
+        // zero window, a reset advertising a window and a lone ACK
+        let expected = [
+            [Filtered, Open, Closed, Closed, Filtered],
+            [OpenFiltered, Open, Closed, Closed, Filtered],
+            [OpenFiltered, Open, Closed, Closed, Filtered],
+            [OpenFiltered, Open, Closed, Closed, Filtered],
+            [Filtered, Open, Unfiltered, Unfiltered, Filtered],
+            [Filtered, Open, Closed, Open, Filtered],
+            [OpenFiltered, Open, Closed, Closed, Filtered],
+            [Filtered, Open, Closed, Closed, Filtered],
+            [OpenFiltered, Open, Closed, Closed, Filtered],
+        ];
+        let replies = [
+            None,
+            Some(reply(SYN | ACK, 64240)),
+            Some(reply(RST, 0)),
+            Some(reply(RST, 1024)),
+            Some(reply(ACK, 64240)),
+        ];
+        for (kind, expected) in kinds.iter().zip(expected) {
+            for (reply, state) in replies.iter().zip(expected) {
+                let reply = reply
+                    .as_ref()
+                    .map(|reply| parse_reply(reply, TARGET, 20000).unwrap());
+                assert_eq!(kind.interpret(reply.as_ref()).0, state, "{} scan", kind);
+            }
+        }
+
+        assert_eq!(ScanKind::Fin.interpret(None).1, "no-response");
+        let reset = parse_reply(&reply(RST, 1024), TARGET, 20000);
+        assert_eq!(ScanKind::Window.interpret(reset.as_ref()).1, "reset-window");
+        assert_eq!(ScanKind::Ack.interpret(reset.as_ref()).1, "reset");
+        let ack = parse_reply(&reply(ACK, 0), TARGET, 20000);
+        assert_eq!(ScanKind::Syn.interpret(ack.as_ref()).1, "unexpected-reply");
+    }
+}
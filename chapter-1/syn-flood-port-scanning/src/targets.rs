@@ -0,0 +1,446 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+
+// Bundled service table used for named ports and --top-ports
+const SERVICES: &str = include_str!("services.txt");
+
+// Refuse to expand specs larger than a /12, the address list is kept in memory
+const MAX_TARGETS: u128 = 1 << 20;
+
+// Expands a list of target specs into addresses, skipping the excluded ones.
+// A spec can be an address, a CIDR block, an octet range or a hostname.
+pub fn parse_targets(specs: &[String], excludes: &[String]) -> Result<Vec<IpAddr>, String> {
+    let mut excluded = Vec::new();
+    for spec in excludes {
+        excluded.extend(Exclusion::parse(spec)?);
+    }
+
+    let mut seen = HashSet::new();
+    let mut targets = Vec::new();
+    for spec in specs {
+        for addr in expand(spec)? {
+            if !excluded.iter().any(|exclusion| exclusion.contains(addr)) && seen.insert(addr) {
+                targets.push(addr);
+            }
+        }
+    }
+    Ok(targets)
+}
+
+// Excluded addresses are matched against the spec rather than expanded, so
+// excluding a block as large as 10.0.0.0/8 costs nothing
+enum Exclusion {
+    // Network address and prefix length, single addresses take the full length
+    Block(IpAddr, u32),
+    // Allowed values of each IPv4 octet, as inclusive ranges
+    Octets(Vec<Vec<(u8, u8)>>),
+}
+
+impl Exclusion {
+    // Hostnames give one exclusion per address family
+    fn parse(spec: &str) -> Result<Vec<Self>, String> {
+        let spec = spec.trim();
+        if let Some((addr, prefix)) = spec.split_once('/') {
+            let (network, prefix) = parse_cidr(spec, addr, prefix)?;
+            return Ok(vec![Exclusion::Block(network, prefix)]);
+        }
+        let addrs = if let Ok(addr) = spec.parse::<IpAddr>() {
+            vec![addr]
+        } else if is_octet_range(spec) {
+            return Ok(vec![Exclusion::Octets(parse_octets(spec)?)]);
+        } else {
+            resolve(spec)?
+        };
+        Ok(addrs
+            .into_iter()
+            .map(|addr| Exclusion::Block(addr, if addr.is_ipv4() { 32 } else { 128 }))
+            .collect())
+    }
+
+    fn contains(&self, addr: IpAddr) -> bool {
+        match (self, addr) {
+            (Exclusion::Block(IpAddr::V4(network), prefix), IpAddr::V4(addr)) => {
+                u32::from(addr) & v4_mask(*prefix) == u32::from(*network)
+            }
+            (Exclusion::Block(IpAddr::V6(network), prefix), IpAddr::V6(addr)) => {
+                u128::from(addr) & v6_mask(*prefix) == u128::from(*network)
+            }
+            (Exclusion::Octets(octets), IpAddr::V4(addr)) => {
+                octets.iter().zip(addr.octets()).all(|(ranges, octet)| {
+                    ranges
+                        .iter()
+                        .any(|(start, end)| (*start..=*end).contains(&octet))
+                })
+            }
+            _ => false,
+        }
+    }
+}
+
+// Reads target specs from a file, one or more per line, ignoring # comments
+pub fn read_spec_file(path: &str) -> io::Result<Vec<String>> {
+    let contents = fs::read_to_string(path)?;
+    Ok(contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(|line| line.split_whitespace())
+        .map(String::from)
+        .collect())
+}
+
+fn expand(spec: &str) -> Result<Vec<IpAddr>, String> {
+    let spec = spec.trim();
+    if let Some((addr, prefix)) = spec.split_once('/') {
+        return expand_cidr(spec, addr, prefix);
+    }
+    if let Ok(addr) = spec.parse::<IpAddr>() {
+        return Ok(vec![addr]);
+    }
+    if is_octet_range(spec) {
+        return expand_octets(spec);
+    }
+    resolve(spec)
+}
+
+fn expand_cidr(spec: &str, addr: &str, prefix: &str) -> Result<Vec<IpAddr>, String> {
+    let (network, prefix) = parse_cidr(spec, addr, prefix)?;
+    let bits = if network.is_ipv4() { 32 } else { 128 };
+    if bits - prefix > MAX_TARGETS.ilog2() {
+        return Err(format!("'{}' holds too many addresses", spec));
+    }
+    let size = 1u32 << (bits - prefix);
+    Ok(match network {
+        IpAddr::V4(network) => (0..size)
+            .map(|i| IpAddr::V4(Ipv4Addr::from(u32::from(network) + i)))
+            .collect(),
+        IpAddr::V6(network) => (0..size)
+            .map(|i| IpAddr::V6(Ipv6Addr::from(u128::from(network) + i as u128)))
+            .collect(),
+    })
+}
+
+// Reads a CIDR block, returns its network address and prefix length
+fn parse_cidr(spec: &str, addr: &str, prefix: &str) -> Result<(IpAddr, u32), String> {
+    let prefix: u32 = prefix
+        .parse()
+        .map_err(|_| format!("Invalid prefix length in '{}'", spec))?;
+    // Hostnames are allowed on the left side, e.g. example.com/24
+    let addr = match addr.parse::<IpAddr>() {
+        Ok(addr) => addr,
+        Err(_) => *resolve(addr)?.first().unwrap(),
+    };
+
+    match addr {
+        IpAddr::V4(addr) if prefix <= 32 => Ok((
+            IpAddr::V4(Ipv4Addr::from(u32::from(addr) & v4_mask(prefix))),
+            prefix,
+        )),
+        IpAddr::V6(addr) if prefix <= 128 => Ok((
+            IpAddr::V6(Ipv6Addr::from(u128::from(addr) & v6_mask(prefix))),
+            prefix,
+        )),
+        _ => Err(format!("Invalid prefix length in '{}'", spec)),
+    }
+}
+
+fn v4_mask(prefix: u32) -> u32 {
+    u32::MAX.checked_shl(32 - prefix).unwrap_or(0)
+}
+
+fn v6_mask(prefix: u32) -> u128 {
+    u128::MAX.checked_shl(128 - prefix).unwrap_or(0)
+}
+
+// Octet ranges look like 10.0.0-3.1-254 or 192.168.1.1,5,10
+fn is_octet_range(spec: &str) -> bool {
+    spec.split('.').count() == 4
+        && spec
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '.' || c == '-' || c == ',' || c == '*')
+}
+
+fn expand_octets(spec: &str) -> Result<Vec<IpAddr>, String> {
+    let octets: Vec<Vec<u8>> = parse_octets(spec)?
+        .iter()
+        .map(|ranges| {
+            ranges
+                .iter()
+                .flat_map(|(start, end)| *start..=*end)
+                .collect()
+        })
+        .collect();
+
+    let size: u128 = octets.iter().map(|values| values.len() as u128).product();
+    if size > MAX_TARGETS {
+        return Err(format!("'{}' holds too many addresses", spec));
+    }
+
+    let mut targets = Vec::with_capacity(size as usize);
+    for a in &octets[0] {
+        for b in &octets[1] {
+            for c in &octets[2] {
+                for d in &octets[3] {
+                    targets.push(IpAddr::V4(Ipv4Addr::new(*a, *b, *c, *d)));
+                }
+            }
+        }
+    }
+    Ok(targets)
+}
+
+// Reads the comma separated values and ranges of each octet
+fn parse_octets(spec: &str) -> Result<Vec<Vec<(u8, u8)>>, String> {
+    let mut octets = Vec::with_capacity(4);
+    for part in spec.split('.') {
+        let mut ranges = Vec::new();
+        for item in part.split(',') {
+            let (start, end) = match item {
+                "*" => (0, 255),
+                _ => match item.split_once('-') {
+                    Some((start, end)) => (parse_octet(spec, start)?, parse_octet(spec, end)?),
+                    None => {
+                        let value = parse_octet(spec, item)?;
+                        (value, value)
+                    }
+                },
+            };
+            if start > end {
+                return Err(format!("Invalid octet range in '{}'", spec));
+            }
+            ranges.push((start, end));
+        }
+        octets.push(ranges);
+    }
+    Ok(octets)
+}
+
+fn parse_octet(spec: &str, value: &str) -> Result<u8, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid octet '{}' in '{}'", value, spec))
+}
+
+// Resolves a hostname through the system resolver
+fn resolve(host: &str) -> Result<Vec<IpAddr>, String> {
+    let addrs: Vec<IpAddr> = (host, 0)
+        .to_socket_addrs()
+        .map_err(|err| format!("Failed to resolve '{}': {}", host, err))?
+        .map(|addr| addr.ip())
+        .collect();
+    // Scan the first address of each family, the rest usually point to the same host
+    let mut first = Vec::new();
+    if let Some(addr) = addrs.iter().find(|addr| addr.is_ipv4()) {
+        first.push(*addr);
+    }
+    if let Some(addr) = addrs.iter().find(|addr| addr.is_ipv6()) {
+        first.push(*addr);
+    }
+    if first.is_empty() {
+        return Err(format!("Failed to resolve '{}'", host));
+    }
+    Ok(first)
+}
+
+// Returns the (name, port, frequency) entries of the service table for a protocol
+fn services(protocol: &str) -> impl Iterator<Item = (&'static str, u16, f64)> + '_ {
+    SERVICES
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+        .filter_map(move |line| {
+            let mut fields = line.split('\t');
+            let name = fields.next()?;
+            let (port, proto) = fields.next()?.split_once('/')?;
+            let frequency = fields.next()?.parse().ok()?;
+            if proto != protocol {
+                return None;
+            }
+            Some((name, port.parse().ok()?, frequency))
+        })
+}
+
+// Returns the N most frequently open ports of a protocol
+pub fn top_ports(count: usize, protocol: &str) -> Vec<u16> {
+    let mut entries: Vec<(&str, u16, f64)> = services(protocol).collect();
+    entries.sort_by(|a, b| b.2.total_cmp(&a.2));
+    entries
+        .into_iter()
+        .take(count)
+        .map(|(_, port, _)| port)
+        .collect()
+}
+
+// Returns the service name registered for a port, if any
+pub fn service_name(port: u16, protocol: &str) -> Option<&'static str> {
+    services(protocol)
+        .find(|(_, p, _)| *p == port)
+        .map(|(name, _, _)| name)
+}
+
+// Parses a port list such as "22,80,8000-8100,https" into sorted unique ports
+pub fn parse_ports(spec: &str, protocol: &str) -> Result<Vec<u16>, String> {
+    let mut ports = Vec::new();
+    for item in spec
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        // Service names such as http-proxy contain dashes too
+        let numeric = item.chars().all(|c| c.is_ascii_digit() || c == '-');
+        if let (true, Some((start, end))) = (numeric, item.split_once('-')) {
+            let start: u16 = if start.is_empty() {
+                1
+            } else {
+                parse_port(start)?
+            };
+            let end: u16 = if end.is_empty() {
+                65535
+            } else {
+                parse_port(end)?
+            };
+            if start > end {
+                return Err(format!("Invalid port range '{}'", item));
+            }
+            ports.extend(start..=end);
+        } else if let Ok(port) = item.parse::<u16>() {
+            ports.push(port);
+        } else {
+            let port = services(protocol)
+                .find(|(name, _, _)| name.eq_ignore_ascii_case(item))
+                .map(|(_, port, _)| port)
+                .ok_or_else(|| format!("Unknown service '{}'", item))?;
+            ports.push(port);
+        }
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    Ok(ports)
+}
+
+fn parse_port(port: &str) -> Result<u16, String> {
+    port.parse().map_err(|_| format!("Invalid port '{}'", port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn specs(specs: &[&str]) -> Vec<String> {
+        specs.iter().map(|spec| spec.to_string()).collect()
+    }
+
+    fn addrs(addrs: &[&str]) -> Vec<IpAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn expands_target_specs() {
+        assert_eq!(
+            parse_targets(&specs(&["192.168.1.5/30"]), &[]).unwrap(),
+            addrs(&["192.168.1.4", "192.168.1.5", "192.168.1.6", "192.168.1.7"])
+        );
+        assert_eq!(
+            parse_targets(&specs(&["10.0.1-2.1,9"]), &[]).unwrap(),
+            addrs(&["10.0.1.1", "10.0.1.9", "10.0.2.1", "10.0.2.9"])
+        );
+        assert_eq!(
+            parse_targets(&specs(&["2001:db8::7/127", "2001:db8::6"]), &[]).unwrap(),
+            addrs(&["2001:db8::6", "2001:db8::7"])
+        );
+        assert_eq!(
+            parse_targets(&specs(&["10.0.0.0/20"]), &[]).unwrap().len(),
+            4096
+        );
+    }
+
+    #[test]
+    fn rejects_bad_target_specs() {
+        let error = |spec: &str| parse_targets(&specs(&[spec]), &[]).unwrap_err();
+        assert_eq!(
+            error("10.0.0.0/11"),
+            "'10.0.0.0/11' holds too many addresses"
+        );
+        assert_eq!(
+            error("2001:db8::/64"),
+            "'2001:db8::/64' holds too many addresses"
+        );
+        assert_eq!(error("10.*.*.*"), "'10.*.*.*' holds too many addresses");
+        assert_eq!(
+            error("10.0.0.0/33"),
+            "Invalid prefix length in '10.0.0.0/33'"
+        );
+        assert_eq!(error("::/129"), "Invalid prefix length in '::/129'");
+        assert_eq!(error("10.0.0.0/x"), "Invalid prefix length in '10.0.0.0/x'");
+        assert_eq!(error("10.0.0.5-1"), "Invalid octet range in '10.0.0.5-1'");
+        assert_eq!(error("10.0.0.300"), "Invalid octet '300' in '10.0.0.300'");
+    }
+
+    #[test]
+    fn excludes_large_blocks_without_expanding_them() {
+        let targets = specs(&["10.255.255.254/31", "11.0.0.1"]);
+        assert_eq!(
+            parse_targets(&targets, &specs(&["10.0.0.0/8"])).unwrap(),
+            addrs(&["11.0.0.1"])
+        );
+        // Host bits of the excluded block are ignored
+        assert_eq!(
+            parse_targets(&targets, &specs(&["10.1.2.3/8"])).unwrap(),
+            addrs(&["11.0.0.1"])
+        );
+        assert!(parse_targets(&targets, &specs(&["0.0.0.0/0"]))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            parse_targets(&specs(&["2001:db8::/126"]), &specs(&["2001:db8::/64"])).unwrap(),
+            Vec::<IpAddr>::new()
+        );
+        assert!(parse_targets(&targets, &specs(&["10.0.0.0/40"])).is_err());
+    }
+
+    #[test]
+    fn excludes_addresses_and_octet_ranges() {
+        let targets = specs(&["192.168.1.0/29"]);
+        assert_eq!(
+            parse_targets(&targets, &specs(&["192.168.1.0", "192.168.1.2-6"])).unwrap(),
+            addrs(&["192.168.1.1", "192.168.1.7"])
+        );
+        // Octet ranges are matched too, however many addresses they cover
+        assert_eq!(
+            parse_targets(&targets, &specs(&["*.*.*.0,7"])).unwrap(),
+            addrs(&[
+                "192.168.1.1",
+                "192.168.1.2",
+                "192.168.1.3",
+                "192.168.1.4",
+                "192.168.1.5",
+                "192.168.1.6"
+            ])
+        );
+        // IPv4 exclusions leave IPv6 targets alone
+        assert_eq!(
+            parse_targets(&specs(&["::1"]), &specs(&["0.0.0.0/0", "*.*.*.*"])).unwrap(),
+            addrs(&["::1"])
+        );
+    }
+
+    #[test]
+    fn matches_exclusions() {
+        let block = Exclusion::parse("172.16.0.0/12").unwrap();
+        assert!(block[0].contains("172.31.255.255".parse().unwrap()));
+        assert!(!block[0].contains("172.32.0.0".parse().unwrap()));
+        assert!(!block[0].contains("::ffff:172.16.0.1".parse().unwrap()));
+
+        let address = Exclusion::parse(" fe80::1 ").unwrap();
+        assert!(address[0].contains("fe80::1".parse().unwrap()));
+        assert!(!address[0].contains("fe80::2".parse().unwrap()));
+    }
+
+    #[test]
+    fn skips_duplicate_targets() {
+        assert_eq!(
+            parse_targets(&specs(&["10.0.0.1", "10.0.0.0/31", "10.0.0.1"]), &[]).unwrap(),
+            addrs(&["10.0.0.1", "10.0.0.0"])
+        );
+    }
+}
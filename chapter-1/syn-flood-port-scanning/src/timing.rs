@@ -0,0 +1,388 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Settings of a timing template, durations are in milliseconds
+struct Template {
+    name: &'static str,
+    // Probes per second
+    rate: f64,
+    parallelism: usize,
+    initial_rtt_timeout: u64,
+    min_rtt_timeout: u64,
+    max_rtt_timeout: u64,
+    max_retries: usize,
+    // Longest random delay added after each probe
+    jitter: u64,
+}
+
+// Timing templates, from the slowest to the fastest
+const TEMPLATES: [Template; 6] = [
+    Template {
+        name: "paranoid",
+        rate: 1.0 / 300.0,
+        parallelism: 1,
+        initial_rtt_timeout: 5000,
+        min_rtt_timeout: 100,
+        max_rtt_timeout: 10000,
+        max_retries: 4,
+        jitter: 60000,
+    },
+    Template {
+        name: "sneaky",
+        rate: 1.0 / 15.0,
+        parallelism: 1,
+        initial_rtt_timeout: 5000,
+        min_rtt_timeout: 100,
+        max_rtt_timeout: 10000,
+        max_retries: 4,
+        jitter: 5000,
+    },
+    Template {
+        name: "polite",
+        rate: 2.5,
+        parallelism: 1,
+        initial_rtt_timeout: 1000,
+        min_rtt_timeout: 100,
+        max_rtt_timeout: 10000,
+        max_retries: 3,
+        jitter: 100,
+    },
+    Template {
+        name: "normal",
+        rate: 100.0,
+        parallelism: 16,
+        initial_rtt_timeout: 1000,
+        min_rtt_timeout: 100,
+        max_rtt_timeout: 10000,
+        max_retries: 2,
+        jitter: 0,
+    },
+    Template {
+        name: "aggressive",
+        rate: 1000.0,
+        parallelism: 64,
+        initial_rtt_timeout: 500,
+        min_rtt_timeout: 100,
+        max_rtt_timeout: 1250,
+        max_retries: 2,
+        jitter: 0,
+    },
+    Template {
+        name: "insane",
+        rate: 5000.0,
+        parallelism: 256,
+        initial_rtt_timeout: 250,
+        min_rtt_timeout: 50,
+        max_rtt_timeout: 300,
+        max_retries: 1,
+        jitter: 0,
+    },
+];
+
+// Default template when -T isn't given
+const DEFAULT_TEMPLATE: usize = 3;
+
+// Timing profile shared by every component sending probes
+pub struct Timing {
+    pub name: &'static str,
+    // Workers used by the connect scan
+    pub parallelism: usize,
+    pub initial_rtt_timeout: Duration,
+    pub min_rtt_timeout: Duration,
+    pub max_rtt_timeout: Duration,
+    // Number of times an unanswered probe is sent again
+    pub max_retries: usize,
+    pub limiter: RateLimiter,
+}
+
+impl Timing {
+    // Builds the profile of a template, given as -T0 to -T5 or by name
+    pub fn from_template(template: &str) -> Result<Self, String> {
+        let index = match template.parse::<usize>() {
+            Ok(index) if index < TEMPLATES.len() => index,
+            _ => TEMPLATES
+                .iter()
+                .position(|t| t.name.eq_ignore_ascii_case(template))
+                .ok_or_else(|| format!("Unknown timing template '{}'", template))?,
+        };
+        let template = &TEMPLATES[index];
+        Ok(Timing {
+            name: template.name,
+            parallelism: template.parallelism,
+            initial_rtt_timeout: Duration::from_millis(template.initial_rtt_timeout),
+            min_rtt_timeout: Duration::from_millis(template.min_rtt_timeout),
+            max_rtt_timeout: Duration::from_millis(template.max_rtt_timeout),
+            max_retries: template.max_retries,
+            limiter: RateLimiter::new(template.rate, Duration::from_millis(template.jitter)),
+        })
+    }
+
+    // Overrides the probe rate of the template
+    pub fn set_max_rate(&mut self, rate: f64) {
+        self.limiter = RateLimiter::new(rate, self.limiter.jitter);
+    }
+
+    pub fn rtt_estimator(&self) -> RttEstimator {
+        RttEstimator::new(
+            self.initial_rtt_timeout,
+            self.min_rtt_timeout,
+            self.max_rtt_timeout,
+        )
+    }
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Timing::from_template(TEMPLATES[DEFAULT_TEMPLATE].name).unwrap()
+    }
+}
+
+// Token bucket handing out the right to send one probe.
+// The bucket holds at most a hundredth of a second worth of tokens, so a burst
+// never exceeds the configured rate by much.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    jitter: Duration,
+    // Tokens left and the time they were counted
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, jitter: Duration) -> Self {
+        let burst = (rate / 100.0).max(1.0);
+        RateLimiter {
+            rate,
+            burst,
+            jitter,
+            bucket: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    // Blocks until a probe may be sent, then waits a random share of the jitter
+    pub fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                let refill = now.duration_since(bucket.1).as_secs_f64() * self.rate;
+                *bucket = ((bucket.0 + refill).min(self.burst), now);
+                if bucket.0 >= 1.0 {
+                    bucket.0 -= 1.0;
+                    break;
+                }
+                Duration::from_secs_f64((1.0 - bucket.0) / self.rate)
+            };
+            thread::sleep(wait);
+        }
+        if !self.jitter.is_zero() {
+            thread::sleep(self.jitter.mul_f64(random_fraction()));
+        }
+    }
+}
+
+// Retransmission timeout estimation following RFC 6298
+pub struct RttEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rto: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl RttEstimator {
+    pub fn new(initial: Duration, min: Duration, max: Duration) -> Self {
+        RttEstimator {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: initial.clamp(min, max),
+            min,
+            max,
+        }
+    }
+
+    // Feeds a round trip time measured on a probe that wasn't retransmitted (Karn's algorithm)
+    pub fn update(&mut self, sample: Duration) {
+        match self.srtt {
+            // RFC 6298 section 2.2: SRTT <- R, RTTVAR <- R/2
+            None => {
+                self.srtt = Some(sample);
+                self.rttvar = sample / 2;
+            }
+            // Section 2.3: RTTVAR <- 3/4 RTTVAR + 1/4 |SRTT - R'|, SRTT <- 7/8 SRTT + 1/8 R'
+            Some(srtt) => {
+                let delta = srtt.abs_diff(sample);
+                self.rttvar = self.rttvar * 3 / 4 + delta / 4;
+                self.srtt = Some(srtt * 7 / 8 + sample / 8);
+            }
+        }
+        // RTO <- SRTT + max(G, 4 * RTTVAR), the clock granularity G is negligible here
+        self.rto = (self.srtt.unwrap() + self.rttvar * 4).clamp(self.min, self.max);
+    }
+
+    // Section 5.5: the timer backs off by doubling after a retransmission timeout
+    pub fn back_off(&mut self) {
+        self.rto = (self.rto * 2).min(self.max);
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.rto
+    }
+}
+
+// Returns a pseudo-random number in [0, 1), good enough to spread probes in time
+fn random_fraction() -> f64 {
+    static STATE: AtomicU64 = AtomicU64::new(0);
+    let mut x = STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        x = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64
+            | 1;
+    }
+    // xorshift64
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    STATE.store(x, Ordering::Relaxed);
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(millis: f64) -> Duration {
+        Duration::from_secs_f64(millis / 1000.0)
+    }
+
+    fn estimator() -> RttEstimator {
+        RttEstimator::new(
+            Duration::from_secs(1),
+            Duration::from_millis(100),
+            Duration::from_secs(10),
+        )
+    }
+
+    #[test]
+    fn estimates_as_rfc_6298() {
+        let mut rtt = estimator();
+        assert_eq!(rtt.timeout(), Duration::from_secs(1));
+
+        // First sample, SRTT = R and RTTVAR = R/2
+        rtt.update(millis(200.0));
+        assert_eq!(rtt.srtt, Some(millis(200.0)));
+        assert_eq!(rtt.rttvar, millis(100.0));
+        assert_eq!(rtt.timeout(), millis(600.0));
+
+        // RTTVAR = 3/4 * 100 + 1/4 * |200 - 400|, SRTT = 7/8 * 200 + 1/8 * 400
+        rtt.update(millis(400.0));
+        assert_eq!(rtt.rttvar, millis(125.0));
+        assert_eq!(rtt.srtt, Some(millis(225.0)));
+        assert_eq!(rtt.timeout(), millis(725.0));
+
+        // RTTVAR = 3/4 * 125 + 1/4 * |225 - 100|, SRTT = 7/8 * 225 + 1/8 * 100
+        rtt.update(millis(100.0));
+        assert_eq!(rtt.rttvar, millis(125.0));
+        assert_eq!(rtt.srtt, Some(millis(209.375)));
+        assert_eq!(rtt.timeout(), millis(709.375));
+    }
+
+    #[test]
+    fn keeps_the_timeout_within_bounds() {
+        let mut rtt = estimator();
+        rtt.update(Duration::from_millis(1));
+        assert_eq!(rtt.timeout(), Duration::from_millis(100));
+        let mut rtt = estimator();
+        rtt.update(Duration::from_secs(5));
+        assert_eq!(rtt.timeout(), Duration::from_secs(10));
+
+        let rtt = RttEstimator::new(
+            Duration::from_secs(20),
+            Duration::from_millis(100),
+            Duration::from_secs(10),
+        );
+        assert_eq!(rtt.timeout(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn backs_off_until_the_next_sample() {
+        let mut rtt = estimator();
+        rtt.update(millis(200.0));
+        let expected = [1200.0, 2400.0, 4800.0, 9600.0, 10000.0, 10000.0];
+        for timeout in expected {
+            rtt.back_off();
+            assert_eq!(rtt.timeout(), millis(timeout));
+        }
+        // Backing off leaves the estimates alone, a new sample resets the timer from them
+        assert_eq!(rtt.srtt, Some(millis(200.0)));
+        rtt.update(millis(200.0));
+        assert_eq!(rtt.timeout(), millis(500.0));
+
+        // Before any sample the initial timeout doubles
+        let mut rtt = estimator();
+        rtt.back_off();
+        assert_eq!(rtt.timeout(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn limits_the_probe_rate() {
+        // 1000 probes a second, a burst of 10 goes out at once
+        let limiter = RateLimiter::new(1000.0, Duration::ZERO);
+        let start = Instant::now();
+        for _ in 0..10 {
+            limiter.acquire();
+        }
+        assert!(start.elapsed() < Duration::from_millis(50));
+        // The next 100 wait for the bucket to refill
+        let start = Instant::now();
+        for _ in 0..100 {
+            limiter.acquire();
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(95), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+
+        // Slow rates still allow one probe at a time
+        let limiter = RateLimiter::new(50.0, Duration::ZERO);
+        assert_eq!(limiter.burst, 1.0);
+        let start = Instant::now();
+        for _ in 0..6 {
+            limiter.acquire();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(95));
+    }
+
+    #[test]
+    fn draws_fractions_below_one() {
+        for _ in 0..1000 {
+            assert!((0.0..1.0).contains(&random_fraction()));
+        }
+    }
+
+    #[test]
+    fn picks_templates() {
+        assert_eq!(Timing::from_template("4").unwrap().name, "aggressive");
+        assert_eq!(Timing::from_template("Polite").unwrap().name, "polite");
+        assert_eq!(Timing::default().name, "normal");
+        assert_eq!(
+            Timing::from_template("6").err().unwrap(),
+            "Unknown timing template '6'"
+        );
+        assert!(Timing::from_template("fast").is_err());
+
+        let mut timing = Timing::from_template("insane").unwrap();
+        assert_eq!(timing.limiter.rate(), 5000.0);
+        assert_eq!(timing.rtt_estimator().timeout(), Duration::from_millis(250));
+        timing.set_max_rate(10.0);
+        assert_eq!(timing.limiter.rate(), 10.0);
+        assert_eq!(timing.limiter.jitter, Duration::ZERO);
+    }
+}
@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use packet_kit::PROTOCOL_UDP;
+use socket2::Socket;
+
+use crate::report::ScanRecord;
+use crate::service::Service;
+use crate::sockets::RawSockets;
+use crate::timing::Timing;
+use crate::{filtered_reason, parse_unreachable, PortResult, PortState};
+
+// Port states collected by the listener threads, with the time the answer arrived
+type Replies = Arc<Mutex<HashMap<u16, (PortState, &'static str, Instant)>>>;
+
+// Highest probe rate per second, on top of the timing template. Most stacks
+// rate limit ICMP port unreachable messages (Linux allows roughly one per
+// second per host by default), so blasting probes only gets the closed ports
+// reported as open|filtered.
+const UDP_PROBE_RATE: u32 = 10;
+
+// DNS: standard query for the root NS records
+const DNS_PROBE: &[u8] = b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x00\x02\x00\x01";
+
+// NTP: version 4 client request (LI = 3, VN = 4, Mode = 3)
+const NTP_PROBE: &[u8] = &[
+    0xe3, 0x00, 0x04, 0xfa, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+// SNMP: v1 GetRequest for sysDescr.0 with the "public" community
+const SNMP_PROBE: &[u8] = &[
+    0x30, 0x26, 0x02, 0x01, 0x00, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', 0xa0, 0x19, 0x02,
+    0x01, 0x01, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x30, 0x0c, 0x06, 0x08, 0x2b, 0x06,
+    0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x05, 0x00,
+];
+
+// SSDP: M-SEARCH discovery request
+const SSDP_PROBE: &[u8] = b"M-SEARCH * HTTP/1.1\r\n\
+HOST: 239.255.255.250:1900\r\n\
+MAN: \"ssdp:discover\"\r\n\
+MX: 1\r\n\
+ST: ssdp:all\r\n\r\n";
+
+// NetBIOS: node status (NBSTAT) query for the wildcard name "*"
+const NETBIOS_PROBE: &[u8] = b"\x80\xf0\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+\x20CKAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\x00\x00\x21\x00\x01";
+
+// Maps well-known ports to the payload their service is expected to answer
+const UDP_PROBES: &[(u16, &str, &[u8])] = &[
+    (53, "dns", DNS_PROBE),
+    (123, "ntp", NTP_PROBE),
+    (137, "netbios-ns", NETBIOS_PROBE),
+    (161, "snmp", SNMP_PROBE),
+    (1900, "ssdp", SSDP_PROBE),
+];
+
+// Returns the service name and payload to send to the given port
+fn probe_for(port: u16) -> (&'static str, &'static [u8]) {
+    UDP_PROBES
+        .iter()
+        .find(|(p, _, _)| *p == port)
+        .map(|(_, name, payload)| (*name, *payload))
+        .unwrap_or(("unknown", &[]))
+}
+
+// Interprets an ICMP error quoting one of our probes.
+// Returns the probed port along with the state and reason it implies.
+fn classify_icmp(packet: &[u8], target: Ipv4Addr) -> Option<(u16, PortState, &'static str)> {
+    let (port, code) = parse_unreachable(packet, target, PROTOCOL_UDP)?;
+    let reason = filtered_reason(code)?;
+    if code == 3 {
+        Some((port, PortState::Closed, reason))
+    } else {
+        Some((port, PortState::Filtered, reason))
+    }
+}
+
+// Listens for ICMP destination unreachable messages about our probes
+fn sniff_icmp(
+    sniffer: Socket,
+    target: Ipv4Addr,
+    results: Replies,
+    done: Arc<AtomicBool>,
+) -> io::Result<()> {
+    sniffer.set_read_timeout(Some(Duration::from_millis(200)))?;
+
+    let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+    while !done.load(Ordering::Relaxed) {
+        let length = match sniffer.recv_from(&mut buffer) {
+            Ok((length, _)) => length,
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(err) => return Err(err),
+        };
+        let raw_buffer: &[u8] =
+            unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, length) };
+
+        if let Some((port, state, reason)) = classify_icmp(raw_buffer, target) {
+            results
+                .lock()
+                .unwrap()
+                .entry(port)
+                .or_insert((state, reason, Instant::now()));
+        }
+    }
+    Ok(())
+}
+
+// Listens for UDP replies coming back from the target
+fn receive_replies(
+    socket: UdpSocket,
+    target: Ipv4Addr,
+    results: Replies,
+    done: Arc<AtomicBool>,
+) -> io::Result<()> {
+    socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+
+    let mut buffer = [0u8; 65535];
+    while !done.load(Ordering::Relaxed) {
+        match socket.recv_from(&mut buffer) {
+            Ok((_, SocketAddr::V4(from))) if *from.ip() == target => {
+                // A reply always wins over an ICMP error seen for an earlier probe
+                results.lock().unwrap().insert(
+                    from.port(),
+                    (PortState::Open, "udp-response", Instant::now()),
+                );
+            }
+            Ok(_) => continue,
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut
+                    || err.kind() == io::ErrorKind::ConnectionRefused =>
+            {
+                continue
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+// Probes every port with its protocol payload and collects the port states along with
+// the round trip time measured from the last probe sent to the port. Without a raw ICMP
+// socket the errors of closed ports go unseen, they show as open|filtered.
+fn scan(
+    target: Ipv4Addr,
+    ports: &[u16],
+    timing: &Timing,
+    icmp: Option<Socket>,
+) -> io::Result<HashMap<u16, PortResult>> {
+    let results = Arc::new(Mutex::new(HashMap::new()));
+    let done = Arc::new(AtomicBool::new(false));
+
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))?;
+
+    let icmp_thread = icmp.map(|sniffer| {
+        let results = results.clone();
+        let done = done.clone();
+        thread::spawn(move || {
+            if let Err(err) = sniff_icmp(sniffer, target, results, done) {
+                eprintln!(
+                    "Error capturing ICMP packets, closed ports will show as open|filtered: {}",
+                    err
+                );
+            }
+        })
+    });
+
+    let reply_thread = thread::spawn({
+        let socket = socket.try_clone()?;
+        let results = results.clone();
+        let done = done.clone();
+        move || {
+            if let Err(err) = receive_replies(socket, target, results, done) {
+                eprintln!("Error receiving UDP replies: {}", err);
+            }
+        }
+    });
+
+    let mut sent = HashMap::new();
+    let mut rtt = timing.rtt_estimator();
+    let mut rate = UDP_PROBE_RATE;
+    for round in 0..=timing.max_retries {
+        let pending: Vec<u16> = {
+            let results = results.lock().unwrap();
+            ports
+                .iter()
+                .copied()
+                .filter(|port| !results.contains_key(port))
+                .collect()
+        };
+        if pending.is_empty() {
+            break;
+        }
+
+        let interval = Duration::from_secs(1) / rate;
+        for port in pending {
+            timing.limiter.acquire();
+            let (service, payload) = probe_for(port);
+            println!("Trying {}:{}/udp ({})", target, port, service);
+            sent.insert(port, Instant::now());
+            socket.send_to(payload, SocketAddr::new(IpAddr::V4(target), port))?;
+            thread::sleep(interval);
+        }
+        thread::sleep(rtt.timeout());
+
+        // Karn's algorithm: only answers to probes sent once give a usable sample
+        if round == 0 {
+            for (port, (_, _, received)) in results.lock().unwrap().iter() {
+                if let Some(sent) = sent.get(port) {
+                    rtt.update(received.saturating_duration_since(*sent));
+                }
+            }
+        } else {
+            rtt.back_off();
+        }
+        // Back off so throttled ICMP errors get a chance on the next round
+        rate = (rate / 2).max(1);
+    }
+
+    done.store(true, Ordering::Relaxed);
+    if let Some(icmp_thread) = icmp_thread {
+        icmp_thread.join().unwrap();
+    }
+    reply_thread.join().unwrap();
+
+    let results = Arc::try_unwrap(results).unwrap().into_inner().unwrap();
+    Ok(ports
+        .iter()
+        .map(|port| {
+            let result = match results.get(port) {
+                Some((state, reason, received)) => (
+                    *state,
+                    *reason,
+                    sent.get(port)
+                        .map(|sent: &Instant| received.saturating_duration_since(*sent)),
+                ),
+                None => (PortState::OpenFiltered, "no-response", None),
+            };
+            (*port, result)
+        })
+        .collect())
+}
+
+// Scans the ports and returns a record per port, ports missing from the
+// service table are named after the probe sent to them
+pub fn run(
+    target: Ipv4Addr,
+    ports: &[u16],
+    timing: &Timing,
+    sockets: Option<&RawSockets>,
+) -> io::Result<Vec<ScanRecord>> {
+    let icmp = sockets.map(RawSockets::icmp).transpose()?;
+    let results = scan(target, ports, timing, icmp)?;
+
+    let mut records: Vec<ScanRecord> = results
+        .into_iter()
+        .map(|(port, (state, reason, rtt))| {
+            let mut record = ScanRecord::new(IpAddr::V4(target), port, "udp", state, reason);
+            if record.service.is_none() && probe_for(port).0 != "unknown" {
+                record.service = Some(Service {
+                    name: probe_for(port).0.to_string(),
+                    ..Default::default()
+                });
+            }
+            ScanRecord { rtt, ..record }
+        })
+        .collect();
+    records.sort_by_key(|record| record.port);
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use packet_kit::{IcmpBuilder, Ipv4Builder, TcpBuilder, UdpBuilder};
+
+    const TARGET: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 10);
+    const US: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const ROUTER: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 1);
+
+    // A destination unreachable message from the given address quoting the IP
+    // header and the first 8 bytes of the probe, as RFC 792 asks
+    fn unreachable(from: Ipv4Addr, code: u8, probe: Vec<u8>) -> Vec<u8> {
+        let quoted = &probe[..(probe[0] & 0x0f) as usize * 4 + 8];
+        Ipv4Builder::new()
+            .src(from)
+            .dst(US)
+            .payload(IcmpBuilder::new(3, code).payload(quoted))
+            .build()
+    }
+
+    fn udp_probe(dst: Ipv4Addr, port: u16) -> Vec<u8> {
+        Ipv4Builder::new()
+            .src(US)
+            .dst(dst)
+            .payload(UdpBuilder::new(40000, port).payload(DNS_PROBE))
+            .build()
+    }
+
+    #[test]
+    fn classifies_icmp_unreachable() {
+        let cases: &[(u8, Option<(PortState, &str)>)] = &[
+            (0, None),
+            (1, Some((PortState::Filtered, "host-unreach"))),
+            (2, Some((PortState::Filtered, "proto-unreach"))),
+            (3, Some((PortState::Closed, "port-unreach"))),
+            (4, None),
+            (9, Some((PortState::Filtered, "admin-prohibited"))),
+            (10, Some((PortState::Filtered, "admin-prohibited"))),
+            (13, Some((PortState::Filtered, "admin-prohibited"))),
+        ];
+        for (code, expected) in cases {
+            let message = unreachable(ROUTER, *code, udp_probe(TARGET, 53));
+            assert_eq!(
+                classify_icmp(&message, TARGET),
+                expected.map(|(state, reason)| (53, state, reason)),
+                "code {}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_errors_about_other_probes() {
+        // Quoting a probe sent to another host
+        let message = unreachable(ROUTER, 3, udp_probe(ROUTER, 53));
+        assert_eq!(classify_icmp(&message, TARGET), None);
+
+        // Quoting a TCP probe to the target
+        let tcp = Ipv4Builder::new()
+            .src(US)
+            .dst(TARGET)
+            .payload(TcpBuilder::new(40000, 53).syn())
+            .build();
+        assert_eq!(classify_icmp(&unreachable(TARGET, 3, tcp), TARGET), None);
+
+        // Not a destination unreachable message
+        let echo = Ipv4Builder::new()
+            .src(TARGET)
+            .dst(US)
+            .payload(IcmpBuilder::echo_reply(1, 1))
+            .build();
+        assert_eq!(classify_icmp(&echo, TARGET), None);
+
+        // Cut before the quoted port
+        let message = unreachable(TARGET, 3, udp_probe(TARGET, 53));
+        assert_eq!(classify_icmp(&message[..message.len() - 6], TARGET), None);
+    }
+
+    #[test]
+    fn quoted_probes_may_carry_ip_options() {
+        let probe = Ipv4Builder::new()
+            .src(US)
+            .dst(TARGET)
+            .options(&[1, 1, 1, 0])
+            .payload(UdpBuilder::new(40000, 161).payload(SNMP_PROBE))
+            .build();
+        assert_eq!(
+            classify_icmp(&unreachable(TARGET, 3, probe), TARGET),
+            Some((161, PortState::Closed, "port-unreach"))
+        );
+    }
+
+    #[test]
+    fn probes_known_services() {
+        assert_eq!(probe_for(53), ("dns", DNS_PROBE));
+        assert_eq!(probe_for(1900), ("ssdp", SSDP_PROBE));
+        assert_eq!(probe_for(9999), ("unknown", &[][..]));
+    }
+}
@@ -0,0 +1,133 @@
+use std::io;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use packet_kit::capture::LiveCapture;
+use packet_kit::privilege::{self, Capability};
+use packet_kit::{GeoIp, PcapReader};
+
+mod stats;
+
+use stats::Stats;
+
+// Set by Ctrl-C so the final report still gets printed
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_stop(_: libc::c_int) {
+    STOP.store(true, Ordering::Relaxed);
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [-i <iface> | -r <capture.pcap>] [-c <count>] [--interval <seconds>] [--top <n>] \
+         [--geoip <database.mmdb>]...",
+        program
+    );
+    eprintln!("  GeoLite2 City, Country or ASN databases annotate the top addresses");
+    std::process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let mut args = std::env::args();
+    let program = args.next().unwrap();
+
+    let mut iface = None;
+    let mut path = None;
+    let mut count = None;
+    let mut interval = None;
+    let mut top = 10;
+    let mut geoip = GeoIp::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage(&program));
+        match arg.as_str() {
+            "-i" => iface = Some(value()),
+            "-r" => path = Some(value()),
+            "-c" => count = Some(value().parse::<u64>().unwrap_or_else(|_| usage(&program))),
+            "--interval" => match value().parse::<f64>() {
+                Ok(seconds) if seconds > 0.0 => interval = Some(Duration::from_secs_f64(seconds)),
+                _ => usage(&program),
+            },
+            "--top" => top = value().parse().unwrap_or_else(|_| usage(&program)),
+            "--geoip" => {
+                if let Err(err) = geoip.open(value()) {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            }
+            _ => usage(&program),
+        }
+    }
+
+    let mut stats = Stats::default();
+    let started = Instant::now();
+    let mut seen = 0;
+
+    if let Some(path) = path {
+        if iface.is_some() {
+            usage(&program);
+        }
+        // A capture file is read at once, the report comes at the end
+        let mut capture = PcapReader::open(&path)?;
+        while let Some(packet) = capture.next_packet()? {
+            // pcapng files carry a link type per interface
+            stats.record(&path, packet.linktype, &packet.data);
+            seen += 1;
+            if count == Some(seen) {
+                break;
+            }
+        }
+        print!("{}", stats.report(started.elapsed(), top, &geoip));
+        return Ok(());
+    }
+
+    let mut capture = LiveCapture::open(iface.as_deref()).unwrap_or_else(|err| {
+        eprintln!(
+            "{}",
+            privilege::explain(
+                "Can't capture live traffic",
+                &err,
+                Capability::NetRaw,
+                Some("count the packets of a capture file with -r <capture.pcap>")
+            )
+        );
+        std::process::exit(1);
+    });
+    // Wake up regularly to print the periodic reports and notice Ctrl-C
+    capture.set_read_timeout(Some(Duration::from_millis(200)))?;
+    // Interface names come from /sys, which anyone can read
+    if let Err(err) = privilege::drop_privileges() {
+        eprintln!("Can't drop privileges: {}", err);
+        std::process::exit(1);
+    }
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            request_stop as *const () as libc::sighandler_t,
+        );
+        libc::signal(
+            libc::SIGTERM,
+            request_stop as *const () as libc::sighandler_t,
+        );
+    }
+
+    let mut buffer: [MaybeUninit<u8>; 65535] = unsafe { MaybeUninit::uninit().assume_init() };
+    let mut last_report = Instant::now();
+    while !STOP.load(Ordering::Relaxed) && count != Some(seen) {
+        if let Some(frame) = capture.next_frame(&mut buffer)? {
+            let raw_buffer: &[u8] =
+                unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, frame.length) };
+            let interface = capture.interface_name(frame.interface);
+            stats.record(&interface, frame.linktype, raw_buffer);
+            seen += 1;
+        }
+        if let Some(interval) = interval {
+            if last_report.elapsed() >= interval {
+                println!("{}", stats.report(started.elapsed(), top, &geoip));
+                last_report = Instant::now();
+            }
+        }
+    }
+    print!("{}", stats.report(started.elapsed(), top, &geoip));
+    Ok(())
+}
@@ -0,0 +1,523 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Write};
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use packet_kit::dissect::{self, Next};
+use packet_kit::ethernet::ETHERTYPE_ARP;
+use packet_kit::geoip::GeoIp;
+use packet_kit::names::{icmp_type_name, protocol_name};
+use packet_kit::pcap::{
+    LINKTYPE_ETHERNET, LINKTYPE_IPV4, LINKTYPE_IPV6, LINKTYPE_LINUX_SLL, LINKTYPE_NULL,
+    LINKTYPE_RAW,
+};
+
+// Lower bounds of the packet size histogram, the buckets Wireshark uses
+const SIZE_BUCKETS: &[usize] = &[0, 20, 40, 80, 160, 320, 640, 1280, 2560, 5120];
+// Width of the longest histogram bar
+const BAR_WIDTH: u64 = 40;
+
+#[derive(Clone, Copy, Default)]
+struct Counter {
+    packets: u64,
+    bytes: u64,
+}
+
+impl Counter {
+    fn add(&mut self, bytes: usize) {
+        self.packets += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+// Everything learnt from the packets seen so far
+#[derive(Default)]
+pub struct Stats {
+    total: Counter,
+    interfaces: BTreeMap<String, Counter>,
+    // Every protocol path such as Ethernet > IPv4 > TCP, along with each of its prefixes
+    hierarchy: BTreeMap<Vec<&'static str>, Counter>,
+    sources: HashMap<IpAddr, Counter>,
+    destinations: HashMap<IpAddr, Counter>,
+    source_ports: HashMap<(&'static str, u16), Counter>,
+    destination_ports: HashMap<(&'static str, u16), Counter>,
+    sizes: [u64; SIZE_BUCKETS.len()],
+    // IPv4 TTL and IPv6 hop limit
+    ttls: BTreeMap<u8, u64>,
+    // ICMP messages by type and code, ICMPv6 ones flagged
+    icmp: HashMap<(bool, u8, u8), u64>,
+}
+
+// Decoded bits of a frame that end up in the report
+struct Dissection {
+    path: Vec<&'static str>,
+    addresses: Option<(IpAddr, IpAddr)>,
+    ports: Option<(&'static str, u16, u16)>,
+    ttl: Option<u8>,
+    icmp: Option<(bool, u8, u8)>,
+}
+
+// Decodes the frame, following tunnels so that the inner packets fill the report
+fn dissect(linktype: u32, frame: &[u8]) -> Dissection {
+    let decoded = dissect::decode(linktype, frame);
+    let mut dissection = Dissection {
+        path: Vec::new(),
+        addresses: decoded.addresses(),
+        ports: None,
+        ttl: None,
+        icmp: None,
+    };
+
+    match linktype {
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => dissection.path.push("Raw IP"),
+        LINKTYPE_ETHERNET | LINKTYPE_LINUX_SLL | LINKTYPE_NULL => {}
+        _ => {
+            dissection.path.push("Unknown link layer");
+            return dissection;
+        }
+    }
+
+    for layer in decoded.layers() {
+        dissection.path.push(layer.name);
+        if layer.name == "Ethernet" && layer.field("VLAN").is_some() {
+            dissection.path.push("802.1Q VLAN");
+        }
+        let field = |name| layer.field(name).and_then(|value| value.parse().ok());
+        match layer.name {
+            "IPv4" => {
+                dissection.ttl = field("TTL");
+                dissection.ports = None;
+                dissection.icmp = None;
+                // Later fragments don't start with a transport header
+                if layer.field("Fragment Offset") != Some("0") {
+                    if let Some(protocol) = field("Protocol") {
+                        dissection.path.push(protocol_name(protocol));
+                    }
+                }
+            }
+            "IPv6" => {
+                dissection.ttl = field("Hop Limit");
+                dissection.ports = None;
+                dissection.icmp = None;
+            }
+            "TCP" | "UDP" => {
+                let transport = if layer.name == "TCP" { "tcp" } else { "udp" };
+                dissection.ports = layer
+                    .ports
+                    .map(|(source, destination)| (transport, source, destination));
+            }
+            "ICMP" | "ICMPv6" => {
+                if let (Some(type_), Some(code)) = (field("Type"), field("Code")) {
+                    dissection.icmp = Some((layer.name == "ICMPv6", type_, code));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // What the last layer announced but nothing here decodes
+    match decoded.next {
+        Next::IpProtocol(protocol) => dissection.path.push(protocol_name(protocol)),
+        Next::EtherType(ethertype) => dissection.path.push(ethertype_name(ethertype)),
+        _ => {}
+    }
+    dissection
+}
+
+fn ethertype_name(ethertype: u16) -> &'static str {
+    match ethertype {
+        ETHERTYPE_ARP => "ARP",
+        _ => "Other EtherType",
+    }
+}
+
+// Busiest entries first, ties broken by key so reports are stable
+fn top<K: Clone + Ord + Hash>(counters: &HashMap<K, Counter>, count: usize) -> Vec<(K, Counter)> {
+    let mut entries: Vec<(K, Counter)> = counters
+        .iter()
+        .map(|(key, counter)| (key.clone(), *counter))
+        .collect();
+    entries.sort_by_key(|(key, counter)| (Reverse(counter.packets), key.clone()));
+    entries.truncate(count);
+    entries
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    part as f64 * 100.0 / total.max(1) as f64
+}
+
+fn write_top<K: Display>(out: &mut String, title: &str, entries: Vec<(K, Counter)>) {
+    let _ = writeln!(out, "\n{}", title);
+    for (key, counter) in entries {
+        let _ = writeln!(
+            out,
+            "  {:<40} {:>10} packets {:>14} bytes",
+            key.to_string(),
+            counter.packets,
+            counter.bytes
+        );
+    }
+}
+
+impl Stats {
+    pub fn record(&mut self, interface: &str, linktype: u32, frame: &[u8]) {
+        let length = frame.len();
+        self.total.add(length);
+        self.interfaces
+            .entry(interface.to_string())
+            .or_default()
+            .add(length);
+
+        let bucket = SIZE_BUCKETS
+            .iter()
+            .rposition(|low| length >= *low)
+            .unwrap_or(0);
+        self.sizes[bucket] += 1;
+
+        let dissection = dissect(linktype, frame);
+        for depth in 1..=dissection.path.len() {
+            self.hierarchy
+                .entry(dissection.path[..depth].to_vec())
+                .or_default()
+                .add(length);
+        }
+        if let Some((src, dst)) = dissection.addresses {
+            self.sources.entry(src).or_default().add(length);
+            self.destinations.entry(dst).or_default().add(length);
+        }
+        if let Some((transport, sport, dport)) = dissection.ports {
+            self.source_ports
+                .entry((transport, sport))
+                .or_default()
+                .add(length);
+            self.destination_ports
+                .entry((transport, dport))
+                .or_default()
+                .add(length);
+        }
+        if let Some(ttl) = dissection.ttl {
+            *self.ttls.entry(ttl).or_insert(0) += 1;
+        }
+        if let Some(message) = dissection.icmp {
+            *self.icmp.entry(message).or_insert(0) += 1;
+        }
+    }
+
+    // The whole report, the top lists limited to `count` entries and their addresses
+    // annotated from the GeoIP databases
+    pub fn report(&self, elapsed: Duration, count: usize, geoip: &GeoIp) -> String {
+        let mut out = String::new();
+        let total = self.total;
+        let _ = writeln!(
+            out,
+            "Traffic statistics: {} packets, {} bytes in {:.1} s",
+            total.packets,
+            total.bytes,
+            elapsed.as_secs_f64()
+        );
+
+        let _ = writeln!(out, "\nInterfaces");
+        for (name, counter) in &self.interfaces {
+            let _ = writeln!(
+                out,
+                "  {:<40} {:>10} packets {:>14} bytes",
+                name, counter.packets, counter.bytes
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "\n{:<42} {:>10} {:>7} {:>14} {:>7}",
+            "Protocol hierarchy", "Packets", "%", "Bytes", "%"
+        );
+        for (path, counter) in &self.hierarchy {
+            let name = format!("{}{}", "  ".repeat(path.len()), path[path.len() - 1]);
+            let _ = writeln!(
+                out,
+                "{:<42} {:>10} {:>6.1}% {:>14} {:>6.1}%",
+                name,
+                counter.packets,
+                percent(counter.packets, total.packets),
+                counter.bytes,
+                percent(counter.bytes, total.bytes)
+            );
+        }
+
+        let address = |(address, counter): (IpAddr, Counter)| (geoip.annotate(address), counter);
+        write_top(
+            &mut out,
+            "Top source addresses",
+            top(&self.sources, count).into_iter().map(address).collect(),
+        );
+        write_top(
+            &mut out,
+            "Top destination addresses",
+            top(&self.destinations, count)
+                .into_iter()
+                .map(address)
+                .collect(),
+        );
+        let port = |((transport, port), counter): ((&str, u16), Counter)| {
+            (format!("{}/{}", port, transport), counter)
+        };
+        write_top(
+            &mut out,
+            "Top source ports",
+            top(&self.source_ports, count)
+                .into_iter()
+                .map(port)
+                .collect(),
+        );
+        write_top(
+            &mut out,
+            "Top destination ports",
+            top(&self.destination_ports, count)
+                .into_iter()
+                .map(port)
+                .collect(),
+        );
+
+        let _ = writeln!(out, "\nPacket sizes");
+        let busiest = self.sizes.iter().copied().max().unwrap_or(0).max(1);
+        for (index, packets) in self.sizes.iter().enumerate() {
+            let range = match SIZE_BUCKETS.get(index + 1) {
+                Some(next) => format!("{}-{}", SIZE_BUCKETS[index], next - 1),
+                None => format!("{}+", SIZE_BUCKETS[index]),
+            };
+            let bar = "#".repeat((packets * BAR_WIDTH).div_ceil(busiest) as usize);
+            let line = format!("  {:<12} {:>10} {}", range, packets, bar);
+            let _ = writeln!(out, "{}", line.trim_end());
+        }
+
+        let _ = writeln!(out, "\nTTL / hop limit");
+        for (ttl, packets) in &self.ttls {
+            let _ = writeln!(out, "  {:<12} {:>10}", ttl, packets);
+        }
+
+        let _ = writeln!(out, "\nICMP messages");
+        let mut messages: Vec<(&(bool, u8, u8), &u64)> = self.icmp.iter().collect();
+        messages.sort_by_key(|(message, packets)| (Reverse(**packets), **message));
+        for ((v6, type_, code), packets) in messages {
+            let name = if *v6 {
+                format!("ICMPv6 Type: {}, Code: {}", type_, code)
+            } else {
+                icmp_type_name(*type_, *code)
+            };
+            let _ = writeln!(out, "  {:<40} {:>10}", name, packets);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use packet_kit::ethernet::ETHERTYPE_IPV4;
+    use packet_kit::{
+        ArpPacket, EthernetHeader, IcmpBuilder, Ipv4Builder, Ipv6Builder, MacAddr, TcpBuilder,
+        UdpBuilder, PROTOCOL_UDP,
+    };
+
+    use super::*;
+
+    const HOST: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 1]);
+    const ROUTER: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 2]);
+
+    fn address(last: u8) -> Ipv4Addr {
+        Ipv4Addr::new(192, 0, 2, last)
+    }
+
+    fn ethernet(packet: &[u8]) -> Vec<u8> {
+        EthernetHeader::new(ROUTER, HOST, ETHERTYPE_IPV4).build(packet)
+    }
+
+    fn tcp(src: u8, dst: u8, dst_port: u16) -> Vec<u8> {
+        ethernet(
+            &Ipv4Builder::new()
+                .src(address(src))
+                .dst(address(dst))
+                .ttl(64)
+                .payload(TcpBuilder::new(40000, dst_port).syn())
+                .build(),
+        )
+    }
+
+    fn paths(stats: &Stats) -> Vec<(String, u64)> {
+        stats
+            .hierarchy
+            .iter()
+            .map(|(path, counter)| (path.join(" > "), counter.packets))
+            .collect()
+    }
+
+    #[test]
+    fn builds_the_protocol_hierarchy() {
+        let mut stats = Stats::default();
+        stats.record("eth0", LINKTYPE_ETHERNET, &tcp(1, 2, 80));
+        stats.record("eth0", LINKTYPE_ETHERNET, &tcp(1, 2, 443));
+        let request = ArpPacket::request(HOST, address(1), address(2)).build();
+        stats.record(
+            "eth0",
+            LINKTYPE_ETHERNET,
+            &EthernetHeader::new(MacAddr::BROADCAST, HOST, ETHERTYPE_ARP).build(&request),
+        );
+        let datagram = Ipv6Builder::new()
+            .src(Ipv6Addr::LOCALHOST)
+            .dst(Ipv6Addr::LOCALHOST)
+            .payload(UdpBuilder::new(5353, 53).payload(b"query"))
+            .build();
+        stats.record("tun0", LINKTYPE_RAW, &datagram);
+        stats.record("can0", 227, &[0; 16]);
+
+        assert_eq!(
+            paths(&stats),
+            [
+                ("Ethernet".to_string(), 3),
+                ("Ethernet > ARP".to_string(), 1),
+                ("Ethernet > IPv4".to_string(), 2),
+                ("Ethernet > IPv4 > TCP".to_string(), 2),
+                ("Raw IP".to_string(), 1),
+                ("Raw IP > IPv6".to_string(), 1),
+                ("Raw IP > IPv6 > UDP".to_string(), 1),
+                ("Unknown link layer".to_string(), 1),
+            ]
+        );
+        assert_eq!(stats.total.packets, 5);
+        let interfaces: Vec<(&str, u64)> = stats
+            .interfaces
+            .iter()
+            .map(|(name, counter)| (name.as_str(), counter.packets))
+            .collect();
+        assert_eq!(interfaces, [("can0", 1), ("eth0", 3), ("tun0", 1)]);
+    }
+
+    #[test]
+    fn counts_later_fragments_under_their_protocol() {
+        let mut stats = Stats::default();
+        let fragment = Ipv4Builder::new()
+            .src(address(1))
+            .dst(address(2))
+            .protocol(PROTOCOL_UDP)
+            .fragment_offset(185)
+            .payload(vec![0u8; 32])
+            .build();
+        stats.record("eth0", LINKTYPE_ETHERNET, &ethernet(&fragment));
+        assert!(paths(&stats).contains(&("Ethernet > IPv4 > UDP".to_string(), 1)));
+        // Without a transport header there are no ports to count
+        assert!(stats.source_ports.is_empty());
+        assert_eq!(stats.sources[&IpAddr::V4(address(1))].packets, 1);
+    }
+
+    #[test]
+    fn ranks_the_busiest_addresses_and_ports() {
+        let mut stats = Stats::default();
+        for (src, dst_port, packets) in [(4, 22, 1), (3, 80, 2), (2, 80, 2), (1, 443, 3)] {
+            for _ in 0..packets {
+                stats.record("eth0", LINKTYPE_ETHERNET, &tcp(src, 9, dst_port));
+            }
+        }
+        let sources: Vec<(IpAddr, u64)> = top(&stats.sources, 3)
+            .into_iter()
+            .map(|(address, counter)| (address, counter.packets))
+            .collect();
+        // Ties go to the lower address
+        assert_eq!(
+            sources,
+            [
+                (IpAddr::V4(address(1)), 3),
+                (IpAddr::V4(address(2)), 2),
+                (IpAddr::V4(address(3)), 2),
+            ]
+        );
+        let ports: Vec<((&str, u16), u64)> = top(&stats.destination_ports, 10)
+            .into_iter()
+            .map(|(port, counter)| (port, counter.packets))
+            .collect();
+        assert_eq!(
+            ports,
+            [(("tcp", 80), 4), (("tcp", 443), 3), (("tcp", 22), 1)]
+        );
+        assert_eq!(
+            stats.destinations[&IpAddr::V4(address(9))].bytes,
+            8 * tcp(1, 9, 80).len() as u64
+        );
+
+        let report = stats.report(Duration::from_secs(1), 1, &GeoIp::new());
+        assert!(report.contains("Top destination ports\n  80/tcp "));
+        assert!(!report.contains("443/tcp"));
+    }
+
+    #[test]
+    fn fills_the_size_histogram() {
+        let mut stats = Stats::default();
+        for length in [0, 19, 20, 79, 80, 1280, 1500, 9000] {
+            stats.record("eth0", LINKTYPE_ETHERNET, &vec![0; length]);
+        }
+        assert_eq!(stats.sizes, [2, 1, 1, 1, 0, 0, 0, 2, 0, 1]);
+
+        let report = stats.report(Duration::from_secs(1), 10, &GeoIp::new());
+        assert!(report.contains(&format!("  0-19                  2 {}\n", "#".repeat(40))));
+        assert!(report.contains(&format!("  20-39                 1 {}\n", "#".repeat(20))));
+        assert!(report.contains("  160-319               0\n"));
+        assert!(report.contains("  5120+                 1 "));
+    }
+
+    #[test]
+    fn counts_ttls_and_icmp_messages() {
+        let mut stats = Stats::default();
+        let icmp = |ttl, message: IcmpBuilder| {
+            ethernet(
+                &Ipv4Builder::new()
+                    .src(address(1))
+                    .dst(address(2))
+                    .ttl(ttl)
+                    .payload(message)
+                    .build(),
+            )
+        };
+        stats.record(
+            "eth0",
+            LINKTYPE_ETHERNET,
+            &icmp(64, IcmpBuilder::echo_request(1, 1)),
+        );
+        stats.record(
+            "eth0",
+            LINKTYPE_ETHERNET,
+            &icmp(64, IcmpBuilder::echo_request(1, 2)),
+        );
+        stats.record(
+            "eth0",
+            LINKTYPE_ETHERNET,
+            &icmp(128, IcmpBuilder::new(3, 3)),
+        );
+        let echo = Ipv6Builder::new()
+            .src(Ipv6Addr::LOCALHOST)
+            .dst(Ipv6Addr::LOCALHOST)
+            .hop_limit(255)
+            .payload(IcmpBuilder::echo_request_v6(1, 1))
+            .build();
+        stats.record("lo", LINKTYPE_RAW, &echo);
+
+        let ttls: Vec<(u8, u64)> = stats
+            .ttls
+            .iter()
+            .map(|(ttl, count)| (*ttl, *count))
+            .collect();
+        assert_eq!(ttls, [(64, 2), (128, 1), (255, 1)]);
+        assert_eq!(stats.icmp[&(false, 8, 0)], 2);
+        assert_eq!(stats.icmp[&(false, 3, 3)], 1);
+        assert_eq!(stats.icmp[&(true, 128, 0)], 1);
+
+        let report = stats.report(Duration::from_secs(1), 10, &GeoIp::new());
+        let messages = report.split("ICMP messages\n").nth(1).unwrap();
+        let lines: Vec<&str> = messages.lines().collect();
+        assert_eq!(lines.len(), 3);
+        // The busiest message comes first
+        assert!(lines[0].starts_with(&format!("  {:<40}", icmp_type_name(8, 0))));
+        assert!(lines[0].ends_with(" 2"));
+        assert!(messages.contains("ICMPv6 Type: 128, Code: 0"));
+    }
+}
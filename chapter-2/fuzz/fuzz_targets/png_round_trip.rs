@@ -0,0 +1,6 @@
+#![no_main]
+
+use chapter_2_fuzz::PngSpec;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|spec: PngSpec| chapter_2_fuzz::png_round_trip(&spec));
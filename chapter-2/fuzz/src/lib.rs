@@ -0,0 +1,79 @@
+// Fuzz targets and property tests for the PNG chunk reader of parse-png. The checks are
+// shared by the libFuzzer targets of the same name, the proptest suites and the replay of
+// corpus/<target>/, where inputs worth keeping go.
+//
+//     cargo +nightly fuzz run png_chunks
+
+use std::io::Cursor;
+
+use arbitrary::Arbitrary;
+use parse_png::MetaChunk;
+
+// The signature every PNG file starts with
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+// A chunk as read back: its type and data
+pub type ChunkRecord = ([u8; 4], Vec<u8>);
+
+// Walks the chunks of the file up to IEND, None when the reader gives up first. A chunk
+// read must hold exactly the data its length announced.
+pub fn png_chunks(data: &[u8]) -> Option<Vec<ChunkRecord>> {
+    let mut file = Cursor::new(data);
+    let mut meta_chunk = MetaChunk::pre_process_image(&mut file).ok()?;
+    let mut chunks = Vec::new();
+    loop {
+        let offset = meta_chunk.get_offset(&mut file).ok()?;
+        assert!(offset <= data.len() as u64);
+        meta_chunk.read_chunk(&mut file).ok()?;
+        assert_eq!(meta_chunk.chk.data.len(), meta_chunk.chk.size as usize);
+        chunks.push((
+            meta_chunk.chk.r#type.to_be_bytes(),
+            meta_chunk.chk.data.clone(),
+        ));
+        if meta_chunk.chunk_type_to_string() == "IEND" {
+            return Some(chunks);
+        }
+    }
+}
+
+// Chunks to write into a file, IEND is appended to them
+#[derive(Arbitrary, Clone, Debug)]
+pub struct PngSpec {
+    pub chunks: Vec<([u8; 4], Vec<u8>)>,
+    pub crc: u32,
+}
+
+impl PngSpec {
+    // Every chunk before the first IEND, the reader stops there
+    pub fn expected(&self) -> Vec<ChunkRecord> {
+        let mut chunks: Vec<ChunkRecord> = self
+            .chunks
+            .iter()
+            .take_while(|(chunk_type, _)| chunk_type != b"IEND")
+            .cloned()
+            .collect();
+        chunks.push((*b"IEND", Vec::new()));
+        chunks
+    }
+
+    // The reader doesn't check CRCs, they are all the same
+    pub fn build(&self) -> Vec<u8> {
+        let mut file = SIGNATURE.to_vec();
+        for (chunk_type, data) in &self.expected() {
+            file.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            file.extend_from_slice(chunk_type);
+            file.extend_from_slice(data);
+            file.extend_from_slice(&self.crc.to_be_bytes());
+        }
+        file
+    }
+}
+
+pub fn png_round_trip(spec: &PngSpec) {
+    let file = spec.build();
+    assert_eq!(png_chunks(&file), Some(spec.expected()));
+    // The same file cut short anywhere never reaches IEND
+    for length in [0, 8, file.len() / 2, file.len() - 1] {
+        assert_eq!(png_chunks(&file[..length]), None);
+    }
+}
@@ -0,0 +1,39 @@
+// Replays the checked-in corpus of both fuzz targets on stable
+
+use std::fs;
+use std::path::Path;
+
+use arbitrary::{Arbitrary, Unstructured};
+use chapter_2_fuzz::PngSpec;
+
+fn replay(target: &str, check: impl Fn(&[u8])) {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("corpus")
+        .join(target);
+    let entries =
+        fs::read_dir(&directory).unwrap_or_else(|err| panic!("{}: {}", directory.display(), err));
+    let mut replayed = 0;
+    for entry in entries {
+        let path = entry.unwrap().path();
+        eprintln!("{}", path.display());
+        check(&fs::read(&path).unwrap());
+        replayed += 1;
+    }
+    assert!(replayed > 0, "the corpus of {} is empty", target);
+}
+
+#[test]
+fn png_chunks_corpus_replays_cleanly() {
+    replay("png_chunks", |data| {
+        chapter_2_fuzz::png_chunks(data);
+    });
+}
+
+#[test]
+fn png_round_trip_corpus_replays_cleanly() {
+    replay("png_round_trip", |data| {
+        if let Ok(spec) = PngSpec::arbitrary_take_rest(Unstructured::new(data)) {
+            chapter_2_fuzz::png_round_trip(&spec);
+        }
+    });
+}
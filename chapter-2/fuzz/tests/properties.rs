@@ -0,0 +1,34 @@
+// Property tests over the checks of the fuzz targets. Files are either arbitrary bytes
+// behind the PNG signature, or built from chunks that must read back unchanged.
+
+use arbitrary::{Arbitrary, Unstructured};
+use chapter_2_fuzz::{PngSpec, SIGNATURE};
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+proptest! {
+    #[test]
+    fn chunk_reader_accepts_any_bytes(data in vec(any::<u8>(), 0..256)) {
+        let mut file = SIGNATURE.to_vec();
+        file.extend(data);
+        chapter_2_fuzz::png_chunks(&file);
+    }
+
+    // A length field claiming gigabytes is only as good as the bytes behind it
+    #[test]
+    fn chunk_reader_survives_huge_lengths(length in any::<u32>(), data in vec(any::<u8>(), 0..64)) {
+        let mut file = SIGNATURE.to_vec();
+        file.extend(length.to_be_bytes());
+        file.extend(b"IDAT");
+        file.extend(data);
+        prop_assume!(file.len() < length as usize + 16);
+        prop_assert_eq!(chapter_2_fuzz::png_chunks(&file), None);
+    }
+
+    #[test]
+    fn built_files_survive_a_round_trip(bytes in vec(any::<u8>(), 0..512)) {
+        let spec = PngSpec::arbitrary_take_rest(Unstructured::new(&bytes));
+        prop_assume!(spec.is_ok());
+        chapter_2_fuzz::png_round_trip(&spec.unwrap());
+    }
+}
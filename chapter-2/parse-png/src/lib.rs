@@ -0,0 +1,127 @@
+use std::io::{self, ErrorKind, Read, Seek};
+
+#[derive(Debug)]
+pub struct Header {
+    pub header: u64,
+}
+
+#[derive(Debug)]
+pub struct Chunk {
+    pub size: u32,
+    pub r#type: u32,
+    pub data: Vec<u8>,
+    pub crc: u32,
+}
+
+pub struct MetaChunk {
+    pub header: Header,
+    pub chk: Chunk,
+    pub offset: u64,
+}
+
+// Reads exactly N bytes, saying what was being read if the file ends first
+fn read_array<R: Read, const N: usize>(file: &mut R, what: &str) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    file.read_exact(&mut bytes)
+        .map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Reached end of file prematurely while reading {}", what),
+            ),
+            _ => err,
+        })?;
+    Ok(bytes)
+}
+
+impl MetaChunk {
+    pub fn pre_process_image<R: Read + Seek>(file: &mut R) -> io::Result<MetaChunk> {
+        let header = Header {
+            header: u64::from_ne_bytes(read_array(file, "the PNG header")?),
+        };
+
+        let b_arr = header.header.to_ne_bytes();
+        if &b_arr[1..4] != b"PNG" {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Not a valid PNG format",
+            ));
+        }
+
+        let offset = file.stream_position()?;
+        Ok(MetaChunk {
+            header,
+            chk: Chunk {
+                size: 0,
+                r#type: 0,
+                data: Vec::new(),
+                crc: 0,
+            },
+            offset,
+        })
+    }
+
+    // Prints the offset of every chunk up to IEND, a file ending before it is an error
+    pub fn process_image<R: Read + Seek>(&mut self, file: &mut R) -> io::Result<()> {
+        let mut count = 1;
+        let mut chunk_type = String::new();
+        let end_chunk_type = "IEND";
+
+        while chunk_type != end_chunk_type {
+            println!("---- Chunk # {} ----", count);
+            let offset = self.get_offset(file)?;
+            println!("Chunk Offset: {:x}", offset);
+            self.read_chunk(file)?;
+            chunk_type = self.chunk_type_to_string();
+            count += 1;
+        }
+        Ok(())
+    }
+
+    pub fn get_offset<S: Seek>(&mut self, file: &mut S) -> io::Result<u64> {
+        self.offset = file.stream_position()?;
+        Ok(self.offset)
+    }
+
+    pub fn read_chunk<R: Read>(&mut self, file: &mut R) -> io::Result<()> {
+        self.read_chunk_size(file)?;
+        self.read_chunk_type(file)?;
+        self.read_chunk_bytes(file, self.chk.size)?;
+        self.read_chunk_crc(file)
+    }
+
+    pub fn read_chunk_size<R: Read>(&mut self, file: &mut R) -> io::Result<()> {
+        self.chk.size = u32::from_be_bytes(read_array(file, "chunk size")?);
+        Ok(())
+    }
+
+    pub fn read_chunk_type<R: Read>(&mut self, file: &mut R) -> io::Result<()> {
+        self.chk.r#type = u32::from_be_bytes(read_array(file, "chunk type")?);
+        Ok(())
+    }
+
+    // The length comes from the file itself, only the bytes actually there are allocated
+    pub fn read_chunk_bytes<R: Read>(&mut self, file: &mut R, len: u32) -> io::Result<()> {
+        self.chk.data.clear();
+        file.take(len as u64).read_to_end(&mut self.chk.data)?;
+        if self.chk.data.len() < len as usize {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "Reached end of file prematurely while reading chunk bytes, {} of {}",
+                    self.chk.data.len(),
+                    len
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn read_chunk_crc<R: Read>(&mut self, file: &mut R) -> io::Result<()> {
+        self.chk.crc = u32::from_be_bytes(read_array(file, "CRC")?);
+        Ok(())
+    }
+
+    pub fn chunk_type_to_string(&self) -> String {
+        String::from_utf8_lossy(&self.chk.r#type.to_be_bytes()).to_string()
+    }
+}